@@ -0,0 +1,214 @@
+use super::{Answer, DnsResult, RecordType, Resolver};
+use crate::common::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/**
+Caches the answers of another resolver for their TTL.
+
+Negative answers (no records) are cached too, failures are not.
+TTLs are capped at `with_max_ttl`, one day by default.
+When the cache is full, expired answers are dropped first,
+then those closest to expiry.
+
+```
+# use samotop_core::dns::*;
+# use std::time::Duration;
+let zone = ZoneResolver::parse("mx.example.org. 300 IN A 192.0.2.1").unwrap();
+let cache = DnsCache::new(zone)
+    .with_capacity(10_000)
+    .with_max_ttl(Duration::from_secs(3600));
+```
+*/
+pub struct DnsCache {
+    inner: Arc<dyn Resolver>,
+    capacity: usize,
+    max_ttl: Duration,
+    entries: Mutex<HashMap<(String, RecordType), (Instant, Answer)>>,
+}
+
+impl DnsCache {
+    pub fn new(inner: impl Resolver + 'static) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            capacity: 4096,
+            max_ttl: Duration::from_secs(86400),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+    /// How many answers to keep, 4096 by default
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+    /// The longest time to keep an answer
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+    /// The cached answer with the remaining TTL
+    fn cached(&self, key: &(String, RecordType), now: Instant) -> Option<Answer> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(key) {
+            Some((expires, answer)) if *expires > now => Some(Answer {
+                records: answer.records.clone(),
+                ttl: *expires - now,
+            }),
+            _ => None,
+        }
+    }
+    fn store(&self, key: (String, RecordType), answer: &Answer, now: Instant) {
+        let ttl = answer.ttl.min(self.max_ttl);
+        if ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (expires, _)| *expires > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            if let Some(soonest) = entries
+                .iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(key, _)| key.clone())
+            {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key, (now + ttl, answer.clone()));
+    }
+}
+
+impl fmt::Debug for DnsCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsCache")
+            .field("inner", &self.inner)
+            .field("capacity", &self.capacity)
+            .field("max_ttl", &self.max_ttl)
+            .finish()
+    }
+}
+
+impl Resolver for DnsCache {
+    fn resolve<'a, 'f>(&'a self, name: &'a str, kind: RecordType) -> S2Fut<'f, DnsResult<Answer>>
+    where
+        'a: 'f,
+    {
+        let key = (name.trim_end_matches('.').to_ascii_lowercase(), kind);
+        if let Some(answer) = self.cached(&key, Instant::now()) {
+            trace!("DNS cache hit {:?}", key);
+            return Box::pin(ready(Ok(answer)));
+        }
+        Box::pin(async move {
+            let answer = self.inner.resolve(name, kind).await?;
+            self.store(key, &answer, Instant::now());
+            Ok(answer)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{DnsError, Record};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts lookups and answers with the given TTL
+    #[derive(Debug, Default)]
+    struct Counter {
+        lookups: AtomicUsize,
+        ttl: u64,
+        fail: bool,
+    }
+
+    impl Resolver for Counter {
+        fn resolve<'a, 'f>(
+            &'a self,
+            name: &'a str,
+            _kind: RecordType,
+        ) -> S2Fut<'f, DnsResult<Answer>>
+        where
+            'a: 'f,
+        {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let result = match (self.fail, name) {
+                (true, _) => Err(DnsError::Timeout),
+                (false, "empty.example.org") => Ok(vec![]),
+                (false, _) => Ok(vec![Record::Txt(name.to_owned())]),
+            };
+            Box::pin(ready(result.map(|records| Answer {
+                records,
+                ttl: Duration::from_secs(self.ttl),
+            })))
+        }
+    }
+
+    #[test]
+    fn answers_are_cached_for_their_ttl() {
+        async_std::task::block_on(async move {
+            let counter = Arc::new(Counter {
+                ttl: 300,
+                ..Default::default()
+            });
+            let sut = DnsCache::new(counter.clone());
+            for _ in 0..3 {
+                let answer = sut.resolve("example.org.", RecordType::Txt).await.unwrap();
+                assert_eq!(answer.records, vec![Record::Txt("example.org.".to_owned())]);
+                assert!(answer.ttl <= Duration::from_secs(300));
+                let answer = sut
+                    .resolve("empty.example.org", RecordType::Txt)
+                    .await
+                    .unwrap();
+                assert!(answer.records.is_empty());
+            }
+            assert_eq!(counter.lookups.load(Ordering::SeqCst), 2);
+
+            // a different type is a different question
+            sut.resolve("EXAMPLE.org", RecordType::A).await.unwrap();
+            sut.resolve("example.org", RecordType::A).await.unwrap();
+            assert_eq!(counter.lookups.load(Ordering::SeqCst), 3);
+        })
+    }
+
+    #[test]
+    fn failures_and_zero_ttl_are_not_cached() {
+        async_std::task::block_on(async move {
+            let failing = Arc::new(Counter {
+                ttl: 300,
+                fail: true,
+                ..Default::default()
+            });
+            let sut = DnsCache::new(failing.clone());
+            for _ in 0..2 {
+                assert_eq!(
+                    sut.resolve("example.org", RecordType::Txt).await,
+                    Err(DnsError::Timeout)
+                );
+            }
+            assert_eq!(failing.lookups.load(Ordering::SeqCst), 2);
+
+            let volatile = Arc::new(Counter::default());
+            let sut = DnsCache::new(volatile.clone());
+            for _ in 0..2 {
+                sut.resolve("example.org", RecordType::Txt).await.unwrap();
+            }
+            assert_eq!(volatile.lookups.load(Ordering::SeqCst), 2);
+        })
+    }
+
+    #[test]
+    fn capacity_is_respected() {
+        async_std::task::block_on(async move {
+            let counter = Arc::new(Counter {
+                ttl: 300,
+                ..Default::default()
+            });
+            let sut = DnsCache::new(counter.clone()).with_capacity(2);
+            for name in ["a.example.org", "b.example.org", "c.example.org"] {
+                sut.resolve(name, RecordType::Txt).await.unwrap();
+            }
+            assert_eq!(sut.entries.lock().unwrap().len(), 2);
+        })
+    }
+}
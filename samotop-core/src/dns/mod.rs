@@ -0,0 +1,305 @@
+//! DNS lookups shared by mail services such as blocklists, SPF, DKIM or reverse DNS checks.
+//!
+//! A `Resolver` is configured once for the whole service with the `Dns` setup
+//! and reaches the mail guards through `SmtpSession::dns`.
+
+mod cache;
+mod zone;
+
+pub use self::cache::*;
+pub use self::zone::*;
+
+use crate::{
+    common::*,
+    io::tls::MayBeTls,
+    mail::{AcceptsSessionService, MailSetup},
+    smtp::{SessionService, SmtpContext},
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+pub type DnsResult<T> = std::result::Result<T, DnsError>;
+
+/// Looks DNS records up
+pub trait Resolver: fmt::Debug + Send + Sync {
+    /// Resolve records of the given kind.
+    ///
+    /// A name without such records resolves to an empty `Answer`,
+    /// so that the negative answer can be cached for its TTL as well.
+    fn resolve<'a, 'f>(&'a self, name: &'a str, kind: RecordType) -> S2Fut<'f, DnsResult<Answer>>
+    where
+        'a: 'f;
+}
+
+/// The supported DNS record types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Mx,
+    Txt,
+    Ptr,
+    Tlsa,
+}
+
+/// A DNS record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    /// The character strings of a TXT record joined together
+    Txt(String),
+    Ptr(String),
+    Tlsa(Tlsa),
+}
+
+/// A TLSA record as per RFC 6698
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlsa {
+    pub usage: u8,
+    pub selector: u8,
+    pub matching: u8,
+    pub data: Vec<u8>,
+}
+
+/// The records found and how long they can be cached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub records: Vec<Record>,
+    pub ttl: Duration,
+}
+
+/// Why a lookup failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsError {
+    /// There are no records of the kind
+    NoRecords,
+    /// The lookup took too long
+    Timeout,
+    /// The lookup failed otherwise, i.e. SERVFAIL or an invalid name
+    Failed(String),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::NoRecords => f.write_str("no records found"),
+            DnsError::Timeout => f.write_str("DNS lookup timed out"),
+            DnsError::Failed(e) => write!(f, "DNS lookup failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+impl<T: Resolver + ?Sized> Resolver for Arc<T> {
+    fn resolve<'a, 'f>(&'a self, name: &'a str, kind: RecordType) -> S2Fut<'f, DnsResult<Answer>>
+    where
+        'a: 'f,
+    {
+        T::resolve(self, name, kind)
+    }
+}
+
+impl<'r> dyn Resolver + 'r {
+    /// IPv4 addresses of the name
+    pub async fn lookup_a(&self, name: &str) -> DnsResult<Vec<Ipv4Addr>> {
+        self.records(name, RecordType::A, |record| match record {
+            Record::A(ip) => Some(ip),
+            _ => None,
+        })
+        .await
+    }
+    /// IPv6 addresses of the name
+    pub async fn lookup_aaaa(&self, name: &str) -> DnsResult<Vec<Ipv6Addr>> {
+        self.records(name, RecordType::Aaaa, |record| match record {
+            Record::Aaaa(ip) => Some(ip),
+            _ => None,
+        })
+        .await
+    }
+    /// Mail exchanges of the name, most preferred first
+    pub async fn lookup_mx(&self, name: &str) -> DnsResult<Vec<String>> {
+        let mut mxs = self
+            .records(name, RecordType::Mx, |record| match record {
+                Record::Mx {
+                    preference,
+                    exchange,
+                } => Some((preference, exchange)),
+                _ => None,
+            })
+            .await?;
+        mxs.sort_by_key(|(preference, _)| *preference);
+        Ok(mxs.into_iter().map(|(_, exchange)| exchange).collect())
+    }
+    /// TXT records of the name
+    pub async fn lookup_txt(&self, name: &str) -> DnsResult<Vec<String>> {
+        self.records(name, RecordType::Txt, |record| match record {
+            Record::Txt(txt) => Some(txt),
+            _ => None,
+        })
+        .await
+    }
+    /// Names of the IP address (reverse lookup)
+    pub async fn lookup_ptr(&self, ip: IpAddr) -> DnsResult<Vec<String>> {
+        self.records(
+            reverse_name(ip).as_str(),
+            RecordType::Ptr,
+            |record| match record {
+                Record::Ptr(name) => Some(name),
+                _ => None,
+            },
+        )
+        .await
+    }
+    /// TLSA records of the name, i.e. `_25._tcp.mx.example.org`
+    pub async fn lookup_tlsa(&self, name: &str) -> DnsResult<Vec<Tlsa>> {
+        self.records(name, RecordType::Tlsa, |record| match record {
+            Record::Tlsa(tlsa) => Some(tlsa),
+            _ => None,
+        })
+        .await
+    }
+    async fn records<T>(
+        &self,
+        name: &str,
+        kind: RecordType,
+        pick: impl Fn(Record) -> Option<T>,
+    ) -> DnsResult<Vec<T>> {
+        let records = self
+            .resolve(name, kind)
+            .await?
+            .records
+            .into_iter()
+            .filter_map(pick)
+            .collect::<Vec<_>>();
+        if records.is_empty() {
+            Err(DnsError::NoRecords)
+        } else {
+            Ok(records)
+        }
+    }
+}
+
+/// The name to look up PTR records of the IP, i.e. `1.2.0.192.in-addr.arpa`
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = ip
+                .octets()
+                .iter()
+                .rev()
+                .map(|byte| format!("{:x}.{:x}.", byte & 0xf, byte >> 4))
+                .collect::<String>();
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/**
+MailSetup providing the DNS resolver to all sessions.
+
+The resolver is shared by all sessions with a `DnsCache` in front of it,
+so it is configured once for the whole service.
+
+```
+# use samotop_core::dns::*;
+# use samotop_core::mail::*;
+let zone = ZoneResolver::parse("example.org. 300 IN TXT \"v=spf1 -all\"").unwrap();
+let service = Builder + Dns::new(zone);
+```
+*/
+#[derive(Debug, Clone)]
+pub struct Dns {
+    resolver: Arc<dyn Resolver>,
+}
+
+impl Dns {
+    /// Use the resolver with a default cache
+    pub fn new(resolver: impl Resolver + 'static) -> Self {
+        Self::uncached(DnsCache::new(resolver))
+    }
+    /// Use the resolver as is, i.e. a preconfigured `DnsCache`
+    pub fn uncached(resolver: impl Resolver + 'static) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+        }
+    }
+    /// The shared resolver
+    pub fn resolver(&self) -> Arc<dyn Resolver> {
+        self.resolver.clone()
+    }
+}
+
+impl<T: AcceptsSessionService> MailSetup<T> for Dns {
+    fn setup(self, config: &mut T) {
+        config.add_first_session_service(self)
+    }
+}
+
+impl SessionService for Dns {
+    fn prepare_session<'a, 'i, 's, 'f>(
+        &'a self,
+        _io: &'i mut Box<dyn MayBeTls>,
+        state: &'s mut SmtpContext,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        'i: 'f,
+        's: 'f,
+    {
+        state.session.dns = Some(self.resolver.clone());
+        Box::pin(ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            reverse_name("192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn lookups_pick_records() {
+        async_std::task::block_on(async move {
+            let zone = ZoneResolver::parse(
+                "example.org. MX 20 backup.example.org.\n\
+                 example.org. MX 10 mx.example.org.\n\
+                 1.2.0.192.in-addr.arpa. PTR mx.example.org.\n",
+            )
+            .unwrap();
+            let sut: &dyn Resolver = &zone;
+            assert_eq!(
+                sut.lookup_mx("example.org").await,
+                Ok(vec![
+                    "mx.example.org".to_owned(),
+                    "backup.example.org".to_owned()
+                ])
+            );
+            assert_eq!(
+                sut.lookup_ptr("192.0.2.1".parse().unwrap()).await,
+                Ok(vec!["mx.example.org".to_owned()])
+            );
+            assert_eq!(sut.lookup_a("example.org").await, Err(DnsError::NoRecords));
+        })
+    }
+}
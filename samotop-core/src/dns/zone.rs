@@ -0,0 +1,390 @@
+use super::{Answer, DnsError, DnsResult, Record, RecordType, Resolver, Tlsa};
+use crate::common::*;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+/**
+Answers from in-memory records, i.e. to test DNS based features without network.
+
+Records are given in the zone file format, one per line: name, optional TTL and class,
+type and data. `$ORIGIN` and `$TTL` directives, `@` for the origin, relative names,
+`;` comments and parentheses spanning lines are supported.
+TXT strings are joined together. Supported types are A, AAAA, MX, TXT, PTR and TLSA.
+
+```
+# use samotop_core::dns::*;
+let zone = ZoneResolver::parse(r#"
+$ORIGIN example.org.
+$TTL 300
+@          IN MX  10 mx
+mx         IN A   192.0.2.1
+@          IN TXT "v=spf1 mx -all"
+sel._domainkey IN TXT ( "v=DKIM1; k=rsa; "
+                        "p=MIGfMA0GCSqGSIb3DQEBAQUAA4GN" )
+"#).unwrap();
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ZoneResolver {
+    records: HashMap<(String, RecordType), (Duration, Vec<Record>)>,
+    failing: HashSet<String>,
+}
+
+const DEFAULT_TTL: u32 = 3600;
+
+impl ZoneResolver {
+    /// Parse records in the zone file format
+    pub fn parse(zone: &str) -> Result<Self> {
+        Self::default().with_zone(zone)
+    }
+    /// Load records from a zone file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(std::fs::read_to_string(path)?.as_str())
+    }
+    /// Add records in the zone file format
+    pub fn with_zone(mut self, zone: &str) -> Result<Self> {
+        let mut origin = String::new();
+        let mut ttl = DEFAULT_TTL;
+        let mut previous_name = None;
+        for (number, entry) in entries(zone).into_iter() {
+            let error = |e: &dyn fmt::Display| format!("Line {}: {}", number, e);
+            let fields = fields(entry.as_str());
+            match fields.first().map(|f| f.text.as_str()) {
+                None => continue,
+                Some("$ORIGIN") => {
+                    origin = fields
+                        .get(1)
+                        .map(|f| f.text.trim_end_matches('.').to_ascii_lowercase())
+                        .ok_or_else(|| error(&"$ORIGIN without a name"))?;
+                    continue;
+                }
+                Some("$TTL") => {
+                    ttl = fields
+                        .get(1)
+                        .ok_or_else(|| error(&"$TTL without a value"))?
+                        .text
+                        .parse()
+                        .map_err(|e| error(&e))?;
+                    continue;
+                }
+                Some(_) => {}
+            }
+            let mut fields = fields.into_iter();
+            // a line starting with blanks continues with the previous name
+            let name = if entry.starts_with(|c: char| c.is_whitespace()) {
+                previous_name
+                    .clone()
+                    .ok_or_else(|| error(&"no previous name"))?
+            } else {
+                let name = fields.next().expect("some field").text;
+                absolute(name.as_str(), origin.as_str())
+            };
+            previous_name = Some(name.clone());
+
+            let mut record_ttl = ttl;
+            let kind = loop {
+                let field = fields.next().ok_or_else(|| error(&"missing record type"))?;
+                match field.text.to_ascii_uppercase().as_str() {
+                    "IN" => continue,
+                    text if text.chars().all(|c| c.is_ascii_digit()) => {
+                        record_ttl = text.parse().map_err(|e| error(&e))?
+                    }
+                    text => break text.to_owned(),
+                }
+            };
+            let data = fields.collect::<Vec<_>>();
+            let record = record(kind.as_str(), &data, origin.as_str()).map_err(|e| error(&e))?;
+            self = self.with_record(name.as_str(), record_ttl, record);
+        }
+        Ok(self)
+    }
+    /// Add a record with the given TTL in seconds
+    pub fn with_record(mut self, name: &str, ttl: u32, record: Record) -> Self {
+        let kind = match record {
+            Record::A(_) => RecordType::A,
+            Record::Aaaa(_) => RecordType::Aaaa,
+            Record::Mx { .. } => RecordType::Mx,
+            Record::Txt(_) => RecordType::Txt,
+            Record::Ptr(_) => RecordType::Ptr,
+            Record::Tlsa(_) => RecordType::Tlsa,
+        };
+        let entry = self
+            .records
+            .entry((normalize(name), kind))
+            .or_insert_with(|| (Duration::from_secs(ttl as u64), vec![]));
+        entry.0 = entry.0.min(Duration::from_secs(ttl as u64));
+        entry.1.push(record);
+        self
+    }
+    /// Fail all lookups of the name as a broken DNS server would
+    pub fn with_failure(mut self, name: &str) -> Self {
+        self.failing.insert(normalize(name));
+        self
+    }
+}
+
+impl Resolver for ZoneResolver {
+    fn resolve<'a, 'f>(&'a self, name: &'a str, kind: RecordType) -> S2Fut<'f, DnsResult<Answer>>
+    where
+        'a: 'f,
+    {
+        let name = normalize(name);
+        let result = if self.failing.contains(&name) {
+            Err(DnsError::Failed(format!("SERVFAIL {}", name)))
+        } else {
+            Ok(match self.records.get(&(name, kind)) {
+                Some((ttl, records)) => Answer {
+                    records: records.clone(),
+                    ttl: *ttl,
+                },
+                None => Answer {
+                    records: vec![],
+                    ttl: Duration::from_secs(DEFAULT_TTL as u64),
+                },
+            })
+        };
+        Box::pin(ready(result))
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_owned()
+    } else if name.ends_with('.') || origin.is_empty() {
+        normalize(name)
+    } else {
+        format!("{}.{}", normalize(name), origin)
+    }
+}
+
+fn record(kind: &str, data: &[Field], origin: &str) -> Result<Record> {
+    let text = |i: usize| -> Result<&str> {
+        data.get(i)
+            .map(|f| f.text.as_str())
+            .ok_or_else(|| format!("{} record is missing data", kind).into())
+    };
+    Ok(match kind {
+        "A" => Record::A(text(0)?.parse()?),
+        "AAAA" => Record::Aaaa(text(0)?.parse()?),
+        "MX" => Record::Mx {
+            preference: text(0)?.parse()?,
+            exchange: absolute(text(1)?, origin),
+        },
+        "PTR" => Record::Ptr(absolute(text(0)?, origin)),
+        "TXT" if data.iter().all(|f| f.quoted) && !data.is_empty() => {
+            Record::Txt(data.iter().map(|f| f.text.as_str()).collect())
+        }
+        "TXT" => return Err("TXT data must be quoted".into()),
+        "TLSA" => Record::Tlsa(Tlsa {
+            usage: text(0)?.parse()?,
+            selector: text(1)?.parse()?,
+            matching: text(2)?.parse()?,
+            data: hex(data[3..]
+                .iter()
+                .map(|f| f.text.as_str())
+                .collect::<String>())?,
+        }),
+        other => return Err(format!("Unsupported record type {}", other).into()),
+    })
+}
+
+fn hex(text: String) -> Result<Vec<u8>> {
+    if text.is_empty() || !text.len().is_multiple_of(2) {
+        return Err(format!("Invalid hex data {:?}", text).into());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("Invalid hex data {:?}", text).into())
+        })
+        .collect()
+}
+
+/// A field of a zone file entry
+#[derive(Debug, PartialEq, Eq)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+/// Split the zone into entries with their line numbers,
+/// dropping comments and joining lines in parentheses
+fn entries(zone: &str) -> Vec<(usize, String)> {
+    let mut entries = vec![];
+    let mut entry = String::new();
+    let mut start = 0;
+    let mut depth = 0;
+    for (number, line) in zone.lines().enumerate() {
+        if depth == 0 {
+            start = number + 1;
+        }
+        let mut quoted = false;
+        let mut escaped = false;
+        for c in line.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ';' if !quoted => break,
+                '(' if !quoted => {
+                    depth += 1;
+                    entry.push(' ');
+                    continue;
+                }
+                ')' if !quoted => {
+                    depth -= 1;
+                    entry.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+            entry.push(c);
+        }
+        if depth <= 0 {
+            depth = 0;
+            if !entry.trim().is_empty() {
+                entries.push((start, std::mem::take(&mut entry)));
+            }
+            entry.clear();
+        } else {
+            entry.push(' ');
+        }
+    }
+    if !entry.trim().is_empty() {
+        entries.push((start, entry));
+    }
+    entries
+}
+
+/// Split an entry into whitespace separated fields, keeping quoted strings together
+fn fields(entry: &str) -> Vec<Field> {
+    let mut fields = vec![];
+    let mut chars = entry.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut text = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => text.extend(chars.next()),
+                        c => text.push(c),
+                    }
+                }
+                fields.push(Field { text, quoted: true });
+            }
+            c => {
+                let mut text = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '"') {
+                    text.push(c);
+                }
+                fields.push(Field {
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(zone: &ZoneResolver, name: &str, kind: RecordType) -> DnsResult<Answer> {
+        async_std::task::block_on(zone.resolve(name, kind))
+    }
+
+    #[test]
+    fn zone_is_parsed() {
+        let sut = ZoneResolver::parse(
+            r#"
+$ORIGIN Example.org.
+$TTL 300
+@          IN MX  10 mx   ; primary
+           IN MX  20 backup.example.net.
+mx    60   IN A   192.0.2.1
+mx         AAAA   2001:db8::1
+sel._domainkey IN TXT ( "v=DKIM1; k=rsa; "
+                        "p=MIGf;MA0G" )
+_25._tcp.mx TLSA 3 1 1 ( 0a0B
+                         ff )
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            resolve(&sut, "example.org.", RecordType::Mx),
+            Ok(Answer {
+                records: vec![
+                    Record::Mx {
+                        preference: 10,
+                        exchange: "mx.example.org".to_owned()
+                    },
+                    Record::Mx {
+                        preference: 20,
+                        exchange: "backup.example.net".to_owned()
+                    }
+                ],
+                ttl: Duration::from_secs(300)
+            })
+        );
+        assert_eq!(
+            resolve(&sut, "MX.example.org", RecordType::A),
+            Ok(Answer {
+                records: vec![Record::A("192.0.2.1".parse().unwrap())],
+                ttl: Duration::from_secs(60)
+            })
+        );
+        assert_eq!(
+            resolve(&sut, "sel._domainkey.example.org", RecordType::Txt)
+                .unwrap()
+                .records,
+            vec![Record::Txt("v=DKIM1; k=rsa; p=MIGf;MA0G".to_owned())]
+        );
+        assert_eq!(
+            resolve(&sut, "_25._tcp.mx.example.org", RecordType::Tlsa)
+                .unwrap()
+                .records,
+            vec![Record::Tlsa(Tlsa {
+                usage: 3,
+                selector: 1,
+                matching: 1,
+                data: vec![0x0a, 0x0b, 0xff]
+            })]
+        );
+        assert_eq!(
+            resolve(&sut, "nowhere.example.org", RecordType::A)
+                .unwrap()
+                .records,
+            vec![]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = ZoneResolver::parse("a.example.org. A 192.0.2.1\n\nb.example.org. TXT bare\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Line 3:"), "{}", error);
+        assert!(ZoneResolver::parse("a.example.org. SRV 0 0 25 mx").is_err());
+        assert!(ZoneResolver::parse("a.example.org. A 192.0.2").is_err());
+    }
+
+    #[test]
+    fn failures_are_simulated() {
+        let sut = ZoneResolver::default().with_failure("broken.example.org");
+        assert!(matches!(
+            resolve(&sut, "broken.example.org.", RecordType::Txt),
+            Err(DnsError::Failed(_))
+        ));
+    }
+}
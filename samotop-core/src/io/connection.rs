@@ -1,4 +1,5 @@
-use crate::common::Identify;
+use crate::common::Dummy;
+use crate::mail::{IdGenerator, Privacy};
 use std::time::{Duration, SystemTime};
 
 /// Carries connection infromation (TCP, unix socket, ...) so that remaining code can abstract away from it as Io
@@ -7,21 +8,36 @@ pub struct ConnectionInfo {
     pub id: String,
     pub local_addr: String,
     pub peer_addr: String,
+    /// Name of the listener which accepted the connection, empty if not named
+    pub listener: String,
     pub established: SystemTime,
 }
 
 impl ConnectionInfo {
     pub fn new(local_addr: String, peer_addr: String) -> Self {
         ConnectionInfo {
-            id: Identify::now().to_string(),
+            id: Dummy.generate(),
             local_addr,
             peer_addr,
+            listener: String::default(),
             established: SystemTime::now(),
         }
     }
+    /// Set the listener name
+    pub fn with_listener(mut self, listener: impl Into<String>) -> Self {
+        self.listener = listener.into();
+        self
+    }
     pub fn age(&self) -> Duration {
         self.established.elapsed().unwrap_or(Duration::ZERO)
     }
+    /// Display the connection with the peer address as the privacy policy allows logging it
+    pub fn redacted<'a>(&'a self, privacy: &'a Privacy) -> impl std::fmt::Display + 'a {
+        Redacted {
+            connection: self,
+            privacy,
+        }
+    }
 }
 impl Default for ConnectionInfo {
     fn default() -> Self {
@@ -31,19 +47,34 @@ impl Default for ConnectionInfo {
 
 impl std::fmt::Display for ConnectionInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        write!(f, "connection id {} from peer ", self.id)?;
-        if self.peer_addr.is_empty() {
+        self.redacted(&Privacy::default()).fmt(f)
+    }
+}
+
+struct Redacted<'a> {
+    connection: &'a ConnectionInfo,
+    privacy: &'a Privacy,
+}
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let connection = self.connection;
+        write!(f, "connection id {} from peer ", connection.id)?;
+        if connection.peer_addr.is_empty() {
             f.write_str("Unknown")?;
         } else {
-            f.write_str(self.peer_addr.as_str())?;
+            f.write_str(self.privacy.ip(&connection.peer_addr).as_str())?;
         }
         write!(f, " to local ")?;
-        if self.local_addr.is_empty() {
+        if connection.local_addr.is_empty() {
             f.write_str("Unknown")?;
         } else {
-            f.write_str(self.local_addr.as_str())?;
+            f.write_str(connection.local_addr.as_str())?;
+        }
+        if !connection.listener.is_empty() {
+            write!(f, " via {}", connection.listener)?;
         }
-        write!(f, " established {}s ago", self.age().as_secs_f64())?;
+        write!(f, " established {}s ago", connection.age().as_secs_f64())?;
         Ok(())
     }
 }
@@ -61,12 +92,21 @@ mod store_tests {
             ..Default::default()
         };
         let dump = sut.to_string();
-        let dump = Regex::new("[0-9]+")
+        let dump = Regex::new("[0-9A-Z]{26}|[0-9]+")
             .expect("regex")
             .replace_all(&dump, "--redaced--");
         insta::assert_display_snapshot!(dump, @"connection id --redaced-- from peer Unknown to local Unknown established --redaced--.--redaced--s ago");
     }
 
+    #[test]
+    pub fn display_listener() {
+        let sut = ConnectionInfo::new("127.0.0.1:587".to_owned(), "10.0.0.1:1234".to_owned())
+            .with_listener("submission");
+        assert!(sut
+            .to_string()
+            .contains("from peer 10.0.0.1:1234 to local 127.0.0.1:587 via submission established"));
+    }
+
     #[test]
     pub fn timely_connection_info() {
         let sut = ConnectionInfo::default();
@@ -79,4 +119,15 @@ mod store_tests {
         let sut2 = ConnectionInfo::default();
         assert_ne!(sut1.id, sut2.id);
     }
+
+    #[test]
+    pub fn display_redacted_connection_info() {
+        let sut = ConnectionInfo::new("127.0.0.1:25".to_owned(), "192.0.2.123:54321".to_owned());
+        let dump = sut.redacted(&Privacy::strict("key")).to_string();
+        assert!(
+            dump.contains("from peer 192.0.2.0/24 to local 127.0.0.1:25"),
+            "{}",
+            dump
+        );
+    }
 }
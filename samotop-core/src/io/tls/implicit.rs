@@ -0,0 +1,44 @@
+use super::{MayBeTls, TlsProvider};
+use crate::common::*;
+use crate::io::{ConnectionInfo, IoService};
+
+/**
+Serves the wrapped service over TLS right from the start rather than after STARTTLS.
+
+This is the implicit TLS of SMTP submission on port 465 (RFC 8314).
+The handshake runs on the first read or write so the service is not aware of it,
+except that the stream reports it is already encrypted.
+*/
+#[derive(Debug, Clone)]
+pub struct ImplicitTls<T, S> {
+    provider: T,
+    service: S,
+}
+
+impl<T, S> ImplicitTls<T, S> {
+    pub fn new(provider: T, service: S) -> Self {
+        Self { provider, service }
+    }
+}
+
+impl<T, S> IoService for ImplicitTls<T, S>
+where
+    T: TlsProvider,
+    S: IoService,
+{
+    fn handle(
+        &self,
+        io: Result<Box<dyn MayBeTls>>,
+        connection: ConnectionInfo,
+    ) -> S1Fut<'static, Result<()>> {
+        let io = io.and_then(|mut io| match self.provider.get_tls_upgrade() {
+            Some(upgrade) => {
+                io.enable_encryption(upgrade, String::default());
+                Pin::new(&mut io).encrypt();
+                Ok(io)
+            }
+            None => Err("TLS is not available for implicit TLS".into()),
+        });
+        self.service.handle(io, connection)
+    }
+}
@@ -1,9 +1,11 @@
+mod implicit;
 mod notls;
 mod stream;
 mod traits;
 
 use core::panic;
 
+pub use implicit::*;
 pub use notls::*;
 pub use stream::*;
 pub use traits::*;
@@ -1,10 +1,15 @@
 //! The domain model of Samotop and core functionality. A base crate for samotop extensions.
 
-#[macro_use]
+#[cfg_attr(not(feature = "tracing"), macro_use)]
 extern crate log;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
 
+pub mod dns;
 pub mod io;
 pub mod mail;
+pub mod runtime;
 pub mod smtp;
 
 #[cfg(feature = "server")]
@@ -22,6 +27,7 @@ pub mod common {
     //pub use async_std::io;
     //pub use async_std::io::prelude::{ReadExt, WriteExt};
     //pub use async_std::io::{Read, Write};
+    pub use bytes::Bytes;
     pub use futures_core::ready;
     use std::any::TypeId;
     pub use std::future::*;
@@ -0,0 +1,625 @@
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{DriverControl, SessionEnd, SessionLifecycle, SmtpSession, TransactionEnd},
+};
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+Writes a structured audit log with one JSON line per mail transaction.
+
+Each line carries the transaction timestamps, connection info, HELO name, TLS status,
+authenticated user, sender, recipients with their individual verdicts, mail data size,
+`AuthenticationResults` such as SPF and DKIM, the dispatch outcome and the final reply.
+Queued and failed transactions are logged, abandoned ones only `with_aborted()`.
+
+The audit wraps the guard and dispatch chains set up so far, so add it after the other mail setups.
+Lines are written synchronously, use a `RotatingFile` to keep the log size in check.
+
+```no_run
+# use samotop_core::mail::*;
+# fn main() -> std::io::Result<()> {
+let audit = AuditLog::new(RotatingFile::new("/var/log/samotop/audit.json", 10 << 20, 5)?);
+let service = Builder + Name::new("mx.example.org") + audit;
+# Ok(())
+# }
+```
+
+A line looks like this (wrapped here for readability):
+
+```json
+{"time":"2021-06-01T10:00:02.345Z","started":"2021-06-01T10:00:01.789Z","id":"01F70...",
+"connection":{"id":"1234","peer":"192.0.2.1:54321","local":"192.0.2.10:25","listener":"","established":"2021-06-01T10:00:01.123Z"},
+"service":"mx.example.org","helo":"mail.example.com","tls":true,"user":null,"sender":"alice@example.com",
+"recipients":[{"address":"bob@example.org","verdict":"accepted"},{"address":"eve@example.org","verdict":"rejected_permanently"}],
+"size":1234,"authentication":{"spf":"pass"},"dispatch":"ok","outcome":"queued","reply":"250 Queued as 01F70..."}
+```
+*/
+#[derive(Clone)]
+pub struct AuditLog {
+    writer: Arc<Mutex<Box<dyn std::io::Write + Send>>>,
+    aborted: bool,
+}
+
+impl AuditLog {
+    /// Write the audit lines to the given writer
+    pub fn new(writer: impl std::io::Write + Send + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            aborted: false,
+        }
+    }
+    /// Append the audit lines to the given file
+    pub fn to_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+    /// Also log transactions abandoned by the client - RSET, QUIT, disconnect...
+    pub fn with_aborted(mut self) -> Self {
+        self.aborted = true;
+        self
+    }
+    fn write(&self, line: &str) {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = writer
+            .write_all(line.as_bytes())
+            .and_then(|()| writer.flush())
+        {
+            warn!("Failed to write the audit log: {}", e);
+        }
+    }
+    fn line(&self, session: &SmtpSession, end: &TransactionEnd) -> String {
+        let connection = &session.connection;
+        let transaction = &session.transaction;
+        let audit = transaction.get::<Audit>();
+        let privacy = session.privacy.clone();
+
+        let mut line = String::new();
+        line.push_str("{\"time\":");
+        line.push_str(json(timestamp(SystemTime::now()).as_str()).as_str());
+        line.push_str(",\"started\":");
+        line.push_str(json_or_null(audit.map(|a| timestamp(a.started)).as_deref()).as_str());
+        line.push_str(",\"id\":");
+        line.push_str(json(transaction.id.as_str()).as_str());
+        line.push_str(",\"connection\":{\"id\":");
+        line.push_str(json(connection.id.as_str()).as_str());
+        line.push_str(",\"peer\":");
+        line.push_str(json(privacy.ip(&connection.peer_addr).as_str()).as_str());
+        line.push_str(",\"local\":");
+        line.push_str(json(connection.local_addr.as_str()).as_str());
+        line.push_str(",\"listener\":");
+        line.push_str(json(connection.listener.as_str()).as_str());
+        line.push_str(",\"established\":");
+        line.push_str(json(timestamp(connection.established).as_str()).as_str());
+        line.push_str("},\"service\":");
+        line.push_str(json(session.service_name.as_str()).as_str());
+        line.push_str(",\"helo\":");
+        line.push_str(json_or_null(session.peer_name.as_deref()).as_str());
+        line.push_str(",\"tls\":");
+        line.push_str(if session.encrypted { "true" } else { "false" });
+        line.push_str(",\"user\":");
+        line.push_str(json_or_null(session.authenticated.as_deref()).as_str());
+        line.push_str(",\"sender\":");
+        line.push_str(
+            json_or_null(
+                transaction
+                    .mail
+                    .as_ref()
+                    .map(|m| privacy.address(m.sender()))
+                    .as_deref(),
+            )
+            .as_str(),
+        );
+
+        line.push_str(",\"recipients\":[");
+        let recipients = audit.map(|a| a.recipients.as_slice()).unwrap_or_default();
+        for (i, (address, verdict)) in recipients.iter().enumerate() {
+            if i != 0 {
+                line.push(',');
+            }
+            line.push_str("{\"address\":");
+            line.push_str(json(address.as_str()).as_str());
+            line.push_str(",\"verdict\":");
+            line.push_str(json(verdict).as_str());
+            line.push('}');
+        }
+
+        line.push_str("],\"size\":");
+        match audit.and_then(|a| a.size.as_ref()) {
+            Some(size) => line.push_str(size.load(Ordering::Relaxed).to_string().as_str()),
+            None => line.push_str("null"),
+        }
+
+        line.push_str(",\"authentication\":{");
+        if let Some(results) = transaction.get::<AuthenticationResults>() {
+            for (i, (method, result)) in results.iter().enumerate() {
+                if i != 0 {
+                    line.push(',');
+                }
+                line.push_str(json(method).as_str());
+                line.push(':');
+                line.push_str(json(result).as_str());
+            }
+        }
+
+        line.push_str("},\"dispatch\":");
+        line.push_str(json_or_null(audit.and_then(|a| a.dispatch)).as_str());
+        line.push_str(",\"outcome\":");
+        line.push_str(
+            json(match end {
+                TransactionEnd::Queued => "queued",
+                TransactionEnd::Failed => "failed",
+                TransactionEnd::Aborted => "aborted",
+            })
+            .as_str(),
+        );
+        line.push_str(",\"reply\":");
+        line.push_str(json_or_null(last_reply(session).as_deref()).as_str());
+        line.push_str("}\n");
+        line
+    }
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("writer", &"*")
+            .field("aborted", &self.aborted)
+            .finish()
+    }
+}
+
+impl<T> MailSetup<T> for AuditLog
+where
+    T: AcceptsSessionLifecycle + AcceptsGuard + AcceptsDispatch,
+{
+    fn setup(self, config: &mut T) {
+        config.wrap_guards(|inner| AuditWrap { inner });
+        config.wrap_dispatches(|inner| AuditWrap { inner });
+        config.add_last_lifecycle(self);
+    }
+}
+
+impl SessionLifecycle for AuditLog {
+    fn on_helo<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if end != TransactionEnd::Aborted || self.aborted {
+            self.write(self.line(session, &end).as_str());
+        }
+        Box::pin(ready(()))
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+}
+
+/// What the audit learns about the transaction as it goes
+#[derive(Debug)]
+struct Audit {
+    started: SystemTime,
+    recipients: Vec<(String, &'static str)>,
+    size: Option<Arc<AtomicU64>>,
+    dispatch: Option<&'static str>,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self {
+            started: SystemTime::now(),
+            recipients: vec![],
+            size: None,
+            dispatch: None,
+        }
+    }
+}
+
+/// Records the results of the inner service
+#[derive(Debug)]
+struct AuditWrap<S> {
+    inner: S,
+}
+
+impl MailGuard for AuditWrap<Box<dyn MailGuard + Sync + Send>> {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let address = session.privacy.address(&rcpt.address);
+            let result = self.inner.add_recipient(session, rcpt).await;
+            let verdict = match result {
+                AddRecipientResult::Inconclusive(_) | AddRecipientResult::Accepted => "accepted",
+                AddRecipientResult::AcceptedWithNewPath(_) => "forwarded",
+                AddRecipientResult::Failed(ref failure, _) => failure.reason(),
+            };
+            session
+                .transaction
+                .get_or_insert(Audit::default)
+                .recipients
+                .push((address, verdict));
+            result
+        })
+    }
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        session.transaction.get_or_insert(Audit::default);
+        self.inner.start_mail(session)
+    }
+}
+
+impl MailDispatch for AuditWrap<Box<dyn MailDispatch + Sync + Send>> {
+    fn open_mail_body<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, DispatchResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let result = self.inner.open_mail_body(session).await;
+            let size = Arc::new(AtomicU64::new(0));
+            let audit = session.transaction.get_or_insert(Audit::default);
+            audit.dispatch = Some(match result {
+                Ok(()) => "ok",
+                Err(DispatchError::Temporary) => "failed_temporarily",
+                Err(DispatchError::Permanent) => "failed_permanently",
+            });
+            audit.size = Some(size.clone());
+            session.transaction.sink = session
+                .transaction
+                .sink
+                .take()
+                .map(|inner| Box::pin(AuditSink { size, inner }) as Pin<Box<dyn MailDataSink>>);
+            result
+        })
+    }
+}
+
+/// Counts mail data bytes
+struct AuditSink {
+    size: Arc<AtomicU64>,
+    inner: Pin<Box<dyn MailDataSink>>,
+}
+
+impl io::Write for AuditSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = self.inner.as_mut().poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            self.size.fetch_add(len as u64, Ordering::Relaxed);
+        }
+        result
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.as_mut().poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.as_mut().poll_close(cx)
+    }
+}
+
+/**
+A file writer that rotates the file once it grows over the size limit.
+
+The current file is renamed to `<path>.1`, older files shift to `<path>.2` and so on,
+keeping up to `keep` rotated files. Rotation happens between writes,
+so each `write_all()` of a line lands in one file.
+*/
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Append to the file at `path`, rotating it over `max_size` bytes and keeping `keep` old files
+    pub fn new(path: impl Into<PathBuf>, max_size: u64, keep: usize) -> std::io::Result<Self> {
+        let path = path.into();
+        let file = Self::open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+    fn open(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = Self::open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl std::io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+        Ok(len)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+/// The last reply said in the session, the transaction end usually follows the final reply.
+///
+/// The transaction addresses in it, such as in the LMTP replies, are redacted.
+fn last_reply(session: &SmtpSession) -> Option<String> {
+    let reply = session
+        .output
+        .iter()
+        .rev()
+        .find_map(|control| match control {
+            DriverControl::Response(bytes) => {
+                Some(String::from_utf8_lossy(bytes).trim_end().to_owned())
+            }
+            DriverControl::StartTls | DriverControl::Shutdown => None,
+        })?;
+    let transaction = &session.transaction;
+    let addresses = transaction
+        .mail
+        .iter()
+        .map(|mail| mail.sender().to_string())
+        .chain(
+            transaction
+                .rcpts
+                .iter()
+                .map(|rcpt| rcpt.address.to_string()),
+        )
+        .filter(|address| !address.is_empty());
+    Some(addresses.fold(reply, |reply, address| {
+        reply.replace(address.as_str(), session.privacy.address(&address).as_str())
+    }))
+}
+
+/// Format the time in RFC 3339 UTC with milliseconds
+fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since.subsec_millis()
+    )
+}
+
+/// A JSON string
+fn json(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A JSON string or null
+fn json_or_null(value: Option<&str>) -> String {
+    value.map(json).unwrap_or_else(|| "null".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smtp::{command::SmtpMail, SmtpPath};
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("lock").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn formats_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_millis(1_614_556_799_123)),
+            "2021-02-28T23:59:59.123Z"
+        );
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn escapes_json() {
+        assert_eq!(json("a\"b\\c\r\n\u{1}"), "\"a\\\"b\\\\c\\r\\n\\u0001\"");
+        assert_eq!(json_or_null(None), "null");
+    }
+
+    #[test]
+    fn logs_transaction() {
+        async_std::task::block_on(async {
+            let lines = Lines::default();
+            let sut = AuditLog::new(lines.clone());
+            let guard = AuditWrap {
+                inner: Box::new(Dummy) as Box<dyn MailGuard + Sync + Send>,
+            };
+
+            let mut session = SmtpSession {
+                peer_name: Some("client.example.com".to_owned()),
+                encrypted: true,
+                ..Default::default()
+            };
+            session.transaction.id = "tx1".to_owned();
+            session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Postmaster, vec![]));
+            guard.start_mail(&mut session).await;
+            guard
+                .add_recipient(&mut session, Recipient::new(SmtpPath::Postmaster))
+                .await;
+            session
+                .transaction
+                .get_or_insert(AuthenticationResults::default)
+                .add("spf", "pass");
+            session.say_mail_queued("tx1");
+
+            sut.on_transaction_end(&mut session, TransactionEnd::Aborted)
+                .await;
+            assert!(lines.0.lock().expect("lock").is_empty());
+
+            sut.on_transaction_end(&mut session, TransactionEnd::Queued)
+                .await;
+            let line = String::from_utf8(lines.0.lock().expect("lock").clone()).expect("utf8");
+            assert!(line.ends_with("}\n"), "{}", line);
+            assert_eq!(line.lines().count(), 1, "{}", line);
+            for part in [
+                "\"id\":\"tx1\"",
+                "\"helo\":\"client.example.com\"",
+                "\"tls\":true",
+                "\"user\":null",
+                "\"sender\":\"<POSTMASTER>\"",
+                "\"recipients\":[{\"address\":\"<POSTMASTER>\",\"verdict\":\"accepted\"}]",
+                "\"size\":null",
+                "\"authentication\":{\"spf\":\"pass\"}",
+                "\"dispatch\":null",
+                "\"outcome\":\"queued\"",
+                "\"reply\":\"250 Queued as tx1\"",
+            ] {
+                assert!(line.contains(part), "{} in {}", part, line);
+            }
+        })
+    }
+
+    #[test]
+    fn redacts_lmtp_reply() {
+        let mut session = SmtpSession {
+            privacy: Arc::new(Privacy::default().with_pseudonyms("key")),
+            ..Default::default()
+        };
+        session
+            .transaction
+            .rcpts
+            .push(Recipient::new(SmtpPath::Postmaster));
+        session.say_mail_queued("tx1 for <POSTMASTER>");
+        assert_eq!(
+            last_reply(&session),
+            Some(format!(
+                "250 Queued as tx1 for {}",
+                session.privacy.address("<POSTMASTER>")
+            ))
+        );
+    }
+
+    #[test]
+    fn rotates_file() {
+        let dir = std::env::temp_dir().join(format!("samotop-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("audit.json");
+        let mut sut = RotatingFile::new(&path, 10, 2).expect("file");
+        for line in ["first line\n", "second line\n", "third line\n", "fourth\n"] {
+            sut.write_all(line.as_bytes()).expect("write");
+            sut.flush().expect("flush");
+        }
+        let read = |p: &Path| std::fs::read_to_string(p).expect("read");
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&dir.join("audit.json.1")), "third line\n");
+        assert_eq!(read(&dir.join("audit.json.2")), "second line\n");
+        assert!(!dir.join("audit.json.3").exists());
+        std::fs::remove_dir_all(dir).expect("cleanup");
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{common::*, smtp::SmtpSession};
+use std::ops::Deref;
+
+/**
+A mail check gives a verdict on the mail content after the final dot of the DATA command,
+before the reply is sent to the client.
+
+By then, all the mail data have been written (and flushed) into the transaction sink.
+A content scanner would typically wrap the sink in `MailDispatch::open_mail_body()`
+and keep its findings in the transaction store so that it can decide here.
+
+If the mail is refused, the sink is dropped without closing so the mail is not dispatched.
+
+```
+# use samotop_core::common::*;
+# use samotop_core::mail::*;
+# use samotop_core::smtp::*;
+/// Refuses all mail with a custom reason
+#[derive(Clone, Debug)]
+struct NoVirusesHere;
+
+impl MailCheck for NoVirusesHere {
+    fn check_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(CheckMailResult::Failed(
+            CheckMailFailure::RejectedPermanently,
+            "5.7.1 virus found".to_owned(),
+        )))
+    }
+}
+```
+*/
+pub trait MailCheck: fmt::Debug {
+    /// Check the received mail. Return `Accepted` to let others check and then dispatch the mail.
+    fn check_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f;
+}
+
+impl<S: MailCheck + ?Sized, T: Deref<Target = S>> MailCheck for T
+where
+    T: fmt::Debug + Send + Sync,
+    S: Sync,
+{
+    fn check_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::check_mail(Deref::deref(self), session).await })
+    }
+}
+
+impl MailCheck for Dummy {
+    /// Always accept
+    fn check_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(CheckMailResult::Accepted))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckMailResult {
+    /// Failure with a message that will be sent back to the client, such as "5.7.1 virus found"
+    Failed(CheckMailFailure, String),
+    /// 250 Mail accepted, it will be dispatched
+    Accepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckMailFailure {
+    /// The mail is refused and the session is closed
+    /// 421  <domain> Service not available, closing transmission channel
+    TerminateSession,
+    /// 451  Requested action aborted: the client may try again later
+    FailedTemporarily,
+    /// 550  Requested action not taken: the mail is refused for good
+    RejectedPermanently,
+}
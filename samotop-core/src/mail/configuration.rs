@@ -5,19 +5,29 @@ use crate::{common::*, io::tls::MayBeTls, mail::*, smtp::*};
 pub struct Configuration {
     /// ID used for identifying this instance in logs
     id: String,
+    connect: Vec<Box<dyn ConnectionGuard + Sync + Send + 'static>>,
     dispatch: Vec<Box<dyn MailDispatch + Sync + Send + 'static>>,
     guard: Vec<Box<dyn MailGuard + Sync + Send + 'static>>,
+    check: Vec<Box<dyn MailCheck + Sync + Send + 'static>>,
     session: Vec<Box<dyn SessionService + Sync + Send + 'static>>,
+    lifecycle: Vec<Box<dyn SessionLifecycle + Sync + Send + 'static>>,
     interpret: Vec<Box<dyn Interpret + Sync + Send + 'static>>,
+    ids: Box<dyn IdGenerator + Sync + Send + 'static>,
+    privacy: Privacy,
 }
 impl Default for Configuration {
     fn default() -> Self {
         Self {
-            id: Identify::now().to_string(),
+            id: Dummy.generate(),
+            connect: Default::default(),
             dispatch: Default::default(),
             guard: Default::default(),
+            check: Default::default(),
             session: Default::default(),
+            lifecycle: Default::default(),
             interpret: Default::default(),
+            ids: Box::new(UlidGenerator::new()),
+            privacy: Privacy::default(),
         }
     }
 }
@@ -25,11 +35,24 @@ impl Configuration {
     pub fn into_service(self, driver: impl Drive + Sync + Send + 'static) -> Service {
         let Configuration {
             id,
-            session,
+            connect,
+            mut session,
+            lifecycle,
             guard,
+            check,
             dispatch,
             interpret,
+            ids,
+            privacy,
         } = self;
+        // connection guards decide before any other session service prepares the session
+        session.insert(
+            0,
+            Box::new(SvcBunch {
+                id: id.clone(),
+                items: connect,
+            }),
+        );
         Service::new(
             driver,
             Interpretter::new(interpret),
@@ -42,10 +65,17 @@ impl Configuration {
                 items: guard,
             },
             SvcBunch {
-                id,
+                id: id.clone(),
                 items: dispatch,
             },
         )
+        .with_lifecycle(SvcBunch {
+            id: id.clone(),
+            items: lifecycle,
+        })
+        .with_check(SvcBunch { id, items: check })
+        .with_id_generator(ids)
+        .with_privacy(privacy)
     }
 }
 impl HasId for Configuration {
@@ -54,6 +84,18 @@ impl HasId for Configuration {
     }
 }
 
+impl AcceptsIdGenerator for Configuration {
+    fn set_id_generator<T: IdGenerator + Send + Sync + 'static>(&mut self, item: T) {
+        self.ids = Box::new(item)
+    }
+}
+
+impl AcceptsPrivacy for Configuration {
+    fn set_privacy(&mut self, privacy: Privacy) {
+        self.privacy = privacy
+    }
+}
+
 impl AcceptsSessionService for Configuration {
     fn add_first_session_service<T: SessionService + Send + Sync + 'static>(&mut self, session: T) {
         self.session.insert(0, Box::new(session));
@@ -81,6 +123,56 @@ impl AcceptsSessionService for Configuration {
     }
 }
 
+impl AcceptsConnectionGuard for Configuration {
+    fn add_first_connection_guard<T: ConnectionGuard + Send + Sync + 'static>(&mut self, item: T) {
+        self.connect.insert(0, Box::new(item));
+    }
+
+    fn add_last_connection_guard<T: ConnectionGuard + Send + Sync + 'static>(&mut self, item: T) {
+        self.connect.push(Box::new(item))
+    }
+
+    fn wrap_connection_guards<
+        T: ConnectionGuard + Send + Sync + 'static,
+        F: FnOnce(Box<dyn ConnectionGuard + Send + Sync>) -> T,
+    >(
+        &mut self,
+        wrap: F,
+    ) {
+        let items = std::mem::take(&mut self.connect);
+        let connect = wrap(Box::new(SvcBunch {
+            id: format!("({})", self.id),
+            items,
+        }));
+        self.connect.push(Box::new(connect))
+    }
+}
+
+impl AcceptsSessionLifecycle for Configuration {
+    fn add_first_lifecycle<T: SessionLifecycle + Send + Sync + 'static>(&mut self, item: T) {
+        self.lifecycle.insert(0, Box::new(item));
+    }
+
+    fn add_last_lifecycle<T: SessionLifecycle + Send + Sync + 'static>(&mut self, item: T) {
+        self.lifecycle.push(Box::new(item))
+    }
+
+    fn wrap_lifecycle<
+        T: SessionLifecycle + Send + Sync + 'static,
+        F: FnOnce(Box<dyn SessionLifecycle + Send + Sync>) -> T,
+    >(
+        &mut self,
+        wrap: F,
+    ) {
+        let items = std::mem::take(&mut self.lifecycle);
+        let lifecycle = wrap(Box::new(SvcBunch {
+            id: format!("({})", self.id),
+            items,
+        }));
+        self.lifecycle.push(Box::new(lifecycle))
+    }
+}
+
 impl AcceptsGuard for Configuration {
     fn add_first_guard<T: MailGuard + Send + Sync + 'static>(&mut self, guard: T) {
         self.guard.insert(0, Box::new(guard));
@@ -131,6 +223,31 @@ impl AcceptsDispatch for Configuration {
     }
 }
 
+impl AcceptsCheck for Configuration {
+    fn add_first_check<T: MailCheck + Send + Sync + 'static>(&mut self, check: T) {
+        self.check.insert(0, Box::new(check));
+    }
+
+    fn add_last_check<T: MailCheck + Send + Sync + 'static>(&mut self, check: T) {
+        self.check.push(Box::new(check))
+    }
+
+    fn wrap_checks<
+        T: MailCheck + Send + Sync + 'static,
+        F: FnOnce(Box<dyn MailCheck + Send + Sync>) -> T,
+    >(
+        &mut self,
+        wrap: F,
+    ) {
+        let items = std::mem::take(&mut self.check);
+        let check = wrap(Box::new(SvcBunch {
+            id: format!("({})", self.id),
+            items,
+        }));
+        self.check.push(Box::new(check))
+    }
+}
+
 impl AcceptsInterpretter for Configuration {
     fn add_first_interpretter<T: Interpret + Send + Sync + 'static>(&mut self, item: T) {
         self.interpret.insert(0, Box::new(item));
@@ -190,6 +307,136 @@ impl SessionService for SvcBunch<Box<dyn SessionService + Sync + Send>> {
     }
 }
 
+impl ConnectionGuard for SvcBunch<Box<dyn ConnectionGuard + Sync + Send>> {
+    fn accept_connection<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, AcceptConnectionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        trace!(
+            "ConnectionGuard {} with {} guards accept_connection conn id {}",
+            self.id,
+            self.items.len(),
+            session.connection.id
+        );
+        let fut = async move {
+            for guard in self.items.iter() {
+                trace!(
+                    "ConnectionGuard {} accept_connection calling {:?}",
+                    self.id,
+                    guard
+                );
+                match guard.accept_connection(session).await {
+                    AcceptConnectionResult::Accepted => {}
+                    otherwise => return otherwise,
+                }
+            }
+            Dummy.accept_connection(session).await
+        };
+        Box::pin(fut)
+    }
+}
+
+/// Runs the connection guards and records the verdict in the session
+impl SessionService for SvcBunch<Box<dyn ConnectionGuard + Sync + Send>> {
+    fn prepare_session<'a, 'i, 's, 'f>(
+        &'a self,
+        _io: &'i mut Box<dyn MayBeTls>,
+        state: &'s mut SmtpContext,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        'i: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            match self.accept_connection(&mut state.session).await {
+                AcceptConnectionResult::Accepted => state.session.refused = None,
+                AcceptConnectionResult::Failed(failure, description) => {
+                    info!(
+                        "Connection {} from {} refused ({:?}): {}",
+                        state.session.connection.id,
+                        state
+                            .session
+                            .privacy
+                            .ip(&state.session.connection.peer_addr),
+                        failure,
+                        description
+                    );
+                    state.session.refused = Some(failure)
+                }
+            }
+        })
+    }
+}
+
+impl SessionLifecycle for SvcBunch<Box<dyn SessionLifecycle + Sync + Send>> {
+    fn on_helo<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            trace!(
+                "SessionLifecycle {} with {} children on_helo conn id {}",
+                self.id,
+                self.items.len(),
+                session.connection.id
+            );
+            for svc in self.items.iter() {
+                svc.on_helo(session).await;
+            }
+        })
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            trace!(
+                "SessionLifecycle {} with {} children on_transaction_end {:?} mail id {}",
+                self.id,
+                self.items.len(),
+                end,
+                session.transaction.id
+            );
+            for svc in self.items.iter() {
+                svc.on_transaction_end(session, end).await;
+            }
+        })
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            trace!(
+                "SessionLifecycle {} with {} children on_session_end {:?} conn id {}",
+                self.id,
+                self.items.len(),
+                reason,
+                session.connection.id
+            );
+            for svc in self.items.iter() {
+                svc.on_session_end(session, reason.clone()).await;
+            }
+        })
+    }
+}
+
 impl MailGuard for SvcBunch<Box<dyn MailGuard + Sync + Send>> {
     fn add_recipient<'a, 's, 'f>(
         &'a self,
@@ -201,10 +448,10 @@ impl MailGuard for SvcBunch<Box<dyn MailGuard + Sync + Send>> {
         's: 'f,
     {
         trace!(
-            "Guard {} with {} guards add_recipient {:?} to mail id {}",
+            "Guard {} with {} guards add_recipient {} to mail id {}",
             self.id,
             self.items.len(),
-            rcpt,
+            session.privacy.address(&rcpt.address),
             session.transaction.id
         );
         let fut = async move {
@@ -271,3 +518,29 @@ impl MailDispatch for SvcBunch<Box<dyn MailDispatch + Sync + Send>> {
         Box::pin(fut)
     }
 }
+
+impl MailCheck for SvcBunch<Box<dyn MailCheck + Sync + Send>> {
+    fn check_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        trace!(
+            "Check {} with {} checks check_mail id {}",
+            self.id,
+            self.items.len(),
+            session.transaction.id
+        );
+        let fut = async move {
+            for check in self.items.iter() {
+                trace!("Check {} check_mail calling {:?}", self.id, check);
+                match check.check_mail(session).await {
+                    CheckMailResult::Accepted => {}
+                    otherwise => return otherwise,
+                }
+            }
+            Dummy.check_mail(session).await
+        };
+        Box::pin(fut)
+    }
+}
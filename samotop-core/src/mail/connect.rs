@@ -0,0 +1,100 @@
+use crate::{common::*, smtp::SmtpSession};
+use std::ops::Deref;
+
+/**
+A connection guard decides whether to serve the client at all, before the banner is sent.
+
+It only has the connection information at hand (`session.connection`).
+If the connection is rejected, the client gets a "554 No SMTP service here"
+instead of the banner and the session will only accept QUIT as per RFC 5321 section 3.1.
+
+```
+# use samotop_core::common::*;
+# use samotop_core::mail::*;
+# use samotop_core::smtp::*;
+/// Refuses clients from the documentation network
+#[derive(Clone, Debug)]
+struct NoDocs;
+
+impl ConnectionGuard for NoDocs {
+    fn accept_connection<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, AcceptConnectionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(
+            if session.connection.peer_addr.starts_with("192.0.2.") {
+                AcceptConnectionResult::Failed(
+                    AcceptConnectionFailure::Rejected,
+                    format!("{} is on the blocklist", session.connection.peer_addr),
+                )
+            } else {
+                AcceptConnectionResult::Accepted
+            },
+        ))
+    }
+}
+```
+*/
+pub trait ConnectionGuard: fmt::Debug {
+    /// Decide if the client will be served
+    fn accept_connection<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, AcceptConnectionResult>
+    where
+        'a: 'f,
+        's: 'f;
+}
+
+impl<S: ConnectionGuard + ?Sized, T: Deref<Target = S>> ConnectionGuard for T
+where
+    T: fmt::Debug + Send + Sync,
+    S: Sync,
+{
+    fn accept_connection<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, AcceptConnectionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::accept_connection(Deref::deref(self), session).await })
+    }
+}
+
+impl ConnectionGuard for Dummy {
+    /// Always accept
+    fn accept_connection<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, AcceptConnectionResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AcceptConnectionResult::Accepted))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcceptConnectionResult {
+    /// Failure with explanation for the logs
+    Failed(AcceptConnectionFailure, String),
+    /// 220 Service ready
+    Accepted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptConnectionFailure {
+    /// The service is not available now (i.e. maintenance), close the connection right away
+    /// 421  <domain> Service not available, closing transmission channel
+    TerminateSession,
+    /// The client is not welcome, wait for QUIT and refuse all other commands with 503
+    /// 554  <domain> No SMTP service here
+    Rejected,
+}
@@ -0,0 +1,799 @@
+use crate::runtime::{default_runtime, Runtime};
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{SessionEnd, SessionLifecycle, SmtpSession, TransactionEnd},
+};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead as _, BufReader, BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/**
+Greylisting - temporarily refuse unknown (client, sender, recipient) triplets.
+
+A legitimate mail server will retry after a while, a lot of spam software will not.
+The first time a triplet is seen, the recipient is refused with a 451 and a retry hint.
+A retry after the delay and within the retry window is accepted and the triplet
+is then remembered for the lifetime. Clients are keyed by their network (/24 and /64 by default)
+as big senders tend to retry from a different address of the same pool.
+
+Once a client has passed greylisting in a given number of delivered transactions,
+it is whitelisted altogether for the lifetime.
+
+The state lives in a `GreylistStore`, `MemoryGreylistStore` by default.
+Use a `FileGreylistStore` to survive restarts.
+Expired entries are removed in the background on the runtime, at most once per expiry interval.
+
+```no_run
+# use samotop_core::mail::*;
+# use std::time::Duration;
+# fn main() -> std::io::Result<()> {
+let greylist = Greylist::new(FileGreylistStore::open("/var/lib/samotop/greylist")?)
+    .with_delay(Duration::from_secs(300))
+    .with_auto_whitelist(5);
+let service = Builder + Name::new("mx.example.org") + greylist;
+# Ok(())
+# }
+```
+*/
+#[derive(Clone)]
+pub struct Greylist {
+    store: Arc<dyn GreylistStore + Send + Sync>,
+    delay: Duration,
+    retry_window: Duration,
+    lifetime: Duration,
+    whitelist_after: Option<u32>,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    expire_every: Option<Duration>,
+    next_expiry: Arc<Mutex<SystemTime>>,
+    /// Runtime for the background expiry, the default runtime if not set
+    runtime: Option<Arc<dyn Runtime + Send + Sync>>,
+}
+
+impl Default for Greylist {
+    fn default() -> Self {
+        Self::new(MemoryGreylistStore::default())
+    }
+}
+
+impl Greylist {
+    /// Greylist with the state kept in the given store
+    pub fn new(store: impl GreylistStore + Send + Sync + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            delay: Duration::from_secs(5 * 60),
+            retry_window: Duration::from_secs(2 * 24 * 3600),
+            lifetime: Duration::from_secs(35 * 24 * 3600),
+            whitelist_after: Some(5),
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+            expire_every: Some(Duration::from_secs(3600)),
+            next_expiry: Arc::new(Mutex::new(UNIX_EPOCH)),
+            runtime: None,
+        }
+    }
+    /// How long must the client wait before retrying, 5 minutes by default
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+    /// How long after the first attempt is a retry still accepted, 2 days by default
+    pub fn with_retry_window(mut self, retry_window: Duration) -> Self {
+        self.retry_window = retry_window;
+        self
+    }
+    /// How long are passed triplets and whitelisted clients remembered, 35 days by default
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+    /// Whitelist clients that passed greylisting in the given number of delivered transactions, 5 by default
+    pub fn with_auto_whitelist(mut self, transactions: u32) -> Self {
+        self.whitelist_after = Some(transactions);
+        self
+    }
+    /// Never whitelist clients, always check the triplet
+    pub fn without_auto_whitelist(mut self) -> Self {
+        self.whitelist_after = None;
+        self
+    }
+    /// Key the clients by network prefix, 24 for IPv4 and 64 for IPv6 by default. Use 32 and 128 for exact IPs.
+    pub fn with_network_prefix(mut self, ipv4_prefix: u8, ipv6_prefix: u8) -> Self {
+        self.ipv4_prefix = ipv4_prefix.min(32);
+        self.ipv6_prefix = ipv6_prefix.min(128);
+        self
+    }
+    /// How often to remove expired entries, every hour by default
+    pub fn with_expiry_interval(mut self, interval: Duration) -> Self {
+        self.expire_every = Some(interval);
+        self
+    }
+    /// Do not remove expired entries automatically, call `expire()` yourself
+    pub fn without_auto_expiry(mut self) -> Self {
+        self.expire_every = None;
+        self
+    }
+    /// Use the given runtime for the background expiry rather than the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+    fn runtime(&self) -> Arc<dyn Runtime + Send + Sync> {
+        self.runtime.clone().unwrap_or_else(default_runtime)
+    }
+    /// Remove entries expired by now, returning the number of entries removed
+    pub async fn expire(&self) -> io::Result<usize> {
+        self.store.expire(SystemTime::now()).await
+    }
+    /// Is it time to remove expired entries? Only one caller per interval gets a yes.
+    fn expiry_due(&self, now: SystemTime) -> bool {
+        let interval = match self.expire_every {
+            None => return false,
+            Some(interval) => interval,
+        };
+        let mut next = self
+            .next_expiry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if *next > now {
+            return false;
+        }
+        *next = now + interval;
+        true
+    }
+    /// Remove expired entries in the background if it is time to
+    fn expire_when_due(&self, now: SystemTime) {
+        if !self.expiry_due(now) {
+            return;
+        }
+        let store = self.store.clone();
+        self.runtime().spawn(Box::pin(async move {
+            match store.expire(now).await {
+                Ok(count) => debug!("Greylist expired {} entries", count),
+                Err(e) => warn!("Greylist expiry failed: {}", e),
+            }
+        }));
+    }
+    /// Decide about the triplet at the given time.
+    ///
+    /// Returns how long the client has to wait yet, or `None` if it passes.
+    async fn decide(&self, key: GreylistKey, now: SystemTime) -> io::Result<Option<Duration>> {
+        let client = GreylistKey::client(key.client.as_str());
+        let whitelisted = match self.whitelist_after {
+            None => None,
+            Some(transactions) => self
+                .store
+                .get(&client)
+                .await?
+                .filter(|entry| entry.expires > now && entry.passes >= transactions),
+        };
+        if let Some(mut entry) = whitelisted {
+            entry.expires = now + self.lifetime;
+            self.store.put(client, entry).await?;
+            return Ok(None);
+        }
+
+        let mut entry = match self.store.get(&key).await? {
+            Some(entry) if entry.expires > now => entry,
+            _ => {
+                let entry = GreylistEntry {
+                    first_seen: now,
+                    passes: 0,
+                    expires: now + self.retry_window,
+                };
+                self.store.put(key, entry).await?;
+                return Ok(Some(self.delay));
+            }
+        };
+        if let Ok(wait) = (entry.first_seen + self.delay).duration_since(now) {
+            if !wait.is_zero() {
+                return Ok(Some(wait));
+            }
+        }
+
+        entry.passes += 1;
+        entry.expires = now + self.lifetime;
+        self.store.put(key, entry).await?;
+        Ok(None)
+    }
+    /// Count a delivered transaction that passed greylisting towards the client's auto whitelisting
+    async fn delivered(&self, client: &str, now: SystemTime) -> io::Result<()> {
+        if self.whitelist_after.is_none() {
+            return Ok(());
+        }
+        let client = GreylistKey::client(client);
+        let mut entry = self.store.get(&client).await?.unwrap_or(GreylistEntry {
+            first_seen: now,
+            passes: 0,
+            expires: now,
+        });
+        entry.passes += 1;
+        entry.expires = now + self.lifetime;
+        self.store.put(client, entry).await
+    }
+}
+
+impl fmt::Debug for Greylist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Greylist")
+            .field("store", &self.store)
+            .field("delay", &self.delay)
+            .field("retry_window", &self.retry_window)
+            .field("lifetime", &self.lifetime)
+            .field("whitelist_after", &self.whitelist_after)
+            .field("ipv4_prefix", &self.ipv4_prefix)
+            .field("ipv6_prefix", &self.ipv6_prefix)
+            .field("expire_every", &self.expire_every)
+            .field("runtime", &self.runtime)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard + AcceptsSessionLifecycle> MailSetup<T> for Greylist {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self.clone());
+        config.add_last_lifecycle(self)
+    }
+}
+
+/// Marks a transaction that passed greylisting, holding the client key
+struct Greylisted(String);
+
+impl MailGuard for Greylist {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let key = GreylistKey {
+            client: super::privacy::network(
+                session.connection.peer_addr.as_str(),
+                self.ipv4_prefix,
+                self.ipv6_prefix,
+            ),
+            sender: session
+                .transaction
+                .mail
+                .as_ref()
+                .map(|mail| mail.sender().to_string().to_ascii_lowercase())
+                .unwrap_or_default(),
+            recipient: rcpt.address.to_string().to_ascii_lowercase(),
+        };
+        Box::pin(async move {
+            let now = SystemTime::now();
+            self.expire_when_due(now);
+            let client = key.client.clone();
+            match self.decide(key, now).await {
+                Ok(None) => {
+                    session.transaction.set(Greylisted(client));
+                    AddRecipientResult::Inconclusive(rcpt)
+                }
+                Ok(Some(wait)) => AddRecipientResult::Failed(
+                    AddRecipientFailure::Deferred(format!(
+                        "Greylisted, please try again in {} seconds",
+                        wait.as_secs().max(1)
+                    )),
+                    format!("Greylisted {}", session.transaction.id),
+                ),
+                Err(e) => {
+                    // do not lose mail because of a broken store
+                    warn!(
+                        "Greylist store failed, letting the recipient through: {}",
+                        e
+                    );
+                    AddRecipientResult::Inconclusive(rcpt)
+                }
+            }
+        })
+    }
+    fn start_mail<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(StartMailResult::Accepted))
+    }
+}
+
+impl SessionLifecycle for Greylist {
+    fn on_helo<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let client = match (end, session.transaction.get::<Greylisted>()) {
+            (TransactionEnd::Queued, Some(Greylisted(client))) => client.clone(),
+            _ => return Box::pin(ready(())),
+        };
+        Box::pin(async move {
+            if let Err(e) = self.delivered(client.as_str(), SystemTime::now()).await {
+                warn!("Greylist store failed to count a delivery: {}", e);
+            }
+        })
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+}
+
+/// Identifies a greylisted triplet. A whitelisted client has an empty sender and recipient.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreylistKey {
+    /// The client IP or network
+    pub client: String,
+    /// The sender path such as `<alice@example.org>`, `<>` for the null sender
+    pub sender: String,
+    /// The recipient path such as `<bob@example.org>`
+    pub recipient: String,
+}
+
+impl GreylistKey {
+    /// The key of a whitelisted client
+    pub fn client(client: impl Into<String>) -> Self {
+        Self {
+            client: client.into(),
+            sender: String::new(),
+            recipient: String::new(),
+        }
+    }
+}
+
+/// What we know about a greylisted triplet or a whitelisted client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GreylistEntry {
+    /// The first attempt
+    pub first_seen: SystemTime,
+    /// How many times it passed greylisting
+    pub passes: u32,
+    /// When the entry can be forgotten
+    pub expires: SystemTime,
+}
+
+/// Keeps the greylisting state
+pub trait GreylistStore: fmt::Debug {
+    /// Look up an entry
+    fn get<'a, 'k, 'f>(
+        &'a self,
+        key: &'k GreylistKey,
+    ) -> S2Fut<'f, io::Result<Option<GreylistEntry>>>
+    where
+        'a: 'f,
+        'k: 'f;
+    /// Insert or replace an entry
+    fn put<'a, 'f>(&'a self, key: GreylistKey, entry: GreylistEntry) -> S2Fut<'f, io::Result<()>>
+    where
+        'a: 'f;
+    /// Remove entries expired at the given time, returning the number of entries removed
+    fn expire<'a, 'f>(&'a self, now: SystemTime) -> S2Fut<'f, io::Result<usize>>
+    where
+        'a: 'f;
+}
+
+/// Greylisting state kept in memory, lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryGreylistStore {
+    entries: Mutex<HashMap<GreylistKey, GreylistEntry>>,
+}
+
+impl MemoryGreylistStore {
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<GreylistKey, GreylistEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl GreylistStore for MemoryGreylistStore {
+    fn get<'a, 'k, 'f>(
+        &'a self,
+        key: &'k GreylistKey,
+    ) -> S2Fut<'f, io::Result<Option<GreylistEntry>>>
+    where
+        'a: 'f,
+        'k: 'f,
+    {
+        Box::pin(ready(Ok(self.entries().get(key).copied())))
+    }
+    fn put<'a, 'f>(&'a self, key: GreylistKey, entry: GreylistEntry) -> S2Fut<'f, io::Result<()>>
+    where
+        'a: 'f,
+    {
+        self.entries().insert(key, entry);
+        Box::pin(ready(Ok(())))
+    }
+    fn expire<'a, 'f>(&'a self, now: SystemTime) -> S2Fut<'f, io::Result<usize>>
+    where
+        'a: 'f,
+    {
+        let mut entries = self.entries();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.expires > now);
+        Box::pin(ready(Ok(before - entries.len())))
+    }
+}
+
+/**
+Greylisting state kept in memory and journaled to a file so that it survives restarts.
+
+Each change is appended to the file as a tab separated line:
+client, sender, recipient, first seen, passes and expiry as unix seconds.
+The last line for a key wins. The file is compacted on expiry.
+
+The file is written by a dedicated thread so that the sessions do not wait for it.
+Dropping the store waits for the pending changes to be written.
+*/
+#[derive(Debug)]
+pub struct FileGreylistStore {
+    state: Arc<Mutex<FileState>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+/// The live entries and the way to the journal writer
+#[derive(Debug)]
+struct FileState {
+    entries: HashMap<GreylistKey, GreylistEntry>,
+    journal: Option<Sender<Journal>>,
+}
+
+/// Changes for the journal writer
+#[derive(Debug)]
+enum Journal {
+    /// Append a line
+    Append(String),
+    /// Replace the journal with the live entries
+    Compact,
+}
+
+impl FileGreylistStore {
+    /// Load the state from the given file, creating it if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut entries = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    match parse_line(line.as_str()) {
+                        Some((key, entry)) => {
+                            entries.insert(key, entry);
+                        }
+                        None if line.is_empty() => {}
+                        None => warn!("Ignoring invalid greylist line {:?}", line),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let (sender, receiver) = channel();
+        let state = Arc::new(Mutex::new(FileState {
+            entries,
+            journal: Some(sender),
+        }));
+        let writer = {
+            let state = state.clone();
+            std::thread::Builder::new()
+                .name("greylist journal".to_owned())
+                .spawn(move || write_journal(path, file, state, receiver))?
+        };
+        Ok(Self {
+            state,
+            writer: Some(writer),
+        })
+    }
+    fn state(&self) -> std::sync::MutexGuard<'_, FileState> {
+        lock(&self.state)
+    }
+    fn put_sync(&self, key: GreylistKey, entry: GreylistEntry) -> io::Result<()> {
+        let mut state = self.state();
+        send(&state.journal, Journal::Append(format_line(&key, &entry)))?;
+        state.entries.insert(key, entry);
+        Ok(())
+    }
+    fn expire_sync(&self, now: SystemTime) -> io::Result<usize> {
+        let mut state = self.state();
+        let before = state.entries.len();
+        state.entries.retain(|_, entry| entry.expires > now);
+        // the writer takes its own snapshot of the live entries
+        send(&state.journal, Journal::Compact)?;
+        Ok(before - state.entries.len())
+    }
+}
+
+fn lock(state: &Mutex<FileState>) -> std::sync::MutexGuard<'_, FileState> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Drop for FileGreylistStore {
+    fn drop(&mut self) {
+        // closing the channel stops the writer once it has written everything
+        self.state().journal.take();
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                error!("Greylist journal writer panicked");
+            }
+        }
+    }
+}
+
+fn send(journal: &Option<Sender<Journal>>, change: Journal) -> io::Result<()> {
+    journal
+        .as_ref()
+        .and_then(|journal| journal.send(change).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "greylist journal is closed"))
+}
+
+/// Write the journal changes as they come, flushing whenever there are no more pending
+fn write_journal(
+    path: PathBuf,
+    file: File,
+    state: Arc<Mutex<FileState>>,
+    changes: Receiver<Journal>,
+) {
+    let mut file = BufWriter::new(file);
+    while let Ok(change) = changes.recv() {
+        let mut result = write_change(&path, &mut file, &state, change);
+        while result.is_ok() {
+            match changes.try_recv() {
+                Ok(change) => result = write_change(&path, &mut file, &state, change),
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = result.and_then(|()| file.flush()) {
+            warn!("Failed to write the greylist journal {:?}: {}", path, e);
+        }
+    }
+}
+
+fn write_change(
+    path: &Path,
+    file: &mut BufWriter<File>,
+    state: &Mutex<FileState>,
+    change: Journal,
+) -> io::Result<()> {
+    match change {
+        Journal::Append(line) => file.write_all(line.as_bytes()),
+        Journal::Compact => {
+            // rewrite the journal with the live entries only.
+            // Changes made since are in the snapshot and still queued, replaying them is harmless.
+            let entries = lock(state)
+                .entries
+                .iter()
+                .map(|(key, entry)| (key.clone(), *entry))
+                .collect::<Vec<_>>();
+            file.flush()?;
+            let mut temp = path.to_owned().into_os_string();
+            temp.push(".tmp");
+            let mut compacted = BufWriter::new(File::create(&temp)?);
+            for (key, entry) in entries {
+                compacted.write_all(format_line(&key, &entry).as_bytes())?;
+            }
+            compacted.into_inner()?.sync_all()?;
+            std::fs::rename(&temp, path)?;
+            *file = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+            Ok(())
+        }
+    }
+}
+
+impl GreylistStore for FileGreylistStore {
+    fn get<'a, 'k, 'f>(
+        &'a self,
+        key: &'k GreylistKey,
+    ) -> S2Fut<'f, io::Result<Option<GreylistEntry>>>
+    where
+        'a: 'f,
+        'k: 'f,
+    {
+        Box::pin(ready(Ok(self.state().entries.get(key).copied())))
+    }
+    fn put<'a, 'f>(&'a self, key: GreylistKey, entry: GreylistEntry) -> S2Fut<'f, io::Result<()>>
+    where
+        'a: 'f,
+    {
+        Box::pin(ready(self.put_sync(key, entry)))
+    }
+    fn expire<'a, 'f>(&'a self, now: SystemTime) -> S2Fut<'f, io::Result<usize>>
+    where
+        'a: 'f,
+    {
+        Box::pin(ready(self.expire_sync(now)))
+    }
+}
+
+fn format_line(key: &GreylistKey, entry: &GreylistEntry) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\n",
+        key.client,
+        key.sender,
+        key.recipient,
+        seconds(entry.first_seen),
+        entry.passes,
+        seconds(entry.expires)
+    )
+}
+
+fn parse_line(line: &str) -> Option<(GreylistKey, GreylistEntry)> {
+    let mut fields = line.split('\t');
+    let key = GreylistKey {
+        client: fields.next()?.to_owned(),
+        sender: fields.next()?.to_owned(),
+        recipient: fields.next()?.to_owned(),
+    };
+    let entry = GreylistEntry {
+        first_seen: UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?),
+        passes: fields.next()?.parse().ok()?,
+        expires: UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?),
+    };
+    match fields.next() {
+        None => Some((key, entry)),
+        Some(_) => None,
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(sender: &str) -> GreylistKey {
+        GreylistKey {
+            client: "192.0.2.0/24".to_owned(),
+            sender: sender.to_owned(),
+            recipient: "<bob@example.org>".to_owned(),
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_600_000_000 + seconds)
+    }
+
+    #[test]
+    fn retry_after_delay_passes() {
+        async_std::task::block_on(async move {
+            let sut = Greylist::default().with_delay(Duration::from_secs(300));
+            let alice = key("<alice@example.org>");
+            assert_eq!(
+                sut.decide(alice.clone(), at(0)).await.unwrap(),
+                Some(Duration::from_secs(300))
+            );
+            assert_eq!(
+                sut.decide(alice.clone(), at(100)).await.unwrap(),
+                Some(Duration::from_secs(200))
+            );
+            assert_eq!(sut.decide(alice.clone(), at(300)).await.unwrap(), None);
+            assert_eq!(sut.decide(alice, at(3600)).await.unwrap(), None);
+            assert!(sut
+                .decide(key("<eve@example.org>"), at(3600))
+                .await
+                .unwrap()
+                .is_some());
+        })
+    }
+
+    #[test]
+    fn late_retry_starts_over() {
+        async_std::task::block_on(async move {
+            let sut = Greylist::default()
+                .with_delay(Duration::from_secs(300))
+                .with_retry_window(Duration::from_secs(3600));
+            let alice = key("<alice@example.org>");
+            assert!(sut.decide(alice.clone(), at(0)).await.unwrap().is_some());
+            assert_eq!(
+                sut.decide(alice.clone(), at(4000)).await.unwrap(),
+                Some(Duration::from_secs(300))
+            );
+            assert_eq!(sut.decide(alice, at(4300)).await.unwrap(), None);
+        })
+    }
+
+    #[test]
+    fn client_gets_whitelisted_after_deliveries() {
+        async_std::task::block_on(async move {
+            let sut = Greylist::default()
+                .with_delay(Duration::from_secs(300))
+                .with_auto_whitelist(2);
+            let alice = key("<alice@example.org>");
+            let client = alice.client.clone();
+            assert!(sut.decide(alice.clone(), at(0)).await.unwrap().is_some());
+            assert_eq!(sut.decide(alice.clone(), at(300)).await.unwrap(), None);
+            // passing without delivering does not count
+            assert_eq!(sut.decide(alice.clone(), at(301)).await.unwrap(), None);
+            sut.delivered(client.as_str(), at(301)).await.unwrap();
+            assert!(sut
+                .decide(key("<eve@example.org>"), at(302))
+                .await
+                .unwrap()
+                .is_some());
+            sut.delivered(client.as_str(), at(400)).await.unwrap();
+            assert_eq!(
+                sut.decide(key("<eve@example.org>"), at(401)).await.unwrap(),
+                None
+            );
+        })
+    }
+
+    #[test]
+    fn expiry_is_due_once_per_interval() {
+        let sut = Greylist::default().with_expiry_interval(Duration::from_secs(60));
+        assert!(sut.expiry_due(at(0)));
+        assert!(!sut.clone().expiry_due(at(30)));
+        assert!(sut.expiry_due(at(60)));
+        assert!(!sut.without_auto_expiry().expiry_due(at(3600)));
+    }
+
+    #[test]
+    fn file_store_survives_restart() {
+        async_std::task::block_on(async move {
+            let path =
+                std::env::temp_dir().join(format!("samotop-greylist-test-{}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let entry = |expires| GreylistEntry {
+                first_seen: at(0),
+                passes: 1,
+                expires: at(expires),
+            };
+
+            let sut = FileGreylistStore::open(&path).unwrap();
+            sut.put(key("<alice@example.org>"), entry(100))
+                .await
+                .unwrap();
+            sut.put(key("<eve@example.org>"), entry(100)).await.unwrap();
+            sut.put(key("<eve@example.org>"), entry(10)).await.unwrap();
+            drop(sut);
+
+            let sut = FileGreylistStore::open(&path).unwrap();
+            assert_eq!(
+                sut.get(&key("<eve@example.org>")).await.unwrap(),
+                Some(entry(10))
+            );
+            assert_eq!(sut.expire(at(50)).await.unwrap(), 1);
+            drop(sut);
+
+            let sut = FileGreylistStore::open(&path).unwrap();
+            assert_eq!(sut.get(&key("<eve@example.org>")).await.unwrap(), None);
+            assert_eq!(
+                sut.get(&key("<alice@example.org>")).await.unwrap(),
+                Some(entry(100))
+            );
+            std::fs::remove_file(&path).unwrap();
+        })
+    }
+}
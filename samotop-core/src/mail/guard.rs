@@ -78,6 +78,12 @@ impl MailGuard for Dummy {
     }
 }
 
+/// Marks an allowlisted mail transaction in the transaction store, i.e. by an access list.
+///
+/// Guards that only weed out unwanted mail should let such a transaction through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allowlisted;
+
 #[derive(Debug, PartialEq, Eq)]
 #[allow(clippy::large_enum_variant)]
 pub enum StartMailResult {
@@ -97,6 +103,8 @@ pub enum StartMailFailure {
     /// 550 Requested action not taken: mailbox unavailable (e.g., mailbox
     /// not found, no access, or command rejected for policy reasons)
     Rejected,
+    /// 550 Requested action not taken with the given explanation, i.e. a blocklist reason
+    Refused(String),
     /// 553  Requested action not taken: mailbox name not allowed (e.g.,
     /// mailbox syntax incorrect)
     InvalidSender,
@@ -148,8 +156,29 @@ pub enum AddRecipientFailure {
     StorageExhaustedTemporarily,
     /// 451  Requested action aborted: local error in processing
     FailedTemporarily,
+    /// 451  Requested action aborted with the given explanation, i.e. when to retry
+    Deferred(String),
     /// 555  MAIL FROM/RCPT TO parameters not recognized or not implemented
     InvalidParameter,
     /// 455  Server unable to accommodate parameters
     InvalidParameterValue,
 }
+
+impl AddRecipientFailure {
+    /// A short snake_case name of the failure, i.e. for metrics and audit logs
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AddRecipientFailure::TerminateSession => "terminate_session",
+            AddRecipientFailure::RejectedPermanently => "rejected_permanently",
+            AddRecipientFailure::RejectedTemporarily => "rejected_temporarily",
+            AddRecipientFailure::Moved(_) => "moved",
+            AddRecipientFailure::InvalidRecipient => "invalid_recipient",
+            AddRecipientFailure::StorageExhaustedPermanently => "storage_exhausted_permanently",
+            AddRecipientFailure::StorageExhaustedTemporarily => "storage_exhausted_temporarily",
+            AddRecipientFailure::FailedTemporarily => "failed_temporarily",
+            AddRecipientFailure::Deferred(_) => "deferred",
+            AddRecipientFailure::InvalidParameter => "invalid_parameter",
+            AddRecipientFailure::InvalidParameterValue => "invalid_parameter_value",
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{
+    common::*,
+    mail::{AcceptsIdGenerator, MailSetup},
+};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+Generates unique identifiers, most notably the mail transaction IDs.
+
+The transaction ID is used in replies and logs and the dispatch may rely on it
+for naming files (maildir), so it must not collide. The default is `UlidGenerator`.
+Set up another one with the `Builder`:
+
+```
+# use samotop_core::mail::*;
+/// Sequential IDs, for testing only
+#[derive(Debug, Default)]
+struct Sequence(std::sync::atomic::AtomicUsize);
+
+impl IdGenerator for Sequence {
+    fn generate(&self) -> String {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed).to_string()
+    }
+}
+impl<T: AcceptsIdGenerator> MailSetup<T> for Sequence {
+    fn setup(self, config: &mut T) {
+        config.set_id_generator(self)
+    }
+}
+
+let mail_svc = Builder + Sequence::default();
+```
+*/
+pub trait IdGenerator: fmt::Debug {
+    /// Produce a new unique ID
+    fn generate(&self) -> String;
+}
+
+impl<S: IdGenerator + ?Sized, T: Deref<Target = S>> IdGenerator for T
+where
+    T: fmt::Debug,
+{
+    fn generate(&self) -> String {
+        S::generate(Deref::deref(self))
+    }
+}
+
+impl IdGenerator for Dummy {
+    /// Use a process wide `UlidGenerator`
+    fn generate(&self) -> String {
+        static DEFAULT: UlidGenerator = UlidGenerator::new();
+        DEFAULT.generate()
+    }
+}
+
+/**
+Generates [ULID](https://github.com/ulid/spec) style identifiers.
+
+These are 26 characters of Crockford's base32 - 48 bits of a millisecond timestamp
+followed by 80 random bits. Within the same millisecond the random part is incremented
+so the IDs produced by one generator are unique and sortable.
+
+The random bits come from the std hasher seed which is not a cryptographic RNG.
+The IDs are unique, but they are not secrets.
+*/
+#[derive(Debug, Default)]
+pub struct UlidGenerator {
+    last: Mutex<(u64, u128)>,
+}
+
+impl UlidGenerator {
+    pub const fn new() -> Self {
+        Self {
+            last: Mutex::new((0, 0)),
+        }
+    }
+    fn next(&self, now: u64) -> u128 {
+        const RANDOM_MAX: u128 = (1 << 80) - 1;
+        let mut last = self
+            .last
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *last = match *last {
+            (time, random) if now <= time && random < RANDOM_MAX => (time, random + 1),
+            (time, _) if now <= time => (time + 1, random_bits() & RANDOM_MAX),
+            _ => (now, random_bits() & RANDOM_MAX),
+        };
+        (u128::from(last.0) << 80) | last.1
+    }
+}
+
+impl IdGenerator for UlidGenerator {
+    fn generate(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        encode(self.next(now & 0xFFFF_FFFF_FFFF))
+    }
+}
+
+impl<T: AcceptsIdGenerator> MailSetup<T> for UlidGenerator {
+    fn setup(self, config: &mut T) {
+        config.set_id_generator(self)
+    }
+}
+
+fn random_bits() -> u128 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let random = || {
+        // each RandomState comes with new keys
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u32(std::process::id());
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        hasher.finish()
+    };
+    (u128::from(random()) << 64) | u128::from(random())
+}
+
+/// Crockford's base32, 26 characters
+fn encode(mut value: u128) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+    let mut id = [0u8; 26];
+    for c in id.iter_mut().rev() {
+        *c = ALPHABET[(value & 31) as usize];
+        value >>= 5;
+    }
+    id.iter().map(|c| *c as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn ulid_format() {
+        let id = UlidGenerator::new().generate();
+        assert_eq!(id.len(), 26);
+        assert!(id
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
+        assert_eq!(encode(0), "00000000000000000000000000");
+        assert_eq!(encode(u128::MAX), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+    }
+
+    #[test]
+    fn ulid_is_unique_and_sorted() {
+        let sut = UlidGenerator::new();
+        let ids = (0..10_000).map(|_| sut.generate()).collect::<Vec<_>>();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    }
+
+    #[test]
+    fn ulid_is_monotonic_when_clock_goes_back() {
+        let sut = UlidGenerator::new();
+        let first = sut.next(1000);
+        assert!(sut.next(999) > first);
+        assert!(sut.next(1000) > first);
+    }
+
+    #[test]
+    fn generators_differ() {
+        assert_ne!(
+            UlidGenerator::new().generate(),
+            UlidGenerator::new().generate()
+        );
+    }
+}
@@ -4,7 +4,9 @@ use crate::{
     common::*,
     io::tls::MayBeTls,
     mail::*,
-    smtp::{SessionService, SmtpContext, SmtpSession},
+    smtp::{
+        SessionEnd, SessionLifecycle, SessionService, SmtpContext, SmtpSession, TransactionEnd,
+    },
 };
 use std::fmt;
 
@@ -18,11 +20,17 @@ pub use SessionLogger as DebugService;
 
 impl<T> MailSetup<T> for SessionLogger
 where
-    T: AcceptsSessionService + AcceptsGuard + AcceptsDispatch,
+    T: AcceptsSessionService
+        + AcceptsSessionLifecycle
+        + AcceptsGuard
+        + AcceptsDispatch
+        + AcceptsCheck,
 {
     fn setup(self, config: &mut T) {
         config.add_last_session_service(self.clone());
+        config.add_last_lifecycle(self.clone());
         config.add_last_guard(self.clone());
+        config.add_last_check(self.clone());
         config.add_last_dispatch(self);
     }
 }
@@ -45,6 +53,53 @@ impl SessionService for SessionLogger {
     }
 }
 
+impl SessionLifecycle for SessionLogger {
+    fn on_helo<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        info!(
+            "{}: HELO from {:?}",
+            session.service_name, session.peer_name
+        );
+        Box::pin(ready(()))
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        info!(
+            "{}: Transaction ended {:?} (mailid: {:?}).",
+            session.service_name, end, session.transaction.id
+        );
+        Box::pin(ready(()))
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        info!(
+            "{}: Session ended {:?} after {}s, {:?}",
+            session.service_name,
+            reason,
+            session.connection.age().as_secs_f64(),
+            session.stats
+        );
+        Box::pin(ready(()))
+    }
+}
+
 impl MailGuard for SessionLogger {
     fn add_recipient<'a, 's, 'f>(
         &'a self,
@@ -55,9 +110,13 @@ impl MailGuard for SessionLogger {
         'a: 'f,
         's: 'f,
     {
+        let privacy = session.privacy.clone();
         info!(
-            "{}: RCPT {} from {:?} (mailid: {:?}).",
-            session.service_name, rcpt.address, session.transaction.mail, session.transaction.id
+            "{}: RCPT {} from {} (mailid: {:?}).",
+            session.service_name,
+            privacy.address(&rcpt.address),
+            sender(&privacy, session),
+            session.transaction.id
         );
         Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
     }
@@ -67,8 +126,11 @@ impl MailGuard for SessionLogger {
         's: 'f,
     {
         info!(
-            "{}: MAIL from {:?} (mailid: {:?}). {}",
-            session.service_name, session.transaction.mail, session.transaction.id, session
+            "{}: MAIL from {} (mailid: {:?}). {}",
+            session.service_name,
+            sender(&session.privacy, session),
+            session.transaction.id,
+            session
         );
         Box::pin(ready(StartMailResult::Accepted))
     }
@@ -83,21 +145,17 @@ impl MailDispatch for SessionLogger {
         'a: 'f,
         's: 'f,
     {
+        let privacy = session.privacy.clone();
         let Transaction {
-            ref mail,
-            ref id,
-            ref rcpts,
-            ..
+            ref id, ref rcpts, ..
         } = session.transaction;
         info!(
-            "{}: Mail from {:?} for {} (mailid: {:?}). {}",
+            "{}: Mail from {} for {} (mailid: {:?}). {}",
             session.service_name,
-            mail.as_ref()
-                .map(|m| m.sender().to_string())
-                .unwrap_or_else(|| "nobody".to_owned()),
+            sender(&privacy, session),
             rcpts.iter().fold(String::new(), |s, r| s + format!(
                 "{:?}, ",
-                r.address.to_string()
+                privacy.address(&r.address)
             )
             .as_ref()),
             id,
@@ -106,6 +164,7 @@ impl MailDispatch for SessionLogger {
         session.transaction.sink = session.transaction.sink.take().map(|inner| {
             Box::pin(DebugSink {
                 id: format!("{}: {}", session.service_name, id.clone()),
+                body: privacy.body(),
                 inner,
             }) as Pin<Box<dyn MailDataSink>>
         });
@@ -113,8 +172,33 @@ impl MailDispatch for SessionLogger {
     }
 }
 
+impl MailCheck for SessionLogger {
+    fn check_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        info!(
+            "{}: Mail checked (mailid: {:?}).",
+            session.service_name, session.transaction.id
+        );
+        Box::pin(ready(CheckMailResult::Accepted))
+    }
+}
+
+/// The sender of the current transaction as the privacy policy allows logging it
+fn sender(privacy: &Privacy, session: &SmtpSession) -> String {
+    session
+        .transaction
+        .mail
+        .as_ref()
+        .map(|m| privacy.address(m.sender()))
+        .unwrap_or_else(|| "nobody".to_owned())
+}
+
 struct DebugSink {
     id: String,
+    body: bool,
     inner: Pin<Box<dyn MailDataSink>>,
 }
 
@@ -141,7 +225,7 @@ impl io::Write for DebugSink {
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
         match self.inner.as_mut().poll_write(cx, buf) {
-            Poll::Ready(Ok(len)) => {
+            Poll::Ready(Ok(len)) if self.body => {
                 debug!(
                     "{}: Mail data written: len {} {:?}",
                     self.id,
@@ -150,6 +234,10 @@ impl io::Write for DebugSink {
                 );
                 Poll::Ready(Ok(len))
             }
+            Poll::Ready(Ok(len)) => {
+                debug!("{}: Mail data written: len {}", self.id, len);
+                Poll::Ready(Ok(len))
+            }
             Poll::Ready(Err(e)) => {
                 info!("{}: Mail data failed: {:?}", self.id, e);
                 Poll::Ready(Err(e))
@@ -163,6 +251,7 @@ impl fmt::Debug for DebugSink {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("DebugSink")
             .field("id", &self.id)
+            .field("body", &self.body)
             .field("inner", &"*")
             .finish()
     }
@@ -0,0 +1,780 @@
+use crate::common::io::{Read as _, Write as _};
+use crate::{
+    common::*,
+    io::{
+        tls::{MayBeTls, TlsUpgrade},
+        ConnectionInfo, IoService,
+    },
+    mail::*,
+    smtp::{
+        Interpret, InterpretResult, ParseError, SessionEnd, SessionLifecycle, SessionService,
+        SmtpContext, SmtpSession, TransactionEnd,
+    },
+};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/**
+Collects service metrics and exposes them in the Prometheus text format.
+
+Counts connections, STARTTLS upgrades, commands by verb, replies by code class,
+accepted and rejected recipients by reason, queued messages and mail data bytes,
+dispatch failures, session durations and active sessions.
+
+The metrics wrap the session service, interpretter, guard and dispatch chains
+set up so far, so add them after the other mail setups.
+Clones share the same counters, so one `Metrics` can be set up in several services.
+
+```
+# use samotop_core::mail::*;
+let metrics = Metrics::new();
+let mta = Builder + Name::new("mx.example.org") + metrics.clone();
+# #[cfg(all(feature = "server", feature = "driver"))]
+# async fn serve(mta: BuilderWithConfig, metrics: Metrics) -> samotop_core::common::Result<()> {
+samotop_core::server::Server::new()
+    .tcp("mta", "0.0.0.0:25", mta.build())
+    .tcp("metrics", "127.0.0.1:9125", metrics.endpoint())
+    .serve()
+    .await
+# }
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    connections: AtomicU64,
+    active_sessions: AtomicI64,
+    tls_upgrades: AtomicU64,
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    dispatch_failures: AtomicU64,
+    /// replies by the first digit of the code
+    replies: [AtomicU64; 6],
+    commands: Mutex<BTreeMap<&'static str, u64>>,
+    recipients: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    durations: Mutex<Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; SESSION_DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Upper bounds of the session duration histogram buckets in seconds
+const SESSION_DURATION_BUCKETS: [f64; 9] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0];
+
+/// Known command verbs, anything else is counted as `OTHER` to keep the label set small
+const VERBS: [&str; 15] = [
+    "HELO", "EHLO", "LHLO", "MAIL", "RCPT", "DATA", "BDAT", "RSET", "VRFY", "EXPN", "HELP", "NOOP",
+    "QUIT", "STARTTLS", "AUTH",
+];
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// An `IoService` serving the metrics over HTTP, i.e. to `TcpServer`
+    pub fn endpoint(&self) -> MetricsEndpoint {
+        MetricsEndpoint {
+            metrics: self.clone(),
+            privacy: Arc::default(),
+        }
+    }
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let r = &self.registry;
+        let mut out = String::new();
+        let head = |out: &mut String, name: &str, kind: &str, help: &str| {
+            // writing to a String does not fail
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        head(
+            &mut out,
+            "samotop_connections_total",
+            "counter",
+            "Sessions started",
+        );
+        let _ = writeln!(out, "samotop_connections_total {}", load(&r.connections));
+
+        head(
+            &mut out,
+            "samotop_active_sessions",
+            "gauge",
+            "Sessions in progress",
+        );
+        let _ = writeln!(
+            out,
+            "samotop_active_sessions {}",
+            r.active_sessions.load(Ordering::Relaxed)
+        );
+
+        head(
+            &mut out,
+            "samotop_tls_upgrades_total",
+            "counter",
+            "STARTTLS upgrades",
+        );
+        let _ = writeln!(out, "samotop_tls_upgrades_total {}", load(&r.tls_upgrades));
+
+        head(
+            &mut out,
+            "samotop_commands_total",
+            "counter",
+            "Commands received by verb",
+        );
+        for (verb, count) in lock(&r.commands).iter() {
+            let _ = writeln!(out, "samotop_commands_total{{verb=\"{}\"}} {}", verb, count);
+        }
+
+        head(
+            &mut out,
+            "samotop_replies_total",
+            "counter",
+            "Replies sent by code class",
+        );
+        for (class, count) in r.replies.iter().enumerate().skip(1) {
+            let _ = writeln!(
+                out,
+                "samotop_replies_total{{class=\"{}xx\"}} {}",
+                class,
+                load(count)
+            );
+        }
+
+        head(
+            &mut out,
+            "samotop_recipients_total",
+            "counter",
+            "Recipients by result and reason",
+        );
+        for ((result, reason), count) in lock(&r.recipients).iter() {
+            let _ = writeln!(
+                out,
+                "samotop_recipients_total{{result=\"{}\",reason=\"{}\"}} {}",
+                result, reason, count
+            );
+        }
+
+        head(
+            &mut out,
+            "samotop_messages_total",
+            "counter",
+            "Mail transactions queued",
+        );
+        let _ = writeln!(out, "samotop_messages_total {}", load(&r.messages));
+
+        head(
+            &mut out,
+            "samotop_received_bytes_total",
+            "counter",
+            "Mail data bytes written to dispatch",
+        );
+        let _ = writeln!(out, "samotop_received_bytes_total {}", load(&r.bytes));
+
+        head(
+            &mut out,
+            "samotop_dispatch_failures_total",
+            "counter",
+            "Mail dispatch failures",
+        );
+        let _ = writeln!(
+            out,
+            "samotop_dispatch_failures_total {}",
+            load(&r.dispatch_failures)
+        );
+
+        head(
+            &mut out,
+            "samotop_session_duration_seconds",
+            "histogram",
+            "Session duration",
+        );
+        let durations = lock(&r.durations);
+        let mut cumulative = 0;
+        for (le, count) in SESSION_DURATION_BUCKETS.iter().zip(durations.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "samotop_session_duration_seconds_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "samotop_session_duration_seconds_bucket{{le=\"+Inf\"}} {}\n\
+            samotop_session_duration_seconds_sum {}\n\
+            samotop_session_duration_seconds_count {}",
+            durations.count, durations.sum, durations.count
+        );
+
+        out
+    }
+
+    fn count_command(&self, input: &[u8]) {
+        let verb = input
+            .split(|b| b.is_ascii_whitespace())
+            .next()
+            .map(|verb| String::from_utf8_lossy(verb).to_ascii_uppercase())
+            .and_then(|verb| VERBS.iter().find(|known| **known == verb).copied())
+            .unwrap_or("OTHER");
+        *lock(&self.registry.commands).entry(verb).or_default() += 1;
+    }
+    fn count_recipient(&self, result: &'static str, reason: &'static str) {
+        *lock(&self.registry.recipients)
+            .entry((result, reason))
+            .or_default() += 1;
+    }
+    fn observe_session(&self, seconds: f64) {
+        let mut durations = lock(&self.registry.durations);
+        if let Some(bucket) = SESSION_DURATION_BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+        {
+            durations.buckets[bucket] += 1;
+        }
+        durations.count += 1;
+        durations.sum += seconds;
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl<T> MailSetup<T> for Metrics
+where
+    T: AcceptsSessionService
+        + AcceptsSessionLifecycle
+        + AcceptsInterpretter
+        + AcceptsGuard
+        + AcceptsDispatch,
+{
+    fn setup(self, config: &mut T) {
+        let metrics = self.clone();
+        config.wrap_session_service(|inner| MetricsWrap { metrics, inner });
+        let metrics = self.clone();
+        config.wrap_interpretter(|inner| MetricsWrap { metrics, inner });
+        let metrics = self.clone();
+        config.wrap_guards(|inner| MetricsWrap { metrics, inner });
+        let metrics = self.clone();
+        config.wrap_dispatches(|inner| MetricsWrap { metrics, inner });
+        config.add_last_lifecycle(self);
+    }
+}
+
+/// Counts the results of the inner service
+#[derive(Debug)]
+struct MetricsWrap<S> {
+    metrics: Metrics,
+    inner: S,
+}
+
+impl SessionService for MetricsWrap<Box<dyn SessionService + Sync + Send>> {
+    fn prepare_session<'a, 'i, 's, 'f>(
+        &'a self,
+        io: &'i mut Box<dyn MayBeTls>,
+        state: &'s mut SmtpContext,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        'i: 'f,
+        's: 'f,
+    {
+        let registry = &self.metrics.registry;
+        registry.connections.fetch_add(1, Ordering::Relaxed);
+        registry.active_sessions.fetch_add(1, Ordering::Relaxed);
+        *io = Box::new(MetricsIo {
+            metrics: self.metrics.clone(),
+            code: Vec::with_capacity(4),
+            started: Instant::now(),
+            io: std::mem::replace(io, Box::new(Dummy)),
+        });
+        self.inner.prepare_session(io, state)
+    }
+}
+
+impl Interpret for MetricsWrap<Box<dyn Interpret + Sync + Send>> {
+    fn interpret<'a, 's, 'f>(&'a self, state: &'s mut SmtpContext) -> S1Fut<'f, InterpretResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let expecting_commands = state.session.mode.is_none();
+            let result = self.inner.interpret(state).await;
+            if expecting_commands {
+                match result {
+                    Ok(Some(len)) => self
+                        .metrics
+                        .count_command(&state.session.input.as_slice()[..len]),
+                    Err(ParseError::Mismatch(_)) | Err(ParseError::Failed(_)) => {
+                        *lock(&self.metrics.registry.commands)
+                            .entry("INVALID")
+                            .or_default() += 1
+                    }
+                    Ok(None) | Err(ParseError::Incomplete) => {}
+                }
+            }
+            result
+        })
+    }
+}
+
+impl MailGuard for MetricsWrap<Box<dyn MailGuard + Sync + Send>> {
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let result = self.inner.add_recipient(session, rcpt).await;
+            let (outcome, reason) = match result {
+                AddRecipientResult::Inconclusive(_) => ("accepted", "inconclusive"),
+                AddRecipientResult::Accepted => ("accepted", "accepted"),
+                AddRecipientResult::AcceptedWithNewPath(_) => ("accepted", "forwarded"),
+                AddRecipientResult::Failed(ref failure, _) => ("rejected", failure.reason()),
+            };
+            self.metrics.count_recipient(outcome, reason);
+            result
+        })
+    }
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.inner.start_mail(session)
+    }
+}
+
+impl MailDispatch for MetricsWrap<Box<dyn MailDispatch + Sync + Send>> {
+    fn open_mail_body<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, DispatchResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let result = self.inner.open_mail_body(session).await;
+            if result.is_err() {
+                self.metrics
+                    .registry
+                    .dispatch_failures
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            let metrics = self.metrics.clone();
+            session.transaction.sink = session.transaction.sink.take().map(|inner| {
+                Box::pin(MetricsSink { metrics, inner }) as Pin<Box<dyn MailDataSink>>
+            });
+            result
+        })
+    }
+}
+
+impl SessionLifecycle for Metrics {
+    fn on_helo<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if end == TransactionEnd::Queued {
+            self.registry.messages.fetch_add(1, Ordering::Relaxed);
+        }
+        Box::pin(ready(()))
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+}
+
+/// Counts the replies and STARTTLS.
+///
+/// The session is over once the io is dropped, even if a virtual host branch
+/// took over the session and the lifecycle of this service is not notified.
+struct MetricsIo {
+    metrics: Metrics,
+    /// the start of the current reply line
+    code: Vec<u8>,
+    started: Instant,
+    io: Box<dyn MayBeTls>,
+}
+
+impl Drop for MetricsIo {
+    fn drop(&mut self) {
+        self.metrics
+            .registry
+            .active_sessions
+            .fetch_sub(1, Ordering::Relaxed);
+        self.metrics
+            .observe_session(self.started.elapsed().as_secs_f64());
+    }
+}
+
+impl MetricsIo {
+    fn written(&mut self, buf: &[u8]) {
+        for b in buf {
+            if *b == b'\n' {
+                self.code.clear();
+            } else if self.code.len() < 4 {
+                self.code.push(*b);
+                // the last line of a reply is `ddd text`, the others are `ddd-text`
+                if let [class @ b'1'..=b'5', b'0'..=b'9', b'0'..=b'9', b' '] = self.code[..] {
+                    self.metrics.registry.replies[usize::from(class - b'0')]
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+impl MayBeTls for MetricsIo {
+    fn enable_encryption(&mut self, upgrade: Box<dyn TlsUpgrade>, name: String) {
+        self.io.enable_encryption(upgrade, name)
+    }
+    fn encrypt(mut self: Pin<&mut Self>) {
+        self.metrics
+            .registry
+            .tls_upgrades
+            .fetch_add(1, Ordering::Relaxed);
+        Pin::new(&mut self.io).encrypt()
+    }
+    fn can_encrypt(&self) -> bool {
+        self.io.can_encrypt()
+    }
+    fn is_encrypted(&self) -> bool {
+        self.io.is_encrypted()
+    }
+}
+
+impl io::Read for MetricsIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl io::Write for MetricsIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = ready!(Pin::new(&mut self.io).poll_write(cx, buf))?;
+        self.written(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
+
+/// Counts the mail data bytes and dispatch failures
+struct MetricsSink {
+    metrics: Metrics,
+    inner: Pin<Box<dyn MailDataSink>>,
+}
+
+impl io::Write for MetricsSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = ready!(self.inner.as_mut().poll_write(cx, buf));
+        match result {
+            Ok(len) => self
+                .metrics
+                .registry
+                .bytes
+                .fetch_add(len as u64, Ordering::Relaxed),
+            Err(_) => self
+                .metrics
+                .registry
+                .dispatch_failures
+                .fetch_add(1, Ordering::Relaxed),
+        };
+        Poll::Ready(result)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.as_mut().poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = ready!(self.inner.as_mut().poll_close(cx));
+        if result.is_err() {
+            self.metrics
+                .registry
+                .dispatch_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl fmt::Debug for MetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsSink").field("inner", &"*").finish()
+    }
+}
+
+/// Serves `Metrics` over HTTP/1.0 at `/metrics`
+#[derive(Debug, Clone)]
+pub struct MetricsEndpoint {
+    metrics: Metrics,
+    privacy: Arc<Privacy>,
+}
+
+impl MetricsEndpoint {
+    /// Redact the clients in the logs with the privacy policy, logged as is by default
+    pub fn with_privacy(mut self, privacy: Privacy) -> Self {
+        self.privacy = Arc::new(privacy);
+        self
+    }
+}
+
+impl IoService for MetricsEndpoint {
+    fn handle(
+        &self,
+        io: Result<Box<dyn MayBeTls>>,
+        connection: ConnectionInfo,
+    ) -> S1Fut<'static, Result<()>> {
+        let metrics = self.metrics.clone();
+        let privacy = self.privacy.clone();
+        Box::pin(async move {
+            let mut io = io?;
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let len = poll_fn(|cx| Pin::new(&mut io).poll_read(cx, &mut buf[..])).await?;
+                if len == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..len]);
+                if request.len() > 8 * 1024 {
+                    break;
+                }
+            }
+
+            let mut words = request.split(|b| *b == b' ');
+            let response = match (words.next(), words.next()) {
+                (Some(b"GET"), Some(b"/metrics")) | (Some(b"GET"), Some(b"/")) => {
+                    let body = metrics.render();
+                    format!(
+                        "HTTP/1.0 200 OK\r\n\
+                        Content-Type: text/plain; version=0.0.4\r\n\
+                        Content-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => {
+                    debug!(
+                        "Invalid metrics request on {}",
+                        connection.redacted(&privacy)
+                    );
+                    "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+                }
+            };
+
+            let mut response = response.as_bytes();
+            while !response.is_empty() {
+                let len = poll_fn(|cx| Pin::new(&mut io).poll_write(cx, response)).await?;
+                if len == 0 {
+                    return Err("Metrics response write failed".into());
+                }
+                response = &response[len..];
+            }
+            poll_fn(|cx| Pin::new(&mut io).poll_flush(cx)).await?;
+            poll_fn(|cx| Pin::new(&mut io).poll_close(cx)).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_commands_and_recipients() {
+        let sut = Metrics::new();
+        sut.count_command(b"mail from:<>\r\n");
+        sut.count_command(b"MAIL FROM:<x@y>\r\n");
+        sut.count_command(b"XFORWARD NAME=x\r\n");
+        sut.count_recipient(
+            "rejected",
+            AddRecipientFailure::RejectedPermanently.reason(),
+        );
+        let text = sut.render();
+        assert!(
+            text.contains("samotop_commands_total{verb=\"MAIL\"} 2\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("samotop_commands_total{verb=\"OTHER\"} 1\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains(
+            "samotop_recipients_total{result=\"rejected\",reason=\"rejected_permanently\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn counts_replies_by_class() {
+        let sut = Metrics::new();
+        let mut io = MetricsIo {
+            metrics: sut.clone(),
+            code: vec![],
+            started: Instant::now(),
+            io: Box::new(Dummy),
+        };
+        io.written(b"220 ready\r\n250-me\r\n25");
+        io.written(b"0 8BITMIME\r\n550 no\r\n354");
+        io.written(b" go on\r\n");
+        let text = sut.render();
+        assert!(
+            text.contains("samotop_replies_total{class=\"2xx\"} 2\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("samotop_replies_total{class=\"3xx\"} 1\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("samotop_replies_total{class=\"5xx\"} 1\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[cfg(all(unix, feature = "driver"))]
+    #[test]
+    fn counts_sessions_taken_over_by_a_branch() {
+        async_std::task::block_on(async {
+            let sut = Metrics::new();
+            let branch = Builder + NullDispatch;
+            let service = (Builder
+                + sut.clone()
+                + VirtualHosts::default().host(
+                    VirtualHost::new("b")
+                        .on_listener("b")
+                        .with_branch(branch.build()),
+                ))
+            .build();
+            let (client, server) = async_std::os::unix::net::UnixStream::pair().expect("pair");
+            // the client leaves right away
+            client
+                .shutdown(std::net::Shutdown::Write)
+                .expect("shutdown");
+            service
+                .handle(
+                    Ok(Box::new(crate::io::tls::TlsCapable::plaintext(Box::new(
+                        server,
+                    )))),
+                    ConnectionInfo::default().with_listener("b"),
+                )
+                .await
+                .expect("session");
+            let text = sut.render();
+            assert!(text.contains("\nsamotop_connections_total 1\n"), "{}", text);
+            assert!(text.contains("\nsamotop_active_sessions 0\n"), "{}", text);
+            assert!(text.contains("\nsamotop_session_duration_seconds_count 1\n"));
+        })
+    }
+
+    #[test]
+    fn histogram_is_cumulative() {
+        let sut = Metrics::new();
+        sut.observe_session(0.05);
+        sut.observe_session(2.0);
+        sut.observe_session(1000.0);
+        let text = sut.render();
+        assert!(text.contains("samotop_session_duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("samotop_session_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(text.contains("samotop_session_duration_seconds_bucket{le=\"600\"} 2\n"));
+        assert!(text.contains("samotop_session_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("samotop_session_duration_seconds_count 3\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn endpoint_serves_metrics() {
+        async_std::task::block_on(async {
+            let sut = Metrics::new();
+            sut.registry.connections.fetch_add(3, Ordering::Relaxed);
+            let (client, server) = async_std::os::unix::net::UnixStream::pair().expect("pair");
+            let served = sut.endpoint().handle(
+                Ok(Box::new(crate::io::tls::TlsCapable::plaintext(Box::new(
+                    server,
+                )))),
+                ConnectionInfo::default(),
+            );
+            let client = async move {
+                use async_std::io::{ReadExt, WriteExt};
+                let mut client = client;
+                client
+                    .write_all(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n")
+                    .await
+                    .expect("request");
+                let mut response = String::new();
+                client
+                    .read_to_string(&mut response)
+                    .await
+                    .expect("response");
+                response
+            };
+            let served = async_std::task::spawn(served);
+            let response = client.await;
+            served.await.expect("served");
+            assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+            assert!(response.contains("\nsamotop_connections_total 3\n"));
+        })
+    }
+}
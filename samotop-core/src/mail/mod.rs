@@ -1,23 +1,41 @@
+mod audit;
 mod builder;
+mod check;
 mod configuration;
+mod connect;
 mod dispatch;
+mod greylist;
 mod guard;
+mod id;
 mod logger;
+mod metrics;
 mod name;
 mod null;
+mod privacy;
 mod recipient;
 mod service;
 mod setup;
+mod submit;
 mod transaction;
+mod vhost;
 
+pub use self::audit::*;
 pub use self::builder::*;
+pub use self::check::*;
 pub use self::configuration::*;
+pub use self::connect::*;
 pub use self::dispatch::*;
+pub use self::greylist::*;
 pub use self::guard::*;
+pub use self::id::*;
 pub use self::logger::*;
+pub use self::metrics::*;
 pub use self::name::*;
 pub use self::null::*;
+pub use self::privacy::*;
 pub use self::recipient::*;
 pub use self::service::*;
 pub use self::setup::*;
+pub use self::submit::*;
 pub use self::transaction::*;
+pub use self::vhost::*;
@@ -1,7 +1,7 @@
 use super::MailSetup;
-use crate::common::{ready, Identify, S1Fut};
+use crate::common::{ready, Dummy, Identify, S1Fut};
 use crate::io::tls::MayBeTls;
-use crate::mail::{AcceptsSessionService, HasId};
+use crate::mail::{AcceptsSessionService, HasId, IdGenerator};
 use crate::smtp::{SessionService, SmtpContext};
 
 /// MailSetup that uses the given service name for a session.
@@ -71,7 +71,7 @@ impl SessionService for Name {
 
         if self.identify_session {
             let session_id = if state.session.connection.id.is_empty() {
-                Identify::now().to_string()
+                Dummy.generate()
             } else {
                 state.session.connection.id.clone()
             };
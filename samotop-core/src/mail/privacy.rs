@@ -0,0 +1,216 @@
+use crate::{
+    common::*,
+    mail::{AcceptsPrivacy, MailSetup},
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/**
+Privacy policy of a service consulted by the built-in components when logging.
+
+By default everything is logged as is. A stricter policy can
+* replace e-mail addresses with keyed HMAC-SHA256 pseudonyms - the same address
+  gets the same pseudonym with the same key so logs can still be correlated,
+* truncate peer IP addresses to a network prefix,
+* never log mail body content, nor raw client input which may contain addresses.
+
+The service hands the policy to each session in `SmtpSession::privacy` so it applies
+to the session logger, audit log, transcripts, tracing spans, driver warnings and configuration traces
+of that service only. Delivery transports and the metrics endpoint take their own policy.
+
+```
+# use samotop_core::mail::*;
+let service = Builder
+    + Privacy::default()
+        .with_pseudonyms("a secret key")
+        .with_truncated_ips(24, 48)
+        .without_body();
+```
+*/
+#[derive(Clone)]
+pub struct Privacy {
+    key: Option<Vec<u8>>,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    body: bool,
+}
+
+impl Default for Privacy {
+    fn default() -> Self {
+        Self {
+            key: None,
+            ipv4_prefix: 32,
+            ipv6_prefix: 128,
+            body: true,
+        }
+    }
+}
+
+impl Privacy {
+    /// Pseudonymous addresses, IPv4 truncated to /24, IPv6 to /48 and no body content
+    pub fn strict(key: impl AsRef<[u8]>) -> Self {
+        Self::default()
+            .with_pseudonyms(key)
+            .with_truncated_ips(24, 48)
+            .without_body()
+    }
+    /// Replace e-mail addresses with HMAC pseudonyms using the given secret key
+    pub fn with_pseudonyms(mut self, key: impl AsRef<[u8]>) -> Self {
+        self.key = Some(key.as_ref().to_vec());
+        self
+    }
+    /// Keep only the given number of leading bits of IPv4 and IPv6 addresses
+    pub fn with_truncated_ips(mut self, ipv4_prefix: u8, ipv6_prefix: u8) -> Self {
+        self.ipv4_prefix = ipv4_prefix.min(32);
+        self.ipv6_prefix = ipv6_prefix.min(128);
+        self
+    }
+    /// Never log mail body content
+    pub fn without_body(mut self) -> Self {
+        self.body = false;
+        self
+    }
+    /// The e-mail address, or its pseudonym
+    pub fn address(&self, address: impl fmt::Display) -> String {
+        let address = address.to_string();
+        match self.key {
+            None => address,
+            Some(ref key) => {
+                let mac = hmac_sha256::HMAC::mac(address.to_ascii_lowercase(), key);
+                mac[..8].iter().fold(String::from("anon-"), |s, b| {
+                    s + format!("{:02x}", b).as_str()
+                })
+            }
+        }
+    }
+    /// The IP address (with an optional port) truncated to the network prefix.
+    ///
+    /// Anything else, i.e. a unix socket path, is returned as is.
+    pub fn ip(&self, address: &str) -> String {
+        network(address, self.ipv4_prefix, self.ipv6_prefix)
+    }
+    /// Raw client input, hidden if addresses are pseudonymous as it may contain them
+    pub fn input(&self, input: &[u8]) -> String {
+        match self.key {
+            None => format!("{:?}", String::from_utf8_lossy(input)),
+            Some(_) => format!("<{} bytes>", input.len()),
+        }
+    }
+    /// May mail body content be logged?
+    pub fn body(&self) -> bool {
+        self.body
+    }
+    /// May the raw session data be recorded? Not if it may contain addresses or body content to hide.
+    pub fn raw(&self) -> bool {
+        self.key.is_none() && self.body
+    }
+}
+
+/// The IP address (with an optional port) as a network with the given prefix length.
+///
+/// Full length prefixes keep the address as is, and so does anything that is not an IP address.
+pub(crate) fn network(address: &str, ipv4_prefix: u8, ipv6_prefix: u8) -> String {
+    let ip = match address.parse::<SocketAddr>() {
+        Ok(socket) => socket.ip(),
+        Err(_) => match address.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => return address.to_owned(),
+        },
+    };
+    let (network, prefix) = match ip {
+        IpAddr::V4(_) if ipv4_prefix >= 32 => return address.to_owned(),
+        IpAddr::V6(_) if ipv6_prefix >= 128 => return address.to_owned(),
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - ipv4_prefix as u32);
+            let network = u32::from(ip) & mask.unwrap_or_default();
+            (IpAddr::V4(Ipv4Addr::from(network)), ipv4_prefix)
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - ipv6_prefix as u32);
+            let network = u128::from(ip) & mask.unwrap_or_default();
+            (IpAddr::V6(Ipv6Addr::from(network)), ipv6_prefix)
+        }
+    };
+    format!("{}/{}", network, prefix)
+}
+
+impl fmt::Debug for Privacy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Privacy")
+            .field("pseudonyms", &self.key.is_some())
+            .field("ipv4_prefix", &self.ipv4_prefix)
+            .field("ipv6_prefix", &self.ipv6_prefix)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+impl<T: AcceptsPrivacy> MailSetup<T> for Privacy {
+    fn setup(self, config: &mut T) {
+        config.set_privacy(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_by_default() {
+        let sut = Privacy::default();
+        assert_eq!(sut.address("alice@example.org"), "alice@example.org");
+        assert_eq!(sut.ip("192.0.2.1:25"), "192.0.2.1:25");
+        assert_eq!(sut.input(b"RCPT TO:<a@b>"), "\"RCPT TO:<a@b>\"");
+        assert!(sut.body());
+    }
+
+    #[test]
+    fn pseudonyms_are_keyed() {
+        let sut = Privacy::default().with_pseudonyms("key");
+        let alice = sut.address("alice@example.org");
+        assert!(alice.starts_with("anon-"), "{}", alice);
+        assert_eq!(alice.len(), 21);
+        assert_eq!(sut.address("Alice@Example.org"), alice);
+        assert_ne!(sut.address("bob@example.org"), alice);
+        assert_ne!(
+            Privacy::default()
+                .with_pseudonyms("other key")
+                .address("alice@example.org"),
+            alice
+        );
+        assert_eq!(sut.input(b"RCPT TO:<a@b>"), "<13 bytes>");
+    }
+
+    #[test]
+    fn ips_are_truncated() {
+        let sut = Privacy::strict("key");
+        assert_eq!(sut.ip("192.0.2.123:54321"), "192.0.2.0/24");
+        assert_eq!(sut.ip("192.0.2.123"), "192.0.2.0/24");
+        assert_eq!(sut.ip("[2001:db8:1:2::5]:25"), "2001:db8:1::/48");
+        assert_eq!(sut.ip("/run/samotop.sock"), "/run/samotop.sock");
+        let sut = Privacy::default().with_truncated_ips(0, 0);
+        assert_eq!(sut.ip("192.0.2.123"), "0.0.0.0/0");
+        assert_eq!(sut.ip("2001:db8::1"), "::/0");
+        assert!(!Privacy::strict("key").body());
+    }
+
+    #[cfg(feature = "driver")]
+    #[test]
+    fn policy_belongs_to_the_service() {
+        use crate::{
+            io::{tls::MayBeTls, ConnectionInfo},
+            mail::Builder,
+            smtp::{SessionService, SmtpContext},
+        };
+        async_std::task::block_on(async move {
+            let strict = (Builder + Privacy::strict("key")).build();
+            let plain = Builder.build();
+            for (service, pseudonym) in [(strict, true), (plain, false)] {
+                let mut state = SmtpContext::new(service.clone(), ConnectionInfo::default());
+                let mut io: Box<dyn MayBeTls> = Box::new(Dummy);
+                service.prepare_session(&mut io, &mut state).await;
+                let alice = state.session.privacy.address("alice@example.org");
+                assert_eq!(alice.starts_with("anon-"), pseudonym, "{}", alice);
+            }
+        })
+    }
+}
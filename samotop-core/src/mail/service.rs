@@ -2,14 +2,24 @@ use crate::{
     common::*,
     io::{tls::MayBeTls, ConnectionInfo, IoService},
     mail::{
-        AddRecipientResult, DispatchResult, MailDispatch, MailGuard, Recipient, StartMailResult,
+        AddRecipientResult, CheckMailResult, DispatchResult, IdGenerator, MailCheck, MailDispatch,
+        MailGuard, Privacy, Recipient, StartMailResult, UlidGenerator,
+    },
+    smtp::{
+        spans, Drive, Interpret, SessionEnd, SessionLifecycle, SessionService, SmtpContext,
+        SmtpSession, TransactionEnd,
     },
-    smtp::{Drive, Interpret, SessionService, SmtpContext, SmtpSession},
 };
 
 /// A short hand for all the mandatory mail services
-pub trait MailService: SessionService + MailGuard + MailDispatch {}
-impl<T> MailService for T where T: SessionService + MailGuard + MailDispatch {}
+pub trait MailService:
+    SessionService + SessionLifecycle + MailGuard + MailDispatch + MailCheck
+{
+}
+impl<T> MailService for T where
+    T: SessionService + SessionLifecycle + MailGuard + MailDispatch + MailCheck
+{
+}
 
 /// Service implements all the mandatory mail services
 /// + IoService so it can be used with `TcpServer` or `UnixServer`.
@@ -18,10 +28,14 @@ impl<T> MailService for T where T: SessionService + MailGuard + MailDispatch {}
 #[derive(Debug, Clone)]
 pub struct Service {
     session: Arc<dyn SessionService + Sync + Send>,
+    lifecycle: Arc<dyn SessionLifecycle + Sync + Send>,
     guard: Arc<dyn MailGuard + Sync + Send>,
     dispatch: Arc<dyn MailDispatch + Sync + Send>,
+    check: Arc<dyn MailCheck + Sync + Send>,
     driver: Arc<dyn Drive + Sync + Send>,
     interpret: Arc<dyn Interpret + Sync + Send>,
+    ids: Arc<dyn IdGenerator + Sync + Send>,
+    privacy: Arc<Privacy>,
 }
 
 impl Service {
@@ -36,12 +50,39 @@ impl Service {
     {
         Self {
             session: Arc::new(session),
+            lifecycle: Arc::new(Dummy),
             dispatch: Arc::new(dispatch),
             guard: Arc::new(guard),
+            check: Arc::new(Dummy),
             driver: Arc::new(drive),
             interpret: Arc::new(interpret),
+            ids: Arc::new(UlidGenerator::new()),
+            privacy: Arc::new(Privacy::default()),
         }
     }
+    /// Notify the lifecycle of HELO, transaction end and session end, nothing by default
+    pub fn with_lifecycle(
+        mut self,
+        lifecycle: impl SessionLifecycle + Sync + Send + 'static,
+    ) -> Self {
+        self.lifecycle = Arc::new(lifecycle);
+        self
+    }
+    /// Check the mail content after the final dot, all mail is accepted by default
+    pub fn with_check(mut self, check: impl MailCheck + Sync + Send + 'static) -> Self {
+        self.check = Arc::new(check);
+        self
+    }
+    /// Replace the default `UlidGenerator`
+    pub fn with_id_generator(mut self, ids: impl IdGenerator + Sync + Send + 'static) -> Self {
+        self.ids = Arc::new(ids);
+        self
+    }
+    /// Redact the logs of all sessions with the privacy policy, everything is logged as is by default
+    pub fn with_privacy(mut self, privacy: Privacy) -> Self {
+        self.privacy = Arc::new(privacy);
+        self
+    }
 }
 
 impl IoService for Service {
@@ -54,14 +95,33 @@ impl IoService for Service {
         let driver = self.driver.clone();
         let interpret = self.interpret.clone();
 
-        trace!("New peer connection {}", connection);
-        let mut state = SmtpContext::new(service, connection);
+        trace!("New peer connection {}", connection.redacted(&self.privacy));
+        let tls = io.as_ref().map(|io| io.is_encrypted()).unwrap_or_default();
+        let mut state = SmtpContext::new(service, connection.clone());
+        state.session.privacy = self.privacy.clone();
+        let span = spans::session(&mut state, &connection, tls);
 
-        Box::pin(async move {
+        Box::pin(spans::instrument(span, async move {
             // fetch and apply commands
-            driver.drive(&mut io?, &interpret, &mut state).await?;
+            let result = match io {
+                Ok(mut io) => driver
+                    .drive(&mut io, &interpret, &mut state)
+                    .await
+                    .map_err(Error::from),
+                Err(e) => Err(e),
+            };
+            let reason = match result {
+                Ok(()) => state.session.end.take().unwrap_or(SessionEnd::Shutdown),
+                Err(ref e) => SessionEnd::Failed(e.to_string()),
+            };
+            state.end_transaction(TransactionEnd::Aborted).await;
+            state
+                .service()
+                .on_session_end(&mut state.session, reason)
+                .await;
+            result?;
             Ok(())
-        })
+        }))
     }
 }
 
@@ -78,6 +138,16 @@ impl MailDispatch for Service {
     }
 }
 
+impl MailCheck for Service {
+    fn check_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.check.check_mail(session)
+    }
+}
+
 impl MailGuard for Service {
     fn add_recipient<'a, 's, 'f>(
         &'a self,
@@ -100,6 +170,38 @@ impl MailGuard for Service {
     }
 }
 
+impl SessionLifecycle for Service {
+    fn on_helo<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.lifecycle.on_helo(session)
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.lifecycle.on_transaction_end(session, end)
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        self.lifecycle.on_session_end(session, reason)
+    }
+}
+
 impl SessionService for Service {
     fn prepare_session<'a, 'i, 's, 'f>(
         &'a self,
@@ -111,6 +213,8 @@ impl SessionService for Service {
         'i: 'f,
         's: 'f,
     {
+        state.set(self.ids.clone());
+        state.session.privacy = self.privacy.clone();
         self.session.prepare_session(io, state)
     }
 }
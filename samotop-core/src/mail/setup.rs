@@ -1,6 +1,6 @@
 use crate::{
-    mail::{MailDispatch, MailGuard},
-    smtp::{Interpret, SessionService},
+    mail::{ConnectionGuard, IdGenerator, MailCheck, MailDispatch, MailGuard, Privacy},
+    smtp::{Interpret, SessionLifecycle, SessionService},
 };
 
 /**
@@ -39,6 +39,22 @@ pub trait AcceptsSessionService {
         T: SessionService + Send + Sync + 'static,
         F: FnOnce(Box<dyn SessionService + Send + Sync>) -> T;
 }
+pub trait AcceptsConnectionGuard {
+    fn add_first_connection_guard<T: ConnectionGuard + Send + Sync + 'static>(&mut self, item: T);
+    fn add_last_connection_guard<T: ConnectionGuard + Send + Sync + 'static>(&mut self, item: T);
+    fn wrap_connection_guards<T, F>(&mut self, wrap: F)
+    where
+        T: ConnectionGuard + Send + Sync + 'static,
+        F: FnOnce(Box<dyn ConnectionGuard + Send + Sync>) -> T;
+}
+pub trait AcceptsSessionLifecycle {
+    fn add_first_lifecycle<T: SessionLifecycle + Send + Sync + 'static>(&mut self, item: T);
+    fn add_last_lifecycle<T: SessionLifecycle + Send + Sync + 'static>(&mut self, item: T);
+    fn wrap_lifecycle<T, F>(&mut self, wrap: F)
+    where
+        T: SessionLifecycle + Send + Sync + 'static,
+        F: FnOnce(Box<dyn SessionLifecycle + Send + Sync>) -> T;
+}
 pub trait AcceptsInterpretter {
     fn add_first_interpretter<T: Interpret + Send + Sync + 'static>(&mut self, item: T);
     fn add_last_interpretter<T: Interpret + Send + Sync + 'static>(&mut self, item: T);
@@ -63,6 +79,22 @@ pub trait AcceptsDispatch {
         T: MailDispatch + Send + Sync + 'static,
         F: FnOnce(Box<dyn MailDispatch + Send + Sync>) -> T;
 }
+pub trait AcceptsCheck {
+    fn add_first_check<T: MailCheck + Send + Sync + 'static>(&mut self, item: T);
+    fn add_last_check<T: MailCheck + Send + Sync + 'static>(&mut self, item: T);
+    fn wrap_checks<T, F>(&mut self, wrap: F)
+    where
+        T: MailCheck + Send + Sync + 'static,
+        F: FnOnce(Box<dyn MailCheck + Send + Sync>) -> T;
+}
+pub trait AcceptsIdGenerator {
+    /// Replace the ID generator
+    fn set_id_generator<T: IdGenerator + Send + Sync + 'static>(&mut self, item: T);
+}
+pub trait AcceptsPrivacy {
+    /// Replace the privacy policy
+    fn set_privacy(&mut self, privacy: Privacy);
+}
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,430 @@
+use crate::{
+    common::{io::*, *},
+    io::ConnectionInfo,
+    mail::*,
+    smtp::{
+        command::SmtpMail, spans, SessionEnd, SessionLifecycle, SmtpContext, SmtpPath,
+        TransactionEnd,
+    },
+};
+
+/// The mail envelope of a submitted mail - what the client would say in HELO, MAIL and RCPT commands
+#[derive(Debug, Clone)]
+pub struct MailEnvelope {
+    /// The name the client would introduce itself with
+    pub peer_name: String,
+    /// The sender, MAIL FROM:
+    pub mail: SmtpMail,
+    /// The recipients, RCPT TO:
+    pub rcpts: Vec<SmtpPath>,
+}
+
+impl MailEnvelope {
+    pub fn new(peer_name: impl ToString, sender: SmtpPath) -> Self {
+        Self {
+            peer_name: peer_name.to_string(),
+            mail: SmtpMail::Mail(sender, vec![]),
+            rcpts: vec![],
+        }
+    }
+    /// Add another recipient
+    pub fn to(mut self, rcpt: SmtpPath) -> Self {
+        self.rcpts.push(rcpt);
+        self
+    }
+}
+
+/// The outcome of `Service::submit()`
+#[derive(Debug, Clone)]
+pub struct Submission {
+    /// The mail transaction ID, empty if the mail has been refused from the start
+    pub id: String,
+    /// Outcome per envelope recipient, in the order of the envelope
+    pub rcpts: Vec<(SmtpPath, RecipientOutcome)>,
+}
+
+impl Submission {
+    /// The mail has been queued for at least one recipient
+    pub fn is_queued(&self) -> bool {
+        self.rcpts.iter().any(|(_, outcome)| outcome.is_queued())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RecipientOutcome {
+    /// The mail has been queued for the recipient
+    Queued,
+    /// The mail has been queued for the recipient, forwarding to another address
+    QueuedWithNewPath(SmtpPath),
+    /// The recipient has been refused by the mail guard
+    Refused(AddRecipientFailure, String),
+    /// The whole mail failed
+    Failed(SubmitFailure),
+}
+
+impl RecipientOutcome {
+    pub fn is_queued(&self) -> bool {
+        matches!(
+            self,
+            RecipientOutcome::Queued | RecipientOutcome::QueuedWithNewPath(_)
+        )
+    }
+}
+
+/// Why a submitted mail failed as a whole
+#[derive(Debug, Clone)]
+pub enum SubmitFailure {
+    /// The mail guard refused the sender
+    MailRefused(StartMailFailure, String),
+    /// All the recipients have been refused, there is nothing to do
+    NoRecipients,
+    /// The mail dispatch failed to take the mail
+    DispatchFailed(DispatchError),
+    /// The mail check refused the content
+    CheckFailed(CheckMailFailure, String),
+    /// The mail body could not be read or written
+    IoFailed(String),
+}
+
+impl Service {
+    /**
+    Push a mail through the mail pipeline without speaking SMTP.
+
+    The mail goes through the same guards, dispatch and checks as mail received over SMTP,
+    and the session lifecycle is notified as if it was a one-mail session.
+    Connection guards and session services are not involved as there is no SMTP session.
+    The body is the raw mail data, no SMTP dot-stuffing.
+
+    ```
+    # use samotop_core::mail::*;
+    # use samotop_core::smtp::SmtpPath;
+    # use samotop_core::io::ConnectionInfo;
+    async fn notify_postmaster(service: &Service) -> bool {
+        let envelope = MailEnvelope::new("gateway.example.org", SmtpPath::Null)
+            .to(SmtpPath::Postmaster);
+        let body = &b"Subject: hi\r\n\r\nhello\r\n"[..];
+        service
+            .submit(envelope, body, ConnectionInfo::default())
+            .await
+            .is_queued()
+    }
+    ```
+    */
+    pub async fn submit<R>(
+        &self,
+        envelope: MailEnvelope,
+        body: R,
+        connection: ConnectionInfo,
+    ) -> Submission
+    where
+        R: Read + Unpin,
+    {
+        let mut state = SmtpContext::new(self.clone(), connection.clone());
+        let span = spans::session(&mut state, &connection, false);
+        spans::instrument(span, async move {
+            let submission = submit_mail(&mut state, envelope, body).await;
+            state
+                .service()
+                .on_session_end(&mut state.session, SessionEnd::Quit)
+                .await;
+            submission
+        })
+        .await
+    }
+}
+
+async fn submit_mail<R>(state: &mut SmtpContext, envelope: MailEnvelope, body: R) -> Submission
+where
+    R: Read + Unpin,
+{
+    let MailEnvelope {
+        peer_name,
+        mail,
+        rcpts,
+    } = envelope;
+    let mut submission = Submission {
+        id: String::new(),
+        rcpts: rcpts
+            .into_iter()
+            .map(|rcpt| (rcpt, RecipientOutcome::Queued))
+            .collect(),
+    };
+    let fail = |submission: &mut Submission, failure: SubmitFailure| {
+        for (_, outcome) in submission.rcpts.iter_mut() {
+            if outcome.is_queued() {
+                *outcome = RecipientOutcome::Failed(failure.clone());
+            }
+        }
+    };
+
+    state.session.peer_name = Some(peer_name);
+    state.session.transaction.mail = Some(mail);
+    spans::start_transaction(state);
+
+    let span = spans::transaction(state);
+    let service = state.service();
+    let start = service.start_mail(&mut state.session);
+    if let StartMailResult::Failed(failure, description) = spans::instrument(span, start).await {
+        fail(
+            &mut submission,
+            SubmitFailure::MailRefused(failure, description),
+        );
+        state.end_transaction(TransactionEnd::Failed).await;
+        return submission;
+    }
+    if state.session.transaction.id.is_empty() {
+        let ids = state.ids();
+        state.session.transaction.id = format!("{}@{}", ids.generate(), state.session.service_name);
+    }
+    submission.id = state.session.transaction.id.clone();
+
+    for (path, outcome) in submission.rcpts.iter_mut() {
+        match state
+            .service()
+            .add_recipient(&mut state.session, Recipient::new(path.clone()))
+            .await
+        {
+            AddRecipientResult::Inconclusive(rcpt) => state.session.transaction.rcpts.push(rcpt),
+            AddRecipientResult::Accepted => {}
+            AddRecipientResult::AcceptedWithNewPath(path) => {
+                *outcome = RecipientOutcome::QueuedWithNewPath(path)
+            }
+            AddRecipientResult::Failed(failure, description) => {
+                *outcome = RecipientOutcome::Refused(failure, description)
+            }
+        }
+    }
+    if !submission.is_queued() {
+        fail(&mut submission, SubmitFailure::NoRecipients);
+        state.end_transaction(TransactionEnd::Failed).await;
+        return submission;
+    }
+
+    spans::record_transaction(state);
+
+    let span = spans::transaction(state);
+    let end = match spans::instrument(span, dispatch_mail(state, body)).await {
+        Ok(()) => TransactionEnd::Queued,
+        Err(failure) => {
+            warn!(
+                "Submitted mail {} failed: {:?}",
+                state.session.transaction.id, failure
+            );
+            fail(&mut submission, failure);
+            TransactionEnd::Failed
+        }
+    };
+    state.end_transaction(end).await;
+    submission
+}
+
+async fn dispatch_mail<R>(
+    state: &mut SmtpContext,
+    mut body: R,
+) -> std::result::Result<(), SubmitFailure>
+where
+    R: Read + Unpin,
+{
+    state
+        .service()
+        .open_mail_body(&mut state.session)
+        .await
+        .map_err(SubmitFailure::DispatchFailed)?;
+    let io_failed = |e: io::Error| SubmitFailure::IoFailed(e.to_string());
+    state
+        .session
+        .transaction
+        .write_extra_headers()
+        .await
+        .map_err(io_failed)?;
+    let mut sink = state
+        .session
+        .transaction
+        .sink
+        .take()
+        .ok_or(SubmitFailure::DispatchFailed(DispatchError::Temporary))?;
+
+    let mut buf = vec![0u8; crate::smtp::InputBuffer::BLOCK_SIZE];
+    loop {
+        let len = poll_fn(|cx| Pin::new(&mut body).poll_read(cx, &mut buf[..]))
+            .await
+            .map_err(io_failed)?;
+        if len == 0 {
+            break;
+        }
+        state.session.stats.bytes_received += len;
+        let mut data = &buf[..len];
+        while !data.is_empty() {
+            match poll_fn(|cx| sink.as_mut().poll_write(cx, data))
+                .await
+                .map_err(io_failed)?
+            {
+                0 => return Err(io_failed(io::ErrorKind::WriteZero.into())),
+                written => data = &data[written..],
+            }
+        }
+    }
+    poll_fn(|cx| sink.as_mut().poll_flush(cx))
+        .await
+        .map_err(io_failed)?;
+
+    if let CheckMailResult::Failed(failure, description) =
+        state.service().check_mail(&mut state.session).await
+    {
+        // dropping the sink without closing it, the mail shall not be dispatched
+        return Err(SubmitFailure::CheckFailed(failure, description));
+    }
+
+    match poll_fn(|cx| sink.as_mut().poll_close(cx)).await {
+        Err(e) if e.kind() != io::ErrorKind::NotConnected => Err(io_failed(e)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, feature = "driver"))]
+mod tests {
+    use super::*;
+    use crate::smtp::SmtpSession;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default, Clone)]
+    struct Collect(Arc<Mutex<Vec<u8>>>);
+
+    impl MailDispatch for Collect {
+        fn open_mail_body<'a, 's, 'f>(
+            &'a self,
+            session: &'s mut SmtpSession,
+        ) -> S1Fut<'f, DispatchResult>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            session.transaction.sink = Some(Box::pin(self.clone()));
+            Box::pin(ready(Ok(())))
+        }
+    }
+
+    impl Write for Collect {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.0.lock().expect("lock").extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<T: AcceptsDispatch> MailSetup<T> for Collect {
+        fn setup(self, config: &mut T) {
+            config.add_last_dispatch(self)
+        }
+    }
+
+    /// Refuses postmaster
+    #[derive(Debug)]
+    struct NoPostmaster;
+
+    impl MailGuard for NoPostmaster {
+        fn add_recipient<'a, 's, 'f>(
+            &'a self,
+            _session: &'s mut SmtpSession,
+            rcpt: Recipient,
+        ) -> S2Fut<'f, AddRecipientResult>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            Box::pin(ready(if rcpt.address == SmtpPath::Postmaster {
+                AddRecipientResult::Failed(
+                    AddRecipientFailure::RejectedPermanently,
+                    "no postmaster".into(),
+                )
+            } else {
+                AddRecipientResult::Inconclusive(rcpt)
+            }))
+        }
+        fn start_mail<'a, 's, 'f>(
+            &'a self,
+            _session: &'s mut SmtpSession,
+        ) -> S2Fut<'f, StartMailResult>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            Box::pin(ready(StartMailResult::Accepted))
+        }
+    }
+
+    impl<T: AcceptsGuard> MailSetup<T> for NoPostmaster {
+        fn setup(self, config: &mut T) {
+            config.add_last_guard(self)
+        }
+    }
+
+    fn rcpt(name: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.into(),
+            host: crate::smtp::SmtpHost::Domain("example.org".into()),
+            relays: vec![],
+        }
+    }
+
+    #[test]
+    fn submit_writes_body() {
+        let collect = Collect::default();
+        let service = (Builder + collect.clone()).build();
+        let envelope = MailEnvelope::new("me", SmtpPath::Null).to(rcpt("alice"));
+        let res = async_std::task::block_on(service.submit(
+            envelope,
+            &b"Subject: hi\r\n\r\n.hello\r\n"[..],
+            ConnectionInfo::default(),
+        ));
+        assert!(!res.id.is_empty());
+        assert!(matches!(res.rcpts[0].1, RecipientOutcome::Queued));
+        assert_eq!(
+            collect.0.lock().expect("lock").as_slice(),
+            b"Subject: hi\r\n\r\n.hello\r\n"
+        );
+    }
+
+    #[test]
+    fn submit_reports_refused_recipients() {
+        let service = (Builder + NoPostmaster + Collect::default()).build();
+        let envelope = MailEnvelope::new("me", SmtpPath::Null)
+            .to(SmtpPath::Postmaster)
+            .to(rcpt("bob"));
+        let res = async_std::task::block_on(service.submit(
+            envelope,
+            &b"hello\r\n"[..],
+            ConnectionInfo::default(),
+        ));
+        assert!(matches!(
+            res.rcpts[0].1,
+            RecipientOutcome::Refused(AddRecipientFailure::RejectedPermanently, _)
+        ));
+        assert!(matches!(res.rcpts[1].1, RecipientOutcome::Queued));
+    }
+
+    #[test]
+    fn submit_fails_without_dispatch() {
+        let service = (Builder + NoPostmaster).build();
+        let envelope = MailEnvelope::new("me", SmtpPath::Null).to(rcpt("bob"));
+        let res = async_std::task::block_on(service.submit(
+            envelope,
+            &b"hello\r\n"[..],
+            ConnectionInfo::default(),
+        ));
+        assert!(!res.is_queued());
+        assert!(matches!(
+            res.rcpts[0].1,
+            RecipientOutcome::Failed(SubmitFailure::DispatchFailed(_))
+        ));
+    }
+}
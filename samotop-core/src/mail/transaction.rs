@@ -1,6 +1,10 @@
 use crate::common::{io::Write, *};
 use crate::mail::Recipient;
 use crate::smtp::*;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
 
 /// Mail envelope before sending mail data
 #[derive(Default)]
@@ -15,6 +19,9 @@ pub struct Transaction {
     pub extra_headers: String,
     /// Write sink to write the mail into
     pub sink: Option<Pin<Box<dyn MailDataSink>>>,
+    /// Implementation-specific value store scoped to this mail transaction.
+    /// It is dropped together with the transaction on RSET, MAIL, HELO and after DATA.
+    store: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
 }
 
 impl Transaction {
@@ -26,6 +33,7 @@ impl Transaction {
         self.mail = None;
         self.rcpts = vec![];
         self.extra_headers = String::new();
+        self.store.clear();
     }
     pub fn is_empty(&self) -> bool {
         let Transaction {
@@ -34,12 +42,65 @@ impl Transaction {
             ref rcpts,
             ref extra_headers,
             ref sink,
+            ref store,
         } = self;
         id.is_empty()
             && mail.is_none()
             && rcpts.is_empty()
             && extra_headers.is_empty()
             && sink.is_none()
+            && store.is_empty()
+    }
+    /// The stored value of the type, if any
+    pub fn get<T: Sync + Send + 'static>(&self) -> Option<&T> {
+        self.store
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+    /// The stored value of the type for modification, if any
+    pub fn get_mut<T: Sync + Send + 'static>(&mut self) -> Option<&mut T> {
+        self.store
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_mut())
+    }
+    /// The stored value of the type, inserting the result of `insert` if missing
+    pub fn get_or_insert<T: Sync + Send + 'static, F>(&mut self, insert: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        let id = TypeId::of::<T>();
+        self.store
+            .entry(id)
+            .or_insert_with(|| Box::new(insert()))
+            .downcast_mut::<T>()
+            .expect("stored type must match")
+    }
+    /// Store the value, replacing any previous value of the type
+    pub fn set<T: Sync + Send + 'static>(&mut self, value: T) {
+        let id = TypeId::of::<T>();
+        self.store.insert(id, Box::new(value));
+    }
+    /// Write the extra headers into the sink, ahead of the mail data
+    pub async fn write_extra_headers(&mut self) -> std::io::Result<()> {
+        let sink = match self.sink.as_mut() {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+        let mut headers = self.extra_headers.as_bytes();
+        while !headers.is_empty() {
+            match poll_fn(|cx| sink.as_mut().poll_write(cx, headers)).await? {
+                0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                written => headers = &headers[written..],
+            }
+        }
+        Ok(())
+    }
+    /// Remove the stored value of the type and return it
+    pub fn take<T: Sync + Send + 'static>(&mut self) -> Option<T> {
+        self.store
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.downcast().ok())
+            .map(|v| *v)
     }
 }
 
@@ -51,6 +112,7 @@ impl fmt::Debug for Transaction {
             ref rcpts,
             ref extra_headers,
             sink: _sink,
+            ref store,
         } = self;
         f.debug_struct("Transaction")
             .field("id", id)
@@ -58,9 +120,105 @@ impl fmt::Debug for Transaction {
             .field("rcpts", rcpts)
             .field("extra_headers", extra_headers)
             .field("sink", &"*")
+            .field("store", &store.len())
             .finish()
     }
 }
 
+/// Results of sender authentication checks (SPF, DKIM...) of the current transaction.
+///
+/// Checks add their results to the transaction store so that other services,
+/// i.e. the `AuditLog`, can report them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AuthenticationResults {
+    results: Vec<(String, String)>,
+}
+
+impl AuthenticationResults {
+    /// Record the result of a check method such as "spf" or "dkim", replacing any previous one
+    pub fn add(&mut self, method: impl Into<String>, result: impl Into<String>) {
+        let method = method.into();
+        let result = result.into();
+        match self.results.iter_mut().find(|(m, _)| *m == method) {
+            Some(entry) => entry.1 = result,
+            None => self.results.push((method, result)),
+        }
+    }
+    /// The result of the given method if it was checked
+    pub fn get(&self, method: &str) -> Option<&str> {
+        self.results
+            .iter()
+            .find(|(m, _)| m == method)
+            .map(|(_, result)| result.as_str())
+    }
+    /// All method and result pairs in the order they were first added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.results.iter().map(|(m, r)| (m.as_str(), r.as_str()))
+    }
+    /// The Authentication-Results header (RFC 8601) of the given
+    /// authentication service, i.e. `mx.example.org`, with CRLF
+    pub fn header(&self, authserv_id: &str) -> String {
+        let mut header = format!("Authentication-Results: {}", authserv_id);
+        if self.results.is_empty() {
+            header.push_str("; none");
+        }
+        for (method, result) in self.iter() {
+            header.push_str(format!(";\r\n\t{}={}", method, result).as_str());
+        }
+        header.push_str("\r\n");
+        header
+    }
+}
+
 pub trait MailDataSink: Write + Send + Sync + 'static {}
 impl<T> MailDataSink for T where T: Write + Send + Sync + 'static {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Verdict(&'static str);
+
+    #[test]
+    fn store_keeps_value() {
+        let mut sut = Transaction::default();
+        sut.set(Verdict("clean"));
+        assert_eq!(sut.get::<Verdict>(), Some(&Verdict("clean")));
+        assert!(!sut.is_empty());
+    }
+
+    #[test]
+    fn store_gets_reset() {
+        let mut sut = Transaction::default();
+        sut.get_or_insert(|| Verdict("virus"));
+        sut.reset();
+        assert_eq!(sut.get::<Verdict>(), None);
+        assert!(sut.is_empty());
+    }
+
+    #[test]
+    fn authentication_results_header() {
+        let mut sut = AuthenticationResults::default();
+        assert_eq!(
+            sut.header("mx.example.org"),
+            "Authentication-Results: mx.example.org; none\r\n"
+        );
+        sut.add("spf", "pass smtp.mailfrom=example.com");
+        sut.add("dkim", "pass header.d=example.com");
+        assert_eq!(
+            sut.header("mx.example.org"),
+            "Authentication-Results: mx.example.org;\r\n\
+            \tspf=pass smtp.mailfrom=example.com;\r\n\
+            \tdkim=pass header.d=example.com\r\n"
+        );
+    }
+
+    #[test]
+    fn store_value_can_be_taken() {
+        let mut sut = Transaction::default();
+        sut.set(Verdict("spam"));
+        assert_eq!(sut.take::<Verdict>(), Some(Verdict("spam")));
+        assert_eq!(sut.take::<Verdict>(), None);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    common::*,
+    io::{
+        tls::{MayBeTls, TlsProvider},
+        ConnectionInfo,
+    },
+    mail::{AcceptsSessionService, MailService, MailSetup},
+    smtp::{SessionService, SmtpContext},
+};
+use std::net::{IpAddr, SocketAddr};
+
+/**
+MailSetup that picks the service name, TLS identity and optionally
+a whole different mail pipeline by the local address of the session.
+
+Serving several brands on several IPs, the banner and `250 <name> greets`
+should match each IP's reverse DNS. The first matching `VirtualHost` wins,
+sessions not matching any host are left as they are.
+
+The TLS provider of the host replaces the default one given to `EsmtpStartTls`,
+so STARTTLS must be set up as well for the host certificate to be offered.
+The host is picked by the local address when the session starts, long before
+the client sends any SNI with STARTTLS, so the service name and the branch
+can never follow SNI. Only the certificate can, pick it by SNI in the TLS provider
+(i.e. `RustlsProvider::from_pem_files_with_sni`) if you need to.
+
+```
+# use samotop_core::mail::*;
+# use samotop_core::io::tls::NoTls;
+# #[cfg(feature = "driver")] {
+let brand_b = Builder + Name::new("ignored") + NullDispatch;
+
+let mail_svc = Builder
+    + Name::new("mx.example.org")
+    + VirtualHosts::default()
+        .host(VirtualHost::new("mx.brand-a.example").on_address("192.0.2.10").with_tls(NoTls))
+        .host(
+            VirtualHost::new("mx.brand-b.example")
+                .on_address("192.0.2.20")
+                .on_address("[2001:db8::20]:25")
+                .on_listener("brand-b-submission")
+                .with_branch(brand_b.build()),
+        );
+# }
+```
+*/
+#[derive(Debug, Default)]
+pub struct VirtualHosts {
+    hosts: Vec<VirtualHost>,
+}
+
+/// One of the `VirtualHosts`
+#[derive(Debug)]
+pub struct VirtualHost {
+    name: String,
+    addresses: Vec<String>,
+    listeners: Vec<String>,
+    tls: Option<Box<dyn TlsProvider + Send + Sync>>,
+    branch: Option<Arc<dyn MailService + Send + Sync>>,
+}
+
+impl VirtualHosts {
+    /// Add a host, hosts are matched in the order they were added
+    pub fn host(mut self, host: VirtualHost) -> Self {
+        self.hosts.push(host);
+        self
+    }
+    /// Find the host serving the given connection
+    pub fn find(&self, connection: &ConnectionInfo) -> Option<&VirtualHost> {
+        self.hosts.iter().find(|host| host.matches(connection))
+    }
+}
+
+impl VirtualHost {
+    /// The host with given service name
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            addresses: vec![],
+            listeners: vec![],
+            tls: None,
+            branch: None,
+        }
+    }
+    /// Serve sessions on the given local IP (any port) or socket address (`ip:port`, unix socket path)
+    pub fn on_address(mut self, address: impl ToString) -> Self {
+        self.addresses.push(address.to_string());
+        self
+    }
+    /// Serve sessions accepted by the named listener, see `Server`
+    pub fn on_listener(mut self, listener: impl ToString) -> Self {
+        self.listeners.push(listener.to_string());
+        self
+    }
+    /// Offer this TLS identity on STARTTLS
+    pub fn with_tls(mut self, provider: impl TlsProvider + Send + Sync + 'static) -> Self {
+        self.tls = Some(Box::new(provider));
+        self
+    }
+    /// Run the session with a different mail service (guards, dispatch...).
+    ///
+    /// The protocol (driver and interpretter) stays the same,
+    /// session services of the branch are applied after the host settings.
+    pub fn with_branch(mut self, service: impl MailService + Send + Sync + 'static) -> Self {
+        self.branch = Some(Arc::new(service));
+        self
+    }
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+    /// Does the host serve the given connection?
+    pub fn matches(&self, connection: &ConnectionInfo) -> bool {
+        if !connection.listener.is_empty() && self.listeners.contains(&connection.listener) {
+            return true;
+        }
+        let local_ip = connection
+            .local_addr
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .ok();
+        self.addresses.iter().any(|address| {
+            *address == connection.local_addr
+                || matches!(
+                    (address.parse::<IpAddr>(), local_ip),
+                    (Ok(ip), Some(local)) if ip == local
+                )
+                || matches!(
+                    (address.parse::<SocketAddr>(), connection.local_addr.parse::<SocketAddr>()),
+                    (Ok(a), Ok(b)) if a == b
+                )
+        })
+    }
+}
+
+impl<T: AcceptsSessionService> MailSetup<T> for VirtualHosts {
+    fn setup(self, config: &mut T) {
+        config.wrap_session_service(|others| VirtualHostsService {
+            hosts: self,
+            others,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct VirtualHostsService {
+    hosts: VirtualHosts,
+    others: Box<dyn SessionService + Sync + Send>,
+}
+
+impl SessionService for VirtualHostsService {
+    fn prepare_session<'a, 'i, 's, 'f>(
+        &'a self,
+        io: &'i mut Box<dyn MayBeTls>,
+        state: &'s mut SmtpContext,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        'i: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            self.others.prepare_session(io, state).await;
+
+            let host = match self.hosts.find(&state.session.connection) {
+                None => return,
+                Some(host) => host,
+            };
+            debug!(
+                "{} served by virtual host {}",
+                state.session.connection.redacted(&state.session.privacy),
+                host.name
+            );
+
+            state.session.service_name = host.name.clone();
+
+            if let Some(ref tls) = host.tls {
+                if !io.is_encrypted() {
+                    if let Some(upgrade) = tls.get_tls_upgrade() {
+                        io.enable_encryption(upgrade, host.name.clone());
+                    }
+                }
+            }
+
+            if let Some(ref branch) = host.branch {
+                state.set(branch.clone());
+                branch.prepare_session(io, state).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tls::{Impossible, TlsCapable, TlsUpgrade};
+    use crate::mail::SessionLogger;
+
+    fn connection(local_addr: &str, listener: &str) -> ConnectionInfo {
+        ConnectionInfo::new(local_addr.to_owned(), "198.51.100.1:12345".to_owned())
+            .with_listener(listener)
+    }
+
+    #[test]
+    fn matches_address_and_listener() {
+        let sut = VirtualHosts::default()
+            .host(VirtualHost::new("a").on_address("192.0.2.10"))
+            .host(VirtualHost::new("b").on_address("[2001:db8::20]:25"))
+            .host(VirtualHost::new("c").on_listener("lmtp"))
+            .host(VirtualHost::new("d").on_address("/run/samotop.sock"));
+        let found = |local: &str, listener: &str| {
+            sut.find(&connection(local, listener))
+                .map(|host| host.name().to_owned())
+        };
+        assert_eq!(found("192.0.2.10:25", ""), Some("a".to_owned()));
+        assert_eq!(found("192.0.2.10:587", "lmtp"), Some("a".to_owned()));
+        assert_eq!(found("[2001:db8::20]:25", ""), Some("b".to_owned()));
+        assert_eq!(found("[2001:db8::20]:587", ""), None);
+        assert_eq!(found("192.0.2.11:25", "lmtp"), Some("c".to_owned()));
+        assert_eq!(found("/run/samotop.sock", ""), Some("d".to_owned()));
+        assert_eq!(found("192.0.2.11:25", ""), None);
+    }
+
+    #[test]
+    fn sets_name_and_branch() {
+        async_std::task::block_on(async {
+            let sut = VirtualHostsService {
+                hosts: VirtualHosts::default()
+                    .host(VirtualHost::new("a").on_address("192.0.2.10"))
+                    .host(
+                        VirtualHost::new("b")
+                            .on_address("192.0.2.20")
+                            .with_branch(SessionLogger),
+                    ),
+                others: Box::new(Dummy),
+            };
+
+            let mut io: Box<dyn MayBeTls> = Box::new(Dummy);
+            let mut state = SmtpContext::new(Dummy, connection("192.0.2.10:25", ""));
+            sut.prepare_session(&mut io, &mut state).await;
+            assert_eq!(state.session.service_name, "a");
+            assert_eq!(format!("{:?}", state.service()), "Dummy");
+
+            let mut state = SmtpContext::new(Dummy, connection("192.0.2.20:25", ""));
+            sut.prepare_session(&mut io, &mut state).await;
+            assert_eq!(state.session.service_name, "b");
+            assert_eq!(format!("{:?}", state.service()), "SessionLogger");
+        })
+    }
+
+    #[test]
+    fn enables_host_tls() {
+        #[derive(Debug)]
+        struct TestTls;
+        impl TlsProvider for TestTls {
+            fn get_tls_upgrade(&self) -> Option<Box<dyn TlsUpgrade>> {
+                Some(Box::new(Impossible {}))
+            }
+        }
+
+        async_std::task::block_on(async {
+            let sut = VirtualHostsService {
+                hosts: VirtualHosts::default().host(
+                    VirtualHost::new("a")
+                        .on_address("192.0.2.10")
+                        .with_tls(TestTls),
+                ),
+                others: Box::new(Dummy),
+            };
+
+            let plain = || -> Box<dyn MayBeTls> {
+                Box::new(TlsCapable::plaintext(Box::new(async_std::io::Cursor::new(
+                    vec![],
+                ))))
+            };
+
+            let mut io = plain();
+            let mut state = SmtpContext::new(Dummy, connection("192.0.2.11:25", ""));
+            sut.prepare_session(&mut io, &mut state).await;
+            assert!(!io.can_encrypt());
+
+            let mut io = plain();
+            let mut state = SmtpContext::new(Dummy, connection("192.0.2.10:25", ""));
+            sut.prepare_session(&mut io, &mut state).await;
+            assert!(io.can_encrypt());
+        })
+    }
+}
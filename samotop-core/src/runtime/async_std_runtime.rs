@@ -0,0 +1,105 @@
+use super::{Listener, Runtime};
+use crate::{
+    common::*,
+    io::{tls::Io, ConnectionInfo},
+};
+use async_std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::{net::SocketAddr, time::Duration};
+
+#[cfg(unix)]
+use async_std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Runs samotop on async-std
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsyncStdRuntime;
+
+impl Runtime for AsyncStdRuntime {
+    fn spawn(&self, task: S1Fut<'static, ()>) {
+        async_std::task::spawn(task);
+    }
+    fn sleep(&self, duration: Duration) -> S2Fut<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+    fn resolve(&self, address: String) -> S2Fut<'static, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok(address.to_socket_addrs().await?.collect()) })
+    }
+    fn listen_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener: Box<dyn Listener> = Box::new(TcpListener::bind(address).await?);
+            Ok(listener)
+        })
+    }
+    fn connect_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        Box::pin(async move {
+            let stream: Box<dyn Io> = Box::new(TcpStream::connect(address).await?);
+            Ok(stream)
+        })
+    }
+    #[cfg(unix)]
+    fn listen_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener: Box<dyn Listener> = Box::new(UnixListener::bind(path).await?);
+            Ok(listener)
+        })
+    }
+    #[cfg(unix)]
+    fn connect_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        Box::pin(async move {
+            let stream: Box<dyn Io> = Box::new(UnixStream::connect(path).await?);
+            Ok(stream)
+        })
+    }
+}
+
+impl Listener for TcpListener {
+    fn local_addr(&self) -> String {
+        TcpListener::local_addr(self)
+            .map(|a| a.to_string())
+            .unwrap_or_default()
+    }
+    fn accept<'a, 'f>(&'a self) -> S2Fut<'f, io::Result<(Box<dyn Io>, ConnectionInfo)>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let (stream, peer) = TcpListener::accept(self).await?;
+            let conn = ConnectionInfo::new(
+                stream
+                    .local_addr()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+                peer.to_string(),
+            );
+            let stream: Box<dyn Io> = Box::new(stream);
+            Ok((stream, conn))
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    fn local_addr(&self) -> String {
+        UnixListener::local_addr(self)
+            .ok()
+            .and_then(|s| s.as_pathname().map(|p| p.display().to_string()))
+            .unwrap_or_default()
+    }
+    fn accept<'a, 'f>(&'a self) -> S2Fut<'f, io::Result<(Box<dyn Io>, ConnectionInfo)>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let (stream, _) = UnixListener::accept(self).await?;
+            let path = |addr: io::Result<async_std::os::unix::net::SocketAddr>| {
+                addr.ok()
+                    .and_then(|s| s.as_pathname().map(|p| p.display().to_string()))
+                    .unwrap_or_default()
+            };
+            let conn = ConnectionInfo::new(path(stream.local_addr()), path(stream.peer_addr()));
+            let stream: Box<dyn Io> = Box::new(stream);
+            Ok((stream, conn))
+        })
+    }
+}
@@ -0,0 +1,204 @@
+/*!
+Abstraction of the async runtime - background tasks, timers and networking.
+
+The SMTP session itself only needs the `futures-io` traits, so it runs on any executor.
+Whatever needs to spawn tasks, wait for some time or open sockets
+(servers, `Prudence` timeouts, delivery connectors) goes through a `Runtime`.
+
+The default runtime is selected by cargo features:
+* `runtime-async-std` gives `AsyncStdRuntime`,
+* `runtime-tokio` gives `TokioRuntime`. It wins if both are enabled as it has to be asked for explicitly.
+
+Without any of those, the default runtime is `Dummy`, which cannot do much.
+You can still implement a `Runtime` for your executor of choice and pass it on explicitly.
+The `prudence` feature requires one of the runtime features as its timers would never fire otherwise.
+*/
+
+#[cfg(feature = "runtime-async-std")]
+mod async_std_runtime;
+#[cfg(feature = "runtime-tokio")]
+mod tokio_runtime;
+
+#[cfg(feature = "runtime-async-std")]
+pub use self::async_std_runtime::*;
+#[cfg(feature = "runtime-tokio")]
+pub use self::tokio_runtime::*;
+
+use crate::{
+    common::*,
+    io::{tls::Io, ConnectionInfo},
+};
+use std::{net::SocketAddr, ops::Deref, time::Duration};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+pub trait Runtime: fmt::Debug {
+    /// Run the task in the background
+    fn spawn(&self, task: S1Fut<'static, ()>);
+    /// Complete after the given duration
+    fn sleep(&self, duration: Duration) -> S2Fut<'static, ()>;
+    /// Resolve an address such as "localhost:25" to socket addresses
+    fn resolve(&self, address: String) -> S2Fut<'static, io::Result<Vec<SocketAddr>>>;
+    /// Listen for TCP connections on the given address
+    fn listen_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Listener>>>;
+    /// Connect to the given TCP address
+    fn connect_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Io>>>;
+    /// Listen for connections on the given Unix socket
+    #[cfg(unix)]
+    fn listen_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Listener>>>;
+    /// Connect to the given Unix socket
+    #[cfg(unix)]
+    fn connect_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Io>>>;
+}
+
+/// Accepts incoming connections, produced by a `Runtime`
+pub trait Listener: fmt::Debug + Send + Sync {
+    /// Describe the local address this listener is bound to
+    fn local_addr(&self) -> String;
+    /// Wait for the next incoming connection
+    fn accept<'a, 'f>(&'a self) -> S2Fut<'f, io::Result<(Box<dyn Io>, ConnectionInfo)>>
+    where
+        'a: 'f;
+}
+
+/// Get the runtime selected by cargo features.
+pub fn default_runtime() -> Arc<dyn Runtime + Send + Sync> {
+    #[cfg(feature = "runtime-tokio")]
+    let runtime = Arc::new(TokioRuntime);
+    #[cfg(all(feature = "runtime-async-std", not(feature = "runtime-tokio")))]
+    let runtime = Arc::new(AsyncStdRuntime);
+    #[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+    let runtime = Arc::new(Dummy);
+    runtime
+}
+
+/// Complete with `None` if the future does not complete within the given duration
+pub async fn timeout<F: Future>(
+    runtime: &(dyn Runtime + Send + Sync),
+    duration: Duration,
+    fut: F,
+) -> Option<F::Output> {
+    let mut fut = Box::pin(fut);
+    let mut expired = runtime.sleep(duration);
+    poll_fn(move |cx| {
+        if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+            Poll::Ready(Some(output))
+        } else {
+            expired.as_mut().poll(cx).map(|()| None)
+        }
+    })
+    .await
+}
+
+impl<S: Runtime + ?Sized, T: Deref<Target = S>> Runtime for T
+where
+    T: fmt::Debug + Send + Sync,
+    S: Sync,
+{
+    fn spawn(&self, task: S1Fut<'static, ()>) {
+        S::spawn(Deref::deref(self), task)
+    }
+    fn sleep(&self, duration: Duration) -> S2Fut<'static, ()> {
+        S::sleep(Deref::deref(self), duration)
+    }
+    fn resolve(&self, address: String) -> S2Fut<'static, io::Result<Vec<SocketAddr>>> {
+        S::resolve(Deref::deref(self), address)
+    }
+    fn listen_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        S::listen_tcp(Deref::deref(self), address)
+    }
+    fn connect_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        S::connect_tcp(Deref::deref(self), address)
+    }
+    #[cfg(unix)]
+    fn listen_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        S::listen_unix(Deref::deref(self), path)
+    }
+    #[cfg(unix)]
+    fn connect_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        S::connect_unix(Deref::deref(self), path)
+    }
+}
+
+/// A runtime that cannot run anything. Tasks are dropped, sleep never ends and networking fails.
+impl Runtime for Dummy {
+    fn spawn(&self, _task: S1Fut<'static, ()>) {
+        error!("No runtime available to spawn a task, enable the runtime-async-std or runtime-tokio feature")
+    }
+    fn sleep(&self, _duration: Duration) -> S2Fut<'static, ()> {
+        Box::pin(pending())
+    }
+    fn resolve(&self, _address: String) -> S2Fut<'static, io::Result<Vec<SocketAddr>>> {
+        Box::pin(ready(Err(no_runtime())))
+    }
+    fn listen_tcp(&self, _address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        Box::pin(ready(Err(no_runtime())))
+    }
+    fn connect_tcp(&self, _address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        Box::pin(ready(Err(no_runtime())))
+    }
+    #[cfg(unix)]
+    fn listen_unix(&self, _path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        Box::pin(ready(Err(no_runtime())))
+    }
+    #[cfg(unix)]
+    fn connect_unix(&self, _path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        Box::pin(ready(Err(no_runtime())))
+    }
+}
+
+fn no_runtime() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "No runtime available, enable the runtime-async-std or runtime-tokio feature",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_expires() {
+        let runtime = Arc::new(NoSleep);
+        let res =
+            async_std::task::block_on(timeout(&runtime, Duration::from_millis(1), pending::<()>()));
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn timeout_gives_result() {
+        let res = async_std::task::block_on(timeout(&Dummy, Duration::from_millis(1), ready(1)));
+        assert_eq!(res, Some(1));
+    }
+
+    /// Sleep is over right away
+    #[derive(Debug)]
+    struct NoSleep;
+    impl Runtime for NoSleep {
+        fn spawn(&self, task: S1Fut<'static, ()>) {
+            Dummy.spawn(task)
+        }
+        fn sleep(&self, _duration: Duration) -> S2Fut<'static, ()> {
+            Box::pin(ready(()))
+        }
+        fn resolve(&self, address: String) -> S2Fut<'static, io::Result<Vec<SocketAddr>>> {
+            Dummy.resolve(address)
+        }
+        fn listen_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+            Dummy.listen_tcp(address)
+        }
+        fn connect_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+            Dummy.connect_tcp(address)
+        }
+        #[cfg(unix)]
+        fn listen_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+            Dummy.listen_unix(path)
+        }
+        #[cfg(unix)]
+        fn connect_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+            Dummy.connect_unix(path)
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use super::{Listener, Runtime};
+use crate::{
+    common::*,
+    io::{tls::Io, ConnectionInfo},
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Runs samotop on tokio.
+///
+/// It must be used from within a tokio runtime with IO and time drivers enabled.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: S1Fut<'static, ()>) {
+        tokio::spawn(task);
+    }
+    fn sleep(&self, duration: Duration) -> S2Fut<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+    fn resolve(&self, address: String) -> S2Fut<'static, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok(tokio::net::lookup_host(address).await?.collect()) })
+    }
+    fn listen_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener: Box<dyn Listener> = Box::new(TcpListener::bind(address).await?);
+            Ok(listener)
+        })
+    }
+    fn connect_tcp(&self, address: SocketAddr) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        Box::pin(async move {
+            let stream: Box<dyn Io> = Box::new(TokioIo(TcpStream::connect(address).await?));
+            Ok(stream)
+        })
+    }
+    #[cfg(unix)]
+    fn listen_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener: Box<dyn Listener> = Box::new(UnixListener::bind(path)?);
+            Ok(listener)
+        })
+    }
+    #[cfg(unix)]
+    fn connect_unix(&self, path: PathBuf) -> S2Fut<'static, io::Result<Box<dyn Io>>> {
+        Box::pin(async move {
+            let stream: Box<dyn Io> = Box::new(TokioIo(UnixStream::connect(path).await?));
+            Ok(stream)
+        })
+    }
+}
+
+impl Listener for TcpListener {
+    fn local_addr(&self) -> String {
+        TcpListener::local_addr(self)
+            .map(|a| a.to_string())
+            .unwrap_or_default()
+    }
+    fn accept<'a, 'f>(&'a self) -> S2Fut<'f, io::Result<(Box<dyn Io>, ConnectionInfo)>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let (stream, peer) = TcpListener::accept(self).await?;
+            let conn = ConnectionInfo::new(
+                stream
+                    .local_addr()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+                peer.to_string(),
+            );
+            let stream: Box<dyn Io> = Box::new(TokioIo(stream));
+            Ok((stream, conn))
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    fn local_addr(&self) -> String {
+        UnixListener::local_addr(self)
+            .ok()
+            .and_then(|s| s.as_pathname().map(|p| p.display().to_string()))
+            .unwrap_or_default()
+    }
+    fn accept<'a, 'f>(&'a self) -> S2Fut<'f, io::Result<(Box<dyn Io>, ConnectionInfo)>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let (stream, _) = UnixListener::accept(self).await?;
+            let path = |addr: io::Result<tokio::net::unix::SocketAddr>| {
+                addr.ok()
+                    .and_then(|s| s.as_pathname().map(|p| p.display().to_string()))
+                    .unwrap_or_default()
+            };
+            let conn = ConnectionInfo::new(path(stream.local_addr()), path(stream.peer_addr()));
+            let stream: Box<dyn Io> = Box::new(TokioIo(stream));
+            Ok((stream, conn))
+        })
+    }
+}
+
+/// Adapts tokio IO to the futures IO traits
+#[derive(Debug)]
+pub struct TokioIo<T>(pub T);
+
+impl<T: AsyncRead + Unpin> io::Read for TokioIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> io::Write for TokioIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::io::{Read, Write};
+    use crate::runtime::timeout;
+
+    #[test]
+    fn tcp_round_trip() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime");
+        rt.block_on(async {
+            let runtime = TokioRuntime;
+            let listener = runtime
+                .listen_tcp("127.0.0.1:0".parse().expect("addr"))
+                .await
+                .expect("listen");
+            let addr = listener.local_addr().parse().expect("local addr");
+            let mut client = runtime.connect_tcp(addr).await.expect("connect");
+            let (mut server, conn) = listener.accept().await.expect("accept");
+            assert_eq!(conn.local_addr, addr.to_string());
+
+            poll_fn(|cx| Pin::new(&mut client).poll_write(cx, b"helo"))
+                .await
+                .expect("write");
+            let mut buf = [0u8; 4];
+            let read = poll_fn(|cx| Pin::new(&mut server).poll_read(cx, &mut buf[..]))
+                .await
+                .expect("read");
+            assert_eq!(&buf[..read], b"helo");
+
+            let res = timeout(&runtime, Duration::from_millis(1), pending::<()>()).await;
+            assert_eq!(res, None);
+        })
+    }
+}
@@ -1,6 +1,10 @@
+mod multi;
+mod shutdown;
 mod tcp;
 #[cfg(unix)]
 mod unix;
+pub use self::multi::*;
+pub use self::shutdown::*;
 pub use self::tcp::*;
 #[cfg(unix)]
 pub use self::unix::*;
@@ -0,0 +1,201 @@
+use super::{Shutdown, TcpServer};
+use crate::common::*;
+use crate::io::IoService;
+use crate::runtime::{default_runtime, Runtime};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+
+/**
+`Server` serves multiple named listeners, each with its own `IoService`,
+sharing one runtime and one `Shutdown` handle.
+
+The listener name is set in `ConnectionInfo::listener` so that a component
+shared by the services can tell where the session came from.
+
+```no_run
+# use samotop_core::mail::*;
+# use samotop_core::server::*;
+# use samotop_core::io::tls::{ImplicitTls, NoTls};
+# async fn serve(mta: Service, submission: Service, lmtp: Service) -> samotop_core::common::Result<()> {
+let server = Server::new()
+    .tcp("mta", "0.0.0.0:25", mta)
+    .tcp("submission", "0.0.0.0:587", submission.clone())
+    .tcp("submissions", "0.0.0.0:465", ImplicitTls::new(NoTls, submission))
+    .unix("lmtp", "/run/samotop/lmtp.sock", lmtp);
+
+let shutdown = server.shutdown_handle();
+// call shutdown.shutdown() to stop accepting connections
+
+server.serve().await
+# }
+```
+*/
+pub struct Server<'a> {
+    runtime: Option<Arc<dyn Runtime + Send + Sync>>,
+    shutdown: Shutdown,
+    listeners: Vec<Box<ServeListener<'a>>>,
+}
+
+type ServeListener<'a> =
+    dyn FnOnce(Arc<dyn Runtime + Send + Sync>, Shutdown) -> S1Fut<'a, Result<()>> + Send + 'a;
+
+impl<'a> Server<'a> {
+    pub fn new() -> Self {
+        Self {
+            runtime: None,
+            shutdown: Shutdown::new(),
+            listeners: vec![],
+        }
+    }
+    /// Serve the service on given TCP ports - usually address:port
+    pub fn tcp<N, S>(mut self, name: impl Into<String>, ports: N, service: S) -> Self
+    where
+        N: ToString + Send + 'a,
+        S: IoService + Send + Sync + 'a,
+    {
+        let name = name.into();
+        self.listeners.push(Box::new(move |runtime, shutdown| {
+            Box::pin(
+                TcpServer::on(ports)
+                    .named(name)
+                    .with_runtime(runtime)
+                    .with_shutdown(shutdown)
+                    .serve(service),
+            )
+        }));
+        self
+    }
+    /// Serve the service on given unix socket
+    #[cfg(unix)]
+    pub fn unix<N, S>(mut self, name: impl Into<String>, socket: N, service: S) -> Self
+    where
+        N: Into<std::path::PathBuf> + Send + 'a,
+        S: IoService + Send + Sync + 'a,
+    {
+        let name = name.into();
+        self.listeners.push(Box::new(move |runtime, shutdown| {
+            Box::pin(
+                super::UnixServer::on(socket)
+                    .named(name)
+                    .with_runtime(runtime)
+                    .with_shutdown(shutdown)
+                    .serve(service),
+            )
+        }));
+        self
+    }
+    /// Accept connections and run sessions with the given runtime rather than the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+    /// The handle to stop all the listeners
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+    /// Serve all the listeners until shut down or until one of them fails
+    pub async fn serve(self) -> Result<()> {
+        let runtime = self.runtime.unwrap_or_else(default_runtime);
+        let shutdown = self.shutdown;
+        let mut serving = self
+            .listeners
+            .into_iter()
+            .map(|listener| listener(runtime.clone(), shutdown.clone()))
+            .collect::<FuturesUnordered<_>>();
+        while let Some(served) = serving.next().await {
+            if let Err(e) = served {
+                // stop the others too
+                shutdown.shutdown();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Default for Server<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> fmt::Debug for Server<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("runtime", &self.runtime)
+            .field("shutdown", &self.shutdown)
+            .field("listeners", &self.listeners.len())
+            .finish()
+    }
+}
+
+#[cfg(all(test, unix, feature = "runtime-async-std"))]
+mod tests {
+    use super::*;
+    use crate::io::{tls::MayBeTls, ConnectionInfo};
+    use crate::runtime::AsyncStdRuntime;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Listeners(Arc<Mutex<Vec<String>>>);
+
+    impl IoService for Listeners {
+        fn handle(
+            &self,
+            _io: Result<Box<dyn MayBeTls>>,
+            connection: ConnectionInfo,
+        ) -> S1Fut<'static, Result<()>> {
+            self.0.lock().expect("lock").push(connection.listener);
+            Box::pin(ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn serves_named_listeners_until_shutdown() {
+        async_std::task::block_on(async {
+            let dir = std::env::temp_dir().join(format!("samotop-multi-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).expect("temp dir");
+            let (a, b) = (dir.join("a.sock"), dir.join("b.sock"));
+            let seen = Listeners::default();
+
+            let server = Server::new()
+                .with_runtime(AsyncStdRuntime)
+                .unix("alpha", a.clone(), seen.clone())
+                .unix("beta", b.clone(), seen.clone());
+            let shutdown = server.shutdown_handle();
+            let serving = async_std::task::spawn(server.serve());
+
+            for socket in [&a, &b] {
+                // the socket file shows up before the listener is ready
+                while AsyncStdRuntime.connect_unix(socket.clone()).await.is_err() {
+                    async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+                }
+            }
+            while seen.0.lock().expect("lock").len() < 2 {
+                async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+            }
+
+            shutdown.shutdown();
+            serving.await.expect("clean shutdown");
+            std::fs::remove_dir_all(&dir).expect("cleanup");
+
+            let mut seen = seen.0.lock().expect("lock").clone();
+            seen.sort();
+            assert_eq!(seen, vec!["alpha", "beta"]);
+        })
+    }
+
+    #[test]
+    fn shutdown_wakes_waiters() {
+        async_std::task::block_on(async {
+            let shutdown = Shutdown::new();
+            let waiting = async_std::task::spawn({
+                let shutdown = shutdown.clone();
+                async move { shutdown.wait().await }
+            });
+            assert!(!shutdown.is_shutdown());
+            shutdown.shutdown();
+            waiting.await;
+            assert!(shutdown.is_shutdown());
+        })
+    }
+}
@@ -0,0 +1,63 @@
+use crate::common::*;
+use std::sync::Mutex;
+use std::task::Waker;
+
+/// A handle to stop the servers from accepting new connections.
+///
+/// Clones share the same state. Sessions already running are not interrupted.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Mutex<ShutdownState>>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    signalled: bool,
+    wakers: Vec<Waker>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Signal the shutdown to all the listeners
+    pub fn shutdown(&self) {
+        let wakers = {
+            let mut state = self.lock();
+            state.signalled = true;
+            std::mem::take(&mut state.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+    /// The shutdown has been signalled
+    pub fn is_shutdown(&self) -> bool {
+        self.lock().signalled
+    }
+    /// Resolves once the shutdown is signalled
+    pub async fn wait(&self) {
+        poll_fn(|cx| self.poll_shutdown(cx)).await
+    }
+    pub(crate) fn poll_shutdown(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.lock();
+        if state.signalled {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, ShutdownState> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("signalled", &self.is_shutdown())
+            .finish()
+    }
+}
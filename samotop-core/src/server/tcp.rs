@@ -1,44 +1,42 @@
 use crate::common::*;
-use crate::io::tls::{Io, MayBeTls, TlsCapable};
+use crate::io::tls::{MayBeTls, TlsCapable};
 use crate::io::*;
-use async_std::stream::StreamExt;
-use async_std::task;
-use futures_util::stream::FuturesUnordered;
-
-use async_std::net::{TcpListener, ToSocketAddrs};
-use futures_util::TryFutureExt;
+use crate::runtime::{default_runtime, Listener, Runtime};
+use crate::server::Shutdown;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use std::net::SocketAddr;
 
 /// `TcpServer` takes care of accepting TCP connections and passing them to an `IoService` to `handle()`.
 #[derive(Default)]
-pub struct TcpServer<'a> {
-    ports: Vec<S1Fut<'a, Result<Vec<SocketAddr>>>>,
+pub struct TcpServer {
+    /// Addresses to resolve with the runtime once serving
+    ports: Vec<String>,
+    runtime: Option<Arc<dyn Runtime + Send + Sync>>,
+    name: String,
+    shutdown: Shutdown,
 }
 
-impl<'a> TcpServer<'a> {
-    /// Listen on this port - usually addres:port. You can call this multiple times to listen on multiple ports.
+impl TcpServer {
+    /// Listen on this port - usually addres:port, resolved by the runtime once serving. You can call this multiple times to listen on multiple ports.
     pub fn on<N>(ports: N) -> Self
     where
-        N: ToSocketAddrs + 'a,
-        N::Iter: Send,
+        N: ToString,
     {
         Self::default().and(ports)
     }
     /// Listen on this port - usually addres:port. You can call this multiple times to listen on multiple ports.
     pub fn and<N>(mut self, ports: N) -> Self
     where
-        N: ToSocketAddrs + 'a,
-        N::Iter: Send,
+        N: ToString,
     {
-        self.ports.push(Box::pin(Self::map_ports(ports)));
+        self.ports.push(ports.to_string());
         self
     }
     /// Listen on multiple ports - usually a list of address:port items
     pub fn on_all<I, N>(ports: I) -> Self
     where
         I: IntoIterator<Item = N>,
-        N: ToSocketAddrs + 'a,
-        N::Iter: Send,
+        N: ToString,
     {
         Self::default().and_all(ports)
     }
@@ -46,24 +44,38 @@ impl<'a> TcpServer<'a> {
     pub fn and_all<I, N>(mut self, ports: I) -> Self
     where
         I: IntoIterator<Item = N>,
-        N: ToSocketAddrs + 'a,
-        N::Iter: Send,
+        N: ToString,
     {
         for port in ports.into_iter() {
             self = self.and(port);
         }
         self
     }
-    fn map_ports(addrs: impl ToSocketAddrs) -> impl Future<Output = Result<Vec<SocketAddr>>> {
-        addrs
-            .to_socket_addrs()
-            .map_ok(|i| i.into_iter().collect())
-            .map_err(|e| e.into())
+    /// Accept connections and run sessions with the given runtime rather than the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+    /// Name the listener so that the services can tell where the connection came from - see `ConnectionInfo::listener`
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+    /// Stop accepting connections once the shutdown is signalled
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
     }
-    async fn resolve_ports(&mut self) -> Result<Vec<SocketAddr>> {
+    async fn resolve_ports(
+        runtime: &(dyn Runtime + Send + Sync),
+        ports: Vec<String>,
+    ) -> Result<Vec<SocketAddr>> {
         let mut result = vec![];
-        for port in self.ports.iter_mut() {
-            let port = port.await?;
+        for port in ports {
+            let port = runtime
+                .resolve(port.clone())
+                .await
+                .map_err(|e| format!("Unable to resolve {:?}: {}", port, e))?;
             result.extend_from_slice(&port[..]);
         }
         Ok(result)
@@ -73,74 +85,97 @@ impl<'a> TcpServer<'a> {
     where
         S: IoService + Send + Sync,
     {
-        Self::serve_ports(service, self.resolve_ports().await?).await
+        let runtime = self.runtime.take().unwrap_or_else(default_runtime);
+        let addrs = Self::resolve_ports(runtime.as_ref(), self.ports).await?;
+        let listener = Arc::new(ListenerInfo {
+            kind: "TCP",
+            name: self.name,
+            shutdown: self.shutdown,
+        });
+        Self::serve_ports(runtime, listener, service, addrs).await
     }
-    async fn serve_ports<S>(service: S, addrs: impl IntoIterator<Item = SocketAddr>) -> Result<()>
+    async fn serve_ports<S>(
+        runtime: Arc<dyn Runtime + Send + Sync>,
+        listener: Arc<ListenerInfo>,
+        service: S,
+        addrs: impl IntoIterator<Item = SocketAddr>,
+    ) -> Result<()>
     where
         S: IoService + Send + Sync,
     {
         let svc = Arc::new(service);
 
-        addrs
+        let mut serving = addrs
             .into_iter()
-            .map(|a| Self::serve_port(svc.clone(), a))
-            .collect::<FuturesUnordered<_>>()
-            .skip_while(|r| r.is_ok())
-            .take(1)
-            .fold(Ok(()), |acc, cur| match cur {
-                Err(e) => Err(e),
-                Ok(()) => acc,
-            })
-            .await
+            .map(|a| Self::serve_port(runtime.clone(), listener.clone(), svc.clone(), a))
+            .collect::<FuturesUnordered<_>>();
+        while let Some(served) = serving.next().await {
+            served?
+        }
+        Ok(())
     }
-    async fn serve_port<S>(service: S, addr: SocketAddr) -> Result<()>
+    async fn serve_port<S>(
+        runtime: Arc<dyn Runtime + Send + Sync>,
+        listener: Arc<ListenerInfo>,
+        service: Arc<S>,
+        addr: SocketAddr,
+    ) -> Result<()>
     where
-        S: IoService + Clone,
+        S: IoService + Send + Sync,
     {
         trace!("Binding on {:?}", addr);
-        let listener = TcpListener::bind(addr)
+        let socket = runtime
+            .listen_tcp(addr)
             .await
             .map_err(|e| format!("Unable to bind {:?}: {}", addr, e))?;
-        let mut incoming = listener.incoming();
-        info!("Listening on {:?}", listener.local_addr());
-        while let Some(stream) = incoming.next().await {
-            let conn = if let Ok(ref stream) = stream {
-                ConnectionInfo::new(
-                    stream
-                        .local_addr()
-                        .map(|s| s.to_string())
-                        .unwrap_or_default(),
-                    stream
-                        .peer_addr()
-                        .map(|s| s.to_string())
-                        .unwrap_or_default(),
-                )
-            } else {
-                ConnectionInfo::default()
-            };
-            let stream = match stream {
-                Ok(s) => {
-                    let s: Box<dyn Io> = Box::new(s);
-                    let s: Box<dyn MayBeTls> = Box::new(TlsCapable::plaintext(s));
-                    Ok(s)
-                }
-                Err(e) => (Err(e.into())),
-            };
-            let service = service.clone();
-            spawn_task_and_swallow_log_errors(
-                format!("TCP transmission {}", conn),
-                service.handle(stream, conn),
-            );
-        }
-        Ok(())
+        info!("Listening on {:?}", socket.local_addr());
+        accept_loop(runtime, service, socket, &listener).await
     }
 }
 
-fn spawn_task_and_swallow_log_errors<F>(task_name: String, fut: F) -> task::JoinHandle<()>
+/// What the accepted connections have in common
+pub(crate) struct ListenerInfo {
+    pub kind: &'static str,
+    pub name: String,
+    pub shutdown: Shutdown,
+}
+
+/// Accept connections from the listener and spawn a session for each of them
+/// until the shutdown is signalled.
+pub(crate) async fn accept_loop<S>(
+    runtime: Arc<dyn Runtime + Send + Sync>,
+    service: Arc<S>,
+    socket: Box<dyn Listener>,
+    listener: &ListenerInfo,
+) -> Result<()>
 where
-    F: Future<Output = Result<()>> + Send + 'static,
+    S: IoService + Send + Sync + ?Sized,
 {
-    task::spawn(async move { log_errors(task_name, fut).await.unwrap_or_default() })
+    loop {
+        let mut accept = socket.accept();
+        let accepted = poll_fn(|cx| match listener.shutdown.poll_shutdown(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => accept.as_mut().poll(cx).map(Some),
+        })
+        .await;
+        let (stream, mut conn) = match accepted {
+            None => {
+                info!("Stopped listening on {:?}", socket.local_addr());
+                return Ok(());
+            }
+            Some(Ok((stream, conn))) => {
+                let stream: Box<dyn MayBeTls> = Box::new(TlsCapable::plaintext(stream));
+                (Ok(stream), conn)
+            }
+            Some(Err(e)) => (Err(e.into()), ConnectionInfo::default()),
+        };
+        conn.listener = listener.name.clone();
+        let task_name = format!("{} transmission {}", listener.kind, conn.id);
+        let session = service.handle(stream, conn);
+        runtime.spawn(Box::pin(async move {
+            log_errors(task_name, session).await.unwrap_or_default()
+        }));
+    }
 }
 
 async fn log_errors<F, T, E>(task_name: String, fut: F) -> Option<T>
@@ -1,16 +1,19 @@
+use super::tcp::{accept_loop, ListenerInfo};
+use super::Shutdown;
 use crate::common::*;
-use crate::io::tls::{Io, MayBeTls, TlsCapable};
 use crate::io::*;
-use async_std::stream::StreamExt;
-use async_std::task;
-use futures_util::stream::FuturesUnordered;
+use crate::runtime::{default_runtime, Runtime};
+use futures_util::stream::{FuturesUnordered, StreamExt};
 
-use async_std::{os::unix::net::UnixListener, path::PathBuf as SocketAddr};
+use std::path::PathBuf as SocketAddr;
 
 /// `UnixServer` takes care of accepting Unix socket connections and passing them to an `IoService` to `handle()`.
 #[derive(Default)]
 pub struct UnixServer<'a> {
     ports: Vec<S1Fut<'a, Result<Vec<SocketAddr>>>>,
+    runtime: Option<Arc<dyn Runtime + Send + Sync>>,
+    name: String,
+    shutdown: Shutdown,
 }
 
 impl<'a> UnixServer<'a> {
@@ -48,6 +51,21 @@ impl<'a> UnixServer<'a> {
         }
         self
     }
+    /// Accept connections and run sessions with the given runtime rather than the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+    /// Name the listener so that the services can tell where the connection came from - see `ConnectionInfo::listener`
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+    /// Stop accepting connections once the shutdown is signalled
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
     fn map_ports(addrs: impl Into<SocketAddr>) -> impl Future<Output = Result<Vec<SocketAddr>>> {
         // todo: check if file exists and is a socket here?
         ready(Ok(vec![addrs.into()]))
@@ -65,99 +83,50 @@ impl<'a> UnixServer<'a> {
     where
         S: IoService + Send + Sync,
     {
-        Self::serve_ports(service, self.resolve_ports().await?).await
+        let addrs = self.resolve_ports().await?;
+        let runtime = self.runtime.take().unwrap_or_else(default_runtime);
+        let listener = Arc::new(ListenerInfo {
+            kind: "Unix",
+            name: self.name,
+            shutdown: self.shutdown,
+        });
+        Self::serve_ports(runtime, listener, service, addrs).await
     }
-    async fn serve_ports<S>(service: S, addrs: impl IntoIterator<Item = SocketAddr>) -> Result<()>
+    async fn serve_ports<S>(
+        runtime: Arc<dyn Runtime + Send + Sync>,
+        listener: Arc<ListenerInfo>,
+        service: S,
+        addrs: impl IntoIterator<Item = SocketAddr>,
+    ) -> Result<()>
     where
         S: IoService + Send + Sync,
     {
         let svc = Arc::new(service);
 
-        addrs
+        let mut serving = addrs
             .into_iter()
-            .map(|a| Self::serve_port(svc.clone(), a))
-            .collect::<FuturesUnordered<_>>()
-            .skip_while(|r| r.is_ok())
-            .take(1)
-            .fold(Ok(()), |acc, cur| match cur {
-                Err(e) => Err(e),
-                Ok(()) => acc,
-            })
-            .await
+            .map(|a| Self::serve_port(runtime.clone(), listener.clone(), svc.clone(), a))
+            .collect::<FuturesUnordered<_>>();
+        while let Some(served) = serving.next().await {
+            served?
+        }
+        Ok(())
     }
-    async fn serve_port<S>(service: S, addr: SocketAddr) -> Result<()>
+    async fn serve_port<S>(
+        runtime: Arc<dyn Runtime + Send + Sync>,
+        listener: Arc<ListenerInfo>,
+        service: Arc<S>,
+        addr: SocketAddr,
+    ) -> Result<()>
     where
-        S: IoService + Clone,
+        S: IoService + Send + Sync,
     {
         trace!("Binding on {:?}", addr);
-        let listener = UnixListener::bind(addr.clone())
+        let socket = runtime
+            .listen_unix(addr.clone())
             .await
             .map_err(|e| format!("Unable to bind {:?}: {}", addr, e))?;
-        let mut incoming = listener.incoming();
-        info!("Listening on {:?}", listener.local_addr());
-        while let Some(stream) = incoming.next().await {
-            let conn = if let Ok(ref stream) = stream {
-                ConnectionInfo::new(
-                    stream
-                        .local_addr()
-                        .ok()
-                        .and_then(|s| {
-                            s.as_pathname()
-                                .and_then(|p| p.to_str())
-                                .map(|s| s.to_owned())
-                        })
-                        .unwrap_or_default(),
-                    stream
-                        .peer_addr()
-                        .ok()
-                        .and_then(|s| {
-                            s.as_pathname()
-                                .and_then(|p| p.to_str())
-                                .map(|s| s.to_owned())
-                        })
-                        .unwrap_or_default(),
-                )
-            } else {
-                ConnectionInfo::default()
-            };
-            let stream = match stream {
-                Ok(s) => {
-                    let s: Box<dyn Io> = Box::new(s);
-                    let s: Box<dyn MayBeTls> = Box::new(TlsCapable::plaintext(s));
-                    Ok(s)
-                }
-                Err(e) => (Err(e.into())),
-            };
-            let service = service.clone();
-            spawn_task_and_swallow_log_errors(
-                format!("TCP transmission {}", conn),
-                service.handle(stream, conn),
-            );
-        }
-        Ok(())
-    }
-}
-
-fn spawn_task_and_swallow_log_errors<F>(task_name: String, fut: F) -> task::JoinHandle<()>
-where
-    F: Future<Output = Result<()>> + Send + 'static,
-{
-    task::spawn(async move { log_errors(task_name, fut).await.unwrap_or_default() })
-}
-
-async fn log_errors<F, T, E>(task_name: String, fut: F) -> Option<T>
-where
-    F: Future<Output = std::result::Result<T, E>>,
-    E: std::fmt::Display,
-{
-    match fut.await {
-        Err(e) => {
-            error!("Error in {}: {}", task_name, e);
-            None
-        }
-        Ok(r) => {
-            info!("{} completed successfully.", task_name);
-            Some(r)
-        }
+        info!("Listening on {:?}", socket.local_addr());
+        accept_loop(runtime, service, socket, &listener).await
     }
 }
@@ -1,8 +1,8 @@
 use crate::{
     common::{Arc, Dummy},
     io::ConnectionInfo,
-    mail::MailService,
-    smtp::SmtpSession,
+    mail::{IdGenerator, MailService},
+    smtp::{SessionLifecycle, SmtpSession, TransactionEnd},
 };
 use std::{
     any::{Any, TypeId},
@@ -64,6 +64,27 @@ impl SmtpContext {
             .cloned()
             .unwrap_or_else(|| Arc::new(Dummy) as Arc<dyn MailService + Send + Sync>)
     }
+    /// The ID generator of the service, a process wide default if not set
+    pub fn ids(&self) -> impl IdGenerator {
+        self.get::<Arc<dyn IdGenerator + Send + Sync + 'static>>()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Dummy) as Arc<dyn IdGenerator + Send + Sync>)
+    }
+    /// End the current mail transaction, notify the lifecycle services and reset the transaction.
+    ///
+    /// Nothing is notified if there is no transaction going on.
+    pub async fn end_transaction(&mut self, end: TransactionEnd) {
+        if !self.session.transaction.is_empty() {
+            self.session.stats.transactions += 1;
+            if end == TransactionEnd::Queued {
+                self.session.stats.mails_queued += 1;
+            }
+            self.service()
+                .on_transaction_end(&mut self.session, end)
+                .await;
+        }
+        self.session.reset();
+    }
     pub(crate) fn set_service(&mut self, service: impl MailService + Send + Sync + 'static) {
         let service = Arc::new(service) as Arc<dyn MailService + Send + Sync + 'static>;
         self.set(service);
@@ -130,22 +151,21 @@ mod store_tests {
         sut.set_service(SessionLogger);
 
         let dump = format!("{:#?}", sut);
-        let dump = Regex::new("[0-9]+")
+        let dump = Regex::new("0x[0-9a-f]+|[0-9A-Z]{26}|[0-9]+")
             .expect("regex")
             .replace_all(dump.as_str(), "--redacted--");
 
         insta::assert_display_snapshot!(dump, @r###"
         SmtpContext {
             store: {
-                TypeId {
-                    t: --redacted--,
-                }: Any { .. },
+                TypeId(--redacted--): Any { .. },
             },
             session: SmtpSession {
                 connection: ConnectionInfo {
                     id: "--redacted--",
                     local_addr: "",
                     peer_addr: "",
+                    listener: "",
                     established: SystemTime {
                         tv_sec: --redacted--,
                         tv_nsec: --redacted--,
@@ -156,6 +176,8 @@ mod store_tests {
                 },
                 service_name: "samotop",
                 peer_name: None,
+                encrypted: false,
+                authenticated: None,
                 output: [],
                 input: [],
                 mode: None,
@@ -165,6 +187,22 @@ mod store_tests {
                     rcpts: [],
                     extra_headers: "",
                     sink: "*",
+                    store: --redacted--,
+                },
+                stats: SessionStats {
+                    bytes_received: --redacted--,
+                    bytes_sent: --redacted--,
+                    transactions: --redacted--,
+                    mails_queued: --redacted--,
+                },
+                end: None,
+                refused: None,
+                dns: None,
+                privacy: Privacy {
+                    pseudonyms: false,
+                    ipv--redacted--_prefix: --redacted--,
+                    ipv--redacted--_prefix: --redacted--,
+                    body: true,
                 },
             },
         }
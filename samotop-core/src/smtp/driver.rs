@@ -2,7 +2,6 @@
 use crate::common::io::*;
 use crate::common::*;
 use crate::io::tls::MayBeTls;
-
 use crate::smtp::*;
 
 pub trait Drive: fmt::Debug {
@@ -39,32 +38,32 @@ impl Drive for SmtpDriver {
     {
         Box::pin(async move {
             state.service().prepare_session(bare_io, state).await;
-            let mut io = async_std::io::BufReader::new(bare_io);
+            state.session.encrypted = bare_io.is_encrypted();
+            let io = bare_io;
             // fetch and apply commands
             loop {
                 // write all pending responses
                 while let Some(response) = state.session.pop_control() {
                     trace!("Processing driver control {:?}", response);
-                    use async_std::io::prelude::WriteExt;
                     match response {
                         DriverControl::Response(bytes) => {
-                            let writer = io.get_mut();
-                            let write = writer
-                                .write_all(bytes.as_ref())
+                            let writer = &mut *io;
+                            let write = write_all(writer, bytes.as_ref())
+                                .await
+                                .map_err(DriverError::WriteFailed);
+                            let flush = poll_fn(|cx| Pin::new(&mut *writer).poll_flush(cx))
                                 .await
                                 .map_err(DriverError::WriteFailed);
-                            let flush = writer.flush().await.map_err(DriverError::WriteFailed);
                             match write.and(flush) {
-                                Ok(()) => {}
+                                Ok(()) => state.session.stats.bytes_sent += bytes.len(),
                                 Err(e) => {
                                     return Err(e);
                                 }
                             }
                         }
                         DriverControl::Shutdown => {
-                            state.session.input.extend_from_slice(io.buffer());
                             // TODO: replace with close() after https://github.com/async-rs/async-std/issues/977
-                            match poll_fn(move |cx| Pin::new(io.get_mut()).poll_close(cx)).await {
+                            match poll_fn(|cx| Pin::new(&mut *io).poll_close(cx)).await {
                                 Ok(()) => {
                                     trace!("Shutdown completed");
                                     return Ok(());
@@ -75,12 +74,17 @@ impl Drive for SmtpDriver {
                             }
                         }
                         DriverControl::StartTls => {
-                            Pin::new(io.get_mut()).encrypt();
+                            // RFC 3207 - anything the client sent before TLS negotiation must be discarded
+                            state.session.input.clear();
+                            Pin::new(&mut *io).encrypt();
+                            state.session.encrypted = io.is_encrypted();
+                            spans::tls(state);
                         }
                     }
                 }
 
-                match interpretter.interpret(state).await {
+                let span = spans::transaction(state);
+                match spans::instrument(span, interpretter.interpret(state)).await {
                     Ok(None) => {
                         // Action taken, but no input consumed (i.e. session setup / shut down)
                     }
@@ -90,26 +94,37 @@ impl Drive for SmtpDriver {
                             consumed <= state.session.input.len(),
                             "The interpreter consumed more than a buffer? How?"
                         );
-                        // TODO: handle buffer more efficiently, now allocating all the time
-                        state.session.input = state.session.input.split_off(consumed);
+                        state.session.input.consume(consumed);
+                    }
+                    Err(ParseError::Incomplete)
+                        if state.session.input.len() >= InputBuffer::MAX_PENDING =>
+                    {
+                        warn!(
+                            "Command too long, {} bytes pending",
+                            state.session.input.len()
+                        );
+                        // the client is not going to finish it any time soon
+                        state.session.input.clear();
+                        state.session.end = Some(SessionEnd::Failed("Command too long".into()));
+                        state.session.say_shutdown(SmtpReply::CommandSyntaxFailure);
                     }
                     Err(ParseError::Incomplete) => {
-                        use async_std::io::prelude::BufReadExt;
-                        // TODO: take care of large chunks without LF
-                        match io.read_until(b'\n', &mut state.session.input).await {
+                        let target = state.session.input.read_room(InputBuffer::MIN_READ);
+                        match poll_fn(|cx| Pin::new(&mut *io).poll_read(cx, target)).await {
                             Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
                                 warn!("session read timeout");
                                 state.session.say_shutdown_timeout();
                             }
                             Err(e) => return Err(e.into()),
                             Ok(0) => {
+                                state.session.end = Some(SessionEnd::Disconnected);
                                 if state.session.input.is_empty() {
                                     // client went silent, we're done!
                                     state.session.shutdown();
                                 } else {
                                     error!(
-                                        "Incomplete and finished: {:?}",
-                                        String::from_utf8_lossy(state.session.input.as_slice())
+                                        "Incomplete and finished: {}",
+                                        state.session.privacy.input(state.session.input.as_slice())
                                     );
                                     // client did not finish the command and left.
                                     state
@@ -117,13 +132,17 @@ impl Drive for SmtpDriver {
                                         .say_shutdown_processing_err("Incomplete command".into());
                                 };
                             }
-                            Ok(_) => { /* good, interpret again */ }
+                            Ok(len) => {
+                                // good, interpret again
+                                state.session.input.filled(len);
+                                state.session.stats.bytes_received += len;
+                            }
                         }
                     }
                     Err(e) => {
                         warn!(
-                            "Invalid command {:?} - {}",
-                            String::from_utf8_lossy(state.session.input.as_slice()),
+                            "Invalid command {} - {}",
+                            state.session.privacy.input(state.session.input.as_slice()),
                             e
                         );
 
@@ -135,7 +154,7 @@ impl Drive for SmtpDriver {
                             .position(|b| *b == b'\n')
                             .map(|p| p + 1)
                             .unwrap_or(state.session.input.len());
-                        state.session.input = state.session.input.split_off(split);
+                        state.session.input.consume(split);
 
                         if split == 0 {
                             warn!("Parsing failed on empty input, this will fail again, stopping the session");
@@ -150,6 +169,17 @@ impl Drive for SmtpDriver {
     }
 }
 
+#[cfg(feature = "driver")]
+async fn write_all(writer: &mut Box<dyn MayBeTls>, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, bytes)).await? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            written => bytes = &bytes[written..],
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum DriverError {
     IoClosed,
@@ -3,3 +3,11 @@ use super::extensions::*;
 pub const STARTTLS: Flag = Flag { code: "STARTTLS" };
 pub const PIPELINING: Flag = Flag { code: "PIPELINING" };
 pub const EIGHTBITMIME: Flag = Flag { code: "8BITMIME" };
+/// RFC 3461 - Delivery Status Notifications
+pub const DSN: Flag = Flag { code: "DSN" };
+/// RFC 3030 - Transmission of Large and Binary MIME Messages
+pub const CHUNKING: Flag = Flag { code: "CHUNKING" };
+/// RFC 1870 - Message Size Declaration
+pub const SIZE: SizeExtension = SizeExtension;
+/// RFC 4954 - Authentication
+pub const AUTH: AuthExtension = AuthExtension;
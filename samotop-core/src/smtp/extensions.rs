@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter, Result as FmtRes};
 
 pub trait Extension: Display {
@@ -25,17 +25,19 @@ impl Display for Error {
     }
 }
 
+/// Set of enabled ESMTP extensions, kept in the order of their codes
 #[derive(Eq, PartialEq, Debug, Clone, Default)]
 pub struct ExtensionSet {
-    map: HashMap<String, String>,
+    map: BTreeMap<String, String>,
 }
 
 impl ExtensionSet {
     pub fn new() -> Self {
         Self {
-            map: HashMap::new(),
+            map: BTreeMap::new(),
         }
     }
+    /// Rendered extensions as they appear in the EHLO response, ordered by extension code
     pub fn iter(&self) -> impl Iterator<Item = &str> {
         self.map.values().map(|s| s.as_str())
     }
@@ -110,6 +112,139 @@ impl Display for Flag {
     }
 }
 
+/// RFC 1870 - SMTP Service Extension for Message Size Declaration
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct SizeExtension;
+impl Extension for SizeExtension {
+    type Value = Size;
+    fn parse(&self, input: &str) -> Result<Option<Size>, Error> {
+        let code = "SIZE";
+        let params = match parse_keyword(code, input)? {
+            None => return Ok(None),
+            Some(params) => params,
+        };
+        if params.is_empty() {
+            return Ok(Some(Size(0)));
+        }
+        match params.bytes().position(|b| !b.is_ascii_digit()) {
+            Some(at) => Err(Error::Invalid(code.len() + 1 + at)),
+            None => params
+                .parse()
+                .map(|max| Some(Size(max)))
+                .map_err(|_| Error::Invalid(code.len() + 1)),
+        }
+    }
+}
+impl Display for SizeExtension {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtRes {
+        f.write_str("SIZE")
+    }
+}
+
+/// Maximum message size in bytes. Zero means there is no fixed limit.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct Size(pub u64);
+impl ExtensionValue for Size {
+    type Extension = SizeExtension;
+    fn extension(&self) -> &Self::Extension {
+        &SizeExtension
+    }
+}
+impl Display for Size {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtRes {
+        match self.0 {
+            0 => write!(f, "{}", SizeExtension),
+            max => write!(f, "{} {}", SizeExtension, max),
+        }
+    }
+}
+
+/// RFC 4954 - SMTP Service Extension for Authentication
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub struct AuthExtension;
+impl Extension for AuthExtension {
+    type Value = Auth;
+    fn parse(&self, input: &str) -> Result<Option<Auth>, Error> {
+        let code = "AUTH";
+        let params = match parse_keyword(code, input)? {
+            None => return Ok(None),
+            Some(params) => params,
+        };
+        let mut mechanisms = vec![];
+        let mut at = code.len() + 1;
+        for mechanism in params.split(' ') {
+            // sasl-mech = 1*20 (UPPER-ALPHA / DIGIT / "-" / "_")
+            if let Some(pos) = mechanism
+                .bytes()
+                .position(|b| !matches!(b, b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_'))
+            {
+                return Err(Error::Invalid(at + pos));
+            }
+            if mechanism.is_empty() || mechanism.len() > 20 {
+                return Err(Error::Invalid(at));
+            }
+            mechanisms.push(mechanism.to_owned());
+            at += mechanism.len() + 1;
+        }
+        Ok(Some(Auth { mechanisms }))
+    }
+}
+impl Display for AuthExtension {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtRes {
+        f.write_str("AUTH")
+    }
+}
+
+/// SASL mechanisms offered for authentication, such as PLAIN or LOGIN
+#[derive(Eq, PartialEq, Debug, Clone, Hash)]
+pub struct Auth {
+    pub mechanisms: Vec<String>,
+}
+impl Auth {
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(mechanisms: I) -> Self {
+        Self {
+            mechanisms: mechanisms.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+impl ExtensionValue for Auth {
+    type Extension = AuthExtension;
+    fn extension(&self) -> &Self::Extension {
+        &AuthExtension
+    }
+}
+impl Display for Auth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtRes {
+        write!(f, "{}", AuthExtension)?;
+        for mechanism in self.mechanisms.iter() {
+            write!(f, " {}", mechanism)?;
+        }
+        Ok(())
+    }
+}
+
+/// Match the extension keyword and return the parameters following it.
+///
+/// Gives `Some("")` if there are no parameters, `None` if it is a different extension.
+fn parse_keyword<'i>(code: &str, input: &'i str) -> Result<Option<&'i str>, Error> {
+    if input == code {
+        Ok(Some(""))
+    } else if code.starts_with(input) {
+        // The input is part of the code, but too short
+        Err(Error::Incomplete)
+    } else if !input.starts_with(code) {
+        Ok(None)
+    } else {
+        match &input.as_bytes()[code.len()..] {
+            // input starts with our code but it is a different longer word
+            [b'a'..=b'z', ..] | [b'A'..=b'Z', ..] | [b'0'..=b'9', ..] => Ok(None),
+            // parameters are separated with a single space
+            [b' ', _, ..] => Ok(Some(&input[code.len() + 1..])),
+            _ => Err(Error::Invalid(code.len())),
+        }
+    }
+}
+
 #[cfg(test)]
 mod extension_set {
     use super::super::extension::*;
@@ -142,6 +277,37 @@ mod extension_set {
         assert_eq!(sut.get(&STARTTLS).unwrap(), Some(STARTTLS));
     }
     #[test]
+    fn iterate_in_order() {
+        let mut sut = ExtensionSet::new();
+        sut.enable(&STARTTLS);
+        sut.enable(&Size(1024));
+        sut.enable(&PIPELINING);
+        sut.enable(&Auth::new(vec!["PLAIN", "LOGIN"]));
+        sut.enable(&CHUNKING);
+        sut.enable(&EIGHTBITMIME);
+        sut.enable(&DSN);
+        assert_eq!(
+            sut.iter().collect::<Vec<_>>(),
+            vec![
+                "8BITMIME",
+                "AUTH PLAIN LOGIN",
+                "CHUNKING",
+                "DSN",
+                "PIPELINING",
+                "SIZE 1024",
+                "STARTTLS"
+            ]
+        );
+    }
+    #[test]
+    fn get_typed_extension() {
+        let mut sut = ExtensionSet::new();
+        sut.enable(&Size(35882577));
+        assert_eq!(sut.get(&SIZE).unwrap(), Some(Size(35882577)));
+        sut.enable(&Auth::new(vec!["PLAIN"]));
+        assert_eq!(sut.get(&AUTH).unwrap(), Some(Auth::new(vec!["PLAIN"])));
+    }
+    #[test]
     fn check_extension() {
         let mut sut = ExtensionSet::new();
         // extension is disabled so gives None
@@ -180,3 +346,59 @@ mod flag_parsing {
         assert_eq!(STARTTLS.parse("STARTTLSx").unwrap(), None);
     }
 }
+
+#[cfg(test)]
+mod value_parsing {
+    use super::super::extension::*;
+    use super::*;
+
+    #[test]
+    fn parse_size() {
+        assert_eq!(SIZE.parse("SIZE 35882577").unwrap(), Some(Size(35882577)));
+        assert_eq!(SIZE.parse("SIZE").unwrap(), Some(Size(0)));
+    }
+    #[test]
+    fn render_size() {
+        assert_eq!(Size(35882577).to_string(), "SIZE 35882577");
+        assert_eq!(Size(0).to_string(), "SIZE");
+    }
+    #[test]
+    fn parse_size_invalid() {
+        assert_eq!(SIZE.parse("SIZE 12x").unwrap_err(), Error::Invalid(7));
+        assert_eq!(SIZE.parse("SIZE ").unwrap_err(), Error::Invalid(4));
+        assert_eq!(SIZE.parse("SIZ").unwrap_err(), Error::Incomplete);
+    }
+    #[test]
+    fn parse_auth() {
+        assert_eq!(
+            AUTH.parse("AUTH PLAIN LOGIN CRAM-MD5").unwrap(),
+            Some(Auth::new(vec!["PLAIN", "LOGIN", "CRAM-MD5"]))
+        );
+    }
+    #[test]
+    fn render_auth() {
+        assert_eq!(
+            Auth::new(vec!["PLAIN", "LOGIN"]).to_string(),
+            "AUTH PLAIN LOGIN"
+        );
+    }
+    #[test]
+    fn parse_auth_invalid() {
+        assert_eq!(AUTH.parse("AUTH").unwrap_err(), Error::Invalid(5));
+        assert_eq!(
+            AUTH.parse("AUTH PLAIN  LOGIN").unwrap_err(),
+            Error::Invalid(11)
+        );
+        assert_eq!(AUTH.parse("AUTH plain").unwrap_err(), Error::Invalid(5));
+    }
+    #[test]
+    fn parse_mismatch() {
+        assert_eq!(SIZE.parse("SIZEABLE 1").unwrap(), None);
+        assert_eq!(AUTH.parse("STARTTLS").unwrap(), None);
+    }
+    #[test]
+    fn parse_flags() {
+        assert_eq!(DSN.parse("DSN").unwrap(), Some(DSN));
+        assert_eq!(CHUNKING.parse("CHUNKING").unwrap(), Some(CHUNKING));
+    }
+}
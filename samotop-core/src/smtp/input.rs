@@ -0,0 +1,252 @@
+use bytes::{Buf, Bytes, BytesMut};
+use std::{fmt, ops::Deref};
+
+/// Buffered input of an SMTP session waiting to be interpretted.
+///
+/// Consuming the input only moves a cursor, nothing gets reallocated or copied.
+/// Parsers can share parts of the input (i.e. mail body chunks) with `share()`
+/// rather than copying them. The memory is released once all the shared parts are dropped.
+///
+/// Reads go to the spare room of one block which is reused until it is used up.
+/// Input left pending by an incomplete command keeps growing in place in the block
+/// and is only copied once it has to move to a new block.
+#[derive(Default)]
+pub struct InputBuffer {
+    data: Bytes,
+    /// Room for reading more input, split off the current block
+    spare: BytesMut,
+    /// Length of the pending input growing in front of the `spare` room, `data` is empty then
+    growing: usize,
+}
+
+impl InputBuffer {
+    /// Default size of a block read from the client
+    pub const BLOCK_SIZE: usize = 64 * 1024;
+    /// Least room to read into before a new block is allocated
+    pub const MIN_READ: usize = 4 * 1024;
+    /// Most input a single command may take before the session is failed
+    pub const MAX_PENDING: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        match self.growing {
+            0 => self.data.as_ref(),
+            growing => &self.spare[..growing],
+        }
+    }
+    /// Drop `len` bytes from the front of the input once they have been interpretted
+    pub fn consume(&mut self, len: usize) {
+        if self.growing != 0 {
+            self.data = self.spare.split_to(self.growing).freeze();
+            self.growing = 0;
+        }
+        self.data.advance(len)
+    }
+    /// Drop all the pending input
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.growing = 0;
+    }
+    /// Append some more input. This copies the input, prefer `read_room()`.
+    pub fn extend_from_slice(&mut self, more: &[u8]) {
+        self.read_room(more.len())[..more.len()].copy_from_slice(more);
+        self.filled(more.len());
+    }
+    /// Get the room for reading at least `size` more bytes of input.
+    ///
+    /// The pending input (usually just an incomplete line) is kept in front of the room
+    /// so the two stay contiguous. A new block is only allocated when the current one
+    /// is used up. Tell the buffer how much has been read with `filled()`.
+    /// The pending input is not lost if the read fails.
+    pub fn read_room(&mut self, size: usize) -> &mut [u8] {
+        let pending = self.as_slice().len();
+        if self.spare.len() < pending + size {
+            let len = Self::BLOCK_SIZE.max(pending + size);
+            if pending == 0 {
+                // let go of the used up block so that it can be reclaimed
+                self.data = Bytes::new();
+                self.spare.clear();
+                // reclaims the block if nothing shares it anymore, allocates a new one otherwise
+                self.spare.reserve(len);
+                // zeroed once per block rather than on every read
+                self.spare.resize(len, 0);
+            } else {
+                let mut block = BytesMut::with_capacity(len);
+                block.extend_from_slice(self.as_slice());
+                block.resize(len, 0);
+                self.data = Bytes::new();
+                self.spare = block;
+                self.growing = pending;
+            }
+        } else if self.growing == 0 && pending != 0 {
+            // the pending input moves in front of the room once and grows in place from now on
+            self.spare[..pending].copy_from_slice(self.data.as_ref());
+            self.data = Bytes::new();
+            self.growing = pending;
+        }
+        &mut self.spare[self.growing..]
+    }
+    /// Append `len` bytes that have been read into the `read_room()`
+    pub fn filled(&mut self, len: usize) {
+        if self.growing == 0 {
+            self.data = self.spare.split_to(len).freeze();
+        } else {
+            self.growing += len;
+        }
+    }
+    /// Get a shared reference to a part of the input without copying it.
+    ///
+    /// If the part does not come from this buffer, it is copied.
+    /// So is the input still growing after an incomplete command.
+    pub fn share(&self, part: &[u8]) -> Bytes {
+        let start = self.data.as_ptr() as usize;
+        let end = start + self.data.len();
+        let part_start = part.as_ptr() as usize;
+        let part_end = part_start + part.len();
+        if start <= part_start && part_end <= end {
+            self.data.slice_ref(part)
+        } else {
+            Bytes::copy_from_slice(part)
+        }
+    }
+}
+
+impl Clone for InputBuffer {
+    fn clone(&self) -> Self {
+        let data = match self.growing {
+            0 => self.data.clone(),
+            _ => Bytes::copy_from_slice(self.as_slice()),
+        };
+        Self {
+            data,
+            spare: BytesMut::new(),
+            growing: 0,
+        }
+    }
+}
+
+impl PartialEq for InputBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for InputBuffer {}
+
+impl Deref for InputBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+
+impl From<&[u8]> for InputBuffer {
+    fn from(data: &[u8]) -> Self {
+        Self {
+            data: Bytes::copy_from_slice(data),
+            spare: BytesMut::new(),
+            growing: 0,
+        }
+    }
+}
+
+impl From<Vec<u8>> for InputBuffer {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            data: data.into(),
+            spare: BytesMut::new(),
+            growing: 0,
+        }
+    }
+}
+
+impl fmt::Debug for InputBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consume_moves_the_cursor() {
+        let mut sut = InputBuffer::from(b"helo there\r\nquit\r\n".to_vec());
+        let before = sut.as_ptr() as usize;
+        sut.consume(12);
+        assert_eq!(sut.as_slice(), b"quit\r\n");
+        assert_eq!(sut.as_ptr() as usize, before + 12);
+    }
+
+    #[test]
+    fn read_keeps_pending_input() {
+        let mut sut = InputBuffer::from(b"quit\r\nhel".to_vec());
+        sut.consume(6);
+        sut.read_room(10)[..2].copy_from_slice(b"o\n");
+        assert_eq!(sut.as_slice(), b"hel");
+        sut.filled(2);
+        assert_eq!(sut.as_slice(), b"helo\n");
+    }
+
+    #[test]
+    fn reads_reuse_the_block() {
+        let mut sut = InputBuffer::new();
+        sut.read_room(100)[..6].copy_from_slice(b"quit\r\n");
+        sut.filled(6);
+        let first = sut.as_ptr() as usize;
+        sut.consume(6);
+        sut.read_room(100)[..6].copy_from_slice(b"rset\r\n");
+        sut.filled(6);
+        assert_eq!(sut.as_slice(), b"rset\r\n");
+        assert_eq!(sut.as_ptr() as usize, first + 6);
+    }
+
+    #[test]
+    fn pending_input_grows_in_place() {
+        let mut sut = InputBuffer::from(b"quit\r\nhel".to_vec());
+        sut.consume(6);
+        sut.read_room(10)[..1].copy_from_slice(b"o");
+        sut.filled(1);
+        let first = sut.as_ptr() as usize;
+        sut.read_room(10)[..3].copy_from_slice(b" me");
+        sut.filled(3);
+        sut.read_room(10)[..2].copy_from_slice(b"\r\n");
+        sut.filled(2);
+        assert_eq!(sut.as_slice(), b"helo me\r\n");
+        assert_eq!(sut.as_ptr() as usize, first);
+        sut.consume(8);
+        assert_eq!(sut.as_slice(), b"\n");
+        assert_eq!(sut.as_ptr() as usize, first + 8);
+    }
+
+    #[test]
+    fn used_up_block_is_reclaimed() {
+        let mut sut = InputBuffer::new();
+        let room = sut.read_room(InputBuffer::MIN_READ).len();
+        sut.filled(room);
+        let first = sut.as_ptr() as usize;
+        sut.consume(room);
+        sut.read_room(InputBuffer::MIN_READ);
+        sut.filled(1);
+        assert_eq!(sut.as_ptr() as usize, first);
+    }
+
+    #[test]
+    fn share_does_not_copy() {
+        let sut = InputBuffer::from(b"Subject: test\r\n".to_vec());
+        let part = &sut.as_slice()[9..13];
+        let shared = sut.share(part);
+        assert_eq!(shared.as_ref(), b"test");
+        assert_eq!(shared.as_ptr(), part.as_ptr());
+    }
+
+    #[test]
+    fn share_copies_foreign_data() {
+        let sut = InputBuffer::from(b"Subject: test\r\n".to_vec());
+        let shared = sut.share(b"test");
+        assert_eq!(shared.as_ref(), b"test");
+    }
+}
@@ -4,6 +4,7 @@ mod driver;
 pub mod extension;
 mod extensions;
 mod host;
+mod input;
 mod interpretter;
 mod parser;
 mod path;
@@ -15,12 +16,16 @@ mod rfc3207;
 mod rfc5321;
 mod rfc821;
 mod session;
+mod session_lifecycle;
 mod session_service;
+pub(crate) mod spans;
+mod transcript;
 
 pub use self::context::*;
 pub use self::driver::*;
 pub use self::extensions::*;
 pub use self::host::*;
+pub use self::input::*;
 pub use self::interpretter::*;
 pub use self::parser::*;
 pub use self::path::*;
@@ -33,4 +38,6 @@ pub use self::rfc5321::*;
 pub use self::rfc5321::*;
 pub use self::rfc821::*;
 pub use self::session::*;
+pub use self::session_lifecycle::*;
 pub use self::session_service::*;
+pub use self::transcript::*;
@@ -1,8 +1,14 @@
+#[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+compile_error!(
+    "Prudence needs a runtime for its timers, enable the runtime-async-std or runtime-tokio feature"
+);
+
+use crate::common::io::*;
 use crate::common::*;
 use crate::io::tls::MayBeTls;
 use crate::mail::{AcceptsInterpretter, AcceptsSessionService, MailSetup};
+use crate::runtime::{default_runtime, timeout, Runtime};
 use crate::smtp::{Interpret, InterpretResult, ParseError, SessionService, SmtpContext};
-use smol_timeout::TimeoutExt;
 use std::time::{Duration, Instant};
 
 /// Prevent bad SMTP behavior
@@ -12,6 +18,8 @@ pub struct Prudence {
     wait_for_banner_delay: Option<Duration>,
     /// Maximum read time
     read_timeout: Option<Duration>,
+    /// Runtime providing the timers, the default runtime if not set
+    runtime: Option<Arc<dyn Runtime + Send + Sync>>,
 }
 
 impl Prudence {
@@ -25,6 +33,14 @@ impl Prudence {
         self.read_timeout = Some(timeout);
         self
     }
+    /// Use the given runtime for timers rather than the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+    fn runtime(&self) -> Arc<dyn Runtime + Send + Sync> {
+        self.runtime.clone().unwrap_or_else(default_runtime)
+    }
 }
 
 impl<T> MailSetup<T> for Prudence
@@ -61,13 +77,20 @@ impl SessionService for PrudentService {
         's: 'f,
     {
         Box::pin(async move {
+            let runtime = self.config.runtime();
             if let Some(delay) = self.config.wait_for_banner_delay {
                 let mut buf = [0u8; 425];
-                use async_std::io::ReadExt;
-                match io.read(&mut buf[..]).timeout(delay).await {
+                let read = poll_fn(|cx| Pin::new(&mut *io).poll_read(cx, &mut buf[..]));
+                match timeout(&runtime, delay, read).await {
                     Some(Ok(0)) => {
                         // this just looks like the client gave up and left
-                        warn!("{} touch and go!", state.session.connection.peer_addr)
+                        warn!(
+                            "{} touch and go!",
+                            state
+                                .session
+                                .privacy
+                                .ip(&state.session.connection.peer_addr)
+                        )
                     }
                     Some(Ok(len)) => {
                         state.session.input.extend_from_slice(&buf[0..len]);
@@ -87,6 +110,7 @@ impl SessionService for PrudentService {
             }
 
             *io = Box::new(PrudentIo::new(
+                runtime,
                 self.config.read_timeout,
                 std::mem::replace(io, Box::new(Dummy)),
             ));
@@ -102,24 +126,33 @@ struct PrudentState {
 }
 
 struct PrudentIo {
-    expired: Pin<Box<dyn Future<Output = ()> + Sync + Send>>,
+    runtime: Arc<dyn Runtime + Send + Sync>,
+    expired: S2Fut<'static, ()>,
     timeout: Option<Duration>,
     io: Box<dyn MayBeTls>,
 }
 
 impl PrudentIo {
-    pub fn new<IO: MayBeTls + 'static>(timeout: Option<Duration>, io: IO) -> Self {
+    pub fn new<IO: MayBeTls + 'static>(
+        runtime: Arc<dyn Runtime + Send + Sync>,
+        timeout: Option<Duration>,
+        io: IO,
+    ) -> Self {
         PrudentIo {
-            expired: Box::pin(Self::expire(timeout)),
+            expired: Self::expire(&runtime, timeout),
+            runtime,
             timeout,
             io: Box::new(io),
         }
     }
-    async fn expire(timeout: Option<Duration>) {
+    fn expire(
+        runtime: &Arc<dyn Runtime + Send + Sync>,
+        timeout: Option<Duration>,
+    ) -> S2Fut<'static, ()> {
         if let Some(timeout) = timeout {
-            pending::<()>().timeout(timeout).await;
+            runtime.sleep(timeout)
         } else {
-            pending::<()>().await;
+            Box::pin(pending())
         }
     }
 }
@@ -155,7 +188,7 @@ impl io::Read for PrudentIo {
         let res = Pin::new(&mut self.io).poll_read(cx, buf);
 
         if let Poll::Ready(Ok(_)) = res {
-            self.expired = Box::pin(Self::expire(self.timeout))
+            self.expired = Self::expire(&self.runtime, self.timeout)
         }
 
         res
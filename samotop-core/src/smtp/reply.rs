@@ -112,6 +112,8 @@ pub enum SmtpReply {
     MailboxNotAvailableError,
     /// 451 Requested action aborted
     ProcesingError,
+    /// 451 Requested action aborted with a custom explanation
+    MailRejectedError(String),
     /// 452 Requested action not taken
     StorageError,
     /// 455 right now the parameters given cannot be accomodated
@@ -119,6 +121,8 @@ pub enum SmtpReply {
     /// 550 Requested action not taken: mailbox unavailable (e.g.,
     ///     mailbox not found, no access, or command rejected for policy reasons)
     MailboxNotAvailableFailure,
+    /// 550 Requested action not taken with a custom explanation (e.g. 5.7.1 virus found)
+    MailRejectedFailure(String),
     /// 551 please try @forward-path (See Section 3.4)
     UserNotLocalFailure(String),
     /// 552 Requested mail action aborted: exceeded storage allocation
@@ -127,6 +131,8 @@ pub enum SmtpReply {
     MailboxNameInvalidFailure,
     /// 554 (Or, in the case of a connection-opening response, "No SMTP service here")
     TransactionFailure,
+    /// 554 @domain No SMTP service here - the connection-opening response
+    NoServiceFailure(String),
     /// 555 MAIL FROM/RCPT TO parameters not recognized or not implemented
     UnknownMailParametersFailure,
     /// 556 RFC 7504
@@ -173,6 +179,8 @@ impl SmtpReply {
             MailboxNotAvailableError => 450,
             // Requested action aborted
             ProcesingError => 451,
+            // Requested action aborted with a custom explanation
+            MailRejectedError(_) => 451,
             // Requested action not taken
             StorageError => 452,
             // right now the parameters given cannot be accomodated
@@ -180,6 +188,8 @@ impl SmtpReply {
             // Requested action not taken: mailbox unavailable (e.g.,
             // mailbox not found, no access, or command rejected for policy reasons)
             MailboxNotAvailableFailure => 550,
+            // Requested action not taken with a custom explanation
+            MailRejectedFailure(_) => 550,
             // please try @forward-path (See Section 3.4)
             UserNotLocalFailure(_) => 551,
             // Requested mail action aborted: exceeded storage allocation
@@ -188,6 +198,7 @@ impl SmtpReply {
             MailboxNameInvalidFailure => 553,
             // (Or, in the case of a connection-opening response, "No SMTP service here")
             TransactionFailure => 554,
+            NoServiceFailure(_) => 554,
             // MAIL FROM/RCPT TO parameters not recognized or not implemented
             UnknownMailParametersFailure => 555,
             // RFC 7504
@@ -236,12 +247,14 @@ impl SmtpReply {
                 "Requested mail action not taken: mailbox unavailable".to_owned()
             }
             ProcesingError => "Requested action aborted: error in processing.".to_owned(),
+            MailRejectedError(ref text) => text.to_string(),
 
             StorageError => "Requested action not taken: insufficient system storage".to_owned(),
             ParametersNotAccommodatedError => "Server unable to accommodate parameters".to_owned(),
             MailboxNotAvailableFailure => {
                 "Requested action not taken: mailbox unavailable".to_owned()
             }
+            MailRejectedFailure(ref text) => text.to_string(),
             UserNotLocalFailure(ref forward_path) => {
                 format!("User not local; please try {}", forward_path)
             }
@@ -252,6 +265,7 @@ impl SmtpReply {
                 "Requested action not taken: mailbox name not allowed".to_owned()
             }
             TransactionFailure => "Transaction failed".to_owned(),
+            NoServiceFailure(ref domain) => format!("{} No SMTP service here", domain),
             UnknownMailParametersFailure => {
                 "MAIL FROM/RCPT TO parameters not recognized or not implemented".to_owned()
             }
@@ -17,7 +17,7 @@ impl Action<SmtpHelo> for Lmtp {
     {
         Box::pin(async move {
             match cmd.verb.to_ascii_uppercase().as_str() {
-                "LHLO" => apply_helo(cmd, true, state),
+                "LHLO" => apply_helo(cmd, true, state).await,
                 _ => Esmtp.apply(SmtpUnknownCommand::default(), state).await,
             }
         })
@@ -33,6 +33,9 @@ impl Action<SmtpCommand> for Lmtp {
         Box::pin(async move {
             use SmtpCommand as C;
             match cmd {
+                C::Helo(_) if state.session.is_refused() => {
+                    state.session.say_command_sequence_fail()
+                }
                 C::Helo(helo) => Lmtp.apply(helo, state).await,
                 cmd => Esmtp.apply(cmd, state).await,
             }
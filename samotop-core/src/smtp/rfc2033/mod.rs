@@ -21,7 +21,7 @@ impl Lmtp {
     pub fn with<P>(&self, parser: P) -> LmtpConfigured<P>
     where
         P: Parser<SmtpCommand>,
-        P: Parser<MailBody<Vec<u8>>>,
+        P: Parser<MailBody<Bytes>>,
         P: Send + Sync + 'static,
     {
         LmtpConfigured {
@@ -38,7 +38,7 @@ pub struct LmtpConfigured<P> {
 impl<T: AcceptsSessionService + AcceptsInterpretter, P> MailSetup<T> for LmtpConfigured<P>
 where
     P: Parser<SmtpCommand>,
-    P: Parser<MailBody<Vec<u8>>>,
+    P: Parser<MailBody<Bytes>>,
     P: fmt::Debug + Send + Sync + 'static,
 {
     fn setup(self, config: &mut T) {
@@ -47,7 +47,7 @@ where
                 .parse::<SmtpCommand>()
                 .with(self.parser.clone())
                 .and_apply(Lmtp)
-                .parse::<MailBody<Vec<u8>>>()
+                .parse::<MailBody<Bytes>>()
                 .with(self.parser.clone())
                 .and_apply(Lmtp),
         );
@@ -58,7 +58,7 @@ where
 impl<P> SessionService for LmtpConfigured<P>
 where
     P: Parser<SmtpCommand>,
-    P: Parser<MailBody<Vec<u8>>>,
+    P: Parser<MailBody<Bytes>>,
     P: fmt::Debug + Send + Sync + 'static,
 {
     fn prepare_session<'a, 'i, 's, 'f>(
@@ -71,7 +71,7 @@ where
         'i: 'f,
         's: 'f,
     {
-        state.session.say_service_ready();
+        state.session.say_banner();
         Box::pin(ready(()))
     }
 }
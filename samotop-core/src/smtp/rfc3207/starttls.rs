@@ -1,6 +1,6 @@
 use super::StartTls;
 use crate::common::S1Fut;
-use crate::smtp::{extension, Action, SmtpContext};
+use crate::smtp::{extension, Action, SmtpContext, TransactionEnd};
 
 impl Action<StartTls> for StartTls {
     /// Applies given helo to the state
@@ -16,7 +16,7 @@ impl Action<StartTls> for StartTls {
             } else {
                 // you cannot STARTTLS twice so we only advertise it before first use
                 if state.session.extensions.disable(&extension::STARTTLS) {
-                    state.session.reset();
+                    state.end_transaction(TransactionEnd::Aborted).await;
                     state.session.say_start_tls()
                 } else {
                     state.session.say_not_implemented()
@@ -1,7 +1,8 @@
 use super::Esmtp;
 use crate::{
     common::*,
-    smtp::{command::MailBody, Action, SmtpContext, SmtpSession},
+    mail::{CheckMailFailure, CheckMailResult, MailCheck},
+    smtp::{command::MailBody, Action, SmtpContext, SmtpSession, TransactionEnd},
 };
 
 impl<B: AsRef<[u8]> + Sync + Send + fmt::Debug + 'static> Action<MailBody<B>> for Esmtp {
@@ -56,7 +57,7 @@ where
                 }
                 Err(e) => {
                     warn!("Failed to write mail data for {} - {}", mailid, e);
-                    state.session.reset();
+                    state.end_transaction(TransactionEnd::Failed).await;
                     // CheckMe: following this reset, we are not sending any response yet. MailBodyEnd should do that.
                 }
             };
@@ -66,10 +67,35 @@ where
                 sink
             } else {
                 state.session.say_mail_queue_failed_temporarily();
-                state.session.reset();
+                state.end_transaction(TransactionEnd::Failed).await;
                 return;
             };
-            if match poll_fn(move |cx| sink.as_mut().poll_close(cx)).await {
+            // all the data must pass through before the content gets checked
+            if let Err(e) = poll_fn(|cx| sink.as_mut().poll_flush(cx)).await {
+                warn!("Failed to flush mail {}: {}", mailid, e);
+                state.session.say_mail_queue_failed_temporarily();
+                state.end_transaction(TransactionEnd::Failed).await;
+                return;
+            }
+            match state.service().check_mail(&mut state.session).await {
+                CheckMailResult::Accepted => {}
+                CheckMailResult::Failed(failure, description) => {
+                    // dropping the sink without closing it, the mail shall not be dispatched
+                    drop(sink);
+                    let replies = match (lmtp, &failure) {
+                        (_, CheckMailFailure::TerminateSession) | (false, _) => 1,
+                        (true, _) => state.session.transaction.rcpts.len(),
+                    };
+                    for _ in 0..replies {
+                        state
+                            .session
+                            .say_mail_check_failed(failure.clone(), description.clone());
+                    }
+                    state.end_transaction(TransactionEnd::Failed).await;
+                    return;
+                }
+            }
+            let end = if match poll_fn(move |cx| sink.as_mut().poll_close(cx)).await {
                 Ok(()) => true,
                 Err(e) if e.kind() == std::io::ErrorKind::NotConnected => true,
                 Err(e) => {
@@ -91,10 +117,12 @@ where
                 } else {
                     state.session.say_mail_queued(mailid.as_str());
                 }
+                TransactionEnd::Queued
             } else {
                 state.session.say_mail_queue_failed_temporarily();
-            }
-            state.session.reset();
+                TransactionEnd::Failed
+            };
+            state.end_transaction(end).await;
         }
     }
 }
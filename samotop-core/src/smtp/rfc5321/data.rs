@@ -1,7 +1,7 @@
 use super::Esmtp;
 use crate::common::S1Fut;
 use crate::mail::{DispatchError, MailDispatch};
-use crate::smtp::{command::SmtpData, Action, SmtpContext};
+use crate::smtp::{command::SmtpData, Action, SmtpContext, TransactionEnd};
 
 impl Action<SmtpData> for Esmtp {
     fn apply<'a, 's, 'f>(&'a self, _cmd: SmtpData, state: &'s mut SmtpContext) -> S1Fut<'f, ()>
@@ -15,8 +15,8 @@ impl Action<SmtpData> for Esmtp {
                 || state.session.transaction.mail.is_none()
                 || state.session.transaction.rcpts.is_empty()
             {
-                state.session.reset();
                 state.session.say_command_sequence_fail();
+                state.end_transaction(TransactionEnd::Failed).await;
                 return;
             }
 
@@ -26,19 +26,27 @@ impl Action<SmtpData> for Esmtp {
                         "Send_mail returned OK message without sink for transaction {}",
                         state.session.transaction.id
                     );
-                    state.session.reset();
                     state.session.say_mail_queue_failed_temporarily();
+                    state.end_transaction(TransactionEnd::Failed).await;
                 }
-                Ok(()) => {
-                    state.session.say_start_data_challenge();
-                }
+                Ok(()) => match state.session.transaction.write_extra_headers().await {
+                    Ok(()) => state.session.say_start_data_challenge(),
+                    Err(e) => {
+                        warn!(
+                            "Failed to write extra headers for transaction {}: {}",
+                            state.session.transaction.id, e
+                        );
+                        state.session.say_mail_queue_failed_temporarily();
+                        state.end_transaction(TransactionEnd::Failed).await;
+                    }
+                },
                 Err(DispatchError::Permanent) => {
-                    state.session.reset();
                     state.session.say_mail_queue_refused();
+                    state.end_transaction(TransactionEnd::Failed).await;
                 }
                 Err(DispatchError::Temporary) => {
-                    state.session.reset();
                     state.session.say_mail_queue_failed_temporarily();
+                    state.end_transaction(TransactionEnd::Failed).await;
                 }
             };
         })
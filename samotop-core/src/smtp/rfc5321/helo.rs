@@ -2,7 +2,7 @@ use crate::{
     common::S1Fut,
     smtp::{
         command::{SmtpHelo, SmtpUnknownCommand},
-        Action, Esmtp, SmtpContext,
+        Action, Esmtp, SessionLifecycle, SmtpContext, TransactionEnd,
     },
 };
 
@@ -14,8 +14,8 @@ impl Action<SmtpHelo> for Esmtp {
     {
         Box::pin(async move {
             match cmd.verb.to_ascii_uppercase().as_str() {
-                "EHLO" => apply_helo(cmd, true, state),
-                "HELO" => apply_helo(cmd, false, state),
+                "EHLO" => apply_helo(cmd, true, state).await,
+                "HELO" => apply_helo(cmd, false, state).await,
                 verb => {
                     Esmtp
                         .apply(
@@ -31,8 +31,10 @@ impl Action<SmtpHelo> for Esmtp {
 
 /// Applies given helo to the state
 /// It assumes it is the right HELO/EHLO/LHLO variant
-pub fn apply_helo(helo: SmtpHelo, is_extended: bool, state: &mut SmtpContext) {
+pub async fn apply_helo(helo: SmtpHelo, is_extended: bool, state: &mut SmtpContext) {
+    state.end_transaction(TransactionEnd::Aborted).await;
     state.session.reset_helo(helo.host.to_string());
+    state.service().on_helo(&mut state.session).await;
 
     match is_extended {
         false => state.session.say_helo(),
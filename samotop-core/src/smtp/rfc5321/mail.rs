@@ -1,7 +1,7 @@
 use crate::{
-    common::{Identify, S1Fut},
-    mail::{MailGuard, StartMailResult},
-    smtp::{command::SmtpMail, Action, Esmtp, SmtpContext},
+    common::S1Fut,
+    mail::{IdGenerator, MailGuard, StartMailResult},
+    smtp::{command::SmtpMail, spans, Action, Esmtp, SmtpContext, TransactionEnd},
 };
 
 impl Action<SmtpMail> for Esmtp {
@@ -15,23 +15,25 @@ impl Action<SmtpMail> for Esmtp {
                 state.session.say_command_sequence_fail();
                 return;
             }
-            state.session.reset();
+            state.end_transaction(TransactionEnd::Aborted).await;
             state.session.transaction.mail = Some(cmd);
+            spans::start_transaction(state);
 
             use StartMailResult as R;
-            match state.service().start_mail(&mut state.session).await {
+            let span = spans::transaction(state);
+            let service = state.service();
+            let start = service.start_mail(&mut state.session);
+            match spans::instrument(span, start).await {
                 R::Failed(failure, description) => {
                     state.session.say_mail_failed(failure, description);
                 }
                 R::Accepted => {
                     if state.session.transaction.id.is_empty() {
-                        let id = format!("{}@{}", Identify::now(), state.session.service_name);
-                        warn!(
-                            "Mail transaction ID is empty. Will use time based ID {}",
-                            id
-                        );
-                        state.session.transaction.id = id;
+                        let ids = state.ids();
+                        state.session.transaction.id =
+                            format!("{}@{}", ids.generate(), state.session.service_name);
                     }
+                    spans::record_transaction(state);
                     state.session.say_ok_info(format!(
                         "Ok! Transaction {} started.",
                         state.session.transaction.id
@@ -96,6 +98,43 @@ mod tests {
         })
     }
 
+    #[cfg(feature = "driver")]
+    #[test]
+    fn transaction_id_is_generated() {
+        use crate::{
+            common::Dummy,
+            io::{tls::MayBeTls, ConnectionInfo},
+            mail::{AcceptsIdGenerator, Builder, IdGenerator, MailSetup},
+            smtp::SessionService,
+        };
+        #[derive(Debug)]
+        struct Fixed;
+        impl IdGenerator for Fixed {
+            fn generate(&self) -> String {
+                "fixed".to_owned()
+            }
+        }
+        impl<T: AcceptsIdGenerator> MailSetup<T> for Fixed {
+            fn setup(self, config: &mut T) {
+                config.set_id_generator(self)
+            }
+        }
+
+        async_std::task::block_on(async move {
+            let service = (Builder + Fixed).build();
+            let mut set = SmtpContext::new(service.clone(), ConnectionInfo::default());
+            let mut io: Box<dyn MayBeTls> = Box::new(Dummy);
+            service.prepare_session(&mut io, &mut set).await;
+            set.session.peer_name = Some("xx.io".to_owned());
+            set.session.service_name = "testik".to_owned();
+
+            Esmtp
+                .apply(SmtpMail::Mail(SmtpPath::Postmaster, vec![]), &mut set)
+                .await;
+            assert_eq!(set.session.transaction.id, "fixed@testik");
+        })
+    }
+
     #[test]
     fn command_sequence_is_enforced() {
         async_std::task::block_on(async move {
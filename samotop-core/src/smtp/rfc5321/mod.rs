@@ -28,7 +28,7 @@ impl Esmtp {
     pub fn with<P>(&self, parser: P) -> EsmtpConfigured<P>
     where
         P: Parser<SmtpCommand>,
-        P: Parser<MailBody<Vec<u8>>>,
+        P: Parser<MailBody<Bytes>>,
         P: Send + Sync + 'static,
     {
         EsmtpConfigured {
@@ -40,7 +40,7 @@ impl<P, T> MailSetup<T> for EsmtpConfigured<P>
 where
     T: AcceptsSessionService + AcceptsInterpretter,
     P: Parser<SmtpCommand>,
-    P: Parser<MailBody<Vec<u8>>>,
+    P: Parser<MailBody<Bytes>>,
     P: fmt::Debug + Sync + Send + 'static,
 {
     fn setup(self, config: &mut T) {
@@ -49,7 +49,7 @@ where
                 .parse::<SmtpCommand>()
                 .with(self.parser.clone())
                 .and_apply(Esmtp)
-                .parse::<MailBody<Vec<u8>>>()
+                .parse::<MailBody<Bytes>>()
                 .with(self.parser.clone())
                 .and_apply(Esmtp),
         );
@@ -65,7 +65,7 @@ pub struct EsmtpConfigured<P> {
 impl<P> SessionService for EsmtpConfigured<P>
 where
     P: Parser<SmtpCommand>,
-    P: Parser<MailBody<Vec<u8>>>,
+    P: Parser<MailBody<Bytes>>,
     P: fmt::Debug + Sync + Send + 'static,
 {
     fn prepare_session<'a, 'i, 's, 'f>(
@@ -78,7 +78,7 @@ where
         'i: 'f,
         's: 'f,
     {
-        state.session.say_service_ready();
+        state.session.say_banner();
         Box::pin(ready(()))
     }
 }
@@ -91,6 +91,11 @@ impl Action<SmtpCommand> for Esmtp {
     {
         Box::pin(async move {
             use SmtpCommand as C;
+            if state.session.is_refused() && cmd != C::Quit {
+                // RFC 5321 3.1 - after 554 refusal, the client should only QUIT
+                state.session.say_command_sequence_fail();
+                return;
+            }
             match cmd {
                 C::Helo(helo) => self.apply(helo, state).await,
                 C::Mail(mail) => self.apply(mail, state).await,
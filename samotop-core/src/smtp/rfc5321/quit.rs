@@ -1,7 +1,7 @@
 use super::Esmtp;
 use crate::{
     common::S1Fut,
-    smtp::{command::SmtpQuit, Action, SmtpContext},
+    smtp::{command::SmtpQuit, Action, SmtpContext, TransactionEnd},
 };
 
 impl Action<SmtpQuit> for Esmtp {
@@ -11,7 +11,7 @@ impl Action<SmtpQuit> for Esmtp {
         's: 'f,
     {
         Box::pin(async move {
-            state.session.reset();
+            state.end_transaction(TransactionEnd::Aborted).await;
             state.session.say_shutdown_ok();
         })
     }
@@ -2,7 +2,7 @@ use super::Esmtp;
 use crate::{
     common::S1Fut,
     mail::{AddRecipientResult, MailGuard, Recipient},
-    smtp::{command::SmtpRcpt, Action, SmtpContext},
+    smtp::{command::SmtpRcpt, spans, Action, SmtpContext},
 };
 
 impl Action<SmtpRcpt> for Esmtp {
@@ -25,6 +25,7 @@ impl Action<SmtpRcpt> for Esmtp {
             {
                 AddRecipientResult::Inconclusive(rcpt) => {
                     state.session.transaction.rcpts.push(rcpt);
+                    spans::record_transaction(state);
                     state.session.say_ok();
                 }
                 AddRecipientResult::Failed(failure, description) => {
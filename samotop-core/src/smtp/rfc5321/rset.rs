@@ -1,6 +1,6 @@
 use crate::{
     common::S1Fut,
-    smtp::{command::SmtpRset, Action, Esmtp, SmtpContext},
+    smtp::{command::SmtpRset, Action, Esmtp, SmtpContext, TransactionEnd},
 };
 
 impl Action<SmtpRset> for Esmtp {
@@ -10,7 +10,7 @@ impl Action<SmtpRset> for Esmtp {
         's: 'f,
     {
         Box::pin(async move {
-            state.session.reset();
+            state.end_transaction(TransactionEnd::Aborted).await;
             state.session.say_ok();
         })
     }
@@ -1,5 +1,10 @@
+use crate::common::Arc;
+use crate::dns::Resolver;
 use crate::io::ConnectionInfo;
-use crate::mail::{AddRecipientFailure, StartMailFailure, Transaction};
+use crate::mail::{
+    AcceptConnectionFailure, AddRecipientFailure, CheckMailFailure, Privacy, StartMailFailure,
+    Transaction,
+};
 use crate::smtp::*;
 
 #[derive(Debug)]
@@ -12,14 +17,28 @@ pub struct SmtpSession {
     pub service_name: String,
     /// The name of the peer as introduced by the HELO command
     pub peer_name: Option<String>,
+    /// Whether the session is TLS encrypted, be it implicit TLS or after STARTTLS
+    pub encrypted: bool,
+    /// The user name authenticated by SMTP AUTH, if any
+    pub authenticated: Option<String>,
     /// Output to be processed by a driver - responses and IO controls
     pub output: Vec<DriverControl>,
     /// Input to be interpretted
-    pub input: Vec<u8>,
+    pub input: InputBuffer,
     /// Special mode used to switch parsers
     pub mode: Option<&'static str>,
     /// Current e-mail transaction
     pub transaction: Transaction,
+    /// Statistics of this session
+    pub stats: SessionStats,
+    /// The reason of shutting the session down, if known
+    pub end: Option<SessionEnd>,
+    /// Set if a connection guard refused to serve the client
+    pub refused: Option<AcceptConnectionFailure>,
+    /// The DNS resolver shared by the service, see `Dns`
+    pub dns: Option<Arc<dyn Resolver>>,
+    /// Privacy policy for logging the session, set by the service
+    pub privacy: Arc<Privacy>,
 }
 
 impl Default for SmtpSession {
@@ -29,10 +48,17 @@ impl Default for SmtpSession {
             extensions: Default::default(),
             service_name: "samotop".to_string(),
             peer_name: Default::default(),
+            encrypted: Default::default(),
+            authenticated: Default::default(),
             output: Default::default(),
             input: Default::default(),
             mode: Default::default(),
             transaction: Default::default(),
+            stats: Default::default(),
+            end: Default::default(),
+            refused: Default::default(),
+            dns: Default::default(),
+            privacy: Default::default(),
         }
     }
 }
@@ -50,6 +76,10 @@ impl SmtpSession {
             ..Default::default()
         }
     }
+    /// Only QUIT is accepted once the connection has been refused
+    pub fn is_refused(&self) -> bool {
+        self.refused.is_some()
+    }
     pub fn is_expecting_commands(&self) -> bool {
         self.mode.is_none() || self.transaction.sink.is_none()
     }
@@ -63,9 +93,12 @@ impl SmtpSession {
         self.mode = None;
     }
 
-    /// Shut the session down without a response
+    /// Shut the session down without a response.
+    ///
+    /// The transaction is not reset here. The service ends it
+    /// as `TransactionEnd::Aborted` together with the session.
     pub fn shutdown(&mut self) -> SayResult {
-        self.reset();
+        self.mode = None;
         self.say(DriverControl::Shutdown)
     }
     pub fn pop_control(&mut self) -> Option<DriverControl> {
@@ -107,6 +140,18 @@ impl SmtpSession {
         // TODO - indicate ESMTP if available
         self.say_reply(SmtpReply::ServiceReadyInfo(self.service_name.clone()))
     }
+    /// Reply with the connection-opening banner as decided by the connection guards:
+    /// "220 @name service ready", "554 @name No SMTP service here"
+    /// or "421 @name service not available, closing transmission channel"
+    pub fn say_banner(&mut self) -> SayResult {
+        match self.refused {
+            None => self.say_service_ready(),
+            Some(AcceptConnectionFailure::Rejected) => {
+                self.say_reply(SmtpReply::NoServiceFailure(self.service_name.clone()))
+            }
+            Some(AcceptConnectionFailure::TerminateSession) => self.say_shutdown_service_err(),
+        }
+    }
     /// Reply something like "250 @local greets @remote"
     pub fn say_helo(&mut self) -> SayResult {
         self.say_reply(SmtpReply::OkHeloInfo {
@@ -140,6 +185,7 @@ impl SmtpSession {
     /// Reply "421 @name service not available, closing transmission channel" and shut the session down
     pub fn say_shutdown_timeout(&mut self) -> SayResult {
         warn!("Timeout expired.");
+        self.end = Some(SessionEnd::Timeout);
         self.say_shutdown_service_err()
     }
     /// Reply "421 @name service not available, closing transmission channel" and shut the session down
@@ -155,6 +201,7 @@ impl SmtpSession {
     }
     /// Normal response to quit command
     pub fn say_shutdown_ok(&mut self) -> SayResult {
+        self.end = Some(SessionEnd::Quit);
         self.say_shutdown(SmtpReply::ClosingConnectionInfo(self.service_name.clone()))
     }
     pub fn say_mail_failed(&mut self, failure: StartMailFailure, description: String) -> SayResult {
@@ -163,6 +210,7 @@ impl SmtpSession {
         match failure {
             F::TerminateSession => self.say_shutdown_service_err(),
             F::Rejected => self.say_reply(SmtpReply::MailboxNotAvailableFailure),
+            F::Refused(text) => self.say_reply(SmtpReply::MailRejectedFailure(text)),
             F::InvalidSender => self.say_reply(SmtpReply::MailboxNameInvalidFailure),
             F::InvalidParameter => self.say_reply(SmtpReply::UnknownMailParametersFailure),
             F::InvalidParameterValue => self.say_reply(SmtpReply::ParametersNotAccommodatedError),
@@ -189,6 +237,26 @@ impl SmtpSession {
             F::StorageExhaustedPermanently => self.say_reply(SmtpReply::StorageFailure),
             F::StorageExhaustedTemporarily => self.say_reply(SmtpReply::StorageError),
             F::FailedTemporarily => self.say_reply(SmtpReply::ProcesingError),
+            F::Deferred(text) => self.say_reply(SmtpReply::MailRejectedError(text)),
+        }
+    }
+    pub fn say_mail_check_failed(
+        &mut self,
+        failure: CheckMailFailure,
+        description: String,
+    ) -> SayResult {
+        use CheckMailFailure as F;
+        info!("Mail check failed: {:?}, {}", failure, description);
+        match failure {
+            F::TerminateSession => self.say_shutdown_service_err(),
+            F::FailedTemporarily if description.is_empty() => {
+                self.say_reply(SmtpReply::ProcesingError)
+            }
+            F::FailedTemporarily => self.say_reply(SmtpReply::MailRejectedError(description)),
+            F::RejectedPermanently if description.is_empty() => {
+                self.say_reply(SmtpReply::MailboxNotAvailableFailure)
+            }
+            F::RejectedPermanently => self.say_reply(SmtpReply::MailRejectedFailure(description)),
         }
     }
     pub fn say_ok_recipient_not_local(&mut self, path: SmtpPath) -> SayResult {
@@ -226,7 +294,7 @@ impl std::fmt::Display for SmtpSession {
             self.extensions
                 .iter()
                 .fold(String::new(), |s, r| s + format!("{}, ", r).as_ref()),
-            self.connection,
+            self.connection.redacted(&self.privacy),
             self.input.len(),
             self.output.len()
         )
@@ -248,6 +316,7 @@ mod tests {
         sut.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Null, vec![]));
         sut.transaction.rcpts.push(Recipient::null());
         sut.transaction.extra_headers.insert_str(0, "feeeha");
+        sut.transaction.set(42u32);
         sut.reset();
         assert!(sut.transaction.is_empty());
     }
@@ -0,0 +1,188 @@
+use crate::common::*;
+use crate::smtp::SmtpSession;
+use std::ops::Deref;
+
+/**
+The service which implements this trait is notified about SMTP session milestones.
+
+It is the place to release per-session or per-transaction resources reliably,
+such as rate limiter slots, quota reservations or metrics gauges.
+
+```
+use log::info;
+use samotop_core::common::*;
+use samotop_core::smtp::*;
+
+/// Counts the mails queued in a session and logs it at the end
+#[derive(Clone, Debug)]
+pub struct CountMail;
+
+impl SessionLifecycle for CountMail
+{
+    fn on_helo<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        info!("{:?}: {} mails", reason, session.stats.mails_queued);
+        Box::pin(ready(()))
+    }
+}
+```
+*/
+pub trait SessionLifecycle: fmt::Debug {
+    /// Called after a HELO/EHLO/LHLO has been accepted, before the reply is sent.
+    fn on_helo<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f;
+    /// Called when a mail transaction ends, just before it is reset.
+    /// The transaction is still available in the session.
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f;
+    /// Called once the session is over, for whatever reason.
+    /// Session statistics are available in `session.stats`.
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f;
+}
+
+/// How a mail transaction ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionEnd {
+    /// The mail has been accepted for delivery
+    Queued,
+    /// The mail has been refused or it failed in processing
+    Failed,
+    /// The client abandoned the transaction - RSET, MAIL, HELO, QUIT or the session ended
+    Aborted,
+}
+
+/// Why an SMTP session ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The client said QUIT
+    Quit,
+    /// The client went away without saying QUIT
+    Disconnected,
+    /// The client took too long
+    Timeout,
+    /// The service shut the session down
+    Shutdown,
+    /// The session failed on an IO or other error
+    Failed(String),
+}
+
+/// SMTP session statistics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Number of bytes received from the client
+    pub bytes_received: usize,
+    /// Number of bytes sent to the client
+    pub bytes_sent: usize,
+    /// Number of mail transactions that have ended
+    pub transactions: usize,
+    /// Number of mails accepted for delivery
+    pub mails_queued: usize,
+}
+
+impl<S: SessionLifecycle + ?Sized, T: Deref<Target = S>> SessionLifecycle for T
+where
+    T: fmt::Debug + Send + Sync,
+    S: Sync,
+{
+    fn on_helo<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::on_helo(Deref::deref(self), session).await })
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::on_transaction_end(Deref::deref(self), session, end).await })
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move { S::on_session_end(Deref::deref(self), session, reason).await })
+    }
+}
+
+impl SessionLifecycle for Dummy {
+    fn on_helo<'a, 's, 'f>(&'a self, _session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_transaction_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _end: TransactionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+    fn on_session_end<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        _reason: SessionEnd,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(()))
+    }
+}
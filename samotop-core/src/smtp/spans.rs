@@ -0,0 +1,192 @@
+//! Optional `tracing` spans correlating the session, its mail transactions and downstream delivery.
+//!
+//! With the `tracing` feature, each session runs in a `session` span and each mail transaction
+//! in a child `transaction` span. Events of guards, dispatches and delivery transports are then
+//! tied to the inbound session. Without the feature these are no-ops.
+
+use crate::{common::*, io::ConnectionInfo, smtp::SmtpContext};
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+/// Placeholder without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub(crate) struct Span;
+
+/// The session span with the connection id, peer address and TLS status
+pub(crate) fn session(_state: &mut SmtpContext, _connection: &ConnectionInfo, _tls: bool) -> Span {
+    #[cfg(feature = "tracing")]
+    {
+        let span = info_span!(
+            "session",
+            connection = %_connection.id,
+            peer = %_state.session.privacy.ip(&_connection.peer_addr),
+            local = %_connection.local_addr,
+            tls = _tls
+        );
+        _state.set(SessionSpan(span.clone()));
+        span
+    }
+    #[cfg(not(feature = "tracing"))]
+    Span
+}
+
+/// The span of the current transaction if there is one, otherwise the current span
+pub(crate) fn transaction(_state: &SmtpContext) -> Span {
+    #[cfg(feature = "tracing")]
+    {
+        _state
+            .session
+            .transaction
+            .get::<TransactionSpan>()
+            .map(|TransactionSpan(span)| span.clone())
+            .unwrap_or_else(Span::current)
+    }
+    #[cfg(not(feature = "tracing"))]
+    Span
+}
+
+/// Run the future within the span
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(span: Span, future: F) -> impl Future<Output = F::Output> {
+    tracing::Instrument::instrument(future, span)
+}
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(_span: Span, future: F) -> F {
+    future
+}
+
+/// The session is now encrypted
+pub(crate) fn tls(_state: &SmtpContext) {
+    #[cfg(feature = "tracing")]
+    if let Some(SessionSpan(span)) = _state.get() {
+        span.record("tls", &true);
+    }
+}
+
+/// Start the transaction span, call after setting the mail command
+pub(crate) fn start_transaction(_state: &mut SmtpContext) {
+    #[cfg(feature = "tracing")]
+    {
+        let parent = _state
+            .get::<SessionSpan>()
+            .map(|SessionSpan(span)| span.clone())
+            .unwrap_or_else(Span::current);
+        let privacy = _state.session.privacy.clone();
+        let transaction = &mut _state.session.transaction;
+        let sender = transaction
+            .mail
+            .as_ref()
+            .map(|mail| privacy.address(mail.sender()))
+            .unwrap_or_default();
+        let span = info_span!(
+            parent: &parent,
+            "transaction",
+            id = tracing::field::Empty,
+            sender = %sender,
+            rcpts = 0usize
+        );
+        transaction.set(TransactionSpan(span));
+    }
+}
+
+/// Record the current transaction ID and recipient count
+pub(crate) fn record_transaction(_state: &SmtpContext) {
+    #[cfg(feature = "tracing")]
+    {
+        let transaction = &_state.session.transaction;
+        if let Some(TransactionSpan(span)) = transaction.get() {
+            if !transaction.id.is_empty() {
+                span.record("id", &transaction.id.as_str());
+            }
+            span.record("rcpts", &transaction.rcpts.len());
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+struct SessionSpan(tracing::Span);
+
+#[cfg(feature = "tracing")]
+struct TransactionSpan(tracing::Span);
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use super::*;
+    use crate::{mail::Recipient, smtp::command::SmtpMail, smtp::SmtpPath};
+    use std::sync::Mutex;
+    use tracing::{field::Visit, span, Event, Metadata, Subscriber};
+
+    /// Span name, parent and fields
+    type Recorded = (&'static str, Option<u64>, Vec<String>);
+
+    /// Records span names, parents and fields
+    #[derive(Default)]
+    struct Spans {
+        spans: Mutex<Vec<Recorded>>,
+    }
+    struct Fields<'a>(&'a mut Vec<String>);
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+    impl Subscriber for &'static Spans {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut fields = vec![];
+            span.record(&mut Fields(&mut fields));
+            let parent = span.parent().map(|id| id.into_u64());
+            let mut spans = self.spans.lock().expect("lock");
+            spans.push((span.metadata().name(), parent, fields));
+            span::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+            let mut spans = self.spans.lock().expect("lock");
+            values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1].2));
+        }
+        fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+        fn event(&self, _event: &Event<'_>) {}
+        fn enter(&self, _span: &span::Id) {}
+        fn exit(&self, _span: &span::Id) {}
+    }
+
+    #[test]
+    fn transaction_span_is_child_of_session() {
+        let spans: &'static Spans = Box::leak(Box::default());
+        tracing::subscriber::with_default(spans, || {
+            let mut state = SmtpContext::default();
+            let connection = ConnectionInfo::new("[::1]:25".to_owned(), "[::1]:5555".to_owned());
+            session(&mut state, &connection, false);
+            tls(&state);
+            state.session.transaction.mail = Some(SmtpMail::Mail(SmtpPath::Postmaster, vec![]));
+            start_transaction(&mut state);
+            state.session.transaction.id = "tx1".to_owned();
+            state.session.transaction.rcpts.push(Recipient::null());
+            record_transaction(&state);
+        });
+
+        let spans = spans.spans.lock().expect("lock");
+        assert_eq!(spans[0].0, "session");
+        assert!(
+            spans[0].2.contains(&"peer=[::1]:5555".to_owned()),
+            "{:?}",
+            spans[0]
+        );
+        assert!(
+            spans[0].2.contains(&"tls=true".to_owned()),
+            "{:?}",
+            spans[0]
+        );
+        assert_eq!(spans[1].0, "transaction");
+        assert_eq!(spans[1].1, Some(1));
+        assert!(
+            spans[1].2.contains(&"id=\"tx1\"".to_owned()),
+            "{:?}",
+            spans[1]
+        );
+        assert!(spans[1].2.contains(&"rcpts=1".to_owned()), "{:?}", spans[1]);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::common::*;
+use crate::io::tls::MayBeTls;
+use crate::mail::{AcceptsSessionService, MailSetup};
+use crate::smtp::{SessionService, SmtpContext};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/**
+Records a transcript of each SMTP session to a file in the given folder.
+
+Everything read from and written to the client is recorded with the time elapsed since
+the session started, one entry per line. The transcript can be replayed with
+`samotop::testing::replay()` to reproduce the session against another service setup.
+
+The data is recorded as the session sees it. For encrypted sessions that is
+the plain text after STARTTLS. Treat the transcripts as sensitive.
+If the session `Privacy` does not allow raw data, only the length of the data is recorded
+and the peer address in the header is redacted. Such a transcript does not replay.
+The files are written by a dedicated thread so that the sessions do not wait for them.
+A transcript is written to `{connection id}.transcript.part` and renamed
+to `{connection id}.transcript` once the session is over.
+
+```
+# use samotop_core::mail::*;
+# use samotop_core::smtp::*;
+let service = Builder + TranscriptRecorder::new("/var/tmp/transcripts");
+```
+*/
+#[derive(Debug, Clone)]
+pub struct TranscriptRecorder {
+    dir: PathBuf,
+}
+
+impl TranscriptRecorder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl<T> MailSetup<T> for TranscriptRecorder
+where
+    T: AcceptsSessionService,
+{
+    fn setup(self, config: &mut T) {
+        let (sender, receiver) = channel();
+        let records = match std::thread::Builder::new()
+            .name("transcript writer".to_owned())
+            .spawn(move || write_transcripts(receiver))
+        {
+            Ok(_) => Some(sender),
+            Err(e) => {
+                warn!("Cannot start the transcript writer: {}", e);
+                None
+            }
+        };
+        config.wrap_session_service(|others| TranscriptService {
+            config: self,
+            records: Mutex::new(records),
+            others,
+        });
+    }
+}
+
+#[derive(Debug)]
+struct TranscriptService {
+    config: TranscriptRecorder,
+    records: Mutex<Option<Sender<Record>>>,
+    others: Box<dyn SessionService + Sync + Send>,
+}
+
+/// Work for the transcript writer
+#[derive(Debug)]
+enum Record {
+    /// Start a transcript file with the header line
+    Open(Arc<str>, PathBuf, String),
+    /// Add an entry to the transcript
+    Entry(Arc<str>, TranscriptEntry),
+    /// The session is over, finish the transcript
+    Close(Arc<str>),
+}
+
+impl SessionService for TranscriptService {
+    fn prepare_session<'a, 'i, 's, 'f>(
+        &'a self,
+        io: &'i mut Box<dyn MayBeTls>,
+        state: &'s mut SmtpContext,
+    ) -> S1Fut<'f, ()>
+    where
+        'a: 'f,
+        'i: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let records = self
+                .records
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone();
+            if let Some(records) = records {
+                let connection = &state.session.connection;
+                let id: Arc<str> = connection.id.as_str().into();
+                let path = self
+                    .config
+                    .dir
+                    .join(format!("{}.transcript", connection.id));
+                let since = connection
+                    .established
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let header = format!(
+                    "# samotop transcript of {} at {}.{:06}\n",
+                    connection.redacted(&state.session.privacy),
+                    since.as_secs(),
+                    since.subsec_micros()
+                );
+                if records.send(Record::Open(id.clone(), path, header)).is_ok() {
+                    *io = Box::new(TranscriptIo {
+                        started: Instant::now(),
+                        raw: state.session.privacy.raw(),
+                        id,
+                        records: Some(records),
+                        io: std::mem::replace(io, Box::new(Dummy)),
+                    });
+                }
+            }
+
+            self.others.prepare_session(io, state).await;
+        })
+    }
+}
+
+struct TranscriptIo {
+    started: Instant,
+    /// Record the data as is, otherwise just its length
+    raw: bool,
+    id: Arc<str>,
+    records: Option<Sender<Record>>,
+    io: Box<dyn MayBeTls>,
+}
+
+impl TranscriptIo {
+    fn record(&mut self, kind: TranscriptEntryKind, data: &[u8]) {
+        let entry = TranscriptEntry {
+            elapsed: self.started.elapsed(),
+            kind,
+            data: match self.raw {
+                true => data.to_vec(),
+                false => format!("<{} bytes>", data.len()).into_bytes(),
+            },
+        };
+        let record = Record::Entry(self.id.clone(), entry);
+        if let Some(Err(_)) = self.records.as_ref().map(|records| records.send(record)) {
+            warn!("Transcript writer is gone, giving up");
+            self.records = None;
+        }
+    }
+}
+
+impl Drop for TranscriptIo {
+    fn drop(&mut self) {
+        if let Some(ref records) = self.records {
+            // the writer may be gone already, nothing to do about it
+            let _ = records.send(Record::Close(self.id.clone()));
+        }
+    }
+}
+
+/// Write the transcripts as the records come, flushing whenever there are no more pending
+fn write_transcripts(records: Receiver<Record>) {
+    let mut files = HashMap::new();
+    while let Ok(record) = records.recv() {
+        write_record(&mut files, record);
+        while let Ok(record) = records.try_recv() {
+            write_record(&mut files, record);
+        }
+        for (id, (path, file)) in files.iter_mut() {
+            if let Err(e) = file.flush() {
+                warn!("Failed to flush transcript {} to {:?}: {}", id, path, e);
+            }
+        }
+    }
+}
+
+fn write_record(files: &mut HashMap<Arc<str>, (PathBuf, BufWriter<File>)>, record: Record) {
+    match record {
+        Record::Open(id, path, header) => {
+            let mut part = path.clone().into_os_string();
+            part.push(".part");
+            match File::create(&part).and_then(|file| {
+                let mut file = BufWriter::new(file);
+                file.write_all(header.as_bytes())?;
+                Ok(file)
+            }) {
+                Ok(file) => {
+                    files.insert(id, (path, file));
+                }
+                Err(e) => warn!("Cannot record transcript to {:?}: {}", part, e),
+            }
+        }
+        Record::Entry(id, entry) => {
+            if let Some((path, file)) = files.get_mut(&id) {
+                if let Err(e) = writeln!(file, "{}", entry) {
+                    warn!("Failed to record transcript {:?}, giving up: {}", path, e);
+                    files.remove(&id);
+                }
+            }
+        }
+        Record::Close(id) => {
+            if let Some((path, file)) = files.remove(&id) {
+                let mut part = path.clone().into_os_string();
+                part.push(".part");
+                if let Err(e) = file
+                    .into_inner()
+                    .map_err(|e| e.into_error())
+                    .and_then(|_| std::fs::rename(&part, &path))
+                {
+                    warn!("Failed to finish transcript {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+impl MayBeTls for TranscriptIo {
+    fn enable_encryption(&mut self, upgrade: Box<dyn crate::io::tls::TlsUpgrade>, name: String) {
+        self.io.enable_encryption(upgrade, name)
+    }
+
+    fn encrypt(mut self: Pin<&mut Self>) {
+        self.record(TranscriptEntryKind::StartTls, b"");
+        Pin::new(&mut self.io).encrypt()
+    }
+
+    fn can_encrypt(&self) -> bool {
+        self.io.can_encrypt()
+    }
+
+    fn is_encrypted(&self) -> bool {
+        self.io.is_encrypted()
+    }
+}
+
+impl io::Read for TranscriptIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = ready!(Pin::new(&mut self.io).poll_read(cx, buf))?;
+        if len != 0 {
+            self.record(TranscriptEntryKind::Read, &buf[..len]);
+        }
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl io::Write for TranscriptIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = ready!(Pin::new(&mut self.io).poll_write(cx, buf))?;
+        self.record(TranscriptEntryKind::Write, &buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
+
+/// One transcript line - `{elapsed seconds} {kind} "{escaped data}"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    /// Time since the session started
+    pub elapsed: Duration,
+    pub kind: TranscriptEntryKind,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptEntryKind {
+    /// Data received from the client
+    Read,
+    /// Data sent to the client
+    Write,
+    /// TLS encryption started
+    StartTls,
+}
+
+impl TranscriptEntry {
+    /// Parse a whole transcript, skipping empty lines and # comments
+    pub fn parse_all(transcript: &str) -> Result<Vec<TranscriptEntry>> {
+        transcript
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl fmt::Display for TranscriptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            TranscriptEntryKind::Read => "read",
+            TranscriptEntryKind::Write => "write",
+            TranscriptEntryKind::StartTls => "starttls",
+        };
+        write!(
+            f,
+            "{}.{:06} {} \"",
+            self.elapsed.as_secs(),
+            self.elapsed.subsec_micros(),
+            kind
+        )?;
+        for byte in self.data.iter() {
+            write!(f, "{}", std::ascii::escape_default(*byte))?;
+        }
+        write!(f, "\"")
+    }
+}
+
+impl FromStr for TranscriptEntry {
+    type Err = Error;
+    fn from_str(line: &str) -> Result<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let elapsed = parts.next().unwrap_or_default();
+        let kind = parts.next().unwrap_or_default();
+        let data = parts.next().unwrap_or_default();
+        let elapsed = parse_elapsed(elapsed)
+            .ok_or_else(|| format!("Invalid transcript time {:?}", elapsed))?;
+        let kind = match kind {
+            "read" => TranscriptEntryKind::Read,
+            "write" => TranscriptEntryKind::Write,
+            "starttls" => TranscriptEntryKind::StartTls,
+            otherwise => return Err(format!("Invalid transcript entry {:?}", otherwise).into()),
+        };
+        let data = data
+            .strip_prefix('"')
+            .and_then(|d| d.strip_suffix('"'))
+            .ok_or_else(|| format!("Transcript data must be quoted: {:?}", data))?;
+        Ok(TranscriptEntry {
+            elapsed,
+            kind,
+            data: unescape(data)?,
+        })
+    }
+}
+
+/// Parse seconds with a decimal fraction, i.e. 1.000123
+fn parse_elapsed(elapsed: &str) -> Option<Duration> {
+    let (secs, fraction) = elapsed.split_once('.').unwrap_or((elapsed, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = format!("{:0<9}", fraction).parse().ok()?;
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// Reverse of `std::ascii::escape_default`
+fn unescape(data: &str) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            result.push(byte);
+            continue;
+        }
+        let escaped = match bytes.next() {
+            Some(b't') => b'\t',
+            Some(b'r') => b'\r',
+            Some(b'n') => b'\n',
+            Some(b'x') => {
+                let hex = [bytes.next().unwrap_or(b'?'), bytes.next().unwrap_or(b'?')];
+                std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("Invalid escape sequence in {:?}", data))?
+            }
+            Some(other @ (b'\\' | b'\'' | b'"')) => other,
+            _ => return Err(format!("Invalid escape sequence in {:?}", data).into()),
+        };
+        result.push(escaped);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = TranscriptEntry {
+            elapsed: Duration::from_micros(1_000_123),
+            kind: TranscriptEntryKind::Read,
+            data: b"mail from:<\"a b\"@x>\r\n\x00\xff".to_vec(),
+        };
+        let line = entry.to_string();
+        assert_eq!(line, r#"1.000123 read "mail from:<\"a b\"@x>\r\n\x00\xff""#);
+        assert_eq!(line.parse::<TranscriptEntry>().expect("parse"), entry);
+    }
+
+    #[test]
+    fn parse_all_skips_comments() {
+        let entries = TranscriptEntry::parse_all(
+            "# header\n0.000001 write \"220 ok\\r\\n\"\n\n0.5 starttls \"\"\n",
+        )
+        .expect("parse");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].data, b"220 ok\r\n");
+        assert_eq!(entries[1].kind, TranscriptEntryKind::StartTls);
+        assert_eq!(entries[1].elapsed, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn records_length_only_when_private() {
+        let (records, written) = channel();
+        let mut sut = TranscriptIo {
+            started: Instant::now(),
+            raw: false,
+            id: "conn".into(),
+            records: Some(records),
+            io: Box::new(Dummy),
+        };
+        sut.record(
+            TranscriptEntryKind::Read,
+            b"mail from:<alice@example.org>\r\n",
+        );
+        match written.try_recv() {
+            Ok(Record::Entry(id, entry)) => {
+                assert_eq!(&*id, "conn");
+                assert_eq!(entry.data, b"<31 bytes>");
+            }
+            otherwise => panic!("Expected a transcript entry, got {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        assert!("0.1 read unquoted".parse::<TranscriptEntry>().is_err());
+        assert!("0.1 bark \"\"".parse::<TranscriptEntry>().is_err());
+        assert!("soon read \"\"".parse::<TranscriptEntry>().is_err());
+    }
+}
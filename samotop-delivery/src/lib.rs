@@ -43,9 +43,13 @@ from the awesome [delta.chat](https://delta.chat) project.
     clippy::unwrap_used
 )]
 
-#[macro_use]
+#[cfg_attr(not(feature = "tracing"), macro_use)]
 extern crate log;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
 
+#[cfg(feature = "dir-transport")]
 pub mod dir;
 mod dispatch;
 #[cfg(feature = "file-transport")]
@@ -60,6 +64,7 @@ pub mod stub;
 pub mod types;
 
 pub mod prelude {
+    #[cfg(feature = "dir-transport")]
     pub use crate::dir::*;
     #[cfg(feature = "file-transport")]
     pub use crate::file::*;
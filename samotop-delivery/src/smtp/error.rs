@@ -48,8 +48,8 @@ pub enum Error {
     /// Parsing error
     #[error("parsing: {0:?}")]
     Parsing(nom::error::ErrorKind),
-    #[error("timeout: {0}")]
-    Timeout(#[from] async_std::future::TimeoutError),
+    #[error("timeout")]
+    Timeout,
     #[error("no stream")]
     NoStream,
     #[error("no server info")]
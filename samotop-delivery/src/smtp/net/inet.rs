@@ -3,15 +3,15 @@ use crate::smtp::net::tls::TlsProvider;
 use crate::smtp::net::Connector;
 use crate::smtp::net::TlsMode;
 use crate::{smtp::net::ConnectionConfiguration, SyncFuture};
-use async_std::io;
-use async_std::net::{TcpStream, ToSocketAddrs};
-use samotop_core::common::Pin;
+use samotop_core::common::{io, Arc, Pin};
 use samotop_core::io::tls::MayBeTls;
+use samotop_core::runtime::{self, default_runtime, Runtime};
 
 #[derive(Debug)]
 pub struct TcpConnector<TLS> {
     pub tls_mode: TlsMode,
     pub provider: TLS,
+    runtime: Arc<dyn Runtime + Send + Sync>,
 }
 
 impl<TLS: Default> Default for TcpConnector<TLS> {
@@ -19,10 +19,19 @@ impl<TLS: Default> Default for TcpConnector<TLS> {
         Self {
             tls_mode: TlsMode::StartTls,
             provider: TLS::default(),
+            runtime: default_runtime(),
         }
     }
 }
 
+impl<TLS> TcpConnector<TLS> {
+    /// Connect and time out the session on the given runtime instead of the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Arc::new(runtime);
+        self
+    }
+}
+
 impl<TLS> Connector for TcpConnector<TLS>
 where
     TLS: TlsProvider + Sync + Send + 'static,
@@ -43,18 +52,20 @@ where
             // TODO: try alternative addresses on failure. Here we just pick the first one.
             let mut to = configuration.address();
             let timeout = configuration.timeout();
-            let addr = to.to_socket_addrs().await?.next().ok_or_else(|| {
+            let addr = self.runtime.resolve(to.clone()).await?;
+            let addr = addr.into_iter().next().ok_or_else(|| {
                 std::io::Error::new(
                     std::io::ErrorKind::NotFound,
                     format!("No address resolved for {}", to),
                 )
             })?;
 
-            let stream = io::timeout(timeout, TcpStream::connect(addr)).await?;
+            let stream = runtime::timeout(&self.runtime, timeout, self.runtime.connect_tcp(addr))
+                .await
+                .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))??;
 
             // remove port part, domain/host remains
             to.find(':').map(|i| to.split_off(i));
-            let stream = Box::new(stream);
             let mut stream = match self.provider.get_tls_upgrade() {
                 Some(u) => TlsCapable::enabled(stream, u, to),
                 None => TlsCapable::plaintext(stream),
@@ -67,4 +78,7 @@ where
             Ok(stream)
         })
     }
+    fn runtime(&self) -> Arc<dyn Runtime + Send + Sync> {
+        self.runtime.clone()
+    }
 }
@@ -6,8 +6,10 @@ mod unix;
 #[cfg(unix)]
 pub use self::unix::*;
 
+#[cfg(feature = "runtime-async-std")]
 mod child;
 mod inet;
+#[cfg(feature = "runtime-async-std")]
 pub use self::child::*;
 pub use self::inet::*;
 
@@ -17,7 +19,10 @@ use crate::{smtp::authentication::Authentication, SyncFuture};
 use crate::{smtp::extension::ClientId, smtp::ClientSecurity};
 use async_std::io::{self, Read, Write};
 use samotop_core::io::tls::MayBeTls;
+use samotop_core::mail::Privacy;
+use samotop_core::runtime::{default_runtime, Runtime};
 use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub trait Connector: fmt::Debug + Sync + Send {
@@ -33,6 +38,10 @@ pub trait Connector: fmt::Debug + Sync + Send {
     where
         's: 'a,
         'c: 'a;
+    /// Runtime used to time out the SMTP commands on the connected stream
+    fn runtime(&self) -> Arc<dyn Runtime + Send + Sync> {
+        default_runtime()
+    }
 }
 
 pub trait ConnectionConfiguration: Sync + Send {
@@ -47,6 +56,10 @@ pub trait ConnectionConfiguration: Sync + Send {
         encrypted: bool,
     ) -> Option<Box<dyn Authentication>>;
     fn lmtp(&self) -> bool;
+    /// Privacy policy for logging the delivery, everything is logged as is by default
+    fn privacy(&self) -> Arc<Privacy> {
+        Arc::default()
+    }
 }
 
 pub type DefaultConnector = inet::TcpConnector<DefaultTls>;
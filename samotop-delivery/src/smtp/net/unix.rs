@@ -3,15 +3,15 @@ use crate::smtp::net::tls::TlsProvider;
 use crate::smtp::net::Connector;
 use crate::smtp::net::TlsMode;
 use crate::{smtp::net::ConnectionConfiguration, SyncFuture};
-use async_std::io;
-use async_std::os::unix::net::UnixStream;
-use samotop_core::common::Pin;
+use samotop_core::common::{io, Arc, Pin};
 use samotop_core::io::tls::MayBeTls;
+use samotop_core::runtime::{self, default_runtime, Runtime};
 
 #[derive(Debug)]
 pub struct UnixConnector<TLS> {
     pub tls_mode: TlsMode,
     pub provider: TLS,
+    runtime: Arc<dyn Runtime + Send + Sync>,
 }
 
 impl<TLS: Default> Default for UnixConnector<TLS> {
@@ -19,10 +19,19 @@ impl<TLS: Default> Default for UnixConnector<TLS> {
         Self {
             tls_mode: TlsMode::StartTls,
             provider: TLS::default(),
+            runtime: default_runtime(),
         }
     }
 }
 
+impl<TLS> UnixConnector<TLS> {
+    /// Connect and time out the session on the given runtime instead of the default one
+    pub fn with_runtime(mut self, runtime: impl Runtime + Send + Sync + 'static) -> Self {
+        self.runtime = Arc::new(runtime);
+        self
+    }
+}
+
 impl<TLS> Connector for UnixConnector<TLS>
 where
     TLS: TlsProvider + Sync + Send + 'static,
@@ -43,8 +52,10 @@ where
             let to = configuration.address();
             let timeout = configuration.timeout();
 
-            let stream = io::timeout(timeout, UnixStream::connect(&to)).await?;
-            let stream = Box::new(stream);
+            let stream =
+                runtime::timeout(&self.runtime, timeout, self.runtime.connect_unix(to.into()))
+                    .await
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))??;
             let mut stream = match self.provider.get_tls_upgrade() {
                 Some(u) => TlsCapable::enabled(stream, u, String::default()),
                 None => TlsCapable::plaintext(stream),
@@ -57,4 +68,7 @@ where
             Ok(stream)
         })
     }
+    fn runtime(&self) -> Arc<dyn Runtime + Send + Sync> {
+        self.runtime.clone()
+    }
 }
@@ -11,6 +11,8 @@ use crate::smtp::stream::SmtpDataStream;
 use crate::smtp::SmtpTransport;
 use crate::{Envelope, Transport};
 use async_std::io::Read;
+use samotop_core::mail::Privacy;
+use std::sync::Arc;
 use std::time::Duration;
 
 // Registered port numbers:
@@ -107,6 +109,8 @@ pub struct SmtpClient {
     pub(crate) timeout: Option<Duration>,
     /// Use LMTP instead of SMTP
     pub(crate) lmtp: bool,
+    /// Privacy policy for logging
+    pub(crate) privacy: Arc<Privacy>,
 }
 
 /// Builder for the SMTP `SmtpTransport`
@@ -145,6 +149,7 @@ impl SmtpClient {
             force_set_auth: false,
             timeout: Some(Duration::new(60, 0)),
             lmtp: false,
+            privacy: Arc::default(),
         })
     }
 
@@ -204,6 +209,12 @@ impl SmtpClient {
         self
     }
 
+    /// Set the privacy policy for logging
+    pub fn privacy(mut self, privacy: Privacy) -> SmtpClient {
+        self.privacy = Arc::new(privacy);
+        self
+    }
+
     /// Build the SMTP client transport
     ///
     /// The transport connects on first use and can be reused if configured so
@@ -299,4 +310,7 @@ impl ConnectionConfiguration for SmtpClient {
     fn lmtp(&self) -> bool {
         self.lmtp
     }
+    fn privacy(&self) -> Arc<Privacy> {
+        self.privacy.clone()
+    }
 }
@@ -12,6 +12,9 @@ use std::time::Duration;
 /// FIXME: this needs to be gracefully degraded to 7bit if 8bit/utf8 is not available
 pub struct SmtpDataStream<S> {
     state: State<S>,
+    /// the span in which the stream was created, entered when writing
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 enum State<S> {
@@ -60,15 +63,18 @@ impl<S> SmtpDataStream<S> {
         lmtp: bool,
         rcpts: u16,
     ) -> Self {
+        let codec = SmtpDataCodec::new().hide_body(!inner.privacy.body());
         SmtpDataStream {
             state: State::Ready(SmtpDataStreamInner {
                 inner,
-                codec: SmtpDataCodec::new(),
+                codec,
                 message_id,
                 timeout,
                 lmtp,
                 rcpts,
             }),
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
         }
     }
     pub fn last_response(&self) -> Option<&Response> {
@@ -100,6 +106,8 @@ where
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.clone().entered();
         trace!("poll_write {} bytes", buf.len());
         loop {
             break match std::mem::replace(&mut self.state, State::Busy) {
@@ -139,6 +147,8 @@ where
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<std::result::Result<(), std::io::Error>> {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.clone().entered();
         trace!("poll_flush");
         loop {
             break match self.state {
@@ -167,6 +177,8 @@ where
         // Here we take care of closing the stream with final dot
         // and reading the response
 
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.clone().entered();
         trace!("poll_close");
         loop {
             break match std::mem::replace(&mut self.state, State::Busy) {
@@ -188,7 +200,11 @@ where
 
                             // collect response
                             trace!("data sent, waiting for confirmation");
-                            let mut client = SmtpProto::new(Pin::new(&mut inner.stream));
+                            let privacy = inner.privacy.clone();
+                            let runtime = inner.runtime.clone();
+                            let mut client = SmtpProto::new(Pin::new(&mut inner.stream))
+                                .with_privacy(privacy)
+                                .with_runtime(runtime);
                             let mut response = None;
                             if lmtp {
                                 // there will be multiple responses - one for each RCPT
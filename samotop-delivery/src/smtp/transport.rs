@@ -10,6 +10,9 @@ use async_std::io::{Read, Write};
 use pin_project::pin_project;
 use potential::{Lease, Potential};
 use samotop_core::io::tls::MayBeTls;
+use samotop_core::mail::Privacy;
+use samotop_core::runtime::Runtime;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, pin::Pin};
 
@@ -39,22 +42,31 @@ where
         connector: &Conn,
     ) -> Result<SmtpConnection<Conn::Stream>, Error> {
         let mut stream = connector.connect(configuration).await?;
-        let server_info = Self::setup(configuration, &mut stream).await?;
+        let runtime = connector.runtime();
+        let server_info = Self::setup(configuration, &runtime, &mut stream).await?;
         let reuse = configuration.max_reuse_count().saturating_add(1);
         Ok(SmtpConnection {
             stream,
             reuse,
             server_info,
+            privacy: configuration.privacy(),
+            runtime,
         })
     }
-    async fn setup(configuration: &Conf, stream: &mut Conn::Stream) -> Result<ServerInfo, Error> {
+    async fn setup(
+        configuration: &Conf,
+        runtime: &Arc<dyn Runtime + Send + Sync>,
+        stream: &mut Conn::Stream,
+    ) -> Result<ServerInfo, Error> {
         let timeout = configuration.timeout();
         let address = configuration.address();
         let my_id = configuration.hello_name();
         let security = configuration.security();
         let lmtp = configuration.lmtp();
 
-        let mut client = SmtpProto::new(Pin::new(stream));
+        let mut client = SmtpProto::new(Pin::new(stream))
+            .with_privacy(configuration.privacy())
+            .with_runtime(runtime.clone());
         let banner = client.read_banner(timeout).await?;
 
         // Log the connection
@@ -94,18 +106,21 @@ where
             }
         }
 
-        Self::try_login(configuration, stream, &server_info, timeout).await?;
+        Self::try_login(configuration, runtime, stream, &server_info, timeout).await?;
 
         Ok(server_info)
     }
     async fn try_login(
         configuration: &Conf,
+        runtime: &Arc<dyn Runtime + Send + Sync>,
         stream: &mut Conn::Stream,
         server_info: &ServerInfo,
         timeout: Duration,
     ) -> Result<(), Error> {
         if let Some(auth) = configuration.get_authentication(server_info, stream.is_encrypted()) {
-            let mut client = SmtpProto::new(Pin::new(stream));
+            let mut client = SmtpProto::new(Pin::new(stream))
+                .with_privacy(configuration.privacy())
+                .with_runtime(runtime.clone());
             client.authenticate(auth, timeout).await?;
         } else {
             info!("No authentication mechanisms are available");
@@ -132,7 +147,11 @@ where
             mail_options.push(MailParameter::SmtpUtfEight);
         }
 
-        let mut client = SmtpProto::new(Pin::new(&mut lease.stream));
+        let privacy = lease.privacy.clone();
+        let runtime = lease.runtime.clone();
+        let mut client = SmtpProto::new(Pin::new(&mut lease.stream))
+            .with_privacy(privacy.clone())
+            .with_runtime(runtime);
 
         // MAIL FROM:<reverse-path>
         client
@@ -149,7 +168,11 @@ where
                 .execute_command(RcptCommand::new(to_address.clone(), vec![]), [2], timeout)
                 .await?;
             // Log the rcpt command
-            debug!("{}: to=<{}>", envelope.message_id(), to_address);
+            debug!(
+                "{}: to=<{}>",
+                envelope.message_id(),
+                privacy.address(to_address)
+            );
         }
 
         // DATA
@@ -170,7 +193,13 @@ impl<Conf: ConnectionConfiguration, Conn: Connector> Transport for SmtpTransport
     where
         's: 'a,
     {
-        Box::pin(async move {
+        #[cfg(feature = "tracing")]
+        let span = info_span!(
+            "delivery",
+            address = %self.configuration.address(),
+            message_id = %envelope.message_id()
+        );
+        let send = async move {
             let mut lease = match self.inner.lease().await {
                 Ok(lease) => lease,
                 Err(gone) => gone.set(Self::connect(&self.configuration, &self.connector).await?),
@@ -196,7 +225,11 @@ impl<Conf: ConnectionConfiguration, Conn: Connector> Transport for SmtpTransport
                 self.configuration.lmtp(),
                 rcpts,
             ))
-        })
+        };
+        // the data stream carries the current span over to the mail data
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::instrument(send, span);
+        Box::pin(send)
     }
 }
 #[derive(Debug)]
@@ -207,6 +240,10 @@ pub(crate) struct SmtpConnection<S> {
     /// Information about the server
     /// Value is None before HELO/EHLO
     pub server_info: ServerInfo,
+    /// Privacy policy for logging
+    pub privacy: Arc<Privacy>,
+    /// Runtime to time out the commands
+    pub runtime: Arc<dyn Runtime + Send + Sync>,
 }
 
 impl<Conf: ConnectionConfiguration, Conn: Connector> fmt::Debug for SmtpTransport<Conf, Conn> {
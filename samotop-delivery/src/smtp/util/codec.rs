@@ -19,6 +19,7 @@ use std::{
 )]
 pub struct SmtpDataCodec {
     state: State,
+    hide_body: bool,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -42,13 +43,21 @@ impl SmtpDataCodec {
     pub fn new() -> Self {
         SmtpDataCodec::default()
     }
+    /// Do not log the mail body content, only its size
+    pub fn hide_body(mut self, hide: bool) -> Self {
+        self.hide_body = hide;
+        self
+    }
 }
 
 impl SmtpDataCodec {
     /// Close the data stream - this writes the appropriate final dot
     pub async fn close<W: Write + Unpin>(&mut self, buf: W) -> io::Result<()> {
         trace!("close");
-        let mut buf = BugIo { inner: buf };
+        let mut buf = BugIo {
+            inner: buf,
+            hide_body: self.hide_body,
+        };
         if State::AfterCrLf == self.state {
             buf.write_all(b".\r\n").await?;
         } else {
@@ -59,8 +68,15 @@ impl SmtpDataCodec {
     }
     /// Encode data - it does not handle final dot
     pub async fn encode<W: Write + Unpin>(&mut self, mut frame: &[u8], buf: W) -> io::Result<()> {
-        debug!("encode {:?}", std::str::from_utf8(frame));
-        let mut buf = BugIo { inner: buf };
+        if self.hide_body {
+            debug!("encode {} bytes", frame.len());
+        } else {
+            debug!("encode {:?}", std::str::from_utf8(frame));
+        }
+        let mut buf = BugIo {
+            inner: buf,
+            hide_body: self.hide_body,
+        };
 
         while !frame.is_empty() {
             // write an escape a dot after CR LF if the first char is a dot
@@ -97,6 +113,8 @@ impl SmtpDataCodec {
 pub struct BugIo<S> {
     #[pin]
     pub inner: S,
+    /// Do not log the data read or written, only the result
+    pub hide_body: bool,
 }
 
 impl<S: Read> Read for BugIo<S> {
@@ -105,8 +123,13 @@ impl<S: Read> Read for BugIo<S> {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
-        let res = self.project().inner.poll_read(cx, buf);
-        debug!("poll_read {:?} {:?}", res, std::str::from_utf8(buf));
+        let this = self.project();
+        let res = this.inner.poll_read(cx, buf);
+        if *this.hide_body {
+            debug!("poll_read {:?}", res);
+        } else {
+            debug!("poll_read {:?} {:?}", res, std::str::from_utf8(buf));
+        }
         res
     }
 }
@@ -116,8 +139,13 @@ impl<S: Write> Write for BugIo<S> {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        let res = self.project().inner.poll_write(cx, buf);
-        debug!("poll_write {:?} {:?}", res, std::str::from_utf8(buf));
+        let this = self.project();
+        let res = this.inner.poll_write(cx, buf);
+        if *this.hide_body {
+            debug!("poll_write {:?}", res);
+        } else {
+            debug!("poll_write {:?} {:?}", res, std::str::from_utf8(buf));
+        }
         res
     }
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
@@ -6,8 +6,11 @@ use crate::smtp::response::Response;
 use async_std::io::prelude::{ReadExt, WriteExt};
 use bytes::{Buf, BufMut, BytesMut};
 use samotop_core::common::*;
+use samotop_core::mail::Privacy;
+use samotop_core::runtime::{self, default_runtime, Runtime};
 use std::fmt::Display;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::smtp::error::{Error, SmtpResult};
@@ -23,6 +26,8 @@ pub struct SmtpProto<'s, S> {
     stream: Pin<&'s mut S>,
     buffer: BytesMut,
     line_limit: usize,
+    privacy: Arc<Privacy>,
+    runtime: Arc<dyn Runtime + Send + Sync>,
 }
 
 impl<'s, S> SmtpProto<'s, S> {
@@ -31,8 +36,20 @@ impl<'s, S> SmtpProto<'s, S> {
             stream,
             buffer: BytesMut::new(),
             line_limit: 8000,
+            privacy: Arc::default(),
+            runtime: default_runtime(),
         }
     }
+    /// Redact the logged commands and responses with the privacy policy
+    pub fn with_privacy(mut self, privacy: Arc<Privacy>) -> Self {
+        self.privacy = privacy;
+        self
+    }
+    /// Time out the commands and responses on the given runtime
+    pub fn with_runtime(mut self, runtime: Arc<dyn Runtime + Send + Sync>) -> Self {
+        self.runtime = runtime;
+        self
+    }
     // pub fn with_line_limit(mut self, limit: usize) -> Self {
     //     self.line_limit = limit;
     //     self
@@ -178,7 +195,7 @@ impl<'s, S: io::Read + io::Write> SmtpProto<'s, S> {
         timeout: Duration,
     ) -> SmtpResult {
         let command = command.to_string();
-        debug!("C: {}", escape_crlf(command.as_str()));
+        debug!("C: {}", self.privacy.input(command.as_bytes()));
         let buff = command.as_bytes();
         let written = self.write_bytes(buff, timeout).await?;
         debug_assert!(written == buff.len(), "Make sure we write all the data");
@@ -187,10 +204,12 @@ impl<'s, S: io::Read + io::Write> SmtpProto<'s, S> {
         response.is(expected)
     }
     async fn write_bytes(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, Error> {
-        with_timeout(timeout, self.stream.write(buf)).await
+        let runtime = self.runtime.clone();
+        with_timeout(&*runtime, timeout, self.stream.write(buf)).await
     }
     async fn read_response(&mut self, timeout: Duration) -> SmtpResult {
-        with_timeout(timeout, async move {
+        let runtime = self.runtime.clone();
+        with_timeout(&*runtime, timeout, async move {
             let mut enough = self.buffer.remaining() != 0;
             loop {
                 self.buffer.reserve(1024);
@@ -218,7 +237,7 @@ impl<'s, S: io::Read + io::Write> SmtpProto<'s, S> {
                     };
                 }
                 let response = std::str::from_utf8(self.buffer.chunk())?;
-                debug!("S: {}", escape_crlf(response));
+                debug!("S: {}", self.privacy.input(response.as_bytes()));
                 break match parse_response(response) {
                     Ok((remaining, response)) => {
                         let consumed = self.buffer.remaining() - remaining.len();
@@ -244,18 +263,17 @@ impl<'s, S: io::Read + io::Write> SmtpProto<'s, S> {
 }
 
 /// Execute io operations with a timeout.
-async fn with_timeout<T, F, E, EOut>(timeout: Duration, f: F) -> std::result::Result<T, EOut>
+async fn with_timeout<T, F, E>(
+    runtime: &(dyn Runtime + Send + Sync),
+    timeout: Duration,
+    f: F,
+) -> std::result::Result<T, Error>
 where
     F: Future<Output = std::result::Result<T, E>>,
-    EOut: From<async_std::future::TimeoutError>,
-    EOut: From<E>,
+    Error: From<E>,
 {
-    let res = async_std::future::timeout(timeout, f).await??;
+    let res = runtime::timeout(runtime, timeout, f)
+        .await
+        .ok_or(Error::Timeout)??;
     Ok(res)
 }
-
-/// Returns the string replacing all the CRLF with "\<CRLF\>"
-/// Used for debug displays
-fn escape_crlf(string: &str) -> String {
-    string.replace("\r\n", "<CRLF>")
-}
@@ -1,8 +1,11 @@
 use crate::SmtpParserPeg;
-use samotop_core::smtp::{command::MailBody, *};
+use samotop_core::{
+    common::Bytes,
+    smtp::{command::MailBody, *},
+};
 
-impl Parser<MailBody<Vec<u8>>> for SmtpParserPeg {
-    fn parse(&self, input: &[u8], state: &SmtpContext) -> ParseResult<MailBody<Vec<u8>>> {
+impl Parser<MailBody<Bytes>> for SmtpParserPeg {
+    fn parse(&self, input: &[u8], state: &SmtpContext) -> ParseResult<MailBody<Bytes>> {
         let crlf = match state.session.mode {
             Some(SmtpSession::DATA_MODE) => true,
             Some(SmtpSession::DATA_PARTIAL_MODE) => false,
@@ -13,22 +16,24 @@ impl Parser<MailBody<Vec<u8>>> for SmtpParserPeg {
                 )))
             }
         };
-        let res = map_cmd(grammar::data(input, crlf));
+        let res = map_cmd(grammar::data(input, crlf), &state.session.input);
         trace!("Parsed {:?} from {:?}", res, String::from_utf8_lossy(input));
         res
     }
 }
 
+/// The chunk data share the session input buffer rather than copying it
 fn map_cmd(
-    res: std::result::Result<ParseResult<Vec<u8>>, peg::error::ParseError<usize>>,
-) -> ParseResult<MailBody<Vec<u8>>> {
+    res: std::result::Result<ParseResult<&[u8]>, peg::error::ParseError<usize>>,
+    input: &InputBuffer,
+) -> ParseResult<MailBody<Bytes>> {
     match res {
-        Ok(Ok((i, data))) if data.is_empty() => Ok((i, MailBody::End)),
+        Ok(Ok((i, []))) => Ok((i, MailBody::End)),
         Ok(Ok((i, data))) => Ok((
             i,
             MailBody::Chunk {
                 ends_with_new_line: data.ends_with(b"\r\n"),
-                data,
+                data: input.share(data),
             },
         )),
         Ok(Err(e)) => Err(e),
@@ -36,13 +41,6 @@ fn map_cmd(
     }
 }
 
-fn utf8(bytes: &[u8]) -> std::result::Result<&str, &'static str> {
-    std::str::from_utf8(bytes).map_err(|_e| "Invalid UTF-8")
-}
-fn utf8s(bytes: &[u8]) -> std::result::Result<String, &'static str> {
-    utf8(bytes).map(|s| s.to_string())
-}
-
 peg::parser! {
     /// The parser takes advantage of keeping external state of reaching CR LF
     /// This state is passed as an argument. Caller detects CR LF end from output.
@@ -50,37 +48,34 @@ peg::parser! {
     ///    as otherwise the scheme is terribly ambiguous and complex.
     grammar grammar() for [u8] {
 
-        pub rule data(crlf:bool) -> ParseResult< Vec<u8>>
+        pub rule data(crlf:bool) -> ParseResult<&'input [u8]>
             = complete(crlf) / incomplete(crlf)
 
-        rule complete(crlf:bool) -> ParseResult< Vec<u8>>
+        rule complete(crlf:bool) -> ParseResult<&'input [u8]>
             = s:( eof(crlf) / data_part(crlf) ) p:position!() rest:$([_]*)
             {Ok((p,s))}
 
-        rule incomplete(crlf:bool) -> ParseResult< Vec<u8>>
+        rule incomplete(crlf:bool) -> ParseResult<&'input [u8]>
             = rest:$([_]*)
             {Err(ParseError::Incomplete)}
 
-        rule eof(crlf:bool) ->  Vec<u8>
+        rule eof(crlf:bool) -> &'input [u8]
             =  b:$(".\r\n")
-            { if crlf {vec![]} else {b.to_vec()} }
-
-        rule data_part(crlf:bool) ->  Vec<u8>
-            = s: ( escaped(crlf) / regular() )
-            {s.into()}
-
-        rule escaped(crlf:bool) -> String    = "." r:$(regular() / ".")
-            {
-                ?match (crlf, utf8s(r)) {
-                    (_, Err(e)) => Err(e),
-                    (true, Ok(r)) => Ok(r),
-                    (false, Ok(r)) => Ok(format!(".{}",r)),
-                }
-            }
-        rule regular() -> String = s:$( ( chr() / eols() )+ ) {?utf8s(s)}
+            { if crlf {&b[..0]} else {b} }
+
+        rule data_part(crlf:bool) -> &'input [u8]
+            = escaped(crlf) / regular()
+
+        rule escaped(crlf:bool) -> &'input [u8]
+            = "." r:$(regular() / ".") {? if crlf {Ok(r)} else {Err("dot escape after CR LF")} }
+            / s:$("." (regular() / ".")) {s}
+
+        /// Regular data span across lines up to a line starting with a dot
+        rule regular() -> &'input [u8] = !"." s:$( ( chr() / eols() !"." )* eols()? )
+            {? if s.is_empty() {Err("some data")} else {Ok(s)} }
 
         rule eols() = quiet!{ "\r"+ !("\r")&[_] / "\n" } / expected!("predictable new line chars CR LF")
-        rule chr() = quiet!{![b'\r'|b'\n'|b'.'] [_]} / expected!("any char except CR LF and .")
+        rule chr() = quiet!{![b'\r'|b'\n'] [_]} / expected!("any char except CR LF")
     }
 }
 
@@ -214,7 +209,7 @@ mod without_crlf {
     #[test]
     fn get_crlf_dot() -> Result<()> {
         match grammar::data(b"\r\n.", CRLF)? {
-            Ok((2, b)) => assert_eq!(b.as_slice(), b"\r\n"),
+            Ok((2, b)) => assert_eq!(b, b"\r\n"),
             otherwise => panic!("Expected crlf, got {:?}", otherwise),
         }
         Ok(())
@@ -353,6 +348,22 @@ mod after_crlf {
         Ok(())
     }
 
+    #[test]
+    fn lf_before_dot() -> Result<()> {
+        match grammar::data(b"\n.foo", CRLF)? {
+            Ok((1, b)) if b == b"\n".to_vec() => {}
+            otherwise => panic!("Expected lf, got {:?}", otherwise),
+        }
+        Ok(())
+    }
+    #[test]
+    fn dots_inside_lines() -> Result<()> {
+        match grammar::data(b"a.b\r\nc.\r\n.\r\n", CRLF)? {
+            Ok((9, b)) if b == b"a.b\r\nc.\r\n".to_vec() => {}
+            otherwise => panic!("Expected two lines, got {:?}", otherwise),
+        }
+        Ok(())
+    }
     #[test]
     fn trailing_lf() -> Result<()> {
         match grammar::data(b"\n\r\n.\r\n", CRLF)? {
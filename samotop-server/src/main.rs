@@ -24,7 +24,7 @@ You can run your own privacy focussed, resource efficient mail server. [Samotop
 - [ ] Mail relaying
 - [ ] Antispam features:
   - [x] SPF - refuse mail with failing SPF check
-  - [ ] Greylisting
+  - [x] Greylisting
 
 ## Mail submission agent (MSA)
 
@@ -63,6 +63,10 @@ Both should produce a usage information not too different from this:
         -b, --base-dir <base dir path>              What is the base dir for other relative paths? [default: .]
         -c, --cert-file <cert file path>            Use this cert file for TLS. Disabled with --no-tls. If a relative path
                                                     is given, it will be relative to base-dir
+            --config <config file path>             Build the service from this TOML or YAML configuration file rather
+                                                    than from the other options. Only the name and base-dir options
+                                                    apply. If a relative path is given, it will be relative to base-dir.
+                                                    Relative paths in the file are relative to the file's dir
             --banner_delay <delay>                  Should we enforce prudent banner deleay? Delay is in miliseconds
         -i, --identity-file <identity file path>    Use this identity file for TLS. Disabled with --no-tls. If a relative
                                                     path is given, it will be relative to base-dir
@@ -73,6 +77,39 @@ Both should produce a usage information not too different from this:
                                                     ports. If no ports are given, the default is to start on localhost:25
             --command_timeout <timeout>             Should we enforce prudent command timeout? Timeout is in miliseconds
 
+# Configuration file
+
+Instead of the command line options, the service can be described in a TOML or YAML file
+and started with `samotop-server --config samotop.toml`:
+
+```toml
+name = "mx.example.org"
+listen = ["0.0.0.0:25"]
+protocol = "esmtp"
+
+[tls]
+cert_file = "Samotop.crt"
+key_file = "Samotop.key"
+
+[prudence]
+banner_delay_ms = 3000
+read_timeout_ms = 60000
+
+[[guards]]
+type = "mapper"
+map = [["@localhost$", "@mx.example.org"]]
+
+[[guards]]
+type = "spf"
+mail_from = { softfail = "tempfail" }
+
+[[dispatches]]
+type = "maildir"
+path = "inmail"
+```
+
+See `samotop::config` for all the components available.
+
 # TLS
 
 You can run these openssl commands in docker as well.
@@ -118,6 +155,7 @@ use async_std::io::ReadExt;
 use async_std::task;
 use async_tls::TlsAcceptor;
 use rustls::ServerConfig;
+use samotop::config::{Registry, ServiceConfig};
 use samotop::io::tls::RustlsProvider;
 use samotop::mail::spf::Spf;
 use samotop::mail::{Builder, DebugService, MailDir, Name};
@@ -137,6 +175,13 @@ fn main() -> Result<()> {
 async fn main_fut() -> Result<()> {
     let setup = Setup::from_args();
 
+    if let Some(mut config) = setup.service_config()? {
+        if config.name.is_none() {
+            config.name = Some(setup.name());
+        }
+        return config.serve(&Registry::default()).await;
+    }
+
     let mut service = Builder
         + Name::new(setup.name())
         + DebugService::default()
@@ -165,6 +210,14 @@ impl Setup {
         }
     }
 
+    /// Load the service configuration file if given
+    pub fn service_config(&self) -> Result<Option<ServiceConfig>> {
+        match self.opt.config {
+            None => Ok(None),
+            Some(ref path) => Ok(Some(ServiceConfig::from_file(self.absolute_path(path))?)),
+        }
+    }
+
     pub fn prudence(&self) -> Prudence {
         let mut prudence = Prudence::default();
         if let Some(delay) = self.opt.prudent_banner_delay {
@@ -282,6 +335,13 @@ struct Opt {
     #[structopt(short = "p", long = "port", name = "port")]
     ports: Vec<String>,
 
+    /// Build the service from this TOML or YAML configuration file
+    /// rather than from the other options. Only the name and base-dir options apply.
+    /// If a relative path is given, it will be relative to base-dir.
+    /// Relative paths in the file are relative to the file's dir.
+    #[structopt(long = "config", name = "config file path")]
+    config: Option<PathBuf>,
+
     /// Disable TLS suport.
     /// It is enabled by default to reduce accidents and remind operators of misconfiguration.
     #[structopt(long = "no-tls")]
@@ -293,7 +353,7 @@ struct Opt {
         short = "i",
         long = "identity-file",
         name = "identity file path",
-        required_unless = "no-tls"
+        required_unless_one = &["no-tls", "config file path"]
     )]
     identity_file: Option<String>,
 
@@ -303,7 +363,7 @@ struct Opt {
         short = "c",
         long = "cert-file",
         name = "cert file path",
-        required_unless = "no-tls"
+        required_unless_one = &["no-tls", "config file path"]
     )]
     cert_file: Option<String>,
 
@@ -1,9 +1,14 @@
 use async_tls::{TlsAcceptor, TlsConnector};
+use rustls::internal::pemfile;
+use rustls::sign::CertifiedKey;
 use samotop_core::{
     common::*,
     io::tls::{Io, TlsProvider, TlsUpgrade},
 };
+use std::collections::HashMap;
 use std::fmt;
+use std::io::BufReader;
+use std::path::Path;
 
 /// TLS provider for RustTLS.
 ///
@@ -24,6 +29,101 @@ impl From<TlsConnector> for RustlsProvider<TlsConnector> {
     }
 }
 
+impl RustlsProvider<TlsAcceptor> {
+    /// Create a server side provider from PEM encoded certificate chain and PKCS8 or RSA private key files
+    pub fn from_pem_files(cert_file: impl AsRef<Path>, key_file: impl AsRef<Path>) -> Result<Self> {
+        let (certs, key) = read_pem_files(cert_file.as_ref(), key_file.as_ref())?;
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config.set_single_cert(certs, key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)).into())
+    }
+    /// Create a server side provider which picks the certificate by the SNI the client sends.
+    ///
+    /// `hosts` are tuples of the server name, certificate chain file and private key file.
+    /// The default certificate is used if the client sends no SNI or an unknown name.
+    pub fn from_pem_files_with_sni<N, C, K>(
+        cert_file: impl AsRef<Path>,
+        key_file: impl AsRef<Path>,
+        hosts: impl IntoIterator<Item = (N, C, K)>,
+    ) -> Result<Self>
+    where
+        N: AsRef<str>,
+        C: AsRef<Path>,
+        K: AsRef<Path>,
+    {
+        let mut resolver = SniResolver {
+            default: certified_key(cert_file.as_ref(), key_file.as_ref())?,
+            hosts: HashMap::new(),
+        };
+        for (name, cert_file, key_file) in hosts {
+            resolver.hosts.insert(
+                name.as_ref().to_ascii_lowercase(),
+                certified_key(cert_file.as_ref(), key_file.as_ref())?,
+            );
+        }
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config.cert_resolver = Arc::new(resolver);
+        Ok(TlsAcceptor::from(Arc::new(config)).into())
+    }
+}
+
+fn read_pem_files(
+    cert_file: &Path,
+    key_file: &Path,
+) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey)> {
+    let read =
+        |path: &Path| std::fs::read(path).map_err(|e| format!("Could not read {:?}: {}", path, e));
+
+    let certs = read(cert_file)?;
+    let certs = pemfile::certs(&mut BufReader::new(certs.as_slice()))
+        .map_err(|_| format!("Could not load certs from {:?}", cert_file))?;
+    if certs.is_empty() {
+        return Err(format!("No certs found in {:?}", cert_file).into());
+    }
+
+    let keys = read(key_file)?;
+    let mut key = pemfile::pkcs8_private_keys(&mut BufReader::new(keys.as_slice()))
+        .map_err(|_| format!("Could not load key from {:?}", key_file))?;
+    if key.is_empty() {
+        key = pemfile::rsa_private_keys(&mut BufReader::new(keys.as_slice()))
+            .map_err(|_| format!("Could not load key from {:?}", key_file))?;
+    }
+    let key = key
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("No private key found in {:?}", key_file))?;
+    Ok((certs, key))
+}
+
+fn certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey> {
+    let (certs, key) = read_pem_files(cert_file, key_file)?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| format!("Unsupported private key in {:?}", key_file))?;
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+/// Picks the certificate by SNI
+struct SniResolver {
+    default: CertifiedKey,
+    hosts: HashMap<String, CertifiedKey>,
+}
+
+impl SniResolver {
+    /// The certificate of the given server name or the default one
+    fn pick(&self, server_name: Option<&str>) -> &CertifiedKey {
+        server_name
+            .and_then(|name| self.hosts.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl rustls::ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: rustls::ClientHello) -> Option<CertifiedKey> {
+        let server_name: Option<&str> = client_hello.server_name().map(Into::into);
+        Some(self.pick(server_name).clone())
+    }
+}
+
 impl TlsUpgrade for RustlsProvider<TlsAcceptor> {
     fn upgrade_to_tls(
         &self,
@@ -81,3 +181,55 @@ impl fmt::Debug for RustlsProvider<TlsConnector> {
         f.debug_struct("RustlsProvider<TlsConnector>").finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::internal::msgs::enums::SignatureAlgorithm;
+    use rustls::sign::{Signer, SigningKey};
+    use rustls::SignatureScheme;
+
+    struct NoKey;
+
+    impl SigningKey for NoKey {
+        fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+            None
+        }
+        fn algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::Anonymous
+        }
+    }
+
+    fn key(cert: &str) -> CertifiedKey {
+        let certs = vec![rustls::Certificate(cert.as_bytes().to_vec())];
+        let key: Box<dyn SigningKey> = Box::new(NoKey);
+        CertifiedKey::new(certs, Arc::new(key))
+    }
+
+    fn sut() -> SniResolver {
+        let mut hosts = HashMap::new();
+        hosts.insert("mx.brand-b.example".to_owned(), key("brand-b"));
+        SniResolver {
+            default: key("default"),
+            hosts,
+        }
+    }
+
+    #[test]
+    fn picks_host_by_sni() {
+        let picked = sut().pick(Some("MX.Brand-B.example")).cert.clone();
+        assert_eq!(picked, vec![rustls::Certificate(b"brand-b".to_vec())]);
+    }
+
+    #[test]
+    fn picks_default_for_unknown_sni() {
+        let picked = sut().pick(Some("mx.brand-c.example")).cert.clone();
+        assert_eq!(picked, vec![rustls::Certificate(b"default".to_vec())]);
+    }
+
+    #[test]
+    fn picks_default_without_sni() {
+        let picked = sut().pick(None).cert.clone();
+        assert_eq!(picked, vec![rustls::Certificate(b"default".to_vec())]);
+    }
+}
@@ -0,0 +1,188 @@
+use ring::digest::{Context, Digest, SHA256};
+
+/// Canonicalization algorithm as per RFC 6376 section 3.4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    Simple,
+    Relaxed,
+}
+
+impl Canonicalization {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "simple" => Some(Canonicalization::Simple),
+            "relaxed" => Some(Canonicalization::Relaxed),
+            _ => None,
+        }
+    }
+
+    /// Canonicalize one raw header field, folding and CRLF included
+    pub fn header(self, raw: &[u8]) -> Vec<u8> {
+        match self {
+            Canonicalization::Simple => raw.to_vec(),
+            Canonicalization::Relaxed => {
+                let colon = raw.iter().position(|b| *b == b':').unwrap_or(raw.len());
+                let mut out = raw[..colon]
+                    .iter()
+                    .filter(|b| !is_wsp(**b) && !is_crlf(**b))
+                    .map(|b| b.to_ascii_lowercase())
+                    .collect::<Vec<u8>>();
+                out.push(b':');
+                if colon < raw.len() {
+                    // unfold, then compress whitespace
+                    let value = raw[colon + 1..]
+                        .iter()
+                        .copied()
+                        .filter(|b| !is_crlf(*b))
+                        .collect::<Vec<u8>>();
+                    out.extend(compress_wsp(&value, false));
+                }
+                out.extend_from_slice(b"\r\n");
+                out
+            }
+        }
+    }
+}
+
+/// Hashes the canonical body as it streams in, line by line
+pub struct BodyHasher {
+    canonicalization: Canonicalization,
+    hash: Context,
+    /// The number of body bytes to hash, all if missing (`l=` tag)
+    limit: Option<u64>,
+    hashed: u64,
+    /// Empty lines are only hashed once followed by content
+    empty_lines: usize,
+    /// Anything but empty lines was seen
+    content: bool,
+}
+
+impl BodyHasher {
+    pub fn new(canonicalization: Canonicalization, limit: Option<u64>) -> Self {
+        Self {
+            canonicalization,
+            hash: Context::new(&SHA256),
+            limit,
+            hashed: 0,
+            empty_lines: 0,
+            content: false,
+        }
+    }
+
+    /// Hash one body line, without the line ending
+    pub fn line(&mut self, line: &[u8]) {
+        let line = match self.canonicalization {
+            Canonicalization::Simple => line.to_vec(),
+            Canonicalization::Relaxed => compress_wsp(line, true),
+        };
+        if line.is_empty() {
+            self.empty_lines += 1;
+            return;
+        }
+        for _ in 0..std::mem::take(&mut self.empty_lines) {
+            self.update(b"\r\n");
+        }
+        self.update(&line);
+        self.update(b"\r\n");
+        self.content = true;
+    }
+
+    pub fn finish(mut self) -> Digest {
+        if !self.content && self.canonicalization == Canonicalization::Simple {
+            // an empty body is a single CRLF in simple canonicalization
+            self.update(b"\r\n");
+        }
+        self.hash.finish()
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let data = match self.limit {
+            Some(limit) => {
+                let left = limit.saturating_sub(self.hashed).min(data.len() as u64);
+                &data[..left as usize]
+            }
+            None => data,
+        };
+        self.hashed += data.len() as u64;
+        self.hash.update(data);
+    }
+}
+
+/// Reduce whitespace runs to a single space and drop the trailing whitespace
+/// as the relaxed canonicalization does with both header values and body lines.
+/// Leading whitespace is only kept in body lines.
+fn compress_wsp(data: &[u8], leading: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut space = false;
+    for b in data {
+        if is_wsp(*b) {
+            space = true;
+        } else {
+            if space && (leading || !out.is_empty()) {
+                out.push(b' ');
+            }
+            space = false;
+            out.push(*b);
+        }
+    }
+    out
+}
+
+fn is_wsp(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+fn is_crlf(b: u8) -> bool {
+    b == b'\r' || b == b'\n'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(canonicalization: Canonicalization, lines: &[&str]) -> Vec<u8> {
+        let mut hasher = BodyHasher::new(canonicalization, None);
+        for line in lines {
+            hasher.line(line.as_bytes());
+        }
+        hasher.finish().as_ref().to_vec()
+    }
+
+    fn sha256(data: &str) -> Vec<u8> {
+        ring::digest::digest(&SHA256, data.as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+
+    /// The example of RFC 6376 section 3.4.5
+    #[test]
+    fn rfc_example() {
+        let relaxed = Canonicalization::Relaxed;
+        assert_eq!(relaxed.header(b"A: X\r\n"), b"a:X\r\n");
+        assert_eq!(relaxed.header(b"B : Y\t\r\n\tZ  \r\n"), b"b:Y Z\r\n");
+        let simple = Canonicalization::Simple;
+        assert_eq!(
+            simple.header(b"B : Y\t\r\n\tZ  \r\n"),
+            b"B : Y\t\r\n\tZ  \r\n"
+        );
+
+        let lines = [" C ", "D \t E", "", ""];
+        assert_eq!(body(relaxed, &lines), sha256(" C\r\nD E\r\n"));
+        assert_eq!(body(simple, &lines), sha256(" C \r\nD \t E\r\n"));
+    }
+
+    #[test]
+    fn empty_bodies() {
+        assert_eq!(body(Canonicalization::Simple, &[]), sha256("\r\n"));
+        assert_eq!(body(Canonicalization::Simple, &["", ""]), sha256("\r\n"));
+        assert_eq!(body(Canonicalization::Relaxed, &[" ", ""]), sha256(""));
+    }
+
+    #[test]
+    fn length_limit() {
+        let mut hasher = BodyHasher::new(Canonicalization::Simple, Some(5));
+        hasher.line(b"Hello");
+        hasher.line(b"appended by a list");
+        assert_eq!(hasher.finish().as_ref(), sha256("Hello").as_slice());
+    }
+}
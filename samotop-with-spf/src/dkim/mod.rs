@@ -0,0 +1,751 @@
+mod canon;
+mod signature;
+
+use self::canon::BodyHasher;
+use self::signature::{field_name, PublicKey, Signature};
+use crate::lookup::resolver_for;
+use samotop_core::{
+    common::*,
+    dns::{DnsError, Resolver},
+    mail::{
+        AcceptsCheck, AcceptsDispatch, AuthenticationResults, CheckMailResult, DispatchResult,
+        MailCheck, MailDataSink, MailDispatch, MailSetup,
+    },
+    smtp::SmtpSession,
+};
+use std::sync::Mutex;
+
+/// Signatures beyond this count are not verified
+const MAX_SIGNATURES: usize = 10;
+/// Messages with a larger header are not verified
+const MAX_HEADER: usize = 256 * 1024;
+/// Messages with longer lines are not verified
+const MAX_LINE: usize = 64 * 1024;
+
+/**
+DKIM signature verification as per RFC 6376.
+
+The mail data are hashed as they are written to the `Transaction::sink`.
+After the final dot, every `DKIM-Signature` is verified with the public key looked up in DNS.
+Relaxed and simple canonicalization is supported with `rsa-sha256` and `ed25519-sha256`.
+
+The results are recorded in the transaction as `DkimResults` for each signature
+and as "dkim" `AuthenticationResults`, the first passing signature or else the first one,
+ready for `AuthenticationResults::header()`.
+No mail is refused here, that is up to a policy check that comes later.
+
+The verification wraps the dispatch chain set up so far, so add it after the dispatches.
+The `Dns` resolver of the service is used unless one is given `with_resolver`.
+
+```
+# use samotop_core::mail::*;
+# use samotop_with_spf::Dkim;
+let service = Builder + NullDispatch + Dkim::default();
+```
+*/
+#[derive(Clone, Default)]
+pub struct Dkim {
+    resolver: Option<Arc<dyn Resolver>>,
+}
+
+impl Dkim {
+    /// Use the given DNS resolver rather than the one of the service
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+}
+
+impl fmt::Debug for Dkim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dkim")
+            .field("resolver", &self.resolver)
+            .finish()
+    }
+}
+
+impl<T: AcceptsDispatch + AcceptsCheck> MailSetup<T> for Dkim {
+    fn setup(self, config: &mut T) {
+        config.wrap_dispatches(|inner| DkimWrap { inner });
+        // policy checks coming later can use the results
+        config.add_first_check(self);
+    }
+}
+
+impl MailCheck for Dkim {
+    fn check_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, CheckMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let verifier = match session.transaction.take::<DkimVerifier>() {
+            Some(verifier) => verifier,
+            None => return Box::pin(ready(CheckMailResult::Accepted)),
+        };
+        let resolver = resolver_for(self.resolver.clone(), session.dns.clone());
+        Box::pin(async move {
+            let resolver = resolver.await;
+            let hashed = verifier
+                .0
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .finish();
+            let mut results = vec![];
+            for signature in hashed {
+                results.push(match (signature, resolver.as_ref()) {
+                    (Err(result), _) => result,
+                    (Ok(hashed), Ok(resolver)) => hashed.verify(resolver.as_ref()).await,
+                    (Ok(hashed), Err(e)) => hashed.result(DkimStatus::TempError, e.to_string()),
+                });
+            }
+            let summary = results
+                .iter()
+                .find(|result| result.status == DkimStatus::Pass)
+                .or_else(|| results.first())
+                .map(|result| result.to_string())
+                .unwrap_or_else(|| "none".to_owned());
+            debug!(
+                "DKIM of {}: {}",
+                session.transaction.id,
+                results
+                    .iter()
+                    .map(|result| result.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            session
+                .transaction
+                .get_or_insert(AuthenticationResults::default)
+                .add("dkim", summary);
+            session.transaction.set(DkimResults(results));
+            CheckMailResult::Accepted
+        })
+    }
+}
+
+/// The outcome of a DKIM signature verification as per RFC 8601
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DkimStatus {
+    Pass,
+    Fail,
+    /// The signature failed, but the signer is only testing
+    Neutral,
+    /// The key could not be retrieved, it may work later
+    TempError,
+    /// The signature or the key is invalid or unsupported
+    PermError,
+}
+
+impl fmt::Display for DkimStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DkimStatus::Pass => "pass",
+            DkimStatus::Fail => "fail",
+            DkimStatus::Neutral => "neutral",
+            DkimStatus::TempError => "temperror",
+            DkimStatus::PermError => "permerror",
+        })
+    }
+}
+
+/// The verification result of one `DKIM-Signature`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DkimResult {
+    pub status: DkimStatus,
+    /// The signing domain, `d=` tag
+    pub domain: String,
+    /// The key selector, `s=` tag
+    pub selector: String,
+    /// The agent or user identity, `i=` tag
+    pub identity: Option<String>,
+    /// Why it did not pass
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for DkimResult {
+    /// Formatted for the Authentication-Results header,
+    /// i.e. `fail reason="body hash did not verify" header.d=example.org header.s=sel`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status)?;
+        if let Some(ref reason) = self.reason {
+            write!(f, " reason={:?}", reason)?;
+        }
+        if !self.domain.is_empty() {
+            write!(f, " header.d={}", self.domain)?;
+        }
+        if !self.selector.is_empty() {
+            write!(f, " header.s={}", self.selector)?;
+        }
+        if let Some(ref identity) = self.identity {
+            write!(f, " header.i={}", identity)?;
+        }
+        Ok(())
+    }
+}
+
+/// Results of all the DKIM signatures of the mail in the order of appearance
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DkimResults(pub Vec<DkimResult>);
+
+impl DkimResults {
+    /// Domains with a passing signature, i.e. for DMARC alignment
+    pub fn passed_domains(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(|result| result.status == DkimStatus::Pass)
+            .map(|result| result.domain.as_str())
+    }
+}
+
+/// A signature with the mail hashed, ready for the key lookup
+struct Hashed {
+    signature: Signature,
+    body_hash: Vec<u8>,
+    signed_data: Vec<u8>,
+}
+
+impl Hashed {
+    async fn verify(self, resolver: &dyn Resolver) -> DkimResult {
+        if self.body_hash != self.signature.body_hash {
+            return self.result(DkimStatus::Fail, "body hash did not verify");
+        }
+        if self.signature.is_expired() {
+            return self.result(DkimStatus::Fail, "signature expired");
+        }
+        let records = match resolver
+            .lookup_txt(self.signature.key_name().as_str())
+            .await
+        {
+            Ok(records) => records,
+            Err(DnsError::NoRecords) => {
+                return self.result(DkimStatus::PermError, "no key for signature")
+            }
+            Err(e) => return self.result(DkimStatus::TempError, e.to_string()),
+        };
+        let key = match records.iter().map(|txt| PublicKey::parse(txt)).next() {
+            Some(Ok(key)) => key,
+            Some(Err(e)) => return self.result(DkimStatus::PermError, e),
+            None => return self.result(DkimStatus::PermError, "no key for signature"),
+        };
+        match self.signature.verify(&key, &self.signed_data) {
+            Ok(()) => DkimResult {
+                reason: None,
+                ..self.result(DkimStatus::Pass, "")
+            },
+            Err(e) if key.testing => self.result(DkimStatus::Neutral, e),
+            Err(e) => self.result(DkimStatus::Fail, e),
+        }
+    }
+    fn result(&self, status: DkimStatus, reason: impl Into<String>) -> DkimResult {
+        DkimResult {
+            status,
+            domain: self.signature.domain.clone(),
+            selector: self.signature.selector.clone(),
+            identity: self.signature.identity.clone(),
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+/// Feeds the mail data to the verifier on their way to the dispatch
+#[derive(Debug)]
+struct DkimWrap<T> {
+    inner: T,
+}
+
+impl MailDispatch for DkimWrap<Box<dyn MailDispatch + Sync + Send>> {
+    fn open_mail_body<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, DispatchResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(async move {
+            let result = self.inner.open_mail_body(session).await;
+            if let Some(inner) = session.transaction.sink.take() {
+                let verifier = DkimVerifier::default();
+                session.transaction.sink = Some(Box::pin(DkimSink {
+                    verifier: verifier.0.clone(),
+                    inner,
+                }));
+                session.transaction.set(verifier);
+            }
+            result
+        })
+    }
+}
+
+struct DkimSink {
+    verifier: Arc<Mutex<Verifier>>,
+    inner: Pin<Box<dyn MailDataSink>>,
+}
+
+impl io::Write for DkimSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = self.inner.as_mut().poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = result {
+            self.verifier
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .write(&buf[..len]);
+        }
+        result
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.as_mut().poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.as_mut().poll_close(cx)
+    }
+}
+
+/// The verifier shared by the sink and the check in the transaction store
+#[derive(Default)]
+struct DkimVerifier(Arc<Mutex<Verifier>>);
+
+/// Splits the mail data into lines, collects the header and hashes the body
+#[derive(Default)]
+struct Verifier {
+    line: Vec<u8>,
+    fields: Vec<Vec<u8>>,
+    header_size: usize,
+    /// Set once the header is complete
+    signatures: Option<Vec<Pending>>,
+    /// The mail is not verified because of its size
+    skipped: bool,
+}
+
+/// A signature being verified or the result if it could not be
+type Pending = std::result::Result<(Signature, BodyHasher), DkimResult>;
+
+impl Verifier {
+    fn write(&mut self, mut data: &[u8]) {
+        while !self.skipped && !data.is_empty() {
+            match data.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    self.line.extend_from_slice(&data[..end]);
+                    data = &data[end + 1..];
+                    let mut line = std::mem::take(&mut self.line);
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    self.line_done(line);
+                }
+                None => {
+                    self.line.extend_from_slice(data);
+                    data = &[];
+                }
+            }
+            if self.line.len() > MAX_LINE {
+                self.skip("line too long");
+            }
+        }
+    }
+    fn line_done(&mut self, line: Vec<u8>) {
+        if let Some(ref mut signatures) = self.signatures {
+            for (_, hasher) in signatures.iter_mut().flatten() {
+                hasher.line(&line);
+            }
+            return;
+        }
+        self.header_size += line.len() + 2;
+        if self.header_size > MAX_HEADER {
+            self.skip("header too large");
+        } else if line.is_empty() {
+            self.header_done();
+        } else if line.starts_with(b" ") || line.starts_with(b"\t") {
+            if let Some(field) = self.fields.last_mut() {
+                field.extend(line);
+                field.extend_from_slice(b"\r\n");
+            }
+        } else {
+            let mut field = line;
+            field.extend_from_slice(b"\r\n");
+            self.fields.push(field);
+        }
+    }
+    fn header_done(&mut self) {
+        let signatures = self
+            .fields
+            .iter()
+            .filter(|field| field_name(field).eq_ignore_ascii_case("DKIM-Signature"))
+            .take(MAX_SIGNATURES)
+            .map(|field| {
+                Signature::parse(field)
+                    .map(|signature| {
+                        let hasher =
+                            BodyHasher::new(signature.body_canonicalization, signature.body_length);
+                        (signature, hasher)
+                    })
+                    .map_err(|e| DkimResult {
+                        status: DkimStatus::PermError,
+                        domain: String::new(),
+                        selector: String::new(),
+                        identity: None,
+                        reason: Some(e),
+                    })
+            })
+            .collect();
+        self.signatures = Some(signatures);
+    }
+    fn skip(&mut self, reason: &str) {
+        warn!("Not verifying DKIM, {}", reason);
+        self.skipped = true;
+        self.signatures = Some(vec![]);
+    }
+    /// The signatures with the mail hashed
+    fn finish(&mut self) -> Vec<std::result::Result<Hashed, DkimResult>> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.line_done(line);
+        }
+        if self.signatures.is_none() {
+            // no body
+            self.header_done();
+        }
+        let fields = std::mem::take(&mut self.fields);
+        self.signatures
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|pending| {
+                pending.map(|(signature, hasher)| Hashed {
+                    body_hash: hasher.finish().as_ref().to_vec(),
+                    signed_data: signature.signed_data(&fields),
+                    signature,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RSA_PKCS1_SHA256};
+    use samotop_core::dns::ZoneResolver;
+    use samotop_core::mail::{NullDispatch, Transaction};
+
+    /// A 2048 bit test key, PKCS#8
+    const RSA_PKCS8: &str = "\
+        MIIEvgIBADANBgkqhkiG9w0BAQEFAASCBKgwggSkAgEAAoIBAQDgbWGQdA1R+HBH8WHL8MSp+FqK\
+        /w6wCwx6S48+otk38QmDwH8vY0urrgPBRaxuw//axqZEaWR7DvRq26pZrq9H7pvqm6x6lMZNYPc+\
+        MgUmDFj5jdHUrb3R3/G8LqQxEvrEzwKjgNU2iIwXT/YsW9E7E85IqsYSelCBA88sNvqMrxzDwqXa\
+        zvWAJbjv1koVsRlvAT+xg8g6rknxu14/+YBVuhimSrtr0VAVJ610h7MG7VBbcbZrobO2IZqc0nnd\
+        hl7fkq/x9tAu6jrOmezhVWThAfPeVLKATUW26DVeDFL0wCxrZmqGz1WynLpaqkjEiK7OG80ngWtR\
+        SMClgMHehjUHAgMBAAECggEAKq6iz4ZCwkQtloEhyFeSD4FcNvMrJ+/x/5A/PfpatXjyBkyl461O\
+        xufhbvCBSs+b6U++b2Rb58uAUJJWY5gFXfjTTlstAmMpE65NV1bMA+B1S4DdV0VzxGaC4vc74tlp\
+        N+Tm65Gk+yoc+pIKj4Um1cV+hfN0mC8LAH5o+E+8duS7tGNsO9QXMekL+CSJZl339XSDoVV1gF1v\
+        bNY2hxXqFEYu7zFffeoOPfmSF1s157qGdZhiHsQR8ZPdvjbGHdQVyVCEMRVskugAVVLLMDQukH0d\
+        QL7+ZEbsu8wb27XTf2mIDth1dQoGCd9/RPSUJmDeQv4Kk8TBVsIdsXbxFIbbgQKBgQDzpZ6v6aJh\
+        Cl8Q7SBfKcOY5zJdjlpcm6o/rzUYTKZOfzlucRtvW33+xE2Gz9GQh7lrmGH7w/YmU68BKOQNW6w1\
+        H8C6HSvVU0G25xnv5XUB8kpxiEjgh8LhkLr/P+fpAbIb0b3BwJpdK9RrMG9IaAgMgCrp5JFhCmF0\
+        inNIWngbJQKBgQDrzk1a2IU/OPmjDofg1r6K+en5BWYTbf14y8ZJStAMfmzhopxtmSqlm55W7rU0\
+        gs8K4VGf465+zxqKnzeeXSpGEIhmMEg8FWcNUzmm0nuF/+Tn7PZ6KRFvmFtj9wBH/koM0DNAUoqD\
+        yLkBMGU1C46rbTyFkNX5R9GEOi1MlkuNuwKBgQC4A9ky8Rizg9rC9OTjGq7gl5TBdhcnJGZTzOcj\
+        4Bgm5w2gLQrUctxZAzeuXtze9LME4UT3mkwZLFDbZl+++wK36LBtoESPzF6wXiCHv9w8SRsSXiT0\
+        d3SnWeDxkPl9UMuNGT7gy6+KOOUow574p6GUl2RR3FA0Y1uxYjOpbLKThQKBgQCJuQt7BL0GSPuc\
+        nY8E5ceSGhbqZ5nPvUzusW2VPwm2Uly7iCL+7Bd5dCONuIvDKKdOPn1S+kLfM4zX69grOVTIaT2R\
+        GaAG36U5piMk6QhWIjgKsV7fyH6zg/PUtd3HLqEHTM6yQcM+5wwOwrpJuf9Q5lMT25ztpGGdbfEp\
+        KxrjowKBgCwV3ea32a9IFpEJHbLnsaiuLKG7Spc8TDxgFZ5HYNWyYQCLVjDdSV5MzwyfpolP6ohG\
+        5+13Saum33/LLkxB2ENJBNTJ6JDeXOBD9x5exMeF0ViAYgCU21oGoCvDBb+q+4771BfLNUGClu9E\
+        5wMsgw7UCGlA7hAr0XH55Y+OjedP";
+    /// Its public part, SubjectPublicKeyInfo as published in DNS
+    const RSA_SPKI: &str = "\
+        MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA4G1hkHQNUfhwR/Fhy/DEqfhaiv8OsAsM\
+        ekuPPqLZN/EJg8B/L2NLq64DwUWsbsP/2samRGlkew70atuqWa6vR+6b6pusepTGTWD3PjIFJgxY\
+        +Y3R1K290d/xvC6kMRL6xM8Co4DVNoiMF0/2LFvROxPOSKrGEnpQgQPPLDb6jK8cw8Kl2s71gCW4\
+        79ZKFbEZbwE/sYPIOq5J8bteP/mAVboYpkq7a9FQFSetdIezBu1QW3G2a6GztiGanNJ53YZe35Kv\
+        8fbQLuo6zpns4VVk4QHz3lSygE1Ftug1XgxS9MAsa2Zqhs9Vspy6WqpIxIiuzhvNJ4FrUUjApYDB\
+        3oY1BwIDAQAB";
+
+    const MAIL: &str = "Received: from relay\r\n\
+        From: Joe SixPack <joe@football.example.com>\r\n\
+        To: Suzie Q <suzie@shopping.example.net>\r\n\
+        Subject:  Is dinner ready?\r\n\
+        \r\n\
+        Hi.\r\n\
+        \r\n\
+        We lost the game.  Are you hungry yet?\r\n\
+        \r\n\
+        Joe.\r\n";
+
+    /// RFC 8463 Appendix A, the published keys
+    const RFC8463_ZONE: &str = "$ORIGIN _domainkey.football.example.com.\n\
+        brisbane TXT \"v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=\"\n\
+        test TXT \"v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDkHlOQoBTzWR\
+        iGs5V6NpP3idY6Wk08a5qhdR6wy5bdOKb2jLQiY/J16JYi0Qvx/byYzCNb3W91y3FutAC\
+        DfzwQ/BC/e/8uBsCR+yz1Lxj+PL6lHvqMKrM3rG4hstT5QjvHO9PzoxZyVYLzBfO2EeC3\
+        Ip3G+2kryOTIKT+l/K4w3QIDAQAB\"\n";
+
+    /// RFC 8463 Appendix A, the signed message
+    const RFC8463_MAIL: &str = "\
+        DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed;\r\n \
+        d=football.example.com; i=@football.example.com;\r\n \
+        q=dns/txt; s=brisbane; t=1528637909; h=from : to :\r\n \
+        subject : date : message-id : from : subject : date;\r\n \
+        bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n \
+        b=/gCrinpcQOoIfuHNQIbq4pgh9kyIK3AQUdt9OdqQehSwhEIug4D11Bus\r\n \
+        Fa3bT3FY5OsU7ZbnKELq+eXdp1Q1Dw==\r\n\
+        DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed;\r\n \
+        d=football.example.com; i=@football.example.com;\r\n \
+        q=dns/txt; s=test; t=1528637909; h=from : to : subject :\r\n \
+        date : message-id : from : subject : date;\r\n \
+        bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=;\r\n \
+        b=F45dVWDfMbQDGHJFlXUNB2HKfbCeLRyhDXgFpEL8GwpsRe0IeIixNTe3\r\n \
+        DhCVlUrSjV4BwcVcOF6+FF3Zo9Rpo1tFOeS9mPYQTnGdaSGsgeefOsk2Jz\r\n \
+        dA+L10TeYt9BgDfQNZtKdN1WO//KgIqXP7OdEFE4LjFYNcUxZQ4FADY+8=\r\n\
+        From: Joe SixPack <joe@football.example.com>\r\n\
+        To: Suzie Q <suzie@shopping.example.net>\r\n\
+        Subject: Is dinner ready?\r\n\
+        Date: Fri, 11 Jul 2003 21:00:37 -0700 (PDT)\r\n\
+        Message-ID: <20030712040037.46341.5F8J@football.example.com>\r\n\
+        \r\n\
+        Hi.\r\n\
+        \r\n\
+        We lost the game.  Are you hungry yet?\r\n\
+        \r\n\
+        Joe.\r\n";
+
+    #[derive(Clone, Copy)]
+    enum Key {
+        Rsa,
+        Ed25519,
+    }
+
+    /// Sign the mail like a sender would
+    fn sign(mail: &str, key: Key, canonicalization: &str) -> String {
+        let (header, body) = mail.split_once("\r\n\r\n").unwrap();
+        let (canon_header, canon_body) = canonicalization.split_once('/').unwrap();
+        let canon_body = canon::Canonicalization::parse(canon_body).unwrap();
+        let mut hasher = BodyHasher::new(canon_body, None);
+        for line in body.strip_suffix("\r\n").unwrap().split("\r\n") {
+            hasher.line(line.as_bytes());
+        }
+        let algorithm = match key {
+            Key::Rsa => "rsa-sha256",
+            Key::Ed25519 => "ed25519-sha256",
+        };
+        let dkim = format!(
+            "DKIM-Signature: v=1; a={}; c={};\r\n\
+            \td=football.example.com; s={}; h=from:to:subject:date;\r\n\
+            \tbh={}; b=\r\n",
+            algorithm,
+            canonicalization,
+            algorithm,
+            base64::encode(hasher.finish())
+        );
+        let mut fields = header
+            .split("\r\n")
+            .map(|f| format!("{}\r\n", f).into_bytes())
+            .collect::<Vec<_>>();
+        fields.push(dkim.clone().into_bytes());
+        let signature = Signature::parse(dkim.replace("b=\r\n", "b=AAAA\r\n").as_bytes())
+            .expect("valid signature");
+        assert_eq!(
+            signature.header_canonicalization,
+            canon::Canonicalization::parse(canon_header).unwrap()
+        );
+        let data = signature.signed_data(&fields[..fields.len() - 1]);
+        let b = match key {
+            Key::Rsa => {
+                let key =
+                    RsaKeyPair::from_pkcs8(&base64::decode(RSA_PKCS8.trim()).unwrap()).unwrap();
+                let mut b = vec![0; key.public_modulus_len()];
+                key.sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), &data, &mut b)
+                    .unwrap();
+                b
+            }
+            Key::Ed25519 => ed25519()
+                .sign(ring::digest::digest(&ring::digest::SHA256, &data).as_ref())
+                .as_ref()
+                .to_vec(),
+        };
+        // the signature header comes on top
+        format!(
+            "{}{}",
+            dkim.replace("b=\r\n", format!("b={}\r\n", base64::encode(b)).as_str()),
+            mail
+        )
+    }
+
+    fn ed25519() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap()
+    }
+
+    fn resolver() -> ZoneResolver {
+        ZoneResolver::parse(
+            format!(
+                "$ORIGIN _domainkey.football.example.com.\n\
+                 rsa-sha256 TXT \"v=DKIM1; k=rsa; p={}\"\n\
+                 ed25519-sha256 TXT \"v=DKIM1; k=ed25519; p={}\"\n",
+                RSA_SPKI.trim(),
+                base64::encode(ed25519().public_key())
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    /// Pass the mail through the sink in small chunks and check it
+    async fn verify(mail: &str) -> Transaction {
+        verify_with(mail, resolver()).await
+    }
+
+    async fn verify_with(mail: &str, resolver: ZoneResolver) -> Transaction {
+        let mut session = SmtpSession {
+            dns: Some(Arc::new(resolver)),
+            ..Default::default()
+        };
+        let sut = Dkim::default();
+        let dispatch = DkimWrap {
+            inner: Box::new(NullDispatch) as Box<dyn MailDispatch + Sync + Send>,
+        };
+        dispatch.open_mail_body(&mut session).await.unwrap();
+        let mut sink = session.transaction.sink.take().unwrap();
+        for chunk in mail.as_bytes().chunks(7) {
+            poll_fn(|cx| sink.as_mut().poll_write(cx, chunk))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            sut.check_mail(&mut session).await,
+            CheckMailResult::Accepted
+        );
+        session.transaction
+    }
+
+    fn statuses(transaction: &Transaction) -> Vec<DkimStatus> {
+        transaction
+            .get::<DkimResults>()
+            .unwrap()
+            .0
+            .iter()
+            .map(|result| result.status)
+            .collect()
+    }
+
+    #[test]
+    fn signatures_pass() {
+        async_std::task::block_on(async move {
+            for key in [Key::Rsa, Key::Ed25519] {
+                for canonicalization in ["relaxed/relaxed", "simple/simple", "relaxed/simple"] {
+                    let algorithm = match key {
+                        Key::Rsa => "rsa-sha256",
+                        Key::Ed25519 => "ed25519-sha256",
+                    };
+                    let mail = sign(MAIL, key, canonicalization);
+                    let transaction = verify(mail.as_str()).await;
+                    assert_eq!(
+                        transaction
+                            .get::<AuthenticationResults>()
+                            .unwrap()
+                            .get("dkim"),
+                        Some(
+                            format!("pass header.d=football.example.com header.s={}", algorithm)
+                                .as_str()
+                        ),
+                        "{}",
+                        mail
+                    );
+                    assert_eq!(
+                        transaction
+                            .get::<DkimResults>()
+                            .unwrap()
+                            .passed_domains()
+                            .collect::<Vec<_>>(),
+                        vec!["football.example.com"]
+                    );
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn rfc8463_vectors_pass() {
+        async_std::task::block_on(async move {
+            let zone = ZoneResolver::parse(RFC8463_ZONE).unwrap();
+            let transaction = verify_with(RFC8463_MAIL, zone).await;
+            assert_eq!(statuses(&transaction), [DkimStatus::Pass, DkimStatus::Pass]);
+        })
+    }
+
+    #[test]
+    fn relaxed_survives_reformatting() {
+        async_std::task::block_on(async move {
+            let mail = sign(MAIL, Key::Ed25519, "relaxed/relaxed")
+                .replace("Subject:  Is", "subject :\r\n\tIs")
+                .replace("lost the game. ", "lost the game.\t");
+            assert_eq!(statuses(&verify(mail.as_str()).await), [DkimStatus::Pass]);
+        })
+    }
+
+    #[test]
+    fn tampering_fails() {
+        async_std::task::block_on(async move {
+            let mail = sign(MAIL, Key::Rsa, "simple/simple");
+            let body = mail.replace("We lost", "We won");
+            let transaction = verify(body.as_str()).await;
+            assert_eq!(
+                transaction
+                    .get::<AuthenticationResults>()
+                    .unwrap()
+                    .get("dkim"),
+                Some(
+                    "fail reason=\"body hash did not verify\" \
+                    header.d=football.example.com header.s=rsa-sha256"
+                )
+            );
+            let header = mail.replace("Is dinner", "Is lunch");
+            assert_eq!(statuses(&verify(header.as_str()).await), [DkimStatus::Fail]);
+            let relaxed =
+                sign(MAIL, Key::Rsa, "simple/simple").replace("Subject:  Is", "Subject: Is");
+            assert_eq!(
+                statuses(&verify(relaxed.as_str()).await),
+                [DkimStatus::Fail]
+            );
+        })
+    }
+
+    #[test]
+    fn unknown_keys_and_broken_signatures_are_errors() {
+        async_std::task::block_on(async move {
+            let mail =
+                sign(MAIL, Key::Ed25519, "relaxed/relaxed").replace("s=ed25519-sha256", "s=gone");
+            let broken = format!("DKIM-Signature: v=1; a=rsa-sha1\r\n{}", mail);
+            let transaction = verify(broken.as_str()).await;
+            assert_eq!(
+                statuses(&transaction),
+                [DkimStatus::PermError, DkimStatus::PermError]
+            );
+            assert_eq!(
+                transaction.get::<DkimResults>().unwrap().0[1]
+                    .reason
+                    .as_deref(),
+                Some("no key for signature")
+            );
+        })
+    }
+
+    #[test]
+    fn unsigned_mail_has_none() {
+        async_std::task::block_on(async move {
+            let transaction = verify(MAIL).await;
+            assert_eq!(
+                transaction
+                    .get::<AuthenticationResults>()
+                    .unwrap()
+                    .get("dkim"),
+                Some("none")
+            );
+            assert!(statuses(&transaction).is_empty());
+        })
+    }
+}
@@ -0,0 +1,451 @@
+use super::canon::Canonicalization;
+use ring::digest::{digest, SHA256};
+use ring::signature::{
+    UnparsedPublicKey, VerificationAlgorithm, ED25519,
+    RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Signing algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    RsaSha256,
+    Ed25519Sha256,
+}
+
+/// A parsed `DKIM-Signature` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub algorithm: Algorithm,
+    pub signature: Vec<u8>,
+    pub body_hash: Vec<u8>,
+    pub header_canonicalization: Canonicalization,
+    pub body_canonicalization: Canonicalization,
+    pub domain: String,
+    pub selector: String,
+    /// Lower case names of the signed header fields
+    pub headers: Vec<String>,
+    pub body_length: Option<u64>,
+    pub identity: Option<String>,
+    pub expires: Option<u64>,
+    /// The signature header value with an empty `b=` tag
+    pub unsigned: Vec<u8>,
+}
+
+/// A public key published in DNS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    pub algorithm: Algorithm,
+    /// PKCS#1 RSAPublicKey for RSA, the raw key for ed25519
+    pub data: Vec<u8>,
+    /// The `t=y` flag, the domain is testing DKIM
+    pub testing: bool,
+    /// The `t=s` flag, the `i=` domain must be the `d=` domain
+    pub strict: bool,
+}
+
+impl Signature {
+    /// Parse a raw `DKIM-Signature` header field, name and folding included
+    pub fn parse(raw: &[u8]) -> Result<Self, String> {
+        let text = std::str::from_utf8(raw).map_err(|_| "signature is not UTF-8".to_owned())?;
+        let value = text
+            .split_once(':')
+            .map(|(_, value)| value)
+            .ok_or_else(|| "not a header".to_owned())?;
+        let tags = tags(value)?;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let required = |name: &str| tag(name).ok_or(format!("missing {}= tag", name));
+
+        if required("v")? != "1" {
+            return Err("unsupported version".to_owned());
+        }
+        let algorithm = match required("a")?.to_ascii_lowercase().as_str() {
+            "rsa-sha256" => Algorithm::RsaSha256,
+            "ed25519-sha256" => Algorithm::Ed25519Sha256,
+            other => return Err(format!("unsupported algorithm {}", other)),
+        };
+        let (header_canonicalization, body_canonicalization) = match tag("c") {
+            None => (Canonicalization::Simple, Canonicalization::Simple),
+            Some(c) => {
+                let (header, body) = c.split_once('/').unwrap_or((c, "simple"));
+                match (
+                    Canonicalization::parse(header),
+                    Canonicalization::parse(body),
+                ) {
+                    (Some(header), Some(body)) => (header, body),
+                    _ => return Err(format!("unsupported canonicalization {}", c)),
+                }
+            }
+        };
+        let domain = required("d")?.trim_end_matches('.').to_ascii_lowercase();
+        let selector = required("s")?.to_ascii_lowercase();
+        let headers = required("h")?
+            .split(':')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect::<Vec<_>>();
+        if !headers.iter().any(|h| h == "from") {
+            return Err("From is not signed".to_owned());
+        }
+        if let Some(q) = tag("q") {
+            if !q.split(':').any(|q| q.trim() == "dns/txt") {
+                return Err(format!("unsupported query method {}", q));
+            }
+        }
+        let identity = tag("i").map(str::to_owned);
+        if let Some(ref identity) = identity {
+            let idomain = identity
+                .rsplit_once('@')
+                .map(|(_, d)| d)
+                .unwrap_or_default()
+                .trim_end_matches('.')
+                .to_ascii_lowercase();
+            if !is_subdomain(&idomain, &domain) {
+                return Err("i= is not within d=".to_owned());
+            }
+        }
+        let number = |name: &str| -> Result<Option<u64>, String> {
+            tag(name)
+                .map(|n| n.parse().map_err(|_| format!("invalid {}= tag", name)))
+                .transpose()
+        };
+        Ok(Signature {
+            algorithm,
+            signature: base64_tag(required("b")?)?,
+            body_hash: base64_tag(required("bh")?)?,
+            header_canonicalization,
+            body_canonicalization,
+            domain,
+            selector,
+            headers,
+            body_length: number("l")?,
+            identity,
+            expires: number("x")?,
+            unsigned: without_signature(raw),
+        })
+    }
+
+    /// Where to find the public key
+    pub fn key_name(&self) -> String {
+        format!("{}._domainkey.{}", self.selector, self.domain)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        matches!(self.expires, Some(expires) if expires < now)
+    }
+
+    /// The data covered by the signature: the signed header fields, bottom up,
+    /// and the signature itself with an empty `b=` tag and no trailing CRLF.
+    ///
+    /// `fields` are the raw header fields of the message in order.
+    pub fn signed_data(&self, fields: &[Vec<u8>]) -> Vec<u8> {
+        let canon = self.header_canonicalization;
+        let mut used = vec![false; fields.len()];
+        let mut data = vec![];
+        for name in self.headers.iter() {
+            // each instance of a header name is signed once, the last first
+            let found = fields
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|(i, field)| !used[*i] && field_name(field).eq_ignore_ascii_case(name));
+            if let Some((i, field)) = found {
+                used[i] = true;
+                data.extend(canon.header(field));
+            }
+        }
+        let mut signature = canon.header(&self.unsigned);
+        signature.truncate(signature.len() - 2);
+        data.extend(signature);
+        data
+    }
+
+    /// Verify the signature of the data with the key
+    pub fn verify(&self, key: &PublicKey, data: &[u8]) -> Result<(), String> {
+        if key.algorithm != self.algorithm {
+            return Err("key type does not match the algorithm".to_owned());
+        }
+        if key.strict {
+            let idomain = self
+                .identity
+                .as_deref()
+                .and_then(|i| i.rsplit_once('@'))
+                .map(|(_, d)| d.to_ascii_lowercase());
+            if matches!(idomain, Some(ref idomain) if *idomain != self.domain) {
+                return Err("i= domain must be d= per the key".to_owned());
+            }
+        }
+        let (algorithm, message): (&dyn VerificationAlgorithm, Vec<u8>) = match self.algorithm {
+            Algorithm::RsaSha256 => (
+                &RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                data.to_vec(),
+            ),
+            // RFC 8463 signs the hash
+            Algorithm::Ed25519Sha256 => (&ED25519, digest(&SHA256, data).as_ref().to_vec()),
+        };
+        UnparsedPublicKey::new(algorithm, &key.data)
+            .verify(&message, &self.signature)
+            .map_err(|_| "signature did not verify".to_owned())
+    }
+}
+
+impl PublicKey {
+    /// Parse the TXT record of the key
+    pub fn parse(record: &str) -> Result<Self, String> {
+        let tags = tags(record)?;
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        match tags.first() {
+            Some((name, value)) if name == "v" && value != "DKIM1" => {
+                return Err("unsupported key version".to_owned())
+            }
+            _ if tags.iter().skip(1).any(|(name, _)| name == "v") => {
+                return Err("v= is not the first tag".to_owned())
+            }
+            _ => {}
+        }
+        if let Some(h) = tag("h") {
+            if !h
+                .split(':')
+                .any(|h| h.trim().eq_ignore_ascii_case("sha256"))
+            {
+                return Err("key does not allow sha256".to_owned());
+            }
+        }
+        let algorithm = match tag("k").map(str::to_ascii_lowercase).as_deref() {
+            None | Some("rsa") => Algorithm::RsaSha256,
+            Some("ed25519") => Algorithm::Ed25519Sha256,
+            Some(other) => return Err(format!("unsupported key type {}", other)),
+        };
+        let data = base64_tag(tag("p").ok_or("missing p= tag")?)?;
+        if data.is_empty() {
+            return Err("key revoked".to_owned());
+        }
+        let data = match algorithm {
+            Algorithm::RsaSha256 => rsa_public_key(&data).ok_or("invalid RSA key")?,
+            Algorithm::Ed25519Sha256 => data,
+        };
+        let flags = tag("t")
+            .unwrap_or_default()
+            .split(':')
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        Ok(PublicKey {
+            algorithm,
+            data,
+            testing: flags.contains(&"y"),
+            strict: flags.contains(&"s"),
+        })
+    }
+}
+
+/// The name of a raw header field
+pub fn field_name(field: &[u8]) -> &str {
+    let colon = field.iter().position(|b| *b == b':').unwrap_or(field.len());
+    std::str::from_utf8(&field[..colon])
+        .unwrap_or_default()
+        .trim()
+}
+
+fn is_subdomain(name: &str, domain: &str) -> bool {
+    name == domain || name.ends_with(format!(".{}", domain).as_str())
+}
+
+/// Parse a tag list such as `v=1; a=rsa-sha256`, whitespace removed around names and values
+fn tags(list: &str) -> Result<Vec<(String, String)>, String> {
+    let mut tags: Vec<(String, String)> = vec![];
+    for spec in list.split(';') {
+        if spec.trim().is_empty() {
+            continue;
+        }
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid tag {:?}", spec.trim()))?;
+        let name = name.trim().to_owned();
+        if tags.iter().any(|(n, _)| *n == name) {
+            return Err(format!("duplicate {}= tag", name));
+        }
+        tags.push((name, value.trim().to_owned()));
+    }
+    Ok(tags)
+}
+
+fn base64_tag(value: &str) -> Result<Vec<u8>, String> {
+    let value = value
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    base64::decode(value).map_err(|e| format!("invalid base64: {}", e))
+}
+
+/// The raw header with the value of the `b=` tag removed
+fn without_signature(raw: &[u8]) -> Vec<u8> {
+    let colon = raw.iter().position(|b| *b == b':').unwrap_or(raw.len());
+    let mut out = raw[..colon].to_vec();
+    let mut specs = raw[colon..].split(|b| *b == b';').peekable();
+    while let Some(spec) = specs.next() {
+        let eq = spec.iter().position(|b| *b == b'=');
+        let name = eq.map(|eq| &spec[..eq]).unwrap_or_default();
+        let name = name
+            .iter()
+            .filter(|b| !b.is_ascii_whitespace() && **b != b':')
+            .collect::<Vec<_>>();
+        match eq {
+            Some(eq) if name == [&b'b'] => {
+                out.extend_from_slice(&spec[..=eq]);
+                // keep the CRLF if the header ends with the b= tag
+                if specs.peek().is_none() && spec.ends_with(b"\r\n") {
+                    out.extend_from_slice(b"\r\n");
+                }
+            }
+            _ => out.extend_from_slice(spec),
+        }
+        if specs.peek().is_some() {
+            out.push(b';');
+        }
+    }
+    out
+}
+
+/// Take the RSAPublicKey from a SubjectPublicKeyInfo, or the key as is if it is one already
+fn rsa_public_key(der: &[u8]) -> Option<Vec<u8>> {
+    let (tag, content, _) = der_element(der)?;
+    if tag != 0x30 {
+        return None;
+    }
+    match der_element(content)? {
+        // SEQUENCE { INTEGER n, INTEGER e } is the RSAPublicKey
+        (0x02, _, _) => Some(der.to_vec()),
+        // SEQUENCE { AlgorithmIdentifier, BIT STRING { RSAPublicKey } }
+        (0x30, _, rest) => match der_element(rest)? {
+            (0x03, [0, key @ ..], _) => Some(key.to_vec()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Split a DER element into the tag, content and the rest of the input
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, der) = der.split_first()?;
+    let (&len, mut der) = der.split_first()?;
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let octets = (len & 0x7f) as usize;
+        if octets == 0 || octets > 4 || der.len() < octets {
+            return None;
+        }
+        let len = der[..octets]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        der = &der[octets..];
+        len
+    };
+    if der.len() < len {
+        return None;
+    }
+    Some((tag, &der[..len], &der[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_parsed() {
+        let raw = b"DKIM-Signature: v=1; a=rsa-sha256; d=Example.org;\r\n\
+            \ts=Sel; c=relaxed/simple; h=From : Subject; i=joe@mail.example.org;\r\n\
+            \tl=10; bh=AAAA; b=QU\r\n\tFB\r\n";
+        let sut = Signature::parse(raw).unwrap();
+        assert_eq!(sut.algorithm, Algorithm::RsaSha256);
+        assert_eq!(sut.header_canonicalization, Canonicalization::Relaxed);
+        assert_eq!(sut.body_canonicalization, Canonicalization::Simple);
+        assert_eq!(sut.key_name(), "sel._domainkey.example.org");
+        assert_eq!(sut.headers, vec!["from", "subject"]);
+        assert_eq!(sut.body_length, Some(10));
+        assert_eq!(sut.signature, b"AAA");
+        assert_eq!(
+            sut.unsigned,
+            b"DKIM-Signature: v=1; a=rsa-sha256; d=Example.org;\r\n\
+            \ts=Sel; c=relaxed/simple; h=From : Subject; i=joe@mail.example.org;\r\n\
+            \tl=10; bh=AAAA; b=\r\n"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn invalid_signatures_are_refused() {
+        let ok = "v=1; a=rsa-sha256; d=example.org; s=sel; h=from; bh=AAAA; b=AAAA";
+        assert!(Signature::parse(format!("DKIM-Signature: {}", ok).as_bytes()).is_ok());
+        for (good, broken, error) in [
+            (
+                "a=rsa-sha256",
+                "a=rsa-sha1",
+                "unsupported algorithm rsa-sha1",
+            ),
+            ("h=from", "h=subject", "From is not signed"),
+            ("s=sel", "s=sel; i=joe@example.com", "i= is not within d="),
+            ("s=sel", "s=sel; s=other", "duplicate s= tag"),
+        ] {
+            let raw = format!("DKIM-Signature: {}", ok.replace(good, broken));
+            assert_eq!(
+                Signature::parse(raw.as_bytes()),
+                Err(error.to_owned()),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn keys_are_parsed() {
+        let key = PublicKey::parse("v=DKIM1; k=ed25519; t=y; p=AAAA").unwrap();
+        assert_eq!(key.algorithm, Algorithm::Ed25519Sha256);
+        assert!(key.testing);
+        assert_eq!(
+            PublicKey::parse("v=DKIM1; p="),
+            Err("key revoked".to_owned())
+        );
+        assert_eq!(
+            PublicKey::parse("k=rsa; v=DKIM1; p=AAAA"),
+            Err("v= is not the first tag".to_owned())
+        );
+        assert_eq!(
+            PublicKey::parse("p=AAAA"),
+            Err("invalid RSA key".to_owned())
+        );
+    }
+
+    #[test]
+    fn signed_headers_are_picked_bottom_up() {
+        let fields = [
+            b"Received: one\r\n".to_vec(),
+            b"From: joe\r\n".to_vec(),
+            b"Received: two\r\n".to_vec(),
+        ];
+        let mut sut = Signature::parse(
+            b"DKIM-Signature: v=1; a=rsa-sha256; d=example.org; s=sel;\r\n\
+            \th=received:from:received:received; bh=AAAA; b=AAAA\r\n",
+        )
+        .unwrap();
+        sut.header_canonicalization = Canonicalization::Simple;
+        assert_eq!(
+            String::from_utf8(sut.signed_data(&fields)).unwrap(),
+            "Received: two\r\nFrom: joe\r\nReceived: one\r\n\
+            DKIM-Signature: v=1; a=rsa-sha256; d=example.org; s=sel;\r\n\
+            \th=received:from:received:received; bh=AAAA; b="
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use crate::lookup::resolver_for;
+use samotop_core::{
+    common::*,
+    dns::{reverse_name, DnsError, Resolver},
+    mail::{
+        AcceptsGuard, AddRecipientResult, Allowlisted, MailGuard, MailSetup, Recipient,
+        StartMailFailure, StartMailResult,
+    },
+    smtp::{SmtpHost, SmtpPath, SmtpSession},
+};
+use std::net::{IpAddr, SocketAddr};
+
+/**
+DNS blocklist checks at MAIL FROM time.
+
+The reversed client IP is looked up in the DNSBL zones (`with_ip_zone`),
+the HELO and sender domains in the RHSBL zones (`with_domain_zone`).
+Each zone listing the client adds its weight to the score. If the score reaches
+the threshold, the mail is refused with a 550 and the TXT reason of the heaviest listing,
+reduced to printable ASCII and truncated.
+Otherwise the result is recorded in an `X-Samotop-DNSBL` header.
+
+Lookup failures count as not listed. `Allowlisted` transactions are not checked.
+The `Dns` resolver of the service is used unless one is given `with_resolver`.
+
+```
+# use samotop_core::mail::*;
+# use samotop_with_spf::Dnsbl;
+let dnsbl = Dnsbl::default()
+    .with_ip_zone("zen.spamhaus.org", 1.0)
+    .with_ip_zone("bl.spamcop.net", 0.5)
+    .with_domain_zone("dbl.spamhaus.org", 1.0)
+    .with_threshold(1.0);
+let service = Builder + dnsbl;
+```
+*/
+#[derive(Clone)]
+pub struct Dnsbl {
+    ip_zones: Vec<(String, f32)>,
+    domain_zones: Vec<(String, f32)>,
+    threshold: f32,
+    resolver: Option<Arc<dyn Resolver>>,
+}
+
+impl Default for Dnsbl {
+    fn default() -> Self {
+        Self {
+            ip_zones: vec![],
+            domain_zones: vec![],
+            threshold: 1.0,
+            resolver: None,
+        }
+    }
+}
+
+impl Dnsbl {
+    /// Look the reversed client IP up in the given DNSBL zone
+    pub fn with_ip_zone(mut self, zone: impl Into<String>, weight: f32) -> Self {
+        self.ip_zones.push((zone.into(), weight));
+        self
+    }
+    /// Look the HELO and sender domains up in the given RHSBL zone
+    pub fn with_domain_zone(mut self, zone: impl Into<String>, weight: f32) -> Self {
+        self.domain_zones.push((zone.into(), weight));
+        self
+    }
+    /// Refuse mail once the sum of weights of listing zones reaches this score, 1.0 by default
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+    /// Use the given DNS resolver rather than the one of the service
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    /// Find the zones listing the client IP or any of the domains
+    async fn listings(
+        &self,
+        resolver: &dyn Resolver,
+        ip: Option<IpAddr>,
+        domains: &[String],
+    ) -> Vec<Listing> {
+        let mut listings = vec![];
+        if let Some(ip) = ip {
+            let reversed = reverse(ip);
+            for (zone, weight) in self.ip_zones.iter() {
+                let query = format!("{}.{}", reversed, zone);
+                if let Some(reason) = listed(resolver, query.as_str()).await {
+                    listings.push(Listing::new(zone, *weight, ip.to_string(), reason));
+                }
+            }
+        }
+        for (zone, weight) in self.domain_zones.iter() {
+            for domain in domains {
+                let query = format!("{}.{}", domain, zone);
+                if let Some(reason) = listed(resolver, query.as_str()).await {
+                    listings.push(Listing::new(zone, *weight, domain.clone(), reason));
+                    // each zone counts once
+                    break;
+                }
+            }
+        }
+        listings
+    }
+}
+
+impl fmt::Debug for Dnsbl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dnsbl")
+            .field("ip_zones", &self.ip_zones)
+            .field("domain_zones", &self.domain_zones)
+            .field("threshold", &self.threshold)
+            .field("resolver", &self.resolver)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for Dnsbl {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for Dnsbl {
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if session.transaction.get::<Allowlisted>().is_some() {
+            return Box::pin(ready(StartMailResult::Accepted));
+        }
+        let addr = session.connection.peer_addr.as_str();
+        let ip = addr
+            .parse::<SocketAddr>()
+            .map(|socket| socket.ip())
+            .or_else(|_| addr.parse::<IpAddr>())
+            .ok();
+        let mut domains = vec![];
+        if let Some(helo) = session.peer_name.as_ref() {
+            if !helo.starts_with('[') && helo.parse::<IpAddr>().is_err() {
+                domains.push(helo.trim_end_matches('.').to_ascii_lowercase());
+            }
+        }
+        if let Some(SmtpPath::Mailbox {
+            host: SmtpHost::Domain(domain),
+            ..
+        }) = session.transaction.mail.as_ref().map(|m| m.sender())
+        {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            if !domains.contains(&domain) {
+                domains.push(domain);
+            }
+        }
+
+        let resolver = resolver_for(self.resolver.clone(), session.dns.clone());
+
+        let fut = async move {
+            let resolver = resolver.await;
+            let listings = match resolver {
+                Ok(resolver) => self.listings(resolver.as_ref(), ip, &domains).await,
+                Err(_) => vec![],
+            };
+
+            let score = listings
+                .iter()
+                .map(|l| l.weight)
+                .fold(0.0, |score, weight| score + weight);
+            if score >= self.threshold && !listings.is_empty() {
+                let worst = listings
+                    .iter()
+                    .max_by(|a, b| a.weight.total_cmp(&b.weight))
+                    .expect("some listing");
+                info!(
+                    "Mail {} refused with DNSBL score {}: {:?}",
+                    session.transaction.id, score, listings
+                );
+                return StartMailResult::Failed(
+                    StartMailFailure::Refused(format!(
+                        "{} is listed by {}: {}",
+                        worst.subject,
+                        worst.zone,
+                        worst.reason.as_deref().unwrap_or("blocked")
+                    )),
+                    format!("DNSBL score {} in {}", score, session.transaction.id),
+                );
+            }
+
+            let header = listings
+                .iter()
+                .fold(format!("X-Samotop-DNSBL: score={}", score), |header, l| {
+                    header + format!("; {}={}", l.zone, l.subject).as_str()
+                });
+            session
+                .transaction
+                .extra_headers
+                .push_str(format!("{}\r\n", header).as_str());
+            StartMailResult::Accepted
+        };
+        Box::pin(fut)
+    }
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+/// A zone listing the client
+#[derive(Debug)]
+struct Listing {
+    zone: String,
+    weight: f32,
+    subject: String,
+    reason: Option<String>,
+}
+
+impl Listing {
+    fn new(zone: &str, weight: f32, subject: String, reason: Option<String>) -> Self {
+        Self {
+            zone: zone.to_owned(),
+            weight,
+            subject,
+            reason,
+        }
+    }
+}
+
+/// How much of the TXT reason goes into the reply
+const MAX_REASON: usize = 200;
+
+/// Is the query name listed? The TXT reason comes along if there is one.
+async fn listed(resolver: &dyn Resolver, query: &str) -> Option<Option<String>> {
+    match resolver.lookup_a(query).await {
+        Ok(addrs) if addrs.iter().any(|a| a.octets()[0] == 127) => Some(
+            resolver
+                .lookup_txt(query)
+                .await
+                .ok()
+                .and_then(|txt| txt.into_iter().next())
+                .map(|txt| printable(txt.as_str())),
+        ),
+        Ok(_) | Err(DnsError::NoRecords) => None,
+        Err(e) => {
+            warn!("DNSBL lookup of {} failed: {}", query, e);
+            None
+        }
+    }
+}
+
+/// The TXT reason safe for an SMTP reply, CR and LF in particular would break it
+fn printable(reason: &str) -> String {
+    reason
+        .chars()
+        .filter(|c| c.is_ascii() && !c.is_ascii_control())
+        .take(MAX_REASON)
+        .collect()
+}
+
+/// The IP in the reversed notation used by DNSBL zones, the PTR name without the arpa zone
+fn reverse(ip: IpAddr) -> String {
+    let mut name = reverse_name(ip);
+    let zone = match ip {
+        IpAddr::V4(_) => ".in-addr.arpa",
+        IpAddr::V6(_) => ".ip6.arpa",
+    };
+    name.truncate(name.len() - zone.len());
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samotop_core::dns::ZoneResolver;
+    use samotop_core::io::ConnectionInfo;
+    use samotop_core::smtp::command::SmtpMail;
+
+    fn session_from(sender: &str) -> SmtpSession {
+        let mut session = SmtpSession {
+            peer_name: Some("mail.example.com".to_owned()),
+            connection: ConnectionInfo::new(String::new(), "192.0.2.1:54321".to_owned()),
+            ..Default::default()
+        };
+        session.transaction.mail = Some(SmtpMail::Mail(
+            SmtpPath::Mailbox {
+                name: "alice".to_owned(),
+                host: SmtpHost::Domain(sender.to_owned()),
+                relays: vec![],
+            },
+            vec![],
+        ));
+        session
+    }
+
+    #[test]
+    fn reverses_ips() {
+        assert_eq!(reverse("192.0.2.1".parse().unwrap()), "1.2.0.192");
+        assert_eq!(
+            reverse("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2"
+        );
+    }
+
+    #[test]
+    fn listed_ip_is_refused_with_reason() {
+        async_std::task::block_on(async move {
+            let sut = Dnsbl::default()
+                .with_ip_zone("bl.example.net", 1.0)
+                .with_resolver(
+                    ZoneResolver::parse(
+                        "1.2.0.192.bl.example.net. A 127.0.0.2\n\
+                         1.2.0.192.bl.example.net. TXT \"go away\"",
+                    )
+                    .unwrap(),
+                );
+            let mut session = session_from("example.org");
+            let result = sut.start_mail(&mut session).await;
+            assert_eq!(
+                result,
+                StartMailResult::Failed(
+                    StartMailFailure::Refused(
+                        "192.0.2.1 is listed by bl.example.net: go away".to_owned()
+                    ),
+                    "DNSBL score 1 in ".to_owned()
+                )
+            );
+        })
+    }
+
+    #[test]
+    fn reason_is_printable() {
+        use samotop_core::dns::{Answer, Record, RecordType};
+        /// Lists everything with a reason trying to inject another reply line
+        #[derive(Debug)]
+        struct Injecting;
+        impl Resolver for Injecting {
+            fn resolve<'a, 'f>(
+                &'a self,
+                _name: &'a str,
+                kind: RecordType,
+            ) -> S2Fut<'f, samotop_core::dns::DnsResult<Answer>>
+            where
+                'a: 'f,
+            {
+                let records = match kind {
+                    RecordType::A => vec![Record::A([127, 0, 0, 2].into())],
+                    RecordType::Txt => vec![Record::Txt(format!(
+                        "go away\r\n250 OK {}",
+                        "x".repeat(1000)
+                    ))],
+                    _ => vec![],
+                };
+                Box::pin(ready(Ok(Answer {
+                    records,
+                    ttl: std::time::Duration::ZERO,
+                })))
+            }
+        }
+        async_std::task::block_on(async move {
+            let sut = Dnsbl::default()
+                .with_ip_zone("bl.example.net", 1.0)
+                .with_resolver(Injecting);
+            let mut session = session_from("example.org");
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::Refused(reason), _) => {
+                    assert!(
+                        reason
+                            .starts_with("192.0.2.1 is listed by bl.example.net: go away250 OK x"),
+                        "{}",
+                        reason
+                    );
+                    assert_eq!(
+                        reason.len(),
+                        "192.0.2.1 is listed by bl.example.net: ".len() + 200
+                    );
+                }
+                otherwise => panic!("Expected refusal, got {:?}", otherwise),
+            }
+        })
+    }
+
+    #[test]
+    fn scores_add_up() {
+        async_std::task::block_on(async move {
+            let resolver = ZoneResolver::parse(
+                "1.2.0.192.bl.example.net. A 127.0.0.2\n\
+                 1.2.0.192.bl.example.net. TXT \"dynamic IP\"\n\
+                 spam.example.org.rhs.example.net. A 127.0.0.2\n\
+                 spam.example.org.rhs.example.net. TXT \"spammy domain\"",
+            )
+            .unwrap();
+            let sut = Dnsbl::default()
+                .with_ip_zone("bl.example.net", 0.5)
+                .with_ip_zone("other.example.net", 0.5)
+                .with_domain_zone("rhs.example.net", 0.7)
+                .with_resolver(resolver);
+
+            let mut session = session_from("example.org");
+            let result = sut.start_mail(&mut session).await;
+            assert_eq!(result, StartMailResult::Accepted);
+            assert_eq!(
+                session.transaction.extra_headers,
+                "X-Samotop-DNSBL: score=0.5; bl.example.net=192.0.2.1\r\n"
+            );
+
+            let mut session = session_from("spam.example.org");
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::Refused(reason), _) => assert_eq!(
+                    reason,
+                    "spam.example.org is listed by rhs.example.net: spammy domain"
+                ),
+                otherwise => panic!("Expected refusal, got {:?}", otherwise),
+            }
+        })
+    }
+}
@@ -1,14 +1,34 @@
+use crate::sync::SyncFuture;
 use async_std::future::timeout;
-use async_std_resolver::{resolver_from_system_conf, AsyncStdResolver, ResolveError};
-use std::pin::Pin;
+use async_std_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    proto::rr::{RData, RecordType as TrustRecordType},
+    proto::xfer::DnsRequestOptions,
+    resolver, resolver_from_system_conf, AsyncStdResolver, ResolveError,
+};
+use samotop_core::common::*;
+use samotop_core::dns::{Answer, DnsError, DnsResult, Record, RecordType, Resolver, Tlsa};
+use std::sync::Mutex;
 use std::{
-    future::Future,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use trust_dns_resolver::error::ResolveErrorKind;
 use viaspf::lookup::{Lookup, LookupError, LookupResult, Name};
 
+/**
+A DNS `Resolver` backed by trust-dns.
+
+Configure it once for the whole service:
+
+```no_run
+# use samotop_core::mail::*;
+# use samotop_core::dns::Dns;
+# use samotop_with_spf::TrustDnsResolver;
+let service = Builder + Dns::new(TrustDnsResolver::from_system_conf().unwrap());
+```
+*/
+#[derive(Clone)]
 pub struct TrustDnsResolver {
     inner: AsyncStdResolver,
     timeout: Duration,
@@ -21,13 +41,131 @@ impl TrustDnsResolver {
             timeout: Duration::from_secs(5),
         }
     }
+    /// Use the system configuration, i.e. `/etc/resolv.conf`
+    pub fn from_system_conf() -> DnsResult<Self> {
+        // the constructor does not wait for anything
+        async_std::task::block_on(resolver_from_system_conf())
+            .map(Self::new)
+            .map_err(|e| DnsError::Failed(e.to_string()))
+    }
+    /// Use the given configuration
+    pub fn from_config(config: ResolverConfig, options: ResolverOpts) -> DnsResult<Self> {
+        async_std::task::block_on(resolver(config, options))
+            .map(Self::new)
+            .map_err(|e| DnsError::Failed(e.to_string()))
+    }
+    /// How long to wait for an answer, 5 seconds by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl fmt::Debug for TrustDnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrustDnsResolver")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Resolver for TrustDnsResolver {
+    fn resolve<'a, 'f>(&'a self, name: &'a str, kind: RecordType) -> S2Fut<'f, DnsResult<Answer>>
+    where
+        'a: 'f,
+    {
+        let record_type = match kind {
+            RecordType::A => TrustRecordType::A,
+            RecordType::Aaaa => TrustRecordType::AAAA,
+            RecordType::Mx => TrustRecordType::MX,
+            RecordType::Txt => TrustRecordType::TXT,
+            RecordType::Ptr => TrustRecordType::PTR,
+            RecordType::Tlsa => TrustRecordType::TLSA,
+        };
+        let lookup = self
+            .inner
+            .lookup(name, record_type, DnsRequestOptions::default());
+        let fut = async move {
+            let lookup = match timeout(self.timeout, lookup).await {
+                Ok(Ok(lookup)) => lookup,
+                Ok(Err(e)) => return to_answer(e),
+                Err(_timeout) => return Err(DnsError::Timeout),
+            };
+            Ok(Answer {
+                records: lookup.iter().filter_map(to_record).collect(),
+                ttl: lookup
+                    .valid_until()
+                    .saturating_duration_since(Instant::now()),
+            })
+        };
+        Box::pin(SyncFuture::new(fut))
+    }
+}
+
+fn to_record(data: &RData) -> Option<Record> {
+    let name = |name: &trust_dns_resolver::Name| name.to_ascii().trim_end_matches('.').to_owned();
+    Some(match data {
+        RData::A(ip) => Record::A(*ip),
+        RData::AAAA(ip) => Record::Aaaa(*ip),
+        RData::MX(mx) => Record::Mx {
+            preference: mx.preference(),
+            exchange: name(mx.exchange()),
+        },
+        RData::TXT(txt) => Record::Txt(txt.to_string()),
+        RData::PTR(ptr) => Record::Ptr(name(ptr)),
+        RData::TLSA(tlsa) => Record::Tlsa(Tlsa {
+            usage: tlsa.cert_usage().into(),
+            selector: tlsa.selector().into(),
+            matching: tlsa.matching().into(),
+            data: tlsa.cert_data().to_vec(),
+        }),
+        _ => return None,
+    })
+}
+
+/// No records is a valid (negative) answer
+fn to_answer(error: ResolveError) -> DnsResult<Answer> {
+    use ResolveErrorKind::*;
+    match error.kind() {
+        NoRecordsFound { negative_ttl, .. } => Ok(Answer {
+            records: vec![],
+            ttl: Duration::from_secs(negative_ttl.unwrap_or(60) as u64),
+        }),
+        Timeout => Err(DnsError::Timeout),
+        _ => Err(DnsError::Failed(error.to_string())),
+    }
 }
 
-pub async fn new_resolver() -> Result<TrustDnsResolver, ResolveError> {
-    Ok(TrustDnsResolver::new(resolver_from_system_conf().await?))
+/// The resolver to use: the given one, the one configured for the service
+/// or else a system resolver shared by all that did not get one.
+pub(crate) async fn resolver_for(
+    given: Option<Arc<dyn Resolver>>,
+    configured: Option<Arc<dyn Resolver>>,
+) -> DnsResult<Arc<dyn Resolver>> {
+    static SYSTEM: Mutex<Option<Arc<dyn Resolver>>> = Mutex::new(None);
+    if let Some(resolver) = given.or(configured) {
+        return Ok(resolver);
+    }
+    if let Some(resolver) = SYSTEM.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+        return Ok(resolver);
+    }
+    // created without holding the lock, the first one stored is shared
+    let resolver = match resolver_from_system_conf().await {
+        Ok(resolver) => TrustDnsResolver::new(resolver),
+        Err(e) => {
+            error!("Could not create resolver! {:?}", e);
+            return Err(DnsError::Failed(e.to_string()));
+        }
+    };
+    let resolver: Arc<dyn Resolver> = Arc::new(samotop_core::dns::DnsCache::new(resolver));
+    let mut system = SYSTEM.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(system.get_or_insert(resolver).clone())
 }
 
-impl Lookup for TrustDnsResolver {
+/// Lends a `Resolver` to viaspf
+pub(crate) struct ViaspfLookup<'a>(pub &'a dyn Resolver);
+
+impl Lookup for ViaspfLookup<'_> {
     fn lookup_a<'s, 'n, 'f>(
         &'s self,
         name: &'n Name,
@@ -37,15 +175,12 @@ impl Lookup for TrustDnsResolver {
         'n: 'f,
     {
         Box::pin(async move {
-            Ok(
-                query_async(self.timeout, self.inner.ipv4_lookup(name.as_str()))
-                    .await?
-                    .into_iter()
-                    .collect(),
-            )
+            self.0
+                .lookup_a(name.as_str())
+                .await
+                .map_err(to_lookup_error)
         })
     }
-
     fn lookup_aaaa<'s, 'n, 'f>(
         &'s self,
         name: &'n Name,
@@ -55,12 +190,10 @@ impl Lookup for TrustDnsResolver {
         'n: 'f,
     {
         Box::pin(async move {
-            Ok(
-                query_async(self.timeout, self.inner.ipv6_lookup(name.as_str()))
-                    .await?
-                    .into_iter()
-                    .collect(),
-            )
+            self.0
+                .lookup_aaaa(name.as_str())
+                .await
+                .map_err(to_lookup_error)
         })
     }
     fn lookup_mx<'s, 'n, 'f>(
@@ -72,17 +205,12 @@ impl Lookup for TrustDnsResolver {
         'n: 'f,
     {
         Box::pin(async move {
-            let mut mxs: Vec<_> = query_async(self.timeout, self.inner.mx_lookup(name.as_str()))
-                .await?
-                .into_iter()
-                .collect();
-            mxs.sort_by_key(|mx| mx.preference());
-            mxs.into_iter()
-                .map(|mx| {
-                    Name::new(&mx.exchange().to_ascii())
-                        .map_err(|e| LookupError::Dns(Some(e.into())))
-                })
-                .collect::<Result<_, _>>()
+            to_names(
+                self.0
+                    .lookup_mx(name.as_str())
+                    .await
+                    .map_err(to_lookup_error)?,
+            )
         })
     }
     fn lookup_txt<'s, 'n, 'f>(
@@ -94,16 +222,12 @@ impl Lookup for TrustDnsResolver {
         'n: 'f,
     {
         Box::pin(async move {
-            Ok(
-                query_async(self.timeout, self.inner.txt_lookup(name.as_str()))
-                    .await?
-                    .into_iter()
-                    .map(|data| data.to_string())
-                    .collect(),
-            )
+            self.0
+                .lookup_txt(name.as_str())
+                .await
+                .map_err(to_lookup_error)
         })
     }
-
     fn lookup_ptr<'s, 'f>(
         &'s self,
         ip: IpAddr,
@@ -111,39 +235,21 @@ impl Lookup for TrustDnsResolver {
     where
         's: 'f,
     {
-        Box::pin(async move {
-            query_async(self.timeout, self.inner.reverse_lookup(ip))
-                .await?
-                .into_iter()
-                .map(|name| {
-                    Name::new(&name.to_ascii()).map_err(|e| LookupError::Dns(Some(e.into())))
-                })
-                .collect::<Result<_, _>>()
-        })
+        Box::pin(async move { to_names(self.0.lookup_ptr(ip).await.map_err(to_lookup_error)?) })
     }
 }
 
-fn query_async<'a, 'f, T>(
-    time_out: Duration,
-    fut: impl Future<Output = Result<T, ResolveError>> + Send + 'a,
-) -> Pin<Box<dyn Future<Output = LookupResult<T>> + Send + 'f>>
-where
-    'a: 'f,
-{
-    Box::pin(async move {
-        match timeout(time_out, fut).await {
-            Ok(r) => r.map_err(to_lookup_error),
-            Err(_timeout) => Err(LookupError::Timeout),
-        }
-    })
+fn to_names(names: Vec<String>) -> LookupResult<Vec<Name>> {
+    names
+        .iter()
+        .map(|name| Name::new(name).map_err(|e| LookupError::Dns(Some(e.into()))))
+        .collect()
 }
 
-fn to_lookup_error(error: ResolveError) -> LookupError {
-    use ResolveErrorKind::*;
-    match error.kind() {
-        NoRecordsFound { .. } => LookupError::NoRecords,
-        Io(_) => LookupError::Dns(Some(error.into())),
-        Timeout => LookupError::Timeout,
-        _ => LookupError::Dns(Some(error.into())),
+fn to_lookup_error(error: DnsError) -> LookupError {
+    match error {
+        DnsError::NoRecords => LookupError::NoRecords,
+        DnsError::Timeout => LookupError::Timeout,
+        e => LookupError::Dns(Some(e.into())),
     }
 }
@@ -0,0 +1,517 @@
+use crate::lookup::resolver_for;
+use samotop_core::{
+    common::*,
+    dns::{DnsError, DnsResult, Resolver},
+    mail::{
+        AcceptsGuard, AddRecipientResult, Allowlisted, AuthenticationResults, MailGuard, MailSetup,
+        Recipient, StartMailFailure, StartMailResult,
+    },
+    smtp::SmtpSession,
+};
+use std::net::{IpAddr, SocketAddr};
+use viaspf::lookup::Name;
+
+/**
+Checks who the client claims to be at MAIL FROM time.
+
+* Forward-confirmed reverse DNS - a PTR name of the client IP must resolve back to the IP.
+* The HELO/EHLO name must be a syntactically valid FQDN,
+* it must not be our own name,
+* and an address literal such as `[192.0.2.1]` must match the client IP.
+
+Each check can reject the mail, fail it temporarily or just note the failure in an
+`X-Samotop-Peer` header, which is the default. The outcome is stored in the transaction
+as `PeerIdentity` for the Received header, the FCrDNS result also as "iprev" `AuthenticationResults`.
+A DNS error never rejects the mail, only a definite failure does. `Allowlisted` transactions are not checked.
+The `Dns` resolver of the service is used unless one is given `with_resolver`.
+
+```
+# use samotop_core::mail::*;
+# use samotop_with_spf::{OnFailure, PeerCheck};
+let check = PeerCheck::default()
+    .with_fcrdns(OnFailure::TempFail)
+    .with_helo_fqdn(OnFailure::Reject);
+let service = Builder + check;
+```
+*/
+#[derive(Clone)]
+pub struct PeerCheck {
+    fcrdns: OnFailure,
+    helo_fqdn: OnFailure,
+    helo_not_ours: OnFailure,
+    helo_literal: OnFailure,
+    resolver: Option<Arc<dyn Resolver>>,
+}
+
+/// What to do when a `PeerCheck` fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Refuse the mail with a 550
+    Reject,
+    /// Refuse the mail with a 451 so the client retries later
+    TempFail,
+    /// Accept the mail and note the failure in a header
+    AddHeader,
+}
+
+/// A check performed by `PeerCheck`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerCheckKind {
+    /// Forward-confirmed reverse DNS
+    Fcrdns,
+    /// The HELO name is a valid FQDN
+    HeloFqdn,
+    /// The HELO name is not our own
+    HeloNotOurs,
+    /// The HELO address literal matches the client IP
+    HeloLiteral,
+}
+
+impl fmt::Display for PeerCheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PeerCheckKind::Fcrdns => "the reverse DNS name does not resolve to the IP",
+            PeerCheckKind::HeloFqdn => "the HELO name is not a valid domain name",
+            PeerCheckKind::HeloNotOurs => "the HELO name is our own",
+            PeerCheckKind::HeloLiteral => "the HELO address literal is not the IP",
+        })
+    }
+}
+
+/// What `PeerCheck` found out about the client, stored in the transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// The client IP
+    pub ip: Option<IpAddr>,
+    /// The forward-confirmed reverse DNS name of the client
+    pub verified_name: Option<String>,
+    /// The HELO/EHLO name as given by the client
+    pub helo: Option<String>,
+    /// The checks that failed
+    pub failed: Vec<PeerCheckKind>,
+}
+
+impl PeerIdentity {
+    /// The "from" clause of the Received header as per RFC 5321 section 4.4,
+    /// i.e. `mail.example.com (mx.example.com [192.0.2.1])`
+    pub fn received_from(&self) -> String {
+        let mut from = self.helo.clone().unwrap_or_else(|| "unknown".to_owned());
+        from.push_str(" (");
+        if let Some(ref name) = self.verified_name {
+            from.push_str(name);
+            from.push(' ');
+        }
+        match self.ip {
+            Some(IpAddr::V4(ip)) => from.push_str(format!("[{}]", ip).as_str()),
+            Some(IpAddr::V6(ip)) => from.push_str(format!("[IPv6:{}]", ip).as_str()),
+            None => from.push_str("[unknown]"),
+        }
+        from.push(')');
+        from
+    }
+}
+
+impl Default for PeerCheck {
+    fn default() -> Self {
+        Self {
+            fcrdns: OnFailure::AddHeader,
+            helo_fqdn: OnFailure::AddHeader,
+            helo_not_ours: OnFailure::AddHeader,
+            helo_literal: OnFailure::AddHeader,
+            resolver: None,
+        }
+    }
+}
+
+impl PeerCheck {
+    /// What to do if the client IP has no forward-confirmed reverse DNS name
+    pub fn with_fcrdns(mut self, on_failure: OnFailure) -> Self {
+        self.fcrdns = on_failure;
+        self
+    }
+    /// What to do if the HELO name is not a valid FQDN
+    pub fn with_helo_fqdn(mut self, on_failure: OnFailure) -> Self {
+        self.helo_fqdn = on_failure;
+        self
+    }
+    /// What to do if the HELO name is our own name
+    pub fn with_helo_not_ours(mut self, on_failure: OnFailure) -> Self {
+        self.helo_not_ours = on_failure;
+        self
+    }
+    /// What to do if the HELO address literal does not match the client IP
+    pub fn with_helo_literal(mut self, on_failure: OnFailure) -> Self {
+        self.helo_literal = on_failure;
+        self
+    }
+    /// Use the given DNS resolver rather than the one of the service
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    fn on_failure(&self, kind: PeerCheckKind) -> OnFailure {
+        match kind {
+            PeerCheckKind::Fcrdns => self.fcrdns,
+            PeerCheckKind::HeloFqdn => self.helo_fqdn,
+            PeerCheckKind::HeloNotOurs => self.helo_not_ours,
+            PeerCheckKind::HeloLiteral => self.helo_literal,
+        }
+    }
+}
+
+impl fmt::Debug for PeerCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerCheck")
+            .field("fcrdns", &self.fcrdns)
+            .field("helo_fqdn", &self.helo_fqdn)
+            .field("helo_not_ours", &self.helo_not_ours)
+            .field("helo_literal", &self.helo_literal)
+            .field("resolver", &self.resolver)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for PeerCheck {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+
+impl MailGuard for PeerCheck {
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if session.transaction.get::<Allowlisted>().is_some() {
+            return Box::pin(ready(StartMailResult::Accepted));
+        }
+        let addr = session.connection.peer_addr.as_str();
+        let mut identity = PeerIdentity {
+            ip: addr
+                .parse::<SocketAddr>()
+                .map(|socket| socket.ip())
+                .or_else(|_| addr.parse::<IpAddr>())
+                .ok(),
+            verified_name: None,
+            helo: session.peer_name.clone(),
+            failed: vec![],
+        };
+
+        if let Some(ref helo) = identity.helo {
+            match address_literal(helo) {
+                Some(literal) if Some(literal) != identity.ip => {
+                    identity.failed.push(PeerCheckKind::HeloLiteral)
+                }
+                Some(_) => {}
+                None if !is_fqdn(helo) => identity.failed.push(PeerCheckKind::HeloFqdn),
+                None if helo
+                    .trim_end_matches('.')
+                    .eq_ignore_ascii_case(session.service_name.trim_end_matches('.')) =>
+                {
+                    identity.failed.push(PeerCheckKind::HeloNotOurs)
+                }
+                None => {}
+            }
+        }
+
+        let resolver = resolver_for(self.resolver.clone(), session.dns.clone());
+
+        let fut = async move {
+            let resolver = resolver.await;
+            let iprev = match (identity.ip, resolver) {
+                (None, _) => Err(DnsError::NoRecords),
+                (Some(ip), Ok(resolver)) => fcrdns(resolver.as_ref(), ip).await,
+                (Some(_), Err(e)) => Err(e),
+            };
+            let iprev = match iprev {
+                Ok(Some(name)) => {
+                    identity.verified_name = Some(name);
+                    "pass"
+                }
+                Ok(None) | Err(DnsError::NoRecords) => {
+                    identity.failed.push(PeerCheckKind::Fcrdns);
+                    "fail"
+                }
+                Err(e) => {
+                    warn!("Reverse DNS of {:?} failed: {}", identity.ip, e);
+                    "temperror"
+                }
+            };
+
+            let verdict = identity
+                .failed
+                .iter()
+                .map(|kind| self.on_failure(*kind))
+                .min_by_key(|on_failure| match on_failure {
+                    OnFailure::Reject => 0,
+                    OnFailure::TempFail => 1,
+                    OnFailure::AddHeader => 2,
+                })
+                .unwrap_or(OnFailure::AddHeader);
+
+            let result = match verdict {
+                OnFailure::Reject => StartMailResult::Failed(
+                    StartMailFailure::Refused(format!(
+                        "Client {} is suspicious: {}",
+                        identity.received_from(),
+                        identity
+                            .failed
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )),
+                    format!("Peer checks failed in {}", session.transaction.id),
+                ),
+                OnFailure::TempFail => StartMailResult::Failed(
+                    StartMailFailure::FailedTemporarily,
+                    format!(
+                        "Peer checks {:?} failed in {}",
+                        identity.failed, session.transaction.id
+                    ),
+                ),
+                OnFailure::AddHeader => {
+                    let helo = identity
+                        .failed
+                        .iter()
+                        .find(|kind| **kind != PeerCheckKind::Fcrdns)
+                        .map(|kind| format!("fail ({:?})", kind))
+                        .unwrap_or_else(|| "pass".to_owned());
+                    session.transaction.extra_headers.push_str(
+                        format!("X-Samotop-Peer: iprev={}; helo={}\r\n", iprev, helo).as_str(),
+                    );
+                    StartMailResult::Accepted
+                }
+            };
+
+            session
+                .transaction
+                .get_or_insert(AuthenticationResults::default)
+                .add("iprev", iprev);
+            session.transaction.set(identity);
+            result
+        };
+        Box::pin(fut)
+    }
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+/// How many PTR names of the client are tried, the same limit as SPF has in RFC 7208 section 4.6.4
+const MAX_PTR_NAMES: usize = 10;
+
+/// The PTR name of the IP that resolves back to the IP, if any
+async fn fcrdns(resolver: &dyn Resolver, ip: IpAddr) -> DnsResult<Option<String>> {
+    for name in resolver
+        .lookup_ptr(ip)
+        .await?
+        .into_iter()
+        .take(MAX_PTR_NAMES)
+    {
+        let confirmed = match ip {
+            IpAddr::V4(ip) => lookup_or_none(resolver.lookup_a(&name).await)?.contains(&ip),
+            IpAddr::V6(ip) => lookup_or_none(resolver.lookup_aaaa(&name).await)?.contains(&ip),
+        };
+        if confirmed {
+            return Ok(Some(name.trim_end_matches('.').to_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// A missing record is just an empty answer
+fn lookup_or_none<T>(result: DnsResult<Vec<T>>) -> DnsResult<Vec<T>> {
+    match result {
+        Err(DnsError::NoRecords) => Ok(vec![]),
+        otherwise => otherwise,
+    }
+}
+
+/// Parse an address literal such as `[192.0.2.1]` or `[IPv6:2001:db8::1]`
+fn address_literal(helo: &str) -> Option<IpAddr> {
+    let literal = helo.strip_prefix('[')?.strip_suffix(']')?;
+    match literal.get(..5) {
+        Some(tag) if tag.eq_ignore_ascii_case("IPv6:") => literal[5..].parse().ok().map(IpAddr::V6),
+        _ => literal.parse().ok().map(IpAddr::V4),
+    }
+}
+
+/// Is the name a fully qualified domain name with valid labels?
+fn is_fqdn(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let labels = name.split('.').collect::<Vec<_>>();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    name.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(valid_label)
+        && !labels
+            .last()
+            .map(|tld| tld.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(true)
+        && Name::new(name).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samotop_core::{dns::ZoneResolver, io::ConnectionInfo};
+
+    fn session_from(helo: &str) -> SmtpSession {
+        SmtpSession {
+            service_name: "mx.example.org".to_owned(),
+            peer_name: Some(helo.to_owned()),
+            connection: ConnectionInfo::new(String::new(), "192.0.2.1:54321".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn resolver() -> ZoneResolver {
+        ZoneResolver::parse(
+            "1.2.0.192.in-addr.arpa. PTR mail.example.com.\n\
+             mail.example.com. A 192.0.2.1",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn helo_is_validated() {
+        assert!(is_fqdn("mail.example.com"));
+        assert!(is_fqdn("mail.example.com."));
+        assert!(!is_fqdn("localhost"));
+        assert!(!is_fqdn("192.0.2.1"));
+        assert!(!is_fqdn("-bad-.example.com"));
+        assert!(!is_fqdn("under_score.example.com"));
+        assert_eq!(
+            address_literal("[192.0.2.1]"),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            address_literal("[IPv6:2001:db8::1]"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(address_literal("mail.example.com"), None);
+    }
+
+    #[test]
+    fn confirmed_client_passes() {
+        async_std::task::block_on(async move {
+            let sut = PeerCheck::default()
+                .with_fcrdns(OnFailure::Reject)
+                .with_resolver(resolver());
+            let mut session = session_from("mail.example.com");
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            assert_eq!(
+                session.transaction.extra_headers,
+                "X-Samotop-Peer: iprev=pass; helo=pass\r\n"
+            );
+            let identity = session.transaction.get::<PeerIdentity>().unwrap();
+            assert_eq!(
+                identity.received_from(),
+                "mail.example.com (mail.example.com [192.0.2.1])"
+            );
+            assert_eq!(
+                session
+                    .transaction
+                    .get::<AuthenticationResults>()
+                    .unwrap()
+                    .get("iprev"),
+                Some("pass")
+            );
+        })
+    }
+
+    #[test]
+    fn unconfirmed_client_fails() {
+        async_std::task::block_on(async move {
+            let forged = ZoneResolver::parse(
+                "1.2.0.192.in-addr.arpa. PTR mail.example.com.\n\
+                 mail.example.com. A 198.51.100.1",
+            )
+            .unwrap();
+            let sut = PeerCheck::default()
+                .with_fcrdns(OnFailure::TempFail)
+                .with_resolver(forged);
+            let mut session = session_from("mail.example.com");
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::FailedTemporarily, _) => {}
+                otherwise => panic!("Expected temporary failure, got {:?}", otherwise),
+            }
+            let identity = session.transaction.get::<PeerIdentity>().unwrap();
+            assert_eq!(identity.verified_name, None);
+            assert_eq!(identity.failed, vec![PeerCheckKind::Fcrdns]);
+        })
+    }
+
+    #[test]
+    fn ptr_names_are_limited() {
+        async_std::task::block_on(async move {
+            let zone = (0..=MAX_PTR_NAMES).fold(String::new(), |zone, n| {
+                zone + format!("1.2.0.192.in-addr.arpa. PTR n{}.example.com.\n", n).as_str()
+            }) + format!("n{}.example.com. A 192.0.2.1", MAX_PTR_NAMES).as_str();
+            let resolver = ZoneResolver::parse(zone.as_str()).unwrap();
+            let resolver: &dyn Resolver = &resolver;
+            assert_eq!(
+                fcrdns(resolver, "192.0.2.1".parse().unwrap()).await,
+                Ok(None)
+            );
+        })
+    }
+
+    #[test]
+    fn helo_failures_follow_configuration() {
+        async_std::task::block_on(async move {
+            let sut = PeerCheck::default()
+                .with_helo_not_ours(OnFailure::Reject)
+                .with_resolver(resolver());
+
+            let mut session = session_from("localhost");
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            assert_eq!(
+                session.transaction.extra_headers,
+                "X-Samotop-Peer: iprev=pass; helo=fail (HeloFqdn)\r\n"
+            );
+
+            let mut session = session_from("[198.51.100.1]");
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            assert_eq!(
+                session.transaction.get::<PeerIdentity>().unwrap().failed,
+                vec![PeerCheckKind::HeloLiteral]
+            );
+
+            let mut session = session_from("MX.example.org");
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::Refused(reason), _) => assert_eq!(
+                    reason,
+                    "Client MX.example.org (mail.example.com [192.0.2.1]) is suspicious: \
+                     the HELO name is our own"
+                ),
+                otherwise => panic!("Expected refusal, got {:?}", otherwise),
+            }
+        })
+    }
+}
@@ -0,0 +1,578 @@
+use crate::lookup::{resolver_for, ViaspfLookup};
+use crate::sync::SyncFuture;
+use async_std::future::timeout;
+use samotop_core::{
+    common::*,
+    dns::Resolver,
+    mail::{
+        AcceptsGuard, AddRecipientResult, Allowlisted, AuthenticationResults, MailGuard, MailSetup,
+        Privacy, Recipient, StartMailFailure, StartMailResult,
+    },
+    smtp::{SmtpHost, SmtpPath, SmtpSession},
+};
+use std::net::{IpAddr, SocketAddr};
+use viaspf::{evaluate_sender, Config, DomainName, Sender, SpfResult};
+
+/// enables checking for SPF records with the default config and policy
+#[derive(Clone, Debug)]
+pub struct Spf;
+
+impl Spf {
+    /// use viaspf config
+    pub fn with_config(self, config: Config) -> SpfWithConfig {
+        SpfWithConfig {
+            config: Arc::new(config),
+            ..Default::default()
+        }
+    }
+}
+
+/**
+SPF checks at MAIL FROM time.
+
+The HELO identity is checked first, then the MAIL FROM identity
+as per RFC 7208. A null sender is checked as `postmaster@` the HELO domain.
+Each result maps to an `SpfAction` for either identity. By default,
+`fail` is rejected, `temperror` fails temporarily and anything else is accepted.
+
+The outcome is recorded as "spf" `AuthenticationResults` and in an `X-Samotop-SPF` header.
+`Allowlisted` transactions are not checked.
+
+```
+# use samotop_core::mail::*;
+# use samotop_with_spf::*;
+let spf = Spf
+    .with_config(Config::default())
+    .with_action(SpfResultKind::Softfail, SpfAction::TempFail)
+    .with_helo_action(SpfResultKind::Softfail, SpfAction::Accept);
+let service = Builder + spf;
+```
+*/
+#[derive(Clone)]
+pub struct SpfWithConfig {
+    config: Arc<Config>,
+    mail_from: SpfPolicy,
+    helo: Option<SpfPolicy>,
+    resolver: Option<Arc<dyn Resolver>>,
+}
+
+/// What to do with mail given the SPF result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfAction {
+    /// Accept the mail
+    Accept,
+    /// Refuse the mail with a 451 so the client retries later
+    TempFail,
+    /// Refuse the mail with a 550
+    Reject,
+}
+
+/// The SPF result without details
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpfResultKind {
+    None,
+    Neutral,
+    Pass,
+    Fail,
+    Softfail,
+    Temperror,
+    Permerror,
+}
+
+impl SpfAction {
+    /// Parse `accept`, `tempfail` or `reject`
+    pub fn parse(action: &str) -> Result<Self> {
+        match action {
+            "accept" => Ok(SpfAction::Accept),
+            "tempfail" => Ok(SpfAction::TempFail),
+            "reject" => Ok(SpfAction::Reject),
+            other => Err(format!(
+                "Unknown SPF action {:?}, use accept, tempfail or reject",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+impl SpfResultKind {
+    /// Parse the result name as in `Received-SPF`, i.e. `softfail`
+    pub fn parse(result: &str) -> Result<Self> {
+        match result {
+            "none" => Ok(SpfResultKind::None),
+            "neutral" => Ok(SpfResultKind::Neutral),
+            "pass" => Ok(SpfResultKind::Pass),
+            "fail" => Ok(SpfResultKind::Fail),
+            "softfail" => Ok(SpfResultKind::Softfail),
+            "temperror" => Ok(SpfResultKind::Temperror),
+            "permerror" => Ok(SpfResultKind::Permerror),
+            other => Err(format!("Unknown SPF result {:?}", other).into()),
+        }
+    }
+}
+
+impl fmt::Display for SpfResultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SpfResultKind::None => "none",
+            SpfResultKind::Neutral => "neutral",
+            SpfResultKind::Pass => "pass",
+            SpfResultKind::Fail => "fail",
+            SpfResultKind::Softfail => "softfail",
+            SpfResultKind::Temperror => "temperror",
+            SpfResultKind::Permerror => "permerror",
+        })
+    }
+}
+
+impl From<&SpfResult> for SpfResultKind {
+    fn from(result: &SpfResult) -> Self {
+        match result {
+            SpfResult::None => SpfResultKind::None,
+            SpfResult::Neutral => SpfResultKind::Neutral,
+            SpfResult::Pass => SpfResultKind::Pass,
+            SpfResult::Fail(_) => SpfResultKind::Fail,
+            SpfResult::Softfail => SpfResultKind::Softfail,
+            SpfResult::Temperror => SpfResultKind::Temperror,
+            SpfResult::Permerror => SpfResultKind::Permerror,
+        }
+    }
+}
+
+/// Actions by SPF result
+#[derive(Debug, Clone)]
+struct SpfPolicy {
+    actions: Vec<(SpfResultKind, SpfAction)>,
+}
+
+impl Default for SpfPolicy {
+    fn default() -> Self {
+        Self {
+            actions: vec![
+                (SpfResultKind::Fail, SpfAction::Reject),
+                (SpfResultKind::Temperror, SpfAction::TempFail),
+            ],
+        }
+    }
+}
+
+impl SpfPolicy {
+    fn set(&mut self, result: SpfResultKind, action: SpfAction) {
+        self.actions.retain(|(r, _)| *r != result);
+        self.actions.push((result, action));
+    }
+    fn action(&self, result: &SpfResult) -> SpfAction {
+        let result = SpfResultKind::from(result);
+        self.actions
+            .iter()
+            .find(|(r, _)| *r == result)
+            .map(|(_, action)| *action)
+            .unwrap_or(SpfAction::Accept)
+    }
+}
+
+impl Default for SpfWithConfig {
+    fn default() -> Self {
+        Self {
+            config: Arc::new(Config::default()),
+            mail_from: SpfPolicy::default(),
+            helo: Some(SpfPolicy::default()),
+            resolver: None,
+        }
+    }
+}
+
+impl SpfWithConfig {
+    /// What to do with the given MAIL FROM result
+    pub fn with_action(mut self, result: SpfResultKind, action: SpfAction) -> Self {
+        self.mail_from.set(result, action);
+        self
+    }
+    /// What to do with the given HELO result, this enables the HELO check
+    pub fn with_helo_action(mut self, result: SpfResultKind, action: SpfAction) -> Self {
+        self.helo
+            .get_or_insert_with(SpfPolicy::default)
+            .set(result, action);
+        self
+    }
+    /// Only check the MAIL FROM identity
+    pub fn without_helo_check(mut self) -> Self {
+        self.helo = None;
+        self
+    }
+    /// Use the given DNS resolver rather than the one of the service
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+    /// Evaluate with the configured timeout, viaspf only has one for tokio
+    async fn evaluate(
+        &self,
+        lookup: &ViaspfLookup<'_>,
+        ip: IpAddr,
+        sender: &Sender,
+        helo: Option<&DomainName>,
+        privacy: &Privacy,
+    ) -> SpfResult {
+        let evaluation = evaluate_sender(lookup, &self.config, ip, sender, helo);
+        match timeout(self.config.timeout(), evaluation).await {
+            Ok(evaluation) => evaluation.spf_result,
+            Err(_) => {
+                warn!("SPF evaluation of {} timed out", privacy.address(sender));
+                SpfResult::Temperror
+            }
+        }
+    }
+    async fn check(
+        &self,
+        resolver: &dyn Resolver,
+        ip: IpAddr,
+        sender: Option<Sender>,
+        helo: Option<DomainName>,
+        privacy: &Privacy,
+    ) -> (SpfResult, SpfAction, String) {
+        let lookup = ViaspfLookup(resolver);
+        let mut helo_result = None;
+        if let (Some(policy), Some(domain)) = (self.helo.as_ref(), helo.as_ref()) {
+            if let Ok(helo_sender) = Sender::from_domain(domain.to_string().as_str()) {
+                let result = self
+                    .evaluate(&lookup, ip, &helo_sender, Some(domain), privacy)
+                    .await;
+                let action = policy.action(&result);
+                let identity = format!("smtp.helo={}", domain);
+                if action != SpfAction::Accept {
+                    return (result, action, identity);
+                }
+                helo_result = Some(result);
+            }
+        }
+        match (sender, helo) {
+            (Some(sender), helo) => {
+                let result = self
+                    .evaluate(&lookup, ip, &sender, helo.as_ref(), privacy)
+                    .await;
+                let action = self.mail_from.action(&result);
+                (result, action, format!("smtp.mailfrom={}", sender.domain()))
+            }
+            (None, Some(helo)) => {
+                // null sender, the HELO identity is the MAIL FROM identity
+                let result = match helo_result {
+                    Some(result) => result,
+                    None => match Sender::from_domain(helo.to_string().as_str()) {
+                        Ok(sender) => {
+                            self.evaluate(&lookup, ip, &sender, Some(&helo), privacy)
+                                .await
+                        }
+                        Err(_) => SpfResult::None,
+                    },
+                };
+                let action = self.mail_from.action(&result);
+                (result, action, format!("smtp.mailfrom=postmaster@{}", helo))
+            }
+            (None, None) => {
+                let result = SpfResult::None;
+                let action = self.mail_from.action(&result);
+                (result, action, "smtp.mailfrom=<>".to_owned())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SpfWithConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpfWithConfig")
+            .field("config", &self.config)
+            .field("mail_from", &self.mail_from)
+            .field("helo", &self.helo)
+            .field("resolver", &self.resolver)
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for SpfWithConfig {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(self)
+    }
+}
+impl<T: AcceptsGuard> MailSetup<T> for Spf {
+    fn setup(self, config: &mut T) {
+        config.add_last_guard(Spf.with_config(Config::default()))
+    }
+}
+
+impl MailGuard for SpfWithConfig {
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        if session.transaction.get::<Allowlisted>().is_some() {
+            return Box::pin(ready(StartMailResult::Accepted));
+        }
+        let addr = session.connection.peer_addr.as_str();
+        let ip = match addr
+            .parse::<SocketAddr>()
+            .map(|socket| socket.ip())
+            .or_else(|_| addr.parse::<IpAddr>())
+        {
+            Ok(ip) => ip,
+            Err(_) => {
+                debug!("Not checking SPF of a client without IP {:?}", addr);
+                return Box::pin(ready(StartMailResult::Accepted));
+            }
+        };
+        let helo = session
+            .peer_name
+            .as_deref()
+            .and_then(|helo| DomainName::new(helo.trim_end_matches('.')).ok());
+        let sender = match session.transaction.mail.as_ref().map(|m| m.sender()) {
+            None | Some(SmtpPath::Null) | Some(SmtpPath::Postmaster) => None,
+            Some(SmtpPath::Mailbox {
+                name,
+                host: SmtpHost::Domain(domain),
+                ..
+            }) => {
+                let domain = domain.trim_end_matches('.');
+                // A local part viaspf does not like still has a valid domain to check
+                Sender::new(format!("{}@{}", name, domain).as_str())
+                    .or_else(|_| Sender::from_domain(domain))
+                    .map_err(|_| debug!("Invalid sender domain {:?}", domain))
+                    .ok()
+            }
+            Some(SmtpPath::Mailbox { .. }) => None,
+        };
+
+        let resolver = resolver_for(self.resolver.clone(), session.dns.clone());
+        let privacy = session.privacy.clone();
+
+        let fut = async move {
+            let resolver = resolver.await;
+            // TODO: improve privacy - a) encrypt DNS, b) do DNS servers need to know who is receiving mail from whom?
+            let (result, action, identity) = match resolver {
+                Ok(resolver) => {
+                    self.check(resolver.as_ref(), ip, sender, helo, &privacy)
+                        .await
+                }
+                Err(_) => {
+                    let result = SpfResult::Temperror;
+                    let action = self.mail_from.action(&result);
+                    (result, action, "smtp.mailfrom=unknown".to_owned())
+                }
+            };
+            let kind = SpfResultKind::from(&result);
+            session
+                .transaction
+                .get_or_insert(AuthenticationResults::default)
+                .add("spf", format!("{} {}", kind, identity));
+
+            match action {
+                SpfAction::Accept => {
+                    debug!("mail OK with SPF result: {} {}", result, identity);
+                    session
+                        .transaction
+                        .extra_headers
+                        .push_str(format!("X-Samotop-SPF: {}\r\n", result).as_str());
+                    StartMailResult::Accepted
+                }
+                SpfAction::TempFail => {
+                    info!("mail deferred due to SPF {} {}", result, identity);
+                    StartMailResult::Failed(
+                        StartMailFailure::FailedTemporarily,
+                        format!("SPF {} in {}", result, session.transaction.id),
+                    )
+                }
+                SpfAction::Reject => {
+                    info!("mail rejected due to SPF {} {}", result, identity);
+                    // RFC 7208 section 6.2 - the explanation must be ASCII
+                    let reason = format!("SPF {} {}", result, identity)
+                        .chars()
+                        .filter(|c| c.is_ascii() && !c.is_ascii_control())
+                        .collect();
+                    StartMailResult::Failed(
+                        StartMailFailure::Refused(reason),
+                        format!("SPF {} in {}", kind, session.transaction.id),
+                    )
+                }
+            }
+        };
+        Box::pin(SyncFuture::new(fut))
+    }
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        _session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use samotop_core::dns::ZoneResolver;
+    use samotop_core::io::ConnectionInfo;
+    use samotop_core::smtp::command::SmtpMail;
+
+    fn session_from(peer: &str, helo: &str, sender: SmtpPath) -> SmtpSession {
+        let mut session = SmtpSession {
+            peer_name: Some(helo.to_owned()),
+            connection: ConnectionInfo::new(String::new(), peer.to_owned()),
+            ..Default::default()
+        };
+        session.transaction.mail = Some(SmtpMail::Mail(sender, vec![]));
+        session
+    }
+
+    fn alice_at(domain: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: "alice".to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        }
+    }
+
+    fn resolver() -> ZoneResolver {
+        ZoneResolver::parse(
+            r#"
+$ORIGIN example.org.
+@     TXT "v=spf1 ip4:192.0.2.0/24 -all"
+soft  TXT "v=spf1 ip4:192.0.2.0/24 ~all"
+mail  TXT "v=spf1 a -all"
+mail  A   192.0.2.1
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn start_mail_fut_is_sync() {
+        let mut sess = SmtpSession::default();
+        let sut = Spf.with_config(Config::default());
+        let fut = sut.start_mail(&mut sess);
+        is_sync(fut);
+    }
+
+    #[test]
+    fn config_is_sync() {
+        let cfg = Config::default();
+        is_sync(cfg);
+    }
+
+    #[test]
+    fn pass_is_accepted_with_port_in_peer_address() {
+        async_std::task::block_on(async move {
+            // the resolver configured for the service
+            let sut = Spf.with_config(Config::default());
+            let mut session = session_from(
+                "192.0.2.1:54321",
+                "mail.example.org",
+                alice_at("example.org"),
+            );
+            session.dns = Some(Arc::new(resolver()));
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            assert_eq!(
+                session
+                    .transaction
+                    .get::<AuthenticationResults>()
+                    .and_then(|r| r.get("spf")),
+                Some("pass smtp.mailfrom=example.org")
+            );
+            assert_eq!(session.transaction.extra_headers, "X-Samotop-SPF: pass\r\n");
+        })
+    }
+
+    #[test]
+    fn fail_is_rejected_at_mail_from() {
+        async_std::task::block_on(async move {
+            let sut = Spf.with_config(Config::default()).with_resolver(resolver());
+            let mut session = session_from(
+                "198.51.100.1:25",
+                "unknown.example",
+                alice_at("example.org"),
+            );
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::Refused(reason), _) => {
+                    assert!(reason.starts_with("SPF fail"), "{}", reason);
+                    assert!(reason.ends_with("smtp.mailfrom=example.org"), "{}", reason);
+                }
+                otherwise => panic!("Expected refusal, got {:?}", otherwise),
+            }
+        })
+    }
+
+    #[test]
+    fn helo_fail_is_rejected_first() {
+        async_std::task::block_on(async move {
+            let sut = Spf.with_config(Config::default()).with_resolver(resolver());
+            let mut session = session_from(
+                "198.51.100.1",
+                "mail.example.org",
+                alice_at("other.example"),
+            );
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::Refused(reason), _) => {
+                    assert!(reason.ends_with("smtp.helo=mail.example.org"), "{}", reason)
+                }
+                otherwise => panic!("Expected refusal, got {:?}", otherwise),
+            }
+
+            let sut = sut.without_helo_check();
+            let mut session = session_from(
+                "198.51.100.1",
+                "mail.example.org",
+                alice_at("other.example"),
+            );
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+        })
+    }
+
+    #[test]
+    fn softfail_follows_the_policy() {
+        async_std::task::block_on(async move {
+            let sut = Spf
+                .with_config(Config::default())
+                .with_resolver(resolver())
+                .with_action(SpfResultKind::Softfail, SpfAction::TempFail);
+            let mut session = session_from(
+                "198.51.100.1",
+                "other.example",
+                alice_at("soft.example.org"),
+            );
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::FailedTemporarily, _) => {}
+                otherwise => panic!("Expected temporary failure, got {:?}", otherwise),
+            }
+        })
+    }
+
+    #[test]
+    fn weird_sender_domain_does_not_panic() {
+        async_std::task::block_on(async move {
+            let sut = Spf.with_config(Config::default()).with_resolver(resolver());
+            let mut session = session_from("192.0.2.1", "[192.0.2.1]", alice_at("-weird_.."));
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            assert_eq!(
+                session
+                    .transaction
+                    .get::<AuthenticationResults>()
+                    .and_then(|r| r.get("spf")),
+                Some("none smtp.mailfrom=<>")
+            );
+        })
+    }
+
+    fn is_sync<T: Sync>(_subject: T) {}
+}
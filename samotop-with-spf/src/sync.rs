@@ -0,0 +1,26 @@
+use samotop_core::common::*;
+use std::sync::Mutex;
+
+/// Makes a `Send` future `Sync` as the mail guards require.
+///
+/// The `Lookup` futures are not `Sync`. Polling takes `&mut self`
+/// so the mutex is never contended, it is only there to share the future safely.
+pub(crate) struct SyncFuture<F>(Mutex<Pin<Box<F>>>);
+
+impl<F: Future + Send> SyncFuture<F> {
+    pub fn new(fut: F) -> Self {
+        Self(Mutex::new(Box::pin(fut)))
+    }
+}
+
+impl<F: Future> Future for SyncFuture<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let fut = self
+            .get_mut()
+            .0
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        fut.as_mut().poll(cx)
+    }
+}
@@ -0,0 +1,93 @@
+//! Measures the throughput of receiving large mails through the whole SMTP pipeline.
+//!
+//! Run with `cargo bench -p samotop --bench large_mail`.
+//! The mail data is served from memory so that the session handling is what gets measured.
+
+use async_std::io::Cursor;
+use samotop::{
+    io::{tls::TlsCapable, ConnectionInfo, IoService},
+    mail::{Builder, NullDispatch},
+    smtp::{Esmtp, SmtpParser},
+};
+use samotop_core::common::*;
+use std::time::{Duration, Instant};
+
+fn main() {
+    for (size, rounds) in &[(1usize, 20u32), (10, 5), (50, 2)] {
+        let input = session(size * 1024 * 1024);
+        let mut elapsed = Duration::default();
+        for _ in 0..*rounds {
+            elapsed += async_std::task::block_on(receive(input.clone()));
+        }
+        let elapsed = elapsed / *rounds;
+        println!(
+            "large_mail {:>3} MiB: {:>8.2?} per mail, {:>8.1} MiB/s",
+            size,
+            elapsed,
+            *size as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+async fn receive(input: Vec<u8>) -> Duration {
+    let io = MemIo {
+        read: Cursor::new(input),
+    };
+    let io = Box::new(TlsCapable::plaintext(Box::new(io)));
+    let service = (Builder + Esmtp.with(SmtpParser) + NullDispatch).build();
+    let start = Instant::now();
+    service
+        .handle(Ok(io), ConnectionInfo::default())
+        .await
+        .expect("session");
+    start.elapsed()
+}
+
+/// An SMTP session delivering one mail with a body of about `size` bytes
+fn session(size: usize) -> Vec<u8> {
+    let line = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod.\r\n";
+    let mut input = Vec::with_capacity(size + 1024);
+    input.extend_from_slice(
+        b"ehlo bench\r\nmail from:<bench@localhost>\r\nrcpt to:<postmaster>\r\ndata\r\n",
+    );
+    input.extend_from_slice(b"Subject: large mail\r\n\r\n");
+    while input.len() < size {
+        for _ in 0..100 {
+            input.extend_from_slice(line);
+        }
+        // dots at the start of a line need escaping
+        input.extend_from_slice(b"..and a dot\r\n");
+    }
+    input.extend_from_slice(b".\r\nquit\r\n");
+    input
+}
+
+struct MemIo {
+    read: Cursor<Vec<u8>>,
+}
+
+impl io::Read for MemIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.read).poll_read(cx, buf)
+    }
+}
+
+impl io::Write for MemIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
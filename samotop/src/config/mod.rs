@@ -0,0 +1,484 @@
+/*!
+Declarative service configuration.
+
+Describe the listeners, protocol, TLS, prudence and the pipeline components
+in a TOML or YAML file and build the `Service` from it, no recompilation needed.
+Guards and dispatches are named components looked up in a `Registry`
+which comes with the samotop components and can be extended with your own.
+
+```
+# #[cfg(feature = "config-toml")]
+# fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+use samotop::config::{Registry, ServiceConfig};
+
+let config = ServiceConfig::from_toml(r#"
+    name = "mx.example.org"
+    listen = ["localhost:2525"]
+    protocol = "esmtp"
+
+    [prudence]
+    banner_delay_ms = 3000
+    read_timeout_ms = 60000
+
+    [[dispatches]]
+    type = "null"
+"#)?;
+
+let service = config.build(&Registry::default())?;
+// config.serve(&Registry::default()).await would then listen on localhost:2525
+# Ok(())
+# }
+# #[cfg(not(feature = "config-toml"))]
+# fn main() {}
+```
+
+The same in YAML:
+
+```yaml
+name: mx.example.org
+listen: ["localhost:2525"]
+protocol: esmtp
+prudence:
+  banner_delay_ms: 3000
+  read_timeout_ms: 60000
+dispatches:
+  - type: "null"
+```
+*/
+
+mod registry;
+
+pub use self::registry::*;
+
+use crate::{
+    common::*,
+    dns::{Dns, DnsCache, Resolver, ZoneResolver},
+    mail::{Builder, BuilderWithConfig, Name, Service},
+    server::TcpServer,
+    smtp::{Esmtp, Lmtp, Prudence, SmtpParser},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The whole service configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// Service name used in SMTP greetings. The default name is used if missing.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// TCP addresses to listen on, such as `localhost:25` or `0.0.0.0:25`
+    #[serde(default)]
+    pub listen: Vec<String>,
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// Enables STARTTLS if present
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub prudence: Option<PrudenceConfig>,
+    /// The resolver shared by DNS based guards, the system one if missing
+    #[serde(default)]
+    pub dns: Option<DnsConfig>,
+    /// Mail guards in the order of evaluation
+    #[serde(default)]
+    pub guards: Vec<ComponentConfig>,
+    /// Mail dispatches in the order of evaluation
+    #[serde(default)]
+    pub dispatches: Vec<ComponentConfig>,
+}
+
+/// The protocol spoken to the clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// ESMTP - RFC 5321
+    #[default]
+    Esmtp,
+    /// LMTP - RFC 2033
+    Lmtp,
+}
+
+/// STARTTLS setup with PEM encoded files
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The certificate chain
+    pub cert_file: PathBuf,
+    /// The private key, PKCS8 or RSA
+    pub key_file: PathBuf,
+}
+
+/// See `Prudence`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrudenceConfig {
+    /// Delay the banner and refuse clients talking before it, in milliseconds
+    #[serde(default)]
+    pub banner_delay_ms: Option<u64>,
+    /// Maximum time to wait for a command, in milliseconds
+    #[serde(default)]
+    pub read_timeout_ms: Option<u64>,
+}
+
+/// See `Dns`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsConfig {
+    /// Answer from these zone files instead of the network, i.e. in tests
+    #[serde(default)]
+    pub zone_files: Vec<PathBuf>,
+    /// How many answers to cache
+    #[serde(default)]
+    pub cache_size: Option<usize>,
+    /// The longest time to cache an answer, in seconds
+    #[serde(default)]
+    pub max_ttl_s: Option<u64>,
+}
+
+/// A named component with its parameters, i.e. `{ type = "maildir", path = "/var/mail" }`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComponentConfig {
+    /// The name under which the component is registered
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Any other fields are parameters of the component
+    #[serde(flatten)]
+    pub params: serde_json::Map<String, serde_json::Value>,
+    /// Relative paths in the parameters are relative to this dir - see `path()`
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+impl ComponentConfig {
+    pub fn new(kind: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            params: Default::default(),
+            base_dir: PathBuf::default(),
+        }
+    }
+    /// Add a parameter
+    pub fn with(mut self, name: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.params.insert(name.into(), value.into());
+        self
+    }
+    /// Deserialize the parameters into the component's own settings type
+    pub fn params<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(serde_json::Value::Object(self.params.clone()))
+            .map_err(|e| format!("Invalid configuration of {:?}: {}", self.kind, e).into())
+    }
+    /// Resolve a path parameter against the `base_dir`, absolute paths are kept
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.base_dir.join(path)
+    }
+}
+
+impl ServiceConfig {
+    #[cfg(feature = "config-toml")]
+    pub fn from_toml(config: &str) -> Result<Self> {
+        Ok(toml::from_str(config)?)
+    }
+    #[cfg(feature = "config-yaml")]
+    pub fn from_yaml(config: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(config)?)
+    }
+    /// Load the configuration from a `.toml`, `.yaml` or `.yml` file.
+    ///
+    /// Relative paths in the configuration are relative to the file's dir.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read configuration {:?}: {}", path, e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "config-toml")]
+            Some("toml") => Self::from_toml(config.as_str()),
+            #[cfg(feature = "config-yaml")]
+            Some("yaml") | Some("yml") => Self::from_yaml(config.as_str()),
+            _ => Err(format!("Unsupported configuration format {:?}", path).into()),
+        }
+        .map_err(|e| format!("Invalid configuration {:?}: {}", path, e).into())
+        .map(|config| match path.parent() {
+            Some(dir) => config.relative_to(dir),
+            None => config,
+        })
+    }
+
+    /// Resolve the relative paths (TLS files, zone files, component paths) against the given dir
+    pub fn relative_to(mut self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        if let Some(ref mut tls) = self.tls {
+            tls.cert_file = dir.join(&tls.cert_file);
+            tls.key_file = dir.join(&tls.key_file);
+        }
+        if let Some(ref mut dns) = self.dns {
+            for zone in dns.zone_files.iter_mut() {
+                *zone = dir.join(&zone);
+            }
+        }
+        for component in self.guards.iter_mut().chain(self.dispatches.iter_mut()) {
+            component.base_dir = dir.join(&component.base_dir);
+        }
+        self
+    }
+
+    /// Set up a builder according to the configuration.
+    ///
+    /// More components can be added to the builder before building the service.
+    pub fn builder(&self, registry: &Registry) -> Result<BuilderWithConfig> {
+        let mut builder = Builder::empty();
+        if let Some(ref name) = self.name {
+            builder += Name::new(name);
+        }
+        builder = match self.protocol {
+            Protocol::Esmtp => builder + Esmtp.with(SmtpParser),
+            Protocol::Lmtp => builder + Lmtp.with(SmtpParser),
+        };
+        if let Some(ref tls) = self.tls {
+            builder += tls.setup()?;
+        }
+        if let Some(ref prudence) = self.prudence {
+            builder += prudence.setup();
+        }
+        if let Some(ref dns) = self.dns {
+            builder += dns.setup()?;
+        }
+        for component in self.guards.iter().chain(self.dispatches.iter()) {
+            builder += registry.create(component)?;
+        }
+        Ok(builder)
+    }
+
+    /// Build the mail service according to the configuration
+    pub fn build(&self, registry: &Registry) -> Result<Service> {
+        Ok(self.builder(registry)?.build())
+    }
+
+    /// Build the mail service and serve it on all the configured addresses
+    pub async fn serve(self, registry: &Registry) -> Result<()> {
+        if self.listen.is_empty() {
+            return Err("No address to listen on is configured".into());
+        }
+        let service = self.build(registry)?;
+        TcpServer::on_all(self.listen).serve(service).await
+    }
+}
+
+impl TlsConfig {
+    #[cfg(feature = "rust-tls")]
+    fn setup(&self) -> Result<impl crate::mail::MailSetup<crate::mail::Configuration>> {
+        use crate::{io::tls::RustlsProvider, smtp::EsmtpStartTls};
+        let provider = RustlsProvider::from_pem_files(&self.cert_file, &self.key_file)?;
+        Ok(EsmtpStartTls.with(SmtpParser, provider))
+    }
+    #[cfg(not(feature = "rust-tls"))]
+    fn setup(&self) -> Result<Name> {
+        Err("TLS configuration requires the rust-tls feature".into())
+    }
+}
+
+impl PrudenceConfig {
+    fn setup(&self) -> Prudence {
+        let mut prudence = Prudence::default();
+        if let Some(delay) = self.banner_delay_ms {
+            prudence = prudence.with_banner_delay(Duration::from_millis(delay));
+        }
+        if let Some(timeout) = self.read_timeout_ms {
+            prudence = prudence.with_read_timeout(Duration::from_millis(timeout));
+        }
+        prudence
+    }
+}
+
+impl DnsConfig {
+    fn setup(&self) -> Result<Dns> {
+        let mut cache = DnsCache::new(self.resolver()?);
+        if let Some(size) = self.cache_size {
+            cache = cache.with_capacity(size);
+        }
+        if let Some(ttl) = self.max_ttl_s {
+            cache = cache.with_max_ttl(Duration::from_secs(ttl));
+        }
+        Ok(Dns::uncached(cache))
+    }
+    fn resolver(&self) -> Result<Arc<dyn Resolver>> {
+        if !self.zone_files.is_empty() {
+            let mut zone = ZoneResolver::default();
+            for path in self.zone_files.iter() {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read zone file {:?}: {}", path, e))?;
+                zone = zone
+                    .with_zone(text.as_str())
+                    .map_err(|e| format!("Invalid zone file {:?}: {}", path, e))?;
+            }
+            return Ok(Arc::new(zone));
+        }
+        #[cfg(feature = "spf")]
+        {
+            let resolver = crate::dns::TrustDnsResolver::from_system_conf()
+                .map_err(|e| format!("Could not create DNS resolver: {}", e))?;
+            Ok(Arc::new(resolver))
+        }
+        #[cfg(not(feature = "spf"))]
+        Err("DNS configuration without zone files requires the spf feature".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestClient;
+
+    #[cfg(feature = "config-toml")]
+    #[test]
+    fn parses_toml() {
+        let config = ServiceConfig::from_toml(
+            r#"
+            name = "testik"
+            listen = ["localhost:25", "localhost:2525"]
+            protocol = "lmtp"
+            [tls]
+            cert_file = "my.crt"
+            key_file = "my.key"
+            [prudence]
+            read_timeout_ms = 1000
+            [dns]
+            zone_files = ["test.zone"]
+            [[guards]]
+            type = "mapper"
+            map = [[".*", "postmaster"]]
+            [[dispatches]]
+            type = "maildir"
+            path = "/var/mail"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(config.name.as_deref(), Some("testik"));
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.protocol, Protocol::Lmtp);
+        assert_eq!(config.tls.expect("tls").cert_file, PathBuf::from("my.crt"));
+        assert_eq!(
+            config.prudence.expect("prudence").read_timeout_ms,
+            Some(1000)
+        );
+        assert_eq!(config.dns.expect("dns").zone_files.len(), 1);
+        assert_eq!(config.guards[0].kind, "mapper");
+        assert_eq!(config.dispatches[0].params["path"], "/var/mail");
+    }
+
+    #[cfg(feature = "config-toml")]
+    #[test]
+    fn resolves_relative_paths() {
+        let dir = std::env::temp_dir().join(format!("samotop-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let file = dir.join("samotop.toml");
+        std::fs::write(
+            &file,
+            r#"
+            [tls]
+            cert_file = "my.crt"
+            key_file = "/etc/my.key"
+            [dns]
+            zone_files = ["test.zone"]
+            [[dispatches]]
+            type = "maildir"
+            path = "inmail"
+            "#,
+        )
+        .expect("config");
+        let config = ServiceConfig::from_file(&file).expect("valid config");
+        std::fs::remove_dir_all(&dir).ok();
+
+        let tls = config.tls.expect("tls");
+        assert_eq!(tls.cert_file, dir.join("my.crt"));
+        assert_eq!(tls.key_file, PathBuf::from("/etc/my.key"));
+        assert_eq!(
+            config.dns.expect("dns").zone_files,
+            vec![dir.join("test.zone")]
+        );
+        assert_eq!(config.dispatches[0].path("inmail"), dir.join("inmail"));
+        assert_eq!(
+            config.dispatches[0].path("/var/mail"),
+            PathBuf::from("/var/mail")
+        );
+    }
+
+    #[test]
+    fn builds_dns_from_zone_files() {
+        async_std::task::block_on(async {
+            let dir = std::env::temp_dir().join(format!("samotop-dns-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).expect("dir");
+            let zone = dir.join("example.org.zone");
+            std::fs::write(&zone, "$ORIGIN example.org.\n@ 300 IN MX 10 mx\n").expect("zone");
+            let dns = DnsConfig {
+                zone_files: vec![zone],
+                cache_size: Some(10),
+                ..Default::default()
+            }
+            .setup()
+            .expect("dns");
+            let resolver = dns.resolver();
+            assert_eq!(
+                resolver.lookup_mx("example.org").await,
+                Ok(vec!["mx.example.org".to_owned()])
+            );
+            std::fs::remove_dir_all(dir).ok();
+
+            let missing = DnsConfig {
+                zone_files: vec!["/nonexistent/zone".into()],
+                ..Default::default()
+            };
+            assert!(missing.setup().is_err());
+        })
+    }
+
+    #[cfg(feature = "config-yaml")]
+    #[test]
+    fn parses_yaml() {
+        let config = ServiceConfig::from_yaml(
+            "name: testik\nlisten: [\"localhost:25\"]\ndispatches:\n  - type: journal\n    path: /tmp\n",
+        )
+        .expect("valid config");
+        assert_eq!(config.protocol, Protocol::Esmtp);
+        assert_eq!(config.dispatches[0].kind, "journal");
+    }
+
+    #[cfg(feature = "config-toml")]
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(ServiceConfig::from_toml("nmae = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_component() {
+        let config = ServiceConfig {
+            dispatches: vec![ComponentConfig::new("carrier-pigeon")],
+            ..Default::default()
+        };
+        let err = config.build(&Registry::default()).expect_err("unknown");
+        assert!(err.to_string().contains("carrier-pigeon"), "{}", err);
+    }
+
+    #[test]
+    fn builds_working_service() {
+        async_std::task::block_on(async {
+            let config = ServiceConfig {
+                name: Some("testik".to_owned()),
+                dispatches: vec![ComponentConfig::new("null")],
+                ..Default::default()
+            };
+            let service = config.build(&Registry::default()).expect("service");
+            let mut client = TestClient::connect(service);
+            assert_eq!(client.expect(220).await.raw, "220 testik service ready\r\n");
+            client.send("helo macca");
+            client.expect(250).await;
+            client.send("mail from:<>");
+            client.expect(250).await;
+            client.send("rcpt to:<postmaster>");
+            client.expect(250).await;
+            client.send("data");
+            client.expect(354).await;
+        })
+    }
+}
@@ -0,0 +1,399 @@
+use super::ComponentConfig;
+use crate::{
+    common::*,
+    mail::{
+        AccessList, Configuration, FileGreylistStore, Greylist, MailSetup, NullDispatch,
+        SessionLogger,
+    },
+    smtp::TranscriptRecorder,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/**
+Maps component names to `MailSetup` constructors.
+
+The default registry knows these components, subject to crate features:
+
+| type         | parameters                                             | feature       |
+|--------------|--------------------------------------------------------|---------------|
+| `null`       |                                                        |               |
+| `logger`     |                                                        |               |
+| `transcript` | `dir`                                                  |               |
+| `access`     | `files` - list of text or TOML access lists            |               |
+| `greylist`   | `file`, `delay_secs`, `auto_whitelist` (count)         |               |
+| `mapper`     | `map` - list of `[regex, replacement]`                 | `mapper`      |
+| `accounts`   | `dir`                                                  | `smime`       |
+| `spf`        | `mail_from`, `helo` - result to action, `helo_check`   | `spf`         |
+| `dnsbl`      | `ip_zones`, `domain_zones`, `threshold`                | `spf`         |
+| `peer`       | `fcrdns`, `helo_fqdn`, `helo_not_ours`, `helo_literal` | `spf`         |
+| `dkim`       |                                                        | `spf`         |
+| `maildir`    | `path`                                                 | `delivery`    |
+| `journal`    | `path`                                                 | `delivery`    |
+| `lmtp`       | `address`, `unix` (bool), `reuse` (count)              | `delivery`    |
+
+The `dnsbl` zones are lists of `[zone, weight]`.
+The `peer` checks take one of `reject`, `tempfail` or `header`.
+The `spf` actions map results such as `softfail` to `accept`, `tempfail` or `reject`.
+The `dkim` verification belongs among the dispatches, after those it should verify the mail for.
+Relative paths are relative to the `ComponentConfig::base_dir`, the configuration file's dir
+if loaded with `ServiceConfig::from_file()`.
+
+Other crates can register their own:
+
+```
+# use samotop::config::*;
+# use samotop::mail::*;
+#[derive(serde::Deserialize)]
+struct Settings {
+    name: String,
+}
+
+let registry = Registry::default()
+    .with("rename", |component: &ComponentConfig| {
+        let settings: Settings = component.params()?;
+        Ok(Name::new(settings.name))
+    });
+
+let component = ComponentConfig::new("rename").with("name", "mx.example.org");
+let service = Builder::empty() + registry.create(&component).expect("known");
+```
+*/
+pub struct Registry {
+    factories: BTreeMap<String, Box<Factory>>,
+}
+
+type Factory = dyn Fn(&ComponentConfig) -> Result<Box<dyn Component>> + Send + Sync;
+
+/// An object safe `MailSetup` produced by the `Registry`
+pub trait Component: fmt::Debug {
+    fn setup_boxed(self: Box<Self>, config: &mut Configuration);
+}
+
+impl<T: MailSetup<Configuration>> Component for T {
+    fn setup_boxed(self: Box<Self>, config: &mut Configuration) {
+        (*self).setup(config)
+    }
+}
+
+impl MailSetup<Configuration> for Box<dyn Component> {
+    fn setup(self, config: &mut Configuration) {
+        self.setup_boxed(config)
+    }
+}
+
+impl Registry {
+    /// A registry with no components
+    pub fn empty() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+    /// Register a component constructor under the given name, replacing any previous one
+    pub fn register<F, S>(&mut self, kind: impl Into<String>, factory: F) -> &mut Self
+    where
+        F: Fn(&ComponentConfig) -> Result<S> + Send + Sync + 'static,
+        S: MailSetup<Configuration> + 'static,
+    {
+        self.factories.insert(
+            kind.into(),
+            Box::new(move |component| {
+                let setup: Box<dyn Component> = Box::new(factory(component)?);
+                Ok(setup)
+            }),
+        );
+        self
+    }
+    /// Register a component constructor, builder style
+    pub fn with<F, S>(mut self, kind: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(&ComponentConfig) -> Result<S> + Send + Sync + 'static,
+        S: MailSetup<Configuration> + 'static,
+    {
+        self.register(kind, factory);
+        self
+    }
+    /// Names of the registered components
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+    /// Construct the configured component
+    pub fn create(&self, component: &ComponentConfig) -> Result<Box<dyn Component>> {
+        match self.factories.get(component.kind.as_str()) {
+            Some(factory) => factory(component),
+            None => Err(format!(
+                "Unknown component {:?}, known are {:?}",
+                component.kind,
+                self.kinds().collect::<Vec<_>>()
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.kinds()).finish()
+    }
+}
+
+/// Parameters of components without any
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoParams {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirParams {
+    dir: PathBuf,
+}
+
+#[cfg(feature = "delivery")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathParams {
+    path: PathBuf,
+}
+
+impl Default for Registry {
+    /// The registry of samotop components
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register("null", |c| c.params::<NoParams>().map(|_| NullDispatch))
+            .register("logger", |c| c.params::<NoParams>().map(|_| SessionLogger))
+            .register("transcript", |c| {
+                c.params::<DirParams>()
+                    .map(|p| TranscriptRecorder::new(c.path(p.dir)))
+            })
+            .register("access", |c| {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct AccessParams {
+                    files: Vec<PathBuf>,
+                }
+                let mut access = AccessList::default();
+                for file in c.params::<AccessParams>()?.files {
+                    access = access.with_file(c.path(file))?;
+                }
+                Ok(access)
+            })
+            .register("greylist", |c| {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct GreylistParams {
+                    file: Option<PathBuf>,
+                    delay_secs: Option<u64>,
+                    auto_whitelist: Option<u32>,
+                }
+                let p = c.params::<GreylistParams>()?;
+                let mut greylist = match p.file {
+                    Some(file) => Greylist::new(FileGreylistStore::open(c.path(file))?),
+                    None => Greylist::default(),
+                };
+                if let Some(delay) = p.delay_secs {
+                    greylist = greylist.with_delay(std::time::Duration::from_secs(delay));
+                }
+                match p.auto_whitelist {
+                    Some(0) => greylist = greylist.without_auto_whitelist(),
+                    Some(transactions) => greylist = greylist.with_auto_whitelist(transactions),
+                    None => {}
+                }
+                Ok(greylist)
+            });
+
+        #[cfg(feature = "mapper")]
+        registry.register("mapper", |c| {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct MapperParams {
+                map: Vec<(String, String)>,
+            }
+            let mut map = vec![];
+            for (regex, replacement) in c.params::<MapperParams>()?.map {
+                map.push((regex::Regex::new(regex.as_str())?, replacement));
+            }
+            Ok(crate::mail::Mapper::new(map))
+        });
+
+        #[cfg(feature = "smime")]
+        registry.register("accounts", |c| {
+            c.params::<DirParams>()
+                .map(|p| crate::mail::smime::Accounts::new(c.path(p.dir)))
+        });
+
+        #[cfg(feature = "spf")]
+        registry.register("spf", |c| {
+            use crate::mail::spf::{Config, Spf, SpfAction, SpfResultKind};
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct SpfParams {
+                #[serde(default)]
+                mail_from: BTreeMap<String, String>,
+                #[serde(default)]
+                helo: BTreeMap<String, String>,
+                helo_check: Option<bool>,
+            }
+            let p = c.params::<SpfParams>()?;
+            let mut spf = Spf.with_config(Config::default());
+            for (result, action) in p.mail_from {
+                spf = spf.with_action(
+                    SpfResultKind::parse(result.as_str())?,
+                    SpfAction::parse(action.as_str())?,
+                );
+            }
+            for (result, action) in p.helo {
+                spf = spf.with_helo_action(
+                    SpfResultKind::parse(result.as_str())?,
+                    SpfAction::parse(action.as_str())?,
+                );
+            }
+            if p.helo_check == Some(false) {
+                spf = spf.without_helo_check();
+            }
+            Ok(spf)
+        });
+
+        #[cfg(feature = "spf")]
+        registry.register("dnsbl", |c| {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct DnsblParams {
+                #[serde(default)]
+                ip_zones: Vec<(String, f32)>,
+                #[serde(default)]
+                domain_zones: Vec<(String, f32)>,
+                threshold: Option<f32>,
+            }
+            let p = c.params::<DnsblParams>()?;
+            let mut dnsbl = crate::mail::spf::Dnsbl::default();
+            for (zone, weight) in p.ip_zones {
+                dnsbl = dnsbl.with_ip_zone(zone, weight);
+            }
+            for (zone, weight) in p.domain_zones {
+                dnsbl = dnsbl.with_domain_zone(zone, weight);
+            }
+            if let Some(threshold) = p.threshold {
+                dnsbl = dnsbl.with_threshold(threshold);
+            }
+            Ok(dnsbl)
+        });
+
+        #[cfg(feature = "spf")]
+        registry.register("peer", |c| {
+            use crate::mail::spf::{OnFailure, PeerCheck};
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct PeerParams {
+                fcrdns: Option<String>,
+                helo_fqdn: Option<String>,
+                helo_not_ours: Option<String>,
+                helo_literal: Option<String>,
+            }
+            let on_failure = |action: Option<String>| -> Result<OnFailure> {
+                match action.as_deref() {
+                    None | Some("header") => Ok(OnFailure::AddHeader),
+                    Some("reject") => Ok(OnFailure::Reject),
+                    Some("tempfail") => Ok(OnFailure::TempFail),
+                    Some(other) => Err(format!(
+                        "Unknown action {:?}, use reject, tempfail or header",
+                        other
+                    )
+                    .into()),
+                }
+            };
+            let p = c.params::<PeerParams>()?;
+            Ok(PeerCheck::default()
+                .with_fcrdns(on_failure(p.fcrdns)?)
+                .with_helo_fqdn(on_failure(p.helo_fqdn)?)
+                .with_helo_not_ours(on_failure(p.helo_not_ours)?)
+                .with_helo_literal(on_failure(p.helo_literal)?))
+        });
+
+        #[cfg(feature = "spf")]
+        registry.register("dkim", |c| {
+            c.params::<NoParams>()
+                .map(|_| crate::mail::spf::Dkim::default())
+        });
+
+        #[cfg(feature = "delivery")]
+        registry
+            .register("maildir", |c| {
+                crate::mail::MailDir::new(c.path(c.params::<PathParams>()?.path))
+            })
+            .register("journal", |c| {
+                c.params::<PathParams>()
+                    .map(|p| crate::mail::Journal::new(c.path(p.path)))
+            })
+            .register("lmtp", |c| {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct LmtpParams {
+                    address: String,
+                    #[serde(default)]
+                    unix: bool,
+                    #[serde(default)]
+                    reuse: u16,
+                }
+                let p = c.params::<LmtpParams>()?;
+                let lmtp: Box<dyn Component> = if p.unix {
+                    #[cfg(unix)]
+                    {
+                        let connector =
+                            crate::mail::net::UnixConnector::<crate::io::tls::NoTls>::default();
+                        let address = c.path(p.address).to_string_lossy().into_owned();
+                        Box::new(crate::mail::LmtpDispatch::new(address, connector)?.reuse(p.reuse))
+                    }
+                    #[cfg(not(unix))]
+                    return Err("LMTP over unix sockets is not supported here".into());
+                } else {
+                    let connector = crate::mail::net::DefaultConnector::default();
+                    Box::new(crate::mail::LmtpDispatch::new(p.address, connector)?.reuse(p.reuse))
+                };
+                Ok(lmtp)
+            });
+
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_registry_knows_builtins() {
+        let registry = Registry::default();
+        let kinds = registry.kinds().collect::<Vec<_>>();
+        assert!(kinds.contains(&"null"));
+        assert!(kinds.contains(&"transcript"));
+        #[cfg(feature = "delivery")]
+        assert!(kinds.contains(&"maildir"));
+    }
+
+    #[test]
+    fn parameters_are_checked() {
+        let registry = Registry::default();
+        assert!(registry
+            .create(&ComponentConfig::new("null").with("path", "/tmp"))
+            .is_err());
+        assert!(registry
+            .create(&ComponentConfig::new("transcript"))
+            .is_err());
+        assert!(registry
+            .create(&ComponentConfig::new("transcript").with("dir", "/tmp"))
+            .is_ok());
+    }
+
+    #[cfg(feature = "mapper")]
+    #[test]
+    fn mapper_needs_valid_regex() {
+        let registry = Registry::default();
+        let mapper = |regex: &str| {
+            ComponentConfig::new("mapper").with("map", serde_json::json!([[regex, "postmaster"]]))
+        };
+        assert!(registry.create(&mapper("(")).is_err());
+        assert!(registry.create(&mapper(".*")).is_ok());
+    }
+}
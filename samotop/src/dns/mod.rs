@@ -0,0 +1,4 @@
+pub use samotop_core::dns::*;
+
+#[cfg(feature = "spf")]
+pub use samotop_with_spf::TrustDnsResolver;
@@ -28,10 +28,12 @@ The [samotop crate is published on crates.io](https://crates.io/crates/samotop).
 - [x] Parse SMTP commands and write responses according to RFCs
 - [x] SMTP state machine - helo, mail, rcpt*, data, rset, quit - must be in correct order according to RFCs
 - [x] DATA are handled and terminated correctly (escape dot, final dot).
-- [x] Async/await with async-std backing
+- [x] Async/await with async-std or tokio backing - see `runtime`
 - [x] Privacy: TLS/STARTTLS supported using [rustls](https://crates.io/crates/rustls) and [native_tls](https://crates.io/crates/native_tls)
 - [x] Privacy: Encryption at rest, S/MIME encrypt e-mails, only the recipient will be able to decrypt
+- [x] Privacy: Log redaction, pseudonymised addresses, truncated peer IPs and no body content in logs
 - [x] MTA: Simple mail relay, logging smtp session to standard output but able to receive mail from common relays
+- [x] MTA: Virtual hosting - service name, TLS certificate and pipeline per local address - `VirtualHosts`
 - [x] MDA: System-wide mailbox - mailbox for all unclaimed domains / addresses - store mail in a folder so it can be processed further
 - [x] MDA: Domain mailbox - mailbox for unclaimed addresses (through LMTP to another LDA)
 - [x] MDA: User mailbox - mailbox for specific address or alias (through LMTP to another LDA)
@@ -40,21 +42,28 @@ The [samotop crate is published on crates.io](https://crates.io/crates/samotop).
 - [x] Integration: LMTP socket - can deliver to LDA over unix or network sockets using LMTP
 - [x] Integration: LMTP child process - can deliver to LDA using LMTP protocol over io with a child process
 - [x] LDA: Can process LMTP session (LHLO + delivery status per rcpt)
-- [x] Antispam: Reject mails failing SPF checks - through `viaspf` crate, now async
+- [x] Antispam: Reject mails failing SPF checks of HELO and MAIL FROM - through `viaspf` crate, now async
+- [x] Antispam: DNS blocklists (DNSBL/RHSBL) with weighted scoring - `Dnsbl`
+- [x] Antispam: Forward-confirmed reverse DNS and HELO validation - `PeerCheck`
+- [x] Antispam: DKIM signature verification, rsa-sha256 and ed25519-sha256 - `Dkim`
+- [x] DNS: Shared async resolver with a TTL cache, zone file resolver for offline tests - `Dns`
+- [x] Antispam: Greylisting with auto whitelisting, in memory or file backed - `Greylist`
+- [x] Antispam: Allow and deny lists of clients, HELO names, senders and recipients - `AccessList`
 - [x] Antispam: Strict SMTP - require CRLF
 - [x] Antispam: Strict SMTP - reject session if client sends mail before banner - `Prudence`
 - [x] Anti-abuse: Command timeout - `Impatience`
 - [x] Extensibility: Modular and composable service - `Builder` + `Configuration` + `MailSetup` => `Service`
+- [x] Operations: Prometheus metrics with a built-in HTTP endpoint - `Metrics`
+- [x] Operations: Tracing spans per session and transaction, carried into delivery - `tracing` feature
+- [x] Operations: JSON audit log line per mail transaction, rotating file - `AuditLog`
+- [x] Extensibility: Declarative TOML/YAML service configuration with a component registry - `config`
 
 ## To do
 
 - [ ] Accounts: Self service account subscription through SMTP/IMAP
 - [ ] MTA: Queue and queue manager, relay mail to another MTA
-- [ ] Antispam: whitelist and blacklist
-- [ ] Antispam: greylisting
 - [ ] Antispam: white/black/grey list with UI - user decides new contact handling
 - [ ] Antispam: is it encrypted?
-- [ ] Antispam: reverse lookup
 - [ ] Antispam: DANE (DNSSEC) with UI - user verifies signatures
 - [ ] Privacy: Refuse unencrypted session
 - [ ] Privacy: Leave no trace, no logs, obfuscated file dates...
@@ -196,10 +205,18 @@ In Rust world I have so far found mostly SMTP clients.
 * [new-tokio-smtp](https://crates.io/crates/new-tokio-smtp) is na SMTP client by **Philipp Korber**, now only pasively maintained
 */
 
+#[cfg(all(
+    feature = "config",
+    any(feature = "parser-peg", feature = "parser-nom")
+))]
+pub mod config;
+pub mod dns;
 pub mod io;
 pub mod mail;
+pub mod runtime;
 pub mod server;
 pub mod smtp;
+pub mod testing;
 
 mod common {
     pub use samotop_core::common::*;
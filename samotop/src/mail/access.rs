@@ -0,0 +1,669 @@
+//! Allow and deny lists of clients, HELO names, senders and recipients.
+
+use crate::{
+    common::*,
+    mail::*,
+    smtp::{SmtpPath, SmtpSession},
+};
+use log::*;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/**
+A mail guard applying allow and deny lists.
+
+Entries match the client IP by CIDR range, the HELO name, the sender
+and the recipient address. Names and addresses may contain `*` and `?` wildcards
+and are matched case insensitively. A sender or recipient pattern without `@` matches the domain.
+The first matching entry decides:
+* `accept` - the transaction is `Allowlisted` and the recipients are accepted right away,
+  skipping the guards set up after - greylisting, blocklists, recipient mapping...
+* `reject` - the mail or the recipient is refused with a 550,
+* `tempfail` - the mail or the recipient is refused temporarily with a 451 or 450.
+
+The access list goes first in the guard chain wherever it is set up.
+
+Lists can be loaded from files, which are reloaded when they change.
+A text file has one entry per line - action, kind and pattern:
+
+```text
+# our backup MX
+accept client 192.0.2.0/24
+accept sender *@partner.example.com
+reject helo *.dynamic.example.net
+reject sender spam.example
+tempfail recipient departed@example.org
+```
+
+With the `config-toml` feature, `.toml` files are supported too.
+The `accept` entries come first, then `reject` and `tempfail`:
+
+```toml
+[accept]
+client = ["192.0.2.0/24"]
+sender = ["*@partner.example.com"]
+
+[reject]
+helo = ["*.dynamic.example.net"]
+```
+
+```
+# use samotop::mail::*;
+let access = AccessList::default()
+    .with_entry(AccessAction::Accept, AccessRule::parse("client", "192.0.2.0/24").unwrap())
+    .with_entry(AccessAction::Reject, AccessRule::parse("sender", "*.spam.example").unwrap());
+let service = Builder + access;
+```
+*/
+#[derive(Clone, Default)]
+pub struct AccessList {
+    entries: Vec<AccessEntry>,
+    files: Vec<Arc<AccessFile>>,
+    reload_interval: Option<Duration>,
+}
+
+/// What to do with matching mail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessAction {
+    /// Accept and skip other checks
+    Accept,
+    /// Refuse permanently
+    Reject,
+    /// Refuse temporarily
+    TempFail,
+}
+
+/// What an entry matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessRule {
+    /// Client IP in the network with the given prefix length
+    Client(IpAddr, u8),
+    /// HELO name pattern
+    Helo(String),
+    /// Sender address or domain pattern
+    Sender(String),
+    /// Recipient address or domain pattern
+    Recipient(String),
+}
+
+/// An access list entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessEntry {
+    pub action: AccessAction,
+    pub rule: AccessRule,
+}
+
+impl AccessAction {
+    /// Parse `accept`, `reject` or `tempfail`
+    pub fn parse(action: &str) -> Result<Self> {
+        match action.to_ascii_lowercase().as_str() {
+            "accept" => Ok(AccessAction::Accept),
+            "reject" => Ok(AccessAction::Reject),
+            "tempfail" => Ok(AccessAction::TempFail),
+            other => Err(format!(
+                "Unknown access action {:?}, use accept, reject or tempfail",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+impl AccessRule {
+    /// Parse a rule of the given kind - `client`, `helo`, `sender` or `recipient`
+    pub fn parse(kind: &str, pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        match kind.to_ascii_lowercase().as_str() {
+            "client" => {
+                let (ip, prefix) = match pattern.split_once('/') {
+                    Some((ip, prefix)) => (ip.parse::<IpAddr>()?, Some(prefix.parse::<u8>()?)),
+                    None => (pattern.parse::<IpAddr>()?, None),
+                };
+                let max = if ip.is_ipv4() { 32 } else { 128 };
+                match prefix.unwrap_or(max) {
+                    prefix if prefix <= max => Ok(AccessRule::Client(ip, prefix)),
+                    prefix => Err(format!("Invalid prefix length {} of {}", prefix, ip).into()),
+                }
+            }
+            "helo" => Ok(AccessRule::Helo(pattern)),
+            "sender" => Ok(AccessRule::Sender(pattern)),
+            "recipient" => Ok(AccessRule::Recipient(pattern)),
+            other => Err(format!(
+                "Unknown access rule {:?}, use client, helo, sender or recipient",
+                other
+            )
+            .into()),
+        }
+    }
+    fn matches_client(&self, ip: Option<IpAddr>) -> bool {
+        match (self, ip) {
+            (AccessRule::Client(IpAddr::V4(net), prefix), Some(IpAddr::V4(ip))) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - *prefix as u32)
+                    .unwrap_or_default();
+                u32::from(*net) & mask == u32::from(ip) & mask
+            }
+            (AccessRule::Client(IpAddr::V6(net), prefix), Some(IpAddr::V6(ip))) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - *prefix as u32)
+                    .unwrap_or_default();
+                u128::from(*net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl AccessList {
+    /// Add an entry
+    pub fn with_entry(mut self, action: AccessAction, rule: AccessRule) -> Self {
+        self.entries.push(AccessEntry { action, rule });
+        self
+    }
+    /// Load entries from a text or TOML file, reloading it when it changes
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let file = AccessFile::new(path.as_ref().to_owned());
+        file.reload()?;
+        self.files.push(Arc::new(file));
+        Ok(self)
+    }
+    /// How often to check the files for changes, every 10 seconds by default
+    pub fn with_reload_interval(mut self, interval: Duration) -> Self {
+        self.reload_interval = Some(interval);
+        self
+    }
+    /// Find the first matching entry
+    fn decide(&self, matches: impl Fn(&AccessRule) -> bool) -> Option<AccessAction> {
+        if let Some(entry) = self.entries.iter().find(|e| matches(&e.rule)) {
+            return Some(entry.action);
+        }
+        let interval = self.reload_interval.unwrap_or(Duration::from_secs(10));
+        for file in self.files.iter() {
+            file.refresh(interval);
+            let loaded = file.loaded.read().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = loaded.entries.iter().find(|e| matches(&e.rule)) {
+                return Some(entry.action);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Debug for AccessList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessList")
+            .field("entries", &self.entries.len())
+            .field(
+                "files",
+                &self.files.iter().map(|f| &f.path).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<T: AcceptsGuard> MailSetup<T> for AccessList {
+    fn setup(self, config: &mut T) {
+        config.add_first_guard(self)
+    }
+}
+
+impl MailGuard for AccessList {
+    fn start_mail<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S2Fut<'f, StartMailResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let addr = session.connection.peer_addr.as_str();
+        let ip = addr
+            .parse::<SocketAddr>()
+            .map(|socket| socket.ip())
+            .or_else(|_| addr.parse::<IpAddr>())
+            .ok();
+        let helo = session
+            .peer_name
+            .as_deref()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let sender = session.transaction.mail.as_ref().map(|m| m.sender());
+
+        let action = self.decide(|rule| match rule {
+            AccessRule::Client(_, _) => rule.matches_client(ip),
+            AccessRule::Helo(pattern) => wildcard(pattern, helo.as_str()),
+            AccessRule::Sender(pattern) => sender.map(|s| matches_path(pattern, s)) == Some(true),
+            AccessRule::Recipient(_) => false,
+        });
+
+        let result = match action {
+            None => StartMailResult::Accepted,
+            Some(AccessAction::Accept) => {
+                debug!("Mail {} is allowlisted", session.transaction.id);
+                session.transaction.set(Allowlisted);
+                StartMailResult::Accepted
+            }
+            Some(AccessAction::Reject) => StartMailResult::Failed(
+                StartMailFailure::Rejected,
+                format!(
+                    "Mail {} is denied by the access list",
+                    session.transaction.id
+                ),
+            ),
+            Some(AccessAction::TempFail) => StartMailResult::Failed(
+                StartMailFailure::FailedTemporarily,
+                format!(
+                    "Mail {} is deferred by the access list",
+                    session.transaction.id
+                ),
+            ),
+        };
+        Box::pin(ready(result))
+    }
+    fn add_recipient<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+        rcpt: Recipient,
+    ) -> S2Fut<'f, AddRecipientResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let action = self.decide(|rule| match rule {
+            AccessRule::Recipient(pattern) => matches_path(pattern, &rcpt.address),
+            _ => false,
+        });
+        let allowlisted = session.transaction.get::<Allowlisted>().is_some();
+
+        let result = match action {
+            Some(AccessAction::Accept) => accept(session, rcpt),
+            None if allowlisted => accept(session, rcpt),
+            None => AddRecipientResult::Inconclusive(rcpt),
+            Some(AccessAction::Reject) => AddRecipientResult::Failed(
+                AddRecipientFailure::RejectedPermanently,
+                format!(
+                    "Recipient is denied by the access list in {}",
+                    session.transaction.id
+                ),
+            ),
+            Some(AccessAction::TempFail) => AddRecipientResult::Failed(
+                AddRecipientFailure::RejectedTemporarily,
+                format!(
+                    "Recipient is deferred by the access list in {}",
+                    session.transaction.id
+                ),
+            ),
+        };
+        Box::pin(ready(result))
+    }
+}
+
+/// Accept the recipient ourselves so that the other guards are skipped
+fn accept(session: &mut SmtpSession, rcpt: Recipient) -> AddRecipientResult {
+    session.transaction.rcpts.push(rcpt);
+    AddRecipientResult::Accepted
+}
+
+/// Match the address, or just its domain if the pattern has no `@`
+fn matches_path(pattern: &str, path: &SmtpPath) -> bool {
+    let address = match path {
+        SmtpPath::Mailbox { name, host, .. } if pattern.contains('@') => {
+            format!("{}@{}", name, host.domain())
+        }
+        SmtpPath::Mailbox { host, .. } => host.domain(),
+        SmtpPath::Postmaster if pattern.contains('@') => "postmaster@".to_owned(),
+        SmtpPath::Postmaster | SmtpPath::Null => return false,
+    };
+    wildcard(pattern, address.to_ascii_lowercase().as_str())
+}
+
+/// Match the text against a pattern with `*` (any sequence) and `?` (any one character) wildcards
+fn wildcard(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    // position of the last star in the pattern and the text position it matched up to
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Access list entries loaded from a file
+#[derive(Debug)]
+struct AccessFile {
+    path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    entries: Vec<AccessEntry>,
+    /// Modification time and size of the loaded file
+    version: Option<(SystemTime, u64)>,
+    checked: Option<Instant>,
+}
+
+impl AccessFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            loaded: RwLock::new(Loaded {
+                entries: vec![],
+                version: None,
+                checked: None,
+            }),
+        }
+    }
+    /// Reload the file if it changed and was not checked recently
+    fn refresh(&self, interval: Duration) {
+        {
+            let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+            if loaded.checked.map(|c| c.elapsed() < interval) == Some(true) {
+                return;
+            }
+        }
+        if let Err(e) = self.reload() {
+            warn!(
+                "Keeping the previous access list, reloading {:?} failed: {}",
+                self.path, e
+            );
+        }
+    }
+    /// Reload the file if it changed. The lock is not held while reading the file.
+    fn reload(&self) -> Result<()> {
+        let previous = {
+            let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+            loaded.checked = Some(Instant::now());
+            loaded.version
+        };
+        let metadata = std::fs::metadata(&self.path)?;
+        let version = (metadata.modified()?, metadata.len());
+        if previous == Some(version) {
+            return Ok(());
+        }
+        let content = std::fs::read_to_string(&self.path)?;
+        let entries = match self.path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => parse_toml(content.as_str())?,
+            _ => parse_text(content.as_str())?,
+        };
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        loaded.entries = entries;
+        loaded.version = Some(version);
+        info!(
+            "Loaded {} access list entries from {:?}",
+            loaded.entries.len(),
+            self.path
+        );
+        Ok(())
+    }
+}
+
+/// Parse lines of `action kind pattern`, skipping empty lines and # comments
+fn parse_text(content: &str) -> Result<Vec<AccessEntry>> {
+    let mut entries = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.as_slice() {
+            [action, kind, pattern] => entries.push(AccessEntry {
+                action: AccessAction::parse(action)?,
+                rule: AccessRule::parse(kind, pattern)?,
+            }),
+            _ => {
+                return Err(format!(
+                    "Line {}: expected action, kind and pattern, got {:?}",
+                    number + 1,
+                    line
+                )
+                .into())
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(feature = "config-toml")]
+fn parse_toml(content: &str) -> Result<Vec<AccessEntry>> {
+    #[derive(serde::Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Rules {
+        client: Vec<String>,
+        helo: Vec<String>,
+        sender: Vec<String>,
+        recipient: Vec<String>,
+    }
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Lists {
+        #[serde(default)]
+        accept: Rules,
+        #[serde(default)]
+        reject: Rules,
+        #[serde(default)]
+        tempfail: Rules,
+    }
+    let lists: Lists = toml::from_str(content)?;
+    let mut entries = vec![];
+    for (action, rules) in [
+        (AccessAction::Accept, lists.accept),
+        (AccessAction::Reject, lists.reject),
+        (AccessAction::TempFail, lists.tempfail),
+    ] {
+        for (kind, patterns) in [
+            ("client", rules.client),
+            ("helo", rules.helo),
+            ("sender", rules.sender),
+            ("recipient", rules.recipient),
+        ] {
+            for pattern in patterns {
+                entries.push(AccessEntry {
+                    action,
+                    rule: AccessRule::parse(kind, pattern.as_str())?,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(not(feature = "config-toml"))]
+fn parse_toml(_content: &str) -> Result<Vec<AccessEntry>> {
+    Err("TOML access lists need the config-toml feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::ConnectionInfo;
+    use crate::smtp::{command::SmtpMail, SmtpHost};
+
+    fn path(name: &str, domain: &str) -> SmtpPath {
+        SmtpPath::Mailbox {
+            name: name.to_owned(),
+            host: SmtpHost::Domain(domain.to_owned()),
+            relays: vec![],
+        }
+    }
+
+    fn session_from(peer: &str, sender: SmtpPath) -> SmtpSession {
+        let mut session = SmtpSession {
+            peer_name: Some("mail.example.com".to_owned()),
+            connection: ConnectionInfo::new(String::new(), peer.to_owned()),
+            ..Default::default()
+        };
+        session.transaction.mail = Some(SmtpMail::Mail(sender, vec![]));
+        session
+    }
+
+    #[test]
+    fn wildcards_match() {
+        assert!(wildcard("*.example.com", "mail.example.com"));
+        assert!(!wildcard("*.example.com", "example.com"));
+        assert!(wildcard("*@example.com", "alice@example.com"));
+        assert!(wildcard("a?ice@*", "alice@example.com"));
+        assert!(wildcard("*", ""));
+        assert!(!wildcard("alice", "alice2"));
+        assert!(wildcard("*ab*c", "xabyabzc"));
+    }
+
+    #[test]
+    fn clients_match_cidr() {
+        let rule = AccessRule::parse("client", "192.0.2.0/24").unwrap();
+        assert!(rule.matches_client(Some("192.0.2.77".parse().unwrap())));
+        assert!(!rule.matches_client(Some("192.0.3.1".parse().unwrap())));
+        assert!(!rule.matches_client(Some("::1".parse().unwrap())));
+        let rule = AccessRule::parse("client", "2001:db8::/32").unwrap();
+        assert!(rule.matches_client(Some("2001:db8:1::5".parse().unwrap())));
+        assert!(AccessRule::parse("client", "192.0.2.0/33").is_err());
+    }
+
+    #[test]
+    fn text_is_parsed() {
+        let entries = parse_text(
+            "# comment\n\naccept client 192.0.2.1 # backup MX\nreject sender *.spam.example\n",
+        )
+        .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                AccessEntry {
+                    action: AccessAction::Accept,
+                    rule: AccessRule::Client("192.0.2.1".parse().unwrap(), 32)
+                },
+                AccessEntry {
+                    action: AccessAction::Reject,
+                    rule: AccessRule::Sender("*.spam.example".to_owned())
+                },
+            ]
+        );
+        assert!(parse_text("accept client").is_err());
+        assert!(parse_text("allow client 192.0.2.1").is_err());
+    }
+
+    #[test]
+    fn allowlisted_recipients_skip_other_guards() {
+        async_std::task::block_on(async move {
+            let sut = AccessList::default()
+                .with_entry(
+                    AccessAction::Accept,
+                    AccessRule::parse("client", "192.0.2.0/24").unwrap(),
+                )
+                .with_entry(
+                    AccessAction::Reject,
+                    AccessRule::parse("sender", "spam.example").unwrap(),
+                );
+
+            let mut session = session_from("192.0.2.1:25", path("bob", "spam.example"));
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            assert!(session.transaction.get::<Allowlisted>().is_some());
+            match sut
+                .add_recipient(&mut session, Recipient::new(path("alice", "example.org")))
+                .await
+            {
+                AddRecipientResult::Accepted => {}
+                otherwise => panic!("Expected acceptance, got {:?}", otherwise),
+            }
+            assert_eq!(session.transaction.rcpts.len(), 1);
+
+            let mut session = session_from("198.51.100.1:25", path("bob", "spam.example"));
+            match sut.start_mail(&mut session).await {
+                StartMailResult::Failed(StartMailFailure::Rejected, _) => {}
+                otherwise => panic!("Expected rejection, got {:?}", otherwise),
+            }
+
+            let mut session = session_from("198.51.100.1:25", path("bob", "example.com"));
+            assert_eq!(
+                sut.start_mail(&mut session).await,
+                StartMailResult::Accepted
+            );
+            match sut
+                .add_recipient(&mut session, Recipient::new(path("alice", "example.org")))
+                .await
+            {
+                AddRecipientResult::Inconclusive(_) => {}
+                otherwise => panic!("Expected no decision, got {:?}", otherwise),
+            }
+        })
+    }
+
+    #[test]
+    fn file_is_reloaded() {
+        let path = std::env::temp_dir().join(format!("samotop-access-{}.txt", std::process::id()));
+        std::fs::write(&path, "reject recipient *@example.org\n").unwrap();
+        let sut = AccessList::default()
+            .with_file(&path)
+            .unwrap()
+            .with_reload_interval(Duration::from_secs(0));
+        let rcpt = |name: &str| path_rule(&sut, name);
+        assert_eq!(rcpt("alice"), Some(AccessAction::Reject));
+
+        // make sure the modification time changes
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(&path, "tempfail recipient bob@example.org\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(rcpt("alice"), None);
+        assert_eq!(rcpt("bob"), Some(AccessAction::TempFail));
+
+        // a broken file keeps the previous entries
+        std::fs::write(&path, "nonsense\n").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(rcpt("bob"), Some(AccessAction::TempFail));
+
+        // a change within the same modification time shows in the size
+        let modified = SystemTime::now() + Duration::from_secs(3);
+        std::fs::write(&path, "reject recipient bob@example.org\n").unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(rcpt("bob"), Some(AccessAction::Reject));
+        std::fs::write(&path, "tempfail recipient carol@example.org\n").unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(rcpt("carol"), Some(AccessAction::TempFail));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn path_rule(sut: &AccessList, name: &str) -> Option<AccessAction> {
+        let address = path(name, "example.org");
+        sut.decide(|rule| match rule {
+            AccessRule::Recipient(pattern) => matches_path(pattern, &address),
+            _ => false,
+        })
+    }
+
+    #[cfg(feature = "config-toml")]
+    #[test]
+    fn toml_is_parsed() {
+        let entries = parse_toml(
+            "[accept]\nclient = [\"192.0.2.0/24\"]\n[tempfail]\nhelo = [\"*.dynamic.example.net\"]\n",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].action, AccessAction::TempFail);
+        assert!(parse_toml("[allow]\nclient = []\n").is_err());
+    }
+}
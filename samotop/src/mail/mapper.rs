@@ -30,7 +30,7 @@ impl<T: AcceptsGuard> MailSetup<T> for Mapper {
 impl MailGuard for Mapper {
     fn add_recipient<'a, 's, 'f>(
         &'a self,
-        _session: &'s mut SmtpSession,
+        session: &'s mut SmtpSession,
         mut rcpt: Recipient,
     ) -> S2Fut<'f, AddRecipientResult>
     where
@@ -48,7 +48,11 @@ impl MailGuard for Mapper {
 
         match SmtpParser.forward_path(addr.as_bytes()) {
             Ok((i, new_path)) => {
-                trace!("Converted {} into {}", rcpt.address, addr);
+                trace!(
+                    "Converted {} into {}",
+                    session.privacy.address(&rcpt.address),
+                    session.privacy.address(&new_path)
+                );
                 assert_eq!(i, addr.len());
                 rcpt.address = new_path;
                 Box::pin(ready(AddRecipientResult::Inconclusive(rcpt)))
@@ -1,3 +1,6 @@
+mod access;
+pub use self::access::*;
+
 #[cfg(all(
     feature = "mapper",
     any(feature = "parser-peg", feature = "parser-nom")
@@ -0,0 +1 @@
+pub use samotop_core::runtime::*;
@@ -0,0 +1,128 @@
+use super::pipe::{SharedPipe, TestIo};
+use super::Reply;
+use crate::common::*;
+use crate::io::{tls::TlsCapable, ConnectionInfo, IoService};
+
+/// Scripted SMTP client talking to a service in memory.
+///
+/// The session runs while the client waits for replies so there is no need to spawn anything.
+/// Waiting for a reply the session never sends hangs, just like a real client would.
+pub struct TestClient {
+    session: Option<S1Fut<'static, Result<()>>>,
+    result: Option<Result<()>>,
+    input: SharedPipe,
+    output: SharedPipe,
+}
+
+impl TestClient {
+    /// Start a new session with the service from a local test address
+    pub fn connect(service: impl IoService) -> Self {
+        Self::connect_from(
+            service,
+            ConnectionInfo::new("127.0.0.1:25".into(), "127.0.0.1:2525".into()),
+        )
+    }
+    /// Start a new session with the service, using given connection info
+    pub fn connect_from(service: impl IoService, connection: ConnectionInfo) -> Self {
+        let input = SharedPipe::default();
+        let output = SharedPipe::default();
+        let io = TestIo {
+            input: input.clone(),
+            output: output.clone(),
+        };
+        let session = service.handle(
+            Ok(Box::new(TlsCapable::plaintext(Box::new(io)))),
+            connection,
+        );
+        TestClient {
+            session: Some(session),
+            result: None,
+            input,
+            output,
+        }
+    }
+    /// Send a line of input, CRLF is appended
+    pub fn send(&mut self, line: &str) {
+        self.send_raw(line.as_bytes());
+        self.send_raw(b"\r\n");
+    }
+    /// Send the bytes as is
+    pub fn send_raw(&mut self, bytes: &[u8]) {
+        self.input.lock().expect("input pipe lock").push(bytes);
+    }
+    /// Wait for the next reply. None if the session ended without one.
+    pub async fn reply(&mut self) -> Option<Reply> {
+        poll_fn(|cx| self.poll_reply(cx, false)).await
+    }
+    /// Wait for the next reply. None if the session ended
+    /// or if it waits for more input without replying.
+    pub async fn try_reply(&mut self) -> Option<Reply> {
+        poll_fn(|cx| self.poll_reply(cx, true)).await
+    }
+    fn poll_reply(&mut self, cx: &mut Context<'_>, give_up_idle: bool) -> Poll<Option<Reply>> {
+        loop {
+            if let Some(reply) = self.take_reply() {
+                break Poll::Ready(Some(reply));
+            }
+            match self.session.as_mut() {
+                None => break Poll::Ready(None),
+                Some(session) => match session.as_mut().poll(cx) {
+                    // the session may have replied before it started waiting
+                    Poll::Pending => match self.take_reply() {
+                        Some(reply) => break Poll::Ready(Some(reply)),
+                        None if give_up_idle && self.is_idle() => break Poll::Ready(None),
+                        None => break Poll::Pending,
+                    },
+                    Poll::Ready(result) => {
+                        self.session = None;
+                        self.result = Some(result);
+                    }
+                },
+            }
+        }
+    }
+    /// Wait for the next reply and check its code.
+    ///
+    /// # Panics
+    /// If the reply code differs or the session ended without a reply.
+    pub async fn expect(&mut self, code: u16) -> Reply {
+        match self.reply().await {
+            Some(reply) if reply.code == code => reply,
+            Some(reply) => panic!("Expected reply {}, got {:?}", code, reply.raw),
+            None => panic!(
+                "Expected reply {}, the session ended with {:?}",
+                code, self.result
+            ),
+        }
+    }
+    /// Close the input and let the session finish.
+    ///
+    /// Returns the replies which have not been read yet or the session error.
+    pub async fn finish(mut self) -> Result<Vec<Reply>> {
+        self.input.lock().expect("input pipe lock").close();
+        let mut replies = vec![];
+        while let Some(reply) = self.reply().await {
+            replies.push(reply);
+        }
+        self.result.take().unwrap_or(Ok(())).map(|()| replies)
+    }
+    /// The session waits for input which is not there
+    fn is_idle(&self) -> bool {
+        self.input.lock().expect("input pipe lock").is_starving()
+    }
+    fn take_reply(&mut self) -> Option<Reply> {
+        let mut output = self.output.lock().expect("output pipe lock");
+        let (reply, len) = Reply::parse(output.data.make_contiguous())?;
+        output.data.drain(..len);
+        Some(reply)
+    }
+}
+
+impl fmt::Debug for TestClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestClient")
+            .field("running", &self.session.is_some())
+            .field("result", &self.result)
+            .finish()
+    }
+}
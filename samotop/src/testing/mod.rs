@@ -0,0 +1,49 @@
+/*!
+In-memory test harness for mail service pipelines.
+
+Drive a `Service` with a scripted `TestClient`, check the reply codes
+and inspect what got delivered with a `RecordingDispatch`.
+No sockets nor runtime are needed, the session runs as part of the client's future.
+
+```
+# #[cfg(feature = "parser-peg")]
+# async_std::task::block_on(async {
+use samotop::mail::{Builder, Name};
+use samotop::smtp::{Esmtp, SmtpParser};
+use samotop::testing::{RecordingDispatch, TestClient};
+
+let mails = RecordingDispatch::default();
+let service = Builder + Esmtp.with(SmtpParser) + Name::new("testik") + mails.clone();
+let mut client = TestClient::connect(service.build());
+
+client.expect(220).await;
+client.send("helo macca");
+client.expect(250).await;
+client.send("mail from:<>");
+let reply = client.expect(250).await;
+assert_eq!(reply.redacted(), "250 Ok! Transaction --redacted--@testik started.\r\n");
+client.send("rcpt to:<postmaster>");
+client.expect(250).await;
+client.send("data");
+client.expect(354).await;
+client.send("Subject: test\r\n\r\nhello\r\n.");
+client.expect(250).await;
+client.send("quit");
+client.expect(221).await;
+client.finish().await.expect("session ends well");
+
+assert_eq!(mails.mails()[0].body, b"Subject: test\r\n\r\nhello\r\n");
+# })
+```
+*/
+
+mod client;
+mod pipe;
+mod recorder;
+mod replay;
+mod reply;
+
+pub use self::client::*;
+pub use self::recorder::*;
+pub use self::replay::*;
+pub use self::reply::*;
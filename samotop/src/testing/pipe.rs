@@ -0,0 +1,88 @@
+use crate::common::*;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::task::Waker;
+
+/// One direction of an in-memory connection
+#[derive(Debug, Default)]
+pub(crate) struct Pipe {
+    pub data: VecDeque<u8>,
+    pub closed: bool,
+    reader: Option<Waker>,
+}
+
+pub(crate) type SharedPipe = Arc<Mutex<Pipe>>;
+
+impl Pipe {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+        self.wake();
+    }
+    pub fn close(&mut self) {
+        self.closed = true;
+        self.wake();
+    }
+    /// The reader waits for data
+    pub fn is_starving(&self) -> bool {
+        self.reader.is_some() && self.data.is_empty() && !self.closed
+    }
+    fn wake(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake()
+        }
+    }
+}
+
+/// Server side of the in-memory connection
+#[derive(Debug)]
+pub(crate) struct TestIo {
+    pub input: SharedPipe,
+    pub output: SharedPipe,
+}
+
+impl io::Read for TestIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut input = self.input.lock().expect("input pipe lock");
+        if input.data.is_empty() {
+            if input.closed {
+                Poll::Ready(Ok(0))
+            } else {
+                input.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        } else {
+            let len = buf.len().min(input.data.len());
+            for (target, byte) in buf.iter_mut().zip(input.data.drain(..len)) {
+                *target = byte;
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+}
+
+impl io::Write for TestIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut output = self.output.lock().expect("output pipe lock");
+        if output.closed {
+            Poll::Ready(Err(io::ErrorKind::NotConnected.into()))
+        } else {
+            output.push(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.output.lock().expect("output pipe lock").close();
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,109 @@
+use crate::common::*;
+use crate::mail::{AcceptsDispatch, DispatchResult, MailDataSink, MailDispatch, MailSetup};
+use crate::smtp::{SmtpPath, SmtpSession};
+use std::sync::Mutex;
+
+/// A mail delivered to the `RecordingDispatch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMail {
+    /// Mail transaction ID
+    pub id: String,
+    /// MAIL FROM: path
+    pub sender: Option<SmtpPath>,
+    /// RCPT TO: paths
+    pub rcpts: Vec<SmtpPath>,
+    /// The mail data
+    pub body: Vec<u8>,
+}
+
+/**
+Records the mails that made it through the pipeline.
+
+A mail is recorded once it is complete and accepted.
+If another dispatch has already opened a mail sink, the data is passed through to it as well.
+Clones share the records so keep one to look at the results.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct RecordingDispatch {
+    mails: Arc<Mutex<Vec<RecordedMail>>>,
+}
+
+impl RecordingDispatch {
+    /// Get the mails recorded so far
+    pub fn mails(&self) -> Vec<RecordedMail> {
+        self.mails.lock().expect("mails lock").clone()
+    }
+}
+
+impl<T: AcceptsDispatch> MailSetup<T> for RecordingDispatch {
+    fn setup(self, config: &mut T) {
+        config.add_last_dispatch(self)
+    }
+}
+
+impl MailDispatch for RecordingDispatch {
+    fn open_mail_body<'a, 's, 'f>(
+        &'a self,
+        session: &'s mut SmtpSession,
+    ) -> S1Fut<'f, DispatchResult>
+    where
+        'a: 'f,
+        's: 'f,
+    {
+        let transaction = &mut session.transaction;
+        let sink = RecordingSink {
+            mails: self.mails.clone(),
+            mail: Some(RecordedMail {
+                id: transaction.id.clone(),
+                sender: transaction.mail.as_ref().map(|m| m.sender().clone()),
+                rcpts: transaction
+                    .rcpts
+                    .iter()
+                    .map(|r| r.address.clone())
+                    .collect(),
+                body: vec![],
+            }),
+            inner: transaction.sink.take(),
+        };
+        transaction.sink = Some(Box::pin(sink));
+        Box::pin(ready(Ok(())))
+    }
+}
+
+struct RecordingSink {
+    mails: Arc<Mutex<Vec<RecordedMail>>>,
+    mail: Option<RecordedMail>,
+    inner: Option<Pin<Box<dyn MailDataSink>>>,
+}
+
+impl io::Write for RecordingSink {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = match self.inner.as_mut() {
+            Some(inner) => ready!(inner.as_mut().poll_write(cx, buf))?,
+            None => buf.len(),
+        };
+        if let Some(mail) = self.mail.as_mut() {
+            mail.body.extend_from_slice(&buf[..len]);
+        }
+        Poll::Ready(Ok(len))
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.as_mut().poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(inner) = self.inner.as_mut() {
+            ready!(inner.as_mut().poll_close(cx))?;
+        }
+        if let Some(mail) = self.mail.take() {
+            self.mails.lock().expect("mails lock").push(mail);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
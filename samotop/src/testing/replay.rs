@@ -0,0 +1,123 @@
+use super::{Reply, TestClient};
+use crate::common::*;
+use crate::io::IoService;
+use crate::smtp::{TranscriptEntry, TranscriptEntryKind};
+
+/**
+Replay a recorded transcript (see `TranscriptRecorder`) against the service and compare the replies.
+
+The client data is sent as recorded and each reply is compared with the recorded one
+after redacting the generated identifiers (see `Reply::redacted()`).
+Timing is not reproduced, the client sends its data as soon as the recorded replies
+preceding it have been received. A service waiting for input instead of replying
+counts as a missing reply. Replay stops at STARTTLS as TLS is not available in memory.
+
+```
+# async_std::task::block_on(async {
+use samotop::mail::Builder;
+use samotop::smtp::TranscriptEntry;
+use samotop::testing::replay;
+
+let transcript = TranscriptEntry::parse_all(concat!(
+    "0.000010 write \"220 testik service ready\\r\\n\"\n",
+    "0.000100 read \"quit\\r\\n\"\n",
+    "0.000120 write \"221 testik service closing transmission channel\\r\\n\"\n",
+)).expect("valid transcript");
+let report = replay(Builder.build(), transcript).await.expect("replay");
+// this service does not speak SMTP
+assert!(!report.is_ok());
+# })
+```
+*/
+pub async fn replay(
+    service: impl IoService,
+    transcript: impl IntoIterator<Item = TranscriptEntry>,
+) -> Result<ReplayReport> {
+    let mut client = TestClient::connect(service);
+    let mut report = ReplayReport::default();
+    let mut recorded = vec![];
+
+    for entry in transcript {
+        match entry.kind {
+            TranscriptEntryKind::Read => {
+                // the recorded replies came before the client sent more data
+                report.compare_with(&mut client, &mut recorded).await;
+                client.send_raw(entry.data.as_slice());
+            }
+            TranscriptEntryKind::Write => recorded.extend_from_slice(entry.data.as_slice()),
+            TranscriptEntryKind::StartTls => {
+                report.compare_with(&mut client, &mut recorded).await;
+                report.stopped_at_tls = true;
+                return Ok(report);
+            }
+        }
+    }
+    report.compare_with(&mut client, &mut recorded).await;
+
+    for extra in client.finish().await? {
+        report.compare(None, Some(extra));
+    }
+    Ok(report)
+}
+
+/// The outcome of a `replay()`
+#[derive(Debug, Default, Clone)]
+pub struct ReplayReport {
+    /// Number of replies compared
+    pub replies: usize,
+    /// Replies that differ, expected vs actual. None when missing.
+    pub differences: Vec<(Option<Reply>, Option<Reply>)>,
+    /// The transcript continued with TLS which was not replayed
+    pub stopped_at_tls: bool,
+}
+
+impl ReplayReport {
+    /// Replay went through the same as recorded
+    pub fn is_ok(&self) -> bool {
+        self.differences.is_empty()
+    }
+    async fn compare_with(&mut self, client: &mut TestClient, recorded: &mut Vec<u8>) {
+        while let Some((expected, len)) = Reply::parse(recorded.as_slice()) {
+            recorded.drain(..len);
+            let actual = client.try_reply().await;
+            self.compare(Some(expected), actual);
+        }
+    }
+    fn compare(&mut self, expected: Option<Reply>, actual: Option<Reply>) {
+        self.replies += 1;
+        let same = match (&expected, &actual) {
+            (Some(expected), Some(actual)) => expected.redacted() == actual.redacted(),
+            _ => false,
+        };
+        if !same {
+            self.differences.push((expected, actual));
+        }
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} replies compared, {} differ{}",
+            self.replies,
+            self.differences.len(),
+            if self.stopped_at_tls {
+                ", stopped at STARTTLS"
+            } else {
+                ""
+            }
+        )?;
+        for (expected, actual) in self.differences.iter() {
+            let show = |reply: &Option<Reply>| {
+                reply
+                    .as_ref()
+                    .map(|r| format!("{:?}", r.raw))
+                    .unwrap_or_else(|| "nothing".to_owned())
+            };
+            writeln!(f, "- {}", show(expected))?;
+            writeln!(f, "+ {}", show(actual))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+/// An SMTP reply as received by the `TestClient`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// The reply code, i.e. 250
+    pub code: u16,
+    /// The reply as sent on the wire, all lines including CRLF
+    pub raw: String,
+}
+
+impl Reply {
+    /// Parse the first complete reply from the given input, returns the reply and its length
+    pub(crate) fn parse(input: &[u8]) -> Option<(Reply, usize)> {
+        let mut pos = 0;
+        loop {
+            let eol = input[pos..].windows(2).position(|w| w == b"\r\n")? + pos + 2;
+            let line = &input[pos..eol];
+            pos = eol;
+            // the last line of a reply has a space or nothing after the code
+            if line.get(3) != Some(&b'-') {
+                let raw = String::from_utf8_lossy(&input[..pos]).to_string();
+                let code = raw
+                    .get(0..3)
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or_default();
+                return Some((Reply { code, raw }, pos));
+            }
+        }
+    }
+    /// The reply text with generated identifiers replaced by `--redacted--`
+    /// so that it can be compared with a snapshot.
+    ///
+    /// An identifier is any word of 9 or more letters and digits, with at least one digit.
+    /// That covers the time based transaction IDs as well as ULIDs or UUIDs without dashes.
+    pub fn redacted(&self) -> String {
+        let mut result = String::with_capacity(self.raw.len());
+        let mut word = String::new();
+        for c in self.raw.chars() {
+            if c.is_ascii_alphanumeric() {
+                word.push(c);
+            } else {
+                result.push_str(redact(word.as_str()));
+                word.clear();
+                result.push(c);
+            }
+        }
+        result.push_str(redact(word.as_str()));
+        result
+    }
+}
+
+fn redact(word: &str) -> &str {
+    if word.len() >= 9 && word.chars().any(|c| c.is_ascii_digit()) {
+        "--redacted--"
+    } else {
+        word
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.raw.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_single_line() {
+        let (reply, len) = Reply::parse(b"250 Ok\r\n354 go").expect("reply");
+        assert_eq!(reply.code, 250);
+        assert_eq!(reply.raw, "250 Ok\r\n");
+        assert_eq!(len, 8);
+    }
+
+    #[test]
+    fn parse_multi_line() {
+        let (reply, _) =
+            Reply::parse(b"250-testik\r\n250-SIZE\r\n250 8BITMIME\r\n").expect("reply");
+        assert_eq!(reply.raw, "250-testik\r\n250-SIZE\r\n250 8BITMIME\r\n");
+    }
+
+    #[test]
+    fn parse_incomplete() {
+        assert_eq!(Reply::parse(b"250-testik\r\n250 8BIT"), None);
+    }
+
+    #[test]
+    fn redact_ids() {
+        let reply = Reply {
+            code: 250,
+            raw: "250 Queued as 3456123412@testik and 01ARZ3NDEKTSV4RRFFQ69G5FAV\r\n".into(),
+        };
+        assert_eq!(
+            reply.redacted(),
+            "250 Queued as --redacted--@testik and --redacted--\r\n"
+        );
+    }
+}
@@ -7,66 +7,294 @@ mod int_tests {
         channel::{Receiver, Sender, TrySendError},
         io::{Cursor, Read, ReadExt},
     };
-    use regex::Regex;
-    use samotop::smtp::{DriverControl, SessionService};
+    use samotop::dns::ZoneResolver;
+    use samotop::mail::spf::Dnsbl;
+    use samotop::smtp::{
+        DriverControl, InputBuffer, SessionEnd, SessionLifecycle, SessionService, SmtpSession,
+        TransactionEnd,
+    };
     use samotop::{
         io::{
             tls::{MayBeTls, TlsCapable},
             ConnectionInfo, IoService,
         },
-        mail::{Builder, Name, NullDispatch},
-        smtp::{Esmtp, Prudence, SmtpParser},
+        mail::{
+            AcceptConnectionFailure, AcceptConnectionResult, AcceptsCheck, AcceptsConnectionGuard,
+            AcceptsSessionLifecycle, Builder, CheckMailFailure, CheckMailResult, ConnectionGuard,
+            MailCheck, MailEnvelope, MailSetup, Metrics, Name, NullDispatch,
+        },
+        smtp::{Esmtp, Prudence, SmtpParser, SmtpPath, TranscriptEntry, TranscriptRecorder},
+        testing::{replay, RecordingDispatch, TestClient},
     };
     use samotop_core::common::*;
+    use std::sync::Mutex;
     use std::time::Duration;
 
     #[async_std::test]
     async fn svc() -> Result<()> {
+        let mails = RecordingDispatch::default();
+        let service = Builder + Esmtp.with(SmtpParser) + Name::new("testik") + mails.clone();
+        let mut client = TestClient::connect(service.build());
+
+        insta::assert_debug_snapshot!(
+        client.expect(220).await.raw,
+        @r###""220 testik service ready\r\n""###);
+        client.send("ehlo macca");
+        insta::assert_debug_snapshot!(
+        client.expect(250).await.raw,
+        @r###""250 testik greets macca\r\n""###);
+        client.send("mail from:<>");
+        insta::assert_debug_snapshot!(
+        client.expect(250).await.redacted(),
+        @r###""250 Ok! Transaction --redacted--@testik started.\r\n""###);
+        client.send("rcpt to:<postmaster>");
+        insta::assert_debug_snapshot!(
+        client.expect(250).await.raw,
+        @r###""250 Ok\r\n""###);
+        client.send("data");
+        insta::assert_debug_snapshot!(
+        client.expect(354).await.raw,
+        @r###""354 Start mail input, end with <CRLF>.<CRLF>\r\n""###);
+        client.send("Subject: nice test\r\n\r\n.");
+        insta::assert_debug_snapshot!(
+        client.expect(250).await.redacted(),
+        @r###""250 Queued as --redacted--@testik\r\n""###);
+        client.send("bugy command nonsense");
+        insta::assert_debug_snapshot!(
+        client.expect(500).await.raw,
+        @r###""500 Syntax error, command unrecognized\r\n""###);
+        client.send("quit");
+        insta::assert_debug_snapshot!(
+        client.expect(221).await.raw,
+        @r###""221 testik service closing transmission channel\r\n""###);
+
+        assert!(client.finish().await?.is_empty(), "Should have no more");
+
+        let mails = mails.mails();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].rcpts, vec![SmtpPath::Postmaster]);
+        assert_eq!(mails[0].body, b"Subject: nice test\r\n\r\n");
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn extra_headers_reach_the_mail() -> Result<()> {
+        let mails = RecordingDispatch::default();
+        let dnsbl = Dnsbl::default()
+            .with_ip_zone("bl.example.net", 1.0)
+            .with_resolver(ZoneResolver::default());
+        let service =
+            (Builder + Esmtp.with(SmtpParser) + Name::new("testik") + dnsbl + mails.clone())
+                .build();
+        let client = || ConnectionInfo::new("192.0.2.25:25".into(), "192.0.2.1:54321".into());
+
+        let mut session = TestClient::connect_from(service.clone(), client());
+        session.expect(220).await;
+        session.send("ehlo macca");
+        session.expect(250).await;
+        session.send("mail from:<>");
+        session.expect(250).await;
+        session.send("rcpt to:<postmaster>");
+        session.expect(250).await;
+        session.send("data");
+        session.expect(354).await;
+        session.send("Subject: nice test\r\n\r\n.");
+        session.expect(250).await;
+        session.send("quit");
+        session.expect(221).await;
+        session.finish().await?;
+
+        let envelope = MailEnvelope::new("macca", SmtpPath::Null).to(SmtpPath::Postmaster);
+        let submission = service
+            .submit(envelope, &b"Subject: nice test\r\n\r\n"[..], client())
+            .await;
+        assert!(submission.is_queued());
+
+        let mails = mails.mails();
+        assert_eq!(mails.len(), 2);
+        for mail in mails {
+            assert_eq!(
+                String::from_utf8_lossy(&mail.body),
+                "X-Samotop-DNSBL: score=0\r\nSubject: nice test\r\n\r\n"
+            );
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn transcript_replays() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("samotop-transcript-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let recording = Builder
+            + TranscriptRecorder::new(&dir)
+            + Esmtp.with(SmtpParser)
+            + Name::new("testik")
+            + NullDispatch;
+        let mut client = TestClient::connect(recording.build());
+        client.expect(220).await;
+        client.send("ehlo macca");
+        client.expect(250).await;
+        client.send("mail from:<>");
+        client.expect(250).await;
+        client.send("rcpt to:<postmaster>");
+        client.expect(250).await;
+        client.send("data");
+        client.expect(354).await;
+        client.send("Subject: nice test\r\n\r\n.");
+        client.expect(250).await;
+        client.send("quit");
+        client.expect(221).await;
+        client.finish().await?;
+
+        // the transcript is finished in the background
+        let path = loop {
+            let finished = std::fs::read_dir(&dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .find(|path| path.extension() == Some("transcript".as_ref()));
+            match finished {
+                Some(path) => break path,
+                None => async_std::task::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let transcript = TranscriptEntry::parse_all(&std::fs::read_to_string(path)?)?;
+        std::fs::remove_dir_all(&dir)?;
+
+        let same = Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NullDispatch;
+        let report = replay(same.build(), transcript.clone()).await?;
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.replies, 7);
+
+        let other = Builder + Esmtp.with(SmtpParser) + Name::new("other") + NullDispatch;
+        let report = replay(other.build(), transcript).await?;
+        assert!(!report.is_ok());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn metrics_count_session() -> Result<()> {
+        let metrics = Metrics::new();
+        let service =
+            Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NullDispatch + metrics.clone();
+        let mut client = TestClient::connect(service.build());
+        client.expect(220).await;
+        client.send("ehlo macca");
+        client.expect(250).await;
+        client.send("mail from:<>");
+        client.expect(250).await;
+        client.send("rcpt to:<postmaster>");
+        client.expect(250).await;
+        client.send("data");
+        client.expect(354).await;
+        client.send("Subject: nice test\r\n\r\n.");
+        client.expect(250).await;
+        client.send("quit");
+        client.expect(221).await;
+        client.finish().await?;
+
+        let text = metrics.render();
+        for expected in [
+            "samotop_connections_total 1\n",
+            "samotop_active_sessions 0\n",
+            "samotop_commands_total{verb=\"MAIL\"} 1\n",
+            "samotop_replies_total{class=\"2xx\"} 6\n",
+            "samotop_replies_total{class=\"3xx\"} 1\n",
+            "samotop_recipients_total{result=\"accepted\",reason=\"inconclusive\"} 1\n",
+            "samotop_messages_total 1\n",
+            "samotop_session_duration_seconds_count 1\n",
+        ] {
+            assert!(text.contains(expected), "{} not in {}", expected, text);
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn check_rejects_mail() -> Result<()> {
         let input = Cursor::new(concat!(
             "ehlo macca\r\n",
             "mail from:<>\r\n",
             "rcpt to:<postmaster>\r\n",
             "data\r\n",
-            "Subject: nice test\r\n",
+            "Subject: EICAR\r\n",
             "\r\n",
             ".\r\n",
-            "bugy command nonsense\r\n",
             "quit\r\n",
         ));
 
         let testio = TestIo::new(input);
         let writes = testio.writes();
         let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
-        let service = Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NullDispatch;
+        let service =
+            Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NullDispatch + VirusFound;
 
         service
             .build()
             .handle(Ok(io), ConnectionInfo::default())
             .await?;
 
+        for _ in 0..5 {
+            writes.recv().await?;
+        }
         insta::assert_debug_snapshot!(
         String::from_utf8_lossy(writes.recv().await?.as_slice()),
-        @r###""220 testik service ready\r\n""###);
+        @r###""550 5.7.1 virus found\r\n""###);
         insta::assert_debug_snapshot!(
         String::from_utf8_lossy(writes.recv().await?.as_slice()),
-        @r###""250 testik greets macca\r\n""###);
-        insta::assert_debug_snapshot!(
-        Regex::new("[0-9]{9}[0-9]*")?.replace(
-        String::from_utf8_lossy(writes.recv().await?.as_slice()).to_string().as_str(),"--redacted--"),
-        @r###""250 Ok! Transaction --redacted--@testik started.\r\n""###);
-        insta::assert_debug_snapshot!(
-        String::from_utf8_lossy(writes.recv().await?.as_slice()).to_string().as_str(),
-        @r###""250 Ok\r\n""###);
+        @r###""221 testik service closing transmission channel\r\n""###);
+
+        assert!(writes.recv().await.is_err(), "Should have no more");
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct VirusFound;
+
+    impl<T: AcceptsCheck> MailSetup<T> for VirusFound {
+        fn setup(self, config: &mut T) {
+            config.add_last_check(self)
+        }
+    }
+
+    impl MailCheck for VirusFound {
+        fn check_mail<'a, 's, 'f>(
+            &'a self,
+            _session: &'s mut SmtpSession,
+        ) -> S1Fut<'f, CheckMailResult>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            Box::pin(ready(CheckMailResult::Failed(
+                CheckMailFailure::RejectedPermanently,
+                "5.7.1 virus found".to_owned(),
+            )))
+        }
+    }
+
+    #[async_std::test]
+    async fn connection_guard_rejects_client() -> Result<()> {
+        let input = Cursor::new(concat!("ehlo macca\r\n", "mail from:<>\r\n", "quit\r\n",));
+
+        let testio = TestIo::new(input);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let service = Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NoService;
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
         insta::assert_debug_snapshot!(
-        String::from_utf8_lossy(writes.recv().await?.as_slice()).to_string().as_str(),
-        @r###""354 Start mail input, end with <CRLF>.<CRLF>\r\n""###);
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""554 testik No SMTP service here\r\n""###);
         insta::assert_debug_snapshot!(
-        Regex::new("[0-9]{9}[0-9]*")?.replace(
-        String::from_utf8_lossy(writes.recv().await?.as_slice()).to_string().as_str(),"--redacted--"),
-        @r###""250 Queued as --redacted--@testik\r\n""###);
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""503 Bad sequence of commands\r\n""###);
         insta::assert_debug_snapshot!(
-        String::from_utf8_lossy(writes.recv().await?.as_slice()).to_string().as_str(),
-        @r###""500 Syntax error, command unrecognized\r\n""###);
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""503 Bad sequence of commands\r\n""###);
         insta::assert_debug_snapshot!(
         String::from_utf8_lossy(writes.recv().await?.as_slice()),
         @r###""221 testik service closing transmission channel\r\n""###);
@@ -76,6 +304,169 @@ mod int_tests {
         Ok(())
     }
 
+    #[derive(Debug)]
+    struct NoService;
+
+    impl<T: AcceptsConnectionGuard> MailSetup<T> for NoService {
+        fn setup(self, config: &mut T) {
+            config.add_last_connection_guard(self)
+        }
+    }
+
+    impl ConnectionGuard for NoService {
+        fn accept_connection<'a, 's, 'f>(
+            &'a self,
+            _session: &'s mut SmtpSession,
+        ) -> S1Fut<'f, AcceptConnectionResult>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            Box::pin(ready(AcceptConnectionResult::Failed(
+                AcceptConnectionFailure::Rejected,
+                "not today".to_owned(),
+            )))
+        }
+    }
+
+    #[async_std::test]
+    async fn lifecycle_is_notified() -> Result<()> {
+        let input = Cursor::new(concat!(
+            "ehlo macca\r\n",
+            "mail from:<>\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "rset\r\n",
+            "mail from:<>\r\n",
+            "rcpt to:<postmaster>\r\n",
+            "data\r\n",
+            "Subject: nice test\r\n",
+            "\r\n",
+            ".\r\n",
+            "quit\r\n",
+        ));
+
+        let testio = TestIo::new(input);
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let events = Recorder::default();
+        let service =
+            Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NullDispatch + events.clone();
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        insta::assert_debug_snapshot!(events.0.lock().expect("lock").as_slice(), @r###"
+        [
+            "helo Some(\"macca\")",
+            "transaction Aborted",
+            "transaction Queued",
+            "session Quit with 2 transactions, 1 queued",
+        ]
+        "###);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn too_long_command_fails_the_session() -> Result<()> {
+        let read = Cursor::new(vec![b'x'; InputBuffer::MAX_PENDING + 1]);
+        let testio = TestIo::new(read);
+        let writes = testio.writes();
+        let io = Box::new(TlsCapable::plaintext(Box::new(testio)));
+        let events = Recorder::default();
+        let service = Builder + Esmtp.with(SmtpParser) + Name::new("testik") + events.clone();
+
+        service
+            .build()
+            .handle(Ok(io), ConnectionInfo::default())
+            .await?;
+
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""220 testik service ready\r\n""###);
+        insta::assert_debug_snapshot!(
+        String::from_utf8_lossy(writes.recv().await?.as_slice()),
+        @r###""500 Syntax error, command unrecognized\r\n""###);
+        assert!(writes.recv().await.is_err(), "Should have no more");
+        insta::assert_debug_snapshot!(events.0.lock().expect("lock").as_slice(), @r###"
+        [
+            "session Failed(\"Command too long\") with 0 transactions, 0 queued",
+        ]
+        "###);
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn lifecycle_is_notified_of_failed_io() {
+        let events = Recorder::default();
+        let service =
+            Builder + Esmtp.with(SmtpParser) + Name::new("testik") + NullDispatch + events.clone();
+
+        let result = service
+            .build()
+            .handle(Err("no stream".into()), ConnectionInfo::default())
+            .await;
+
+        assert!(result.is_err(), "Should fail");
+        insta::assert_debug_snapshot!(events.0.lock().expect("lock").as_slice(), @r###"
+        [
+            "session Failed(\"no stream\") with 0 transactions, 0 queued",
+        ]
+        "###);
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl<T: AcceptsSessionLifecycle> MailSetup<T> for Recorder {
+        fn setup(self, config: &mut T) {
+            config.add_last_lifecycle(self)
+        }
+    }
+
+    impl SessionLifecycle for Recorder {
+        fn on_helo<'a, 's, 'f>(&'a self, session: &'s mut SmtpSession) -> S1Fut<'f, ()>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            let event = format!("helo {:?}", session.peer_name);
+            self.0.lock().expect("lock").push(event);
+            Box::pin(ready(()))
+        }
+        fn on_transaction_end<'a, 's, 'f>(
+            &'a self,
+            _session: &'s mut SmtpSession,
+            end: TransactionEnd,
+        ) -> S1Fut<'f, ()>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            let event = format!("transaction {:?}", end);
+            self.0.lock().expect("lock").push(event);
+            Box::pin(ready(()))
+        }
+        fn on_session_end<'a, 's, 'f>(
+            &'a self,
+            session: &'s mut SmtpSession,
+            reason: SessionEnd,
+        ) -> S1Fut<'f, ()>
+        where
+            'a: 'f,
+            's: 'f,
+        {
+            let event = format!(
+                "session {:?} with {} transactions, {} queued",
+                reason, session.stats.transactions, session.stats.mails_queued
+            );
+            self.0.lock().expect("lock").push(event);
+            Box::pin(ready(()))
+        }
+    }
+
     #[async_std::test]
     async fn prudent_blocks_bad_client_simple() {
         let sut = Prudence::default().with_banner_delay(Duration::from_millis(50));